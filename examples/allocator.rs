@@ -0,0 +1,74 @@
+//! Times reading and applying an input of 5 million records with the workers, as a run of
+//! the binary does, under the global allocator of the build. Deserialising each record
+//! allocates, so running it with and without the `mimalloc` or `jemalloc` feature on the
+//! machine which processes the inputs tells whether either is worth building with there.
+//!
+//!     cargo run --release --example allocator
+//!     cargo run --release --example allocator --features mimalloc
+//!     cargo run --release --example allocator --features jemalloc
+
+use std::time::Instant;
+
+use effective_train::{cli::Args, ledger::WorkerSinks, pipeline::process_file, shutdown::Shutdown};
+
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+const RECORDS: u32 = 5_000_000;
+const RUNS: u32 = 3;
+
+fn allocator() -> &'static str {
+    if cfg!(feature = "mimalloc") {
+        "mimalloc"
+    } else if cfg!(feature = "jemalloc") {
+        "jemalloc"
+    } else {
+        "system"
+    }
+}
+
+/// Deposits, withdrawals and disputes spread over every client
+fn input(records: u32) -> String {
+    let mut contents = String::from("type,client,tx,amount\n");
+    for tx in 1..=records {
+        let client = tx % (1 << 16);
+        match tx % 8 {
+            // Of the deposit before
+            0 => contents.push_str(&format!("dispute,{},{},\n", (tx - 1) % (1 << 16), tx - 1)),
+            1..=2 => contents.push_str(&format!("withdrawal,{client},{tx},0.5\n")),
+            _ => contents.push_str(&format!("deposit,{client},{tx},1.2575\n")),
+        }
+    }
+    contents
+}
+
+#[tokio::main]
+async fn main() {
+    let path = std::env::temp_dir().join("effective-train-allocator.csv");
+    std::fs::write(&path, input(RECORDS)).unwrap();
+    let path_str = path.to_string_lossy().into_owned();
+    for run in 1..=RUNS {
+        let start = Instant::now();
+        let ledger = process_file(
+            &path_str,
+            &Args::default(),
+            WorkerSinks::default(),
+            &Shutdown::new(),
+        )
+        .await
+        .unwrap();
+        assert!(!ledger.into_accounts().is_empty());
+        let elapsed = start.elapsed();
+        println!(
+            "{} run {run}: {elapsed:?} for {RECORDS} records, {:.0} records/s",
+            allocator(),
+            f64::from(RECORDS) / elapsed.as_secs_f64()
+        );
+    }
+    std::fs::remove_file(path).unwrap();
+}
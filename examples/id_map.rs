@@ -0,0 +1,41 @@
+//! Compares the ledger's `IdMap` with a `HashMap` using the default hasher on the lookups
+//! a ledger makes: inserting each approved transaction and looking up its client.
+//!
+//!     cargo run --release --example id_map
+
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, BuildHasherDefault},
+    hint::black_box,
+    time::Instant,
+};
+
+use effective_train::hasher::IdHasher;
+
+const TRANSACTIONS: u32 = 5_000_000;
+const CLIENTS: u32 = 65_536;
+
+fn run<S: BuildHasher + Default>(name: &str) {
+    let start = Instant::now();
+    let mut accounts: HashMap<u16, u64, S> = HashMap::default();
+    let mut approved: HashMap<u32, u64, S> = HashMap::default();
+    for tx in 0..TRANSACTIONS {
+        let client = u16::try_from(tx.wrapping_mul(2_654_435_761) % CLIENTS).unwrap();
+        *accounts.entry(client).or_default() += 1;
+        approved.insert(tx, u64::from(client));
+    }
+    for tx in (0..TRANSACTIONS).step_by(3) {
+        black_box(approved.get(&tx));
+    }
+    println!(
+        "{name}: {:?} for {} accounts and {} transactions",
+        start.elapsed(),
+        accounts.len(),
+        approved.len()
+    );
+}
+
+fn main() {
+    run::<RandomState>("HashMap (SipHash)");
+    run::<BuildHasherDefault<IdHasher>>("IdMap");
+}
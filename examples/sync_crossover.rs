@@ -0,0 +1,80 @@
+//! Times `--sync`, which applies an input on one thread, against the workers of
+//! `process_file` for inputs of growing size, and prints the smallest size from which the
+//! workers are faster on this machine.
+//!
+//!     cargo run --release --example sync_crossover
+
+use std::time::{Duration, Instant};
+
+use effective_train::{
+    cli::Args,
+    io_ops::read_transactions_blocking,
+    ledger::{Ledger, WorkerSinks},
+    pipeline::{process_file, process_iter},
+    shutdown::Shutdown,
+};
+
+const SIZES: [u32; 5] = [100, 1_000, 10_000, 100_000, 1_000_000];
+
+/// Deposits and withdrawals spread over 1000 clients
+fn input(records: u32) -> String {
+    let mut contents = String::from("type,client,tx,amount\n");
+    for tx in 1..=records {
+        let client = tx % 1000;
+        if tx % 4 == 0 {
+            contents.push_str(&format!("withdrawal,{client},{tx},0.5\n"));
+        } else {
+            contents.push_str(&format!("deposit,{client},{tx},1.25\n"));
+        }
+    }
+    contents
+}
+
+/// Reads and applies the input as `--sync` does
+fn time_sync(path: &str) -> Duration {
+    let start = Instant::now();
+    let contents = std::fs::read(path).unwrap();
+    let transactions = read_transactions_blocking(&contents, Args::default().csv).unwrap();
+    let ledger = process_iter(Ledger::new(), transactions);
+    assert!(!ledger.into_accounts().is_empty());
+    start.elapsed()
+}
+
+/// Starts a runtime and applies the input with its workers
+fn time_workers(path: &str) -> Duration {
+    let start = Instant::now();
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let ledger = runtime
+        .block_on(process_file(
+            path,
+            &Args::default(),
+            WorkerSinks::default(),
+            &Shutdown::new(),
+        ))
+        .unwrap();
+    assert!(!ledger.into_accounts().is_empty());
+    start.elapsed()
+}
+
+fn main() {
+    let path = std::env::temp_dir().join("effective-train-sync-crossover.csv");
+    let path_str = path.to_string_lossy().into_owned();
+    let mut crossover = None;
+    for records in SIZES {
+        std::fs::write(&path, input(records)).unwrap();
+        let sync = time_sync(&path_str);
+        let workers = time_workers(&path_str);
+        println!("{records} records: sync {sync:?}, workers {workers:?}");
+        if crossover.is_none() && workers < sync {
+            crossover = Some(records);
+        }
+    }
+    std::fs::remove_file(path).unwrap();
+    match crossover {
+        Some(records) => println!("The workers are faster from {records} records"),
+        None => println!("--sync was faster for every size"),
+    }
+}
@@ -0,0 +1,47 @@
+//! Times writing 10 million accounts as a run streams them to its output, to a sink and to a
+//! file, through the buffered account writer.
+//!
+//!     cargo run --release --example write_accounts
+
+use std::time::Instant;
+
+use effective_train::{
+    account::{ClientState, SummaryColumns},
+    io_ops::stream_results,
+};
+use rust_decimal::Decimal;
+use tokio::{io::AsyncWrite, sync::mpsc};
+
+const ACCOUNTS: u32 = 10_000_000;
+
+async fn run<W: AsyncWrite + Unpin + Send + 'static>(name: &str, output: W) {
+    let start = Instant::now();
+    let (sender, receiver) = mpsc::channel(1024);
+    let writer = tokio::spawn(stream_results(
+        receiver,
+        SummaryColumns::default(),
+        None,
+        output,
+    ));
+    for i in 0..ACCOUNTS {
+        let client = u16::try_from(i % (1 << 16)).unwrap();
+        let available = Decimal::new(i64::from(i), 4);
+        let state = ClientState::restore(client, available, Decimal::ZERO, false);
+        sender.send(state).await.unwrap();
+    }
+    drop(sender);
+    writer.await.unwrap().unwrap();
+    let elapsed = start.elapsed();
+    println!(
+        "{name}: {elapsed:?} for {ACCOUNTS} accounts, {:.0} accounts/s",
+        f64::from(ACCOUNTS) / elapsed.as_secs_f64()
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    run("sink", tokio::io::sink()).await;
+    let path = std::env::temp_dir().join("effective-train-accounts.csv");
+    run("file", tokio::fs::File::create(&path).await.unwrap()).await;
+    std::fs::remove_file(path).unwrap();
+}
@@ -1,16 +1,51 @@
 #![allow(clippy::module_name_repetitions)]
 use anyhow::{bail, Ok, Result};
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Serialize;
 
-use crate::{data::Transaction, ledger::Transact};
+use crate::{
+    data::{DisputeRecord, Transaction, TransactionType},
+    digest,
+    ledger::Transact,
+    pseudonym::{ClientLabel, Pseudonymizer},
+    units::MinorUnits,
+};
 
 /// A client account with valid transactions
+#[derive(Debug)]
 pub struct ClientState {
     client_id: u16,
     available: Decimal,
     held: Decimal,
     /// An account is locked if a chargeback occurs
     locked: bool,
+    /// An account is frozen once a chargeback drives its chargeback ratio past
+    /// `chargeback_limit`, and then accepts nothing whatever `locked_policy`
+    frozen: bool,
+    /// Head of the hash chain over every transaction applied to the account
+    audit_head: [u8; 32],
+    /// Applied transactions the risk policy flagged
+    risk_flags: u32,
+    /// Applied deposits and withdrawals
+    funding_txs: u32,
+    chargebacks: u32,
+    /// Lifetime amounts of the applied deposits and withdrawals, less those reversed
+    deposited: Decimal,
+    withdrawn: Decimal,
+    /// Lifetime amount taken out of `held` by chargebacks
+    charged_back: Decimal,
+    /// Net amount of the applied adjustments and custom kinds
+    adjusted: Decimal,
+    /// Disputes ever opened against the account's transactions
+    disputes: u32,
+    /// How far withdrawals may drive `available` below zero
+    overdraft_limit: Decimal,
+    /// Transactions still applied once the account is locked
+    locked_policy: LockedAccountPolicy,
+    /// How much a dispute exceeding the funds available holds
+    dispute_policy: DisputeFundsPolicy,
+    /// Chargeback ratio beyond which the account is frozen
+    chargeback_limit: Option<Decimal>,
 }
 
 impl ClientState {
@@ -20,9 +55,40 @@ impl ClientState {
             available: Decimal::ZERO,
             held: Decimal::ZERO,
             locked: false,
+            frozen: false,
+            audit_head: [0; 32],
+            risk_flags: 0,
+            funding_txs: 0,
+            chargebacks: 0,
+            deposited: Decimal::ZERO,
+            withdrawn: Decimal::ZERO,
+            charged_back: Decimal::ZERO,
+            adjusted: Decimal::ZERO,
+            disputes: 0,
+            overdraft_limit: Decimal::ZERO,
+            locked_policy: LockedAccountPolicy::default(),
+            dispute_policy: DisputeFundsPolicy::default(),
+            chargeback_limit: None,
         }
     }
 
+    /// An account as reported by a previous run, without its transaction history
+    pub fn restore(client_id: u16, available: Decimal, held: Decimal, locked: bool) -> Self {
+        Self {
+            available,
+            held,
+            locked,
+            ..Self::new(client_id)
+        }
+    }
+
+    /// Allows withdrawals to drive `available` negative down to `-limit`
+    #[must_use]
+    pub fn with_overdraft_limit(mut self, limit: Decimal) -> Self {
+        self.overdraft_limit = limit;
+        self
+    }
+
     pub fn id(&self) -> u16 {
         self.client_id
     }
@@ -39,8 +105,19 @@ impl ClientState {
         self.available.saturating_add(self.held)
     }
 
-    fn account_ready(&self, client_id: u16) -> Result<()> {
-        if self.locked {
+    pub fn overdraft_limit(&self) -> Decimal {
+        self.overdraft_limit
+    }
+
+    /// How far `available` is below zero
+    pub fn overdraft_used(&self) -> Decimal {
+        (-self.available).max(Decimal::ZERO)
+    }
+
+    fn account_ready(&self, client_id: u16, tx_type: TransactionType) -> Result<()> {
+        if self.frozen {
+            bail!("Account '{}' is frozen", self.client_id)
+        } else if !self.accepts(tx_type) {
             bail!("Account '{}' is locked", self.client_id)
         } else if client_id != self.client_id {
             bail!(
@@ -52,84 +129,424 @@ impl ClientState {
         Ok(())
     }
 
+    /// Adds `amount`, which may be negative, to the funds available, for the handlers of
+    /// custom transaction kinds
+    ///
+    /// # Errors
+    /// If the account is locked or belongs to another client than `tx`
+    pub fn adjust_available(&mut self, tx: &Transaction, amount: Decimal) -> Result<()> {
+        self.account_ready(tx.client_id(), tx.tx_type())?;
+        self.available = self.available.saturating_add(amount);
+        self.adjusted = self.adjusted.saturating_add(amount);
+        Ok(())
+    }
+
     pub fn is_locked(&self) -> bool {
         self.locked
     }
+
+    #[must_use]
+    pub fn with_locked_policy(mut self, policy: LockedAccountPolicy) -> Self {
+        self.locked_policy = policy;
+        self
+    }
+
+    #[must_use]
+    pub fn with_dispute_policy(mut self, policy: DisputeFundsPolicy) -> Self {
+        self.dispute_policy = policy;
+        self
+    }
+
+    /// Freezes the account once a chargeback drives its chargeback ratio past `limit`
+    #[must_use]
+    pub fn with_chargeback_limit(mut self, limit: Option<Decimal>) -> Self {
+        self.chargeback_limit = limit;
+        self
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Whether a transaction of `tx_type` may be applied given the account's lock
+    pub fn accepts(&self, tx_type: TransactionType) -> bool {
+        !self.frozen && (!self.locked || self.locked_policy.allows(tx_type))
+    }
+
+    /// Only the ledger unlocks accounts, as it replays what was quarantined meanwhile. A
+    /// frozen account is thawed as well, its admin review being the same.
+    pub(crate) fn unlock(&mut self) {
+        self.locked = false;
+        self.frozen = false;
+    }
+
+    pub fn audit_head(&self) -> &[u8; 32] {
+        &self.audit_head
+    }
+
+    /// Extends the audit chain with a transaction which was just applied
+    pub fn record_applied(&mut self, tx: &Transaction) {
+        self.audit_head = digest::chain(&self.audit_head, tx, self);
+    }
+
+    pub fn risk_flags(&self) -> u32 {
+        self.risk_flags
+    }
+
+    pub fn flag_risk(&mut self) {
+        self.risk_flags = self.risk_flags.saturating_add(1);
+    }
+
+    pub fn funding_txs(&self) -> u32 {
+        self.funding_txs
+    }
+
+    pub fn chargebacks(&self) -> u32 {
+        self.chargebacks
+    }
+
+    pub fn deposited(&self) -> Decimal {
+        self.deposited
+    }
+
+    pub fn withdrawn(&self) -> Decimal {
+        self.withdrawn
+    }
+
+    pub fn charged_back(&self) -> Decimal {
+        self.charged_back
+    }
+
+    pub fn adjusted(&self) -> Decimal {
+        self.adjusted
+    }
+
+    pub fn disputes(&self) -> u32 {
+        self.disputes
+    }
+
+    /// Chargebacks per applied deposit or withdrawal, zero before any were applied
+    pub fn chargeback_ratio(&self) -> Decimal {
+        if self.funding_txs == 0 {
+            return Decimal::ZERO;
+        }
+        Decimal::from(self.chargebacks) / Decimal::from(self.funding_txs)
+    }
+
+    /// Whether the chargeback ratio exceeds `chargeback_limit`, as it does for an account
+    /// frozen by its last chargeback
+    pub fn is_high_risk(&self, chargeback_limit: Decimal) -> bool {
+        self.chargeback_ratio() > chargeback_limit
+    }
+}
+
+/// What a locked account still accepts, as processors differ on deposits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockedAccountPolicy {
+    /// Every transaction is rejected
+    #[default]
+    RejectAll,
+    /// Deposits are applied, anything else is rejected
+    AllowDeposits,
+}
+
+impl LockedAccountPolicy {
+    fn allows(self, tx_type: TransactionType) -> bool {
+        self == Self::AllowDeposits && tx_type == TransactionType::Deposit
+    }
+}
+
+impl std::str::FromStr for LockedAccountPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "reject-all" => Ok(Self::RejectAll),
+            "allow-deposits" => Ok(Self::AllowDeposits),
+            _ => bail!("unknown policy `{s}`"),
+        }
+    }
+}
+
+/// What a dispute holds when the amount of its transaction exceeds the funds available
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisputeFundsPolicy {
+    /// The whole amount is held, driving `available` negative if need be
+    #[default]
+    HoldFullAllowNegative,
+    /// Only the funds available are held, a chargeback takes the rest out of `available`
+    HoldPartial,
+    /// The dispute is rejected
+    Reject,
+}
+
+impl std::str::FromStr for DisputeFundsPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hold-full-allow-negative" => Ok(Self::HoldFullAllowNegative),
+            "hold-partial" => Ok(Self::HoldPartial),
+            "reject" => Ok(Self::Reject),
+            _ => bail!("unknown policy `{s}`"),
+        }
+    }
+}
+
+/// Optional columns appended to the account output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SummaryColumns {
+    /// Transactions flagged by the risk policy
+    pub flags: bool,
+    /// Amount drawn from the overdraft facility
+    pub overdraft: bool,
+    /// Lifetime amounts deposited and withdrawn and the number of disputes
+    pub extended: bool,
+    /// Amounts are written in these minor units rather than in major units
+    pub minor_units: Option<MinorUnits>,
+}
+
+/// The reported state of a client account, with amounts rounded to four decimal places.
+/// Optional columns are only serialised when populated.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AccountSummary {
+    #[serde(skip)]
+    pub client: u16,
+    /// What the `client` column holds
+    #[serde(rename = "client")]
+    pub label: ClientLabel,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overdraft_used: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deposited: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub withdrawn: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disputes: Option<u32>,
+}
+
+impl AccountSummary {
+    /// Column names, in the order the fields are serialised
+    pub const HEADER: [&'static str; 5] = ["client", "available", "held", "total", "locked"];
+
+    /// Column names including the enabled optional columns
+    pub fn header(columns: SummaryColumns) -> Vec<&'static str> {
+        let mut header = Self::HEADER.to_vec();
+        if columns.flags {
+            header.push("flags");
+        }
+        if columns.overdraft {
+            header.push("overdraft_used");
+        }
+        if columns.extended {
+            header.extend(["deposited", "withdrawn", "disputes"]);
+        }
+        header
+    }
+
+    pub fn with_columns(client: &ClientState, columns: SummaryColumns) -> Self {
+        let summary = Self {
+            flags: columns.flags.then(|| client.risk_flags()),
+            overdraft_used: columns
+                .overdraft
+                .then(|| round_decimal(client.overdraft_used())),
+            deposited: columns.extended.then(|| round_decimal(client.deposited())),
+            withdrawn: columns.extended.then(|| round_decimal(client.withdrawn())),
+            disputes: columns.extended.then(|| client.disputes()),
+            ..Self::from(client)
+        };
+        match columns.minor_units {
+            Some(units) => summary.in_minor_units(units),
+            None => summary,
+        }
+    }
+
+    /// The summary with every amount in `units`
+    fn in_minor_units(self, units: MinorUnits) -> Self {
+        let convert = |amount| units.from_major(amount);
+        Self {
+            available: convert(self.available),
+            held: convert(self.held),
+            total: convert(self.total),
+            overdraft_used: self.overdraft_used.map(convert),
+            deposited: self.deposited.map(convert),
+            withdrawn: self.withdrawn.map(convert),
+            ..self
+        }
+    }
+
+    /// Writes the client's pseudonym in place of its id if `pseudonyms` are enabled
+    #[must_use]
+    pub fn pseudonymized(self, pseudonyms: Option<&Pseudonymizer>) -> Self {
+        Self {
+            label: ClientLabel::new(self.client, pseudonyms),
+            ..self
+        }
+    }
+}
+
+fn round_decimal(v: Decimal) -> Decimal {
+    v.round_dp_with_strategy(4, RoundingStrategy::MidpointAwayFromZero)
+}
+
+impl From<&ClientState> for AccountSummary {
+    fn from(client: &ClientState) -> Self {
+        Self {
+            client: client.id(),
+            label: ClientLabel::Id(client.id()),
+            available: round_decimal(client.available()),
+            held: round_decimal(client.held()),
+            total: round_decimal(client.total()),
+            locked: client.is_locked(),
+            flags: None,
+            overdraft_used: None,
+            deposited: None,
+            withdrawn: None,
+            disputes: None,
+        }
+    }
 }
 
 impl Transact for ClientState {
-    fn chargeback(&mut self, tx: &Transaction, chargeback_tx: &Transaction) -> Result<()> {
-        self.account_ready(tx.client_id())?;
-        self.locked = true;
+    fn adjust(&mut self, tx: &Transaction) -> Result<()> {
+        self.account_ready(tx.client_id(), tx.tx_type())?;
 
-        match chargeback_tx.amount() {
+        match tx.amount() {
             Some(amount) => {
-                self.held = self.held.saturating_sub(amount);
+                self.available = self.available.saturating_add(amount);
+                self.adjusted = self.adjusted.saturating_add(amount);
                 Ok(())
             }
-            _ => bail!("Chargeback to Client account '{}' failed", self.client_id),
+            _ => bail!("Adjustment to Client account '{}' failed", self.client_id),
         }
     }
 
+    fn chargeback(
+        &mut self,
+        tx: &Transaction,
+        chargeback_tx: &mut DisputeRecord,
+        held: Decimal,
+    ) -> Result<()> {
+        self.account_ready(tx.client_id(), tx.tx_type())?;
+        self.account_ready(chargeback_tx.client_id(), tx.tx_type())?;
+        chargeback_tx.mark_charged_back(tx.tx_id())?;
+        self.locked = true;
+        self.held = self.held.saturating_sub(held);
+        // Whatever the dispute could not hold is taken from the funds available
+        self.available = self
+            .available
+            .saturating_sub(chargeback_tx.amount().saturating_sub(held));
+        self.charged_back = self.charged_back.saturating_add(chargeback_tx.amount());
+        self.chargebacks = self.chargebacks.saturating_add(1);
+        if self
+            .chargeback_limit
+            .is_some_and(|limit| self.is_high_risk(limit))
+        {
+            self.frozen = true;
+        }
+        Ok(())
+    }
+
     fn deposit(&mut self, tx: &Transaction) -> Result<()> {
-        self.account_ready(tx.client_id())?;
+        self.account_ready(tx.client_id(), tx.tx_type())?;
 
         match tx.amount() {
             Some(amount) => {
                 self.available = self.available.saturating_add(amount);
+                self.deposited = self.deposited.saturating_add(amount);
+                self.funding_txs = self.funding_txs.saturating_add(1);
                 Ok(())
             }
             _ => bail!("Deposit to Client account '{}' failed", self.client_id),
         }
     }
 
-    fn dispute(&mut self, tx: &Transaction, disputed_tx: &mut Transaction) -> Result<()> {
-        self.account_ready(tx.client_id())?;
-        self.account_ready(disputed_tx.client_id())?;
+    fn dispute(&mut self, tx: &Transaction, disputed_tx: &mut DisputeRecord) -> Result<Decimal> {
+        self.account_ready(tx.client_id(), tx.tx_type())?;
+        self.account_ready(disputed_tx.client_id(), tx.tx_type())?;
 
-        match disputed_tx.amount() {
-            Some(amount) if disputed_tx.is_disputable() => {
-                self.available = self.available.saturating_sub(amount);
-                self.held = self.held.saturating_add(amount);
-                disputed_tx.dispute();
-                Ok(())
-            }
-            _ => {
-                bail!("Transaction `{}` cannot be disputed", disputed_tx.tx_id())
+        let amount = disputed_tx.amount();
+        let held = match self.dispute_policy {
+            DisputeFundsPolicy::HoldFullAllowNegative => amount,
+            DisputeFundsPolicy::HoldPartial => amount.min(self.available.max(Decimal::ZERO)),
+            DisputeFundsPolicy::Reject if amount > self.available => {
+                bail!(
+                    "Dispute failed due to insufficient funds in Client Account `{}`",
+                    self.client_id
+                )
             }
-        }
+            DisputeFundsPolicy::Reject => amount,
+        };
+        disputed_tx.mark_disputed(tx.tx_id())?;
+        self.available = self.available.saturating_sub(held);
+        self.held = self.held.saturating_add(held);
+        self.disputes = self.disputes.saturating_add(1);
+        Ok(held)
     }
 
-    fn resolve(&mut self, tx: &Transaction, disputed_tx: &mut Transaction) -> Result<()> {
-        self.account_ready(tx.client_id())?;
-        self.account_ready(disputed_tx.client_id())?;
+    fn resolve(
+        &mut self,
+        tx: &Transaction,
+        disputed_tx: &mut DisputeRecord,
+        held: Decimal,
+    ) -> Result<()> {
+        self.account_ready(tx.client_id(), tx.tx_type())?;
+        self.account_ready(disputed_tx.client_id(), tx.tx_type())?;
 
-        match disputed_tx.amount() {
-            Some(amount) if disputed_tx.in_dispute() => {
-                self.available = self.available.saturating_add(amount);
-                self.held = self.held.saturating_sub(amount);
-                disputed_tx.in_dispute = false;
-                Ok(())
-            }
-            _ if !disputed_tx.in_dispute() => {
-                bail!(
-                    "Resolving Transaction failed as TxId `{}` is not under dispute",
-                    disputed_tx.tx_id()
-                )
+        disputed_tx.clear_dispute(tx.tx_id())?;
+        self.available = self.available.saturating_add(held);
+        self.held = self.held.saturating_sub(held);
+        Ok(())
+    }
+
+    fn reverse(&mut self, tx: &Transaction, reversed_tx: &mut DisputeRecord) -> Result<()> {
+        self.account_ready(tx.client_id(), tx.tx_type())?;
+        self.account_ready(reversed_tx.client_id(), tx.tx_type())?;
+
+        let change = match reversed_tx.tx_type() {
+            TransactionType::Withdrawal => reversed_tx.amount(),
+            _ => -reversed_tx.amount(),
+        };
+        if self
+            .available
+            .saturating_add(self.overdraft_limit)
+            .saturating_add(change)
+            < Decimal::ZERO
+        {
+            bail!(
+                "Reversal failed due to insufficient funds in Client Account `{}`",
+                self.client_id
+            )
+        }
+
+        reversed_tx.mark_reversed(tx.tx_id())?;
+        self.available = self.available.saturating_add(change);
+        match reversed_tx.tx_type() {
+            TransactionType::Withdrawal => {
+                self.withdrawn = self.withdrawn.saturating_sub(reversed_tx.amount());
             }
-            _ => bail!("Attempting to resolve a dispute but has not got a amount"),
+            _ => self.deposited = self.deposited.saturating_sub(reversed_tx.amount()),
         }
+        Ok(())
     }
 
     fn withdraw(&mut self, tx: &Transaction) -> Result<()> {
-        self.account_ready(tx.client_id())?;
+        self.account_ready(tx.client_id(), tx.tx_type())?;
 
         match tx.amount() {
-            Some(amount) if self.available >= amount => {
+            Some(amount) if self.available.saturating_add(self.overdraft_limit) >= amount => {
                 self.available = self.available.saturating_sub(amount);
+                self.withdrawn = self.withdrawn.saturating_add(amount);
+                self.funding_txs = self.funding_txs.saturating_add(1);
                 Ok(())
             }
-            Some(amount) if self.available < amount => {
+            Some(_) => {
                 bail!(
                     "Withdrawal failed due to insufficient funds in Client Account `{}`",
                     self.client_id
@@ -140,14 +557,101 @@ impl Transact for ClientState {
     }
 }
 
+/// An account right after a transaction was applied to it, amounts rounded as in
+/// `AccountSummary`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AccountUpdate {
+    #[serde(skip)]
+    pub client: u16,
+    /// What the `client` column holds
+    #[serde(rename = "client")]
+    pub label: ClientLabel,
+    pub tx: u32,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl AccountUpdate {
+    /// Column names, in the order the fields are serialised
+    pub const HEADER: [&'static str; 6] = ["client", "tx", "available", "held", "total", "locked"];
+
+    pub fn new(client: &ClientState, tx: u32) -> Self {
+        let summary = AccountSummary::from(client);
+        Self {
+            client: summary.client,
+            label: summary.label,
+            tx,
+            available: summary.available,
+            held: summary.held,
+            total: summary.total,
+            locked: summary.locked,
+        }
+    }
+
+    /// Writes the client's pseudonym in place of its id if `pseudonyms` are enabled
+    #[must_use]
+    pub fn pseudonymized(self, pseudonyms: Option<&Pseudonymizer>) -> Self {
+        Self {
+            label: ClientLabel::new(self.client, pseudonyms),
+            ..self
+        }
+    }
+}
+
+/// An account whose chargeback ratio exceeded the configured limit
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct HighRiskAccount {
+    #[serde(skip)]
+    pub client: u16,
+    /// What the `client` column holds
+    #[serde(rename = "client")]
+    pub label: ClientLabel,
+    pub chargebacks: u32,
+    pub transactions: u32,
+    pub chargeback_ratio: Decimal,
+}
+
+impl HighRiskAccount {
+    /// Column names, in the order the fields are serialised
+    pub const HEADER: [&'static str; 4] =
+        ["client", "chargebacks", "transactions", "chargeback_ratio"];
+
+    /// Writes the client's pseudonym in place of its id if `pseudonyms` are enabled
+    #[must_use]
+    pub fn pseudonymized(self, pseudonyms: Option<&Pseudonymizer>) -> Self {
+        Self {
+            label: ClientLabel::new(self.client, pseudonyms),
+            ..self
+        }
+    }
+}
+
+impl From<&ClientState> for HighRiskAccount {
+    fn from(client: &ClientState) -> Self {
+        Self {
+            client: client.id(),
+            label: ClientLabel::Id(client.id()),
+            chargebacks: client.chargebacks(),
+            transactions: client.funding_txs(),
+            chargeback_ratio: round_decimal(client.chargeback_ratio()).normalize(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use rust_decimal::{prelude::FromPrimitive, Decimal};
 
     use crate::{
-        account::ClientState,
-        data::{Transaction, TransactionType},
+        account::{
+            AccountSummary, ClientState, DisputeFundsPolicy, HighRiskAccount, SummaryColumns,
+        },
+        data::Transaction,
         ledger::Transact,
+        pseudonym::ClientLabel,
+        units::MinorUnits,
     };
 
     #[test]
@@ -166,6 +670,23 @@ mod test {
         );
     }
 
+    #[test]
+    fn summary_rounds_amounts_to_four_places() {
+        let user_account = ClientState {
+            client_id: 9,
+            available: Decimal::new(123_456_789, 8),
+            held: Decimal::new(5, 5),
+            locked: true,
+            ..ClientState::new(9)
+        };
+
+        let summary = AccountSummary::from(&user_account);
+        assert_eq!(summary.available.to_string(), "1.2346");
+        assert_eq!(summary.held.to_string(), "0.0001");
+        assert_eq!(summary.total.to_string(), "1.2346");
+        assert!(summary.locked);
+    }
+
     #[test]
     fn deposit_into_unlocked_account() {
         let mut user_account = ClientState {
@@ -173,14 +694,9 @@ mod test {
             available: Decimal::ZERO,
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
+        let tx = Transaction::deposit(123, 1, Decimal::from_f64(100.).unwrap());
 
         // Should SUCCEED: When the account is unlocked it should succeed
         let result = user_account.deposit(&tx);
@@ -194,14 +710,9 @@ mod test {
             available: Decimal::ZERO,
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
+        let tx = Transaction::deposit(123, 1, Decimal::from_f64(100.).unwrap());
 
         user_account.locked = true;
         let result = user_account.deposit(&tx);
@@ -219,18 +730,12 @@ mod test {
             available: Decimal::ZERO,
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let mut tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
+        let tx = Transaction::deposit(2, 1, Decimal::from_f64(100.).unwrap());
 
         // Should FAIL: When the account client id is different from the tx id
         user_account.locked = false;
-        tx.client_id = 2;
         let result = user_account.deposit(&tx);
         assert!(result.is_err());
         assert_eq!(
@@ -246,14 +751,9 @@ mod test {
             available: Decimal::from_f64(100.).unwrap(),
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let tx = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
+        let tx = Transaction::withdrawal(123, 1, Decimal::from_f64(100.).unwrap());
 
         // Should SUCCEED: When the account is unlocked it should succeed
         let result = user_account.withdraw(&tx);
@@ -267,14 +767,9 @@ mod test {
             available: Decimal::from_f64(100.).unwrap(),
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let tx = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
+        let tx = Transaction::withdrawal(123, 1, Decimal::from_f64(100.).unwrap());
 
         // Should FAIL: When the account is locked it should fail
         user_account.locked = true;
@@ -293,18 +788,12 @@ mod test {
             available: Decimal::from_f64(100.).unwrap(),
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let mut tx = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
+        let tx = Transaction::withdrawal(2, 1, Decimal::from_f64(100.).unwrap());
 
         // Should FAIL: When the account client id is different from the tx id
         user_account.locked = false;
-        tx.client_id = 2;
         let result = user_account.withdraw(&tx);
         assert!(result.is_err());
         assert_eq!(
@@ -320,14 +809,9 @@ mod test {
             available: Decimal::from_f64(100.).unwrap(),
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let tx = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(120.).unwrap()),
-            in_dispute: false,
-        };
+        let tx = Transaction::withdrawal(123, 1, Decimal::from_f64(120.).unwrap());
 
         // Should FAIL: When available funds < tx.amount
         let result = user_account.withdraw(&tx);
@@ -345,29 +829,19 @@ mod test {
             available: Decimal::from_f64(100.).unwrap(),
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let mut disputed_tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
-        let tx = Transaction {
-            tx_type: TransactionType::Dispute,
-            client_id: 123,
-            tx_id: 1,
-            amount: None,
-            in_dispute: false,
-        };
-        let result = user_account.deposit(&disputed_tx);
+        let deposit_tx = Transaction::deposit(123, 1, Decimal::from_f64(100.).unwrap());
+        let mut disputed_tx = deposit_tx.dispute_record().unwrap();
+        let tx = Transaction::dispute(123, 1);
+        let result = user_account.deposit(&deposit_tx);
         assert!(result.is_ok());
 
         // Should SUCCEED: To move amounts to held and set
         let result = user_account.dispute(&tx, &mut disputed_tx);
         assert!(result.is_ok());
         assert!(disputed_tx.in_dispute());
-        assert!(user_account.held() == disputed_tx.amount.unwrap());
+        assert!(user_account.held() == disputed_tx.amount());
     }
 
     #[test]
@@ -377,32 +851,17 @@ mod test {
             available: Decimal::from_f64(100.).unwrap(),
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let disputed_tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
-        let tx = Transaction {
-            tx_type: TransactionType::Dispute,
-            client_id: 123,
-            tx_id: 1,
-            amount: None,
-            in_dispute: false,
-        };
+        let disputed_tx = Transaction::deposit(123, 1, Decimal::from_f64(100.).unwrap());
+        let tx = Transaction::dispute(123, 1);
         let result = user_account.deposit(&disputed_tx);
         assert!(result.is_ok());
 
         // Should SUCCEED: To generate an error when tx.client_id != disputed.client_id
-        let mut disputed_tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 1234,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
+        let mut disputed_tx = Transaction::deposit(1234, 1, Decimal::from_f64(100.).unwrap())
+            .dispute_record()
+            .unwrap();
         let result = user_account.dispute(&tx, &mut disputed_tx);
         assert!(result.is_err());
         assert_eq!(
@@ -412,44 +871,29 @@ mod test {
     }
 
     #[test]
-    fn disputed_transaction_has_no_amount() {
+    fn disputed_transaction_is_already_under_dispute() {
         let mut user_account = ClientState {
             client_id: 123,
             available: Decimal::from_f64(100.).unwrap(),
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let disputed_tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
-        let tx = Transaction {
-            tx_type: TransactionType::Dispute,
-            client_id: 123,
-            tx_id: 1,
-            amount: None,
-            in_dispute: false,
-        };
+        let disputed_tx = Transaction::deposit(123, 1, Decimal::from_f64(100.).unwrap());
+        let tx = Transaction::dispute(123, 1);
         let result = user_account.deposit(&disputed_tx);
         assert!(result.is_ok());
 
-        // Should FAIL: To do dispute if the disputed transaction as no amount
-        let mut disputed_tx = Transaction {
-            tx_type: TransactionType::Dispute,
-            client_id: 123,
-            tx_id: 1,
-            amount: None,
-            in_dispute: false,
-        };
+        // Should FAIL: To dispute a transaction twice without holding its amount again
+        let mut disputed_tx = disputed_tx.dispute_record().unwrap();
+        user_account.dispute(&tx, &mut disputed_tx).unwrap();
         let result = user_account.dispute(&tx, &mut disputed_tx);
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().to_string(),
-            "Transaction `1` cannot be disputed".to_string()
+            "Transaction `1` is already under dispute".to_string()
         );
+        assert!(user_account.held() == Decimal::from_f64(100.).unwrap());
     }
 
     #[test]
@@ -459,40 +903,67 @@ mod test {
             available: Decimal::from_f64(100.).unwrap(),
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let mut disputed_tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
-        let dispute_tx = Transaction {
-            tx_type: TransactionType::Dispute,
-            client_id: 123,
-            tx_id: 1,
-            amount: None,
-            in_dispute: false,
-        };
-        let resolve_tx = Transaction {
-            tx_type: TransactionType::Resolve,
-            client_id: 123,
-            tx_id: 1,
-            amount: None,
-            in_dispute: false,
-        };
+        let deposit_tx = Transaction::deposit(123, 1, Decimal::from_f64(100.).unwrap());
+        let mut disputed_tx = deposit_tx.dispute_record().unwrap();
+        let dispute_tx = Transaction::dispute(123, 1);
+        let resolve_tx = Transaction::resolve(123, 1);
 
-        user_account.deposit(&disputed_tx).unwrap();
-        user_account.dispute(&dispute_tx, &mut disputed_tx).unwrap();
+        user_account.deposit(&deposit_tx).unwrap();
+        let held = user_account.dispute(&dispute_tx, &mut disputed_tx).unwrap();
         assert!(disputed_tx.in_dispute());
 
-        disputed_tx.dispute();
-        let result = user_account.resolve(&resolve_tx, &mut disputed_tx);
+        assert!(disputed_tx.mark_disputed(1).is_err());
+        let result = user_account.resolve(&resolve_tx, &mut disputed_tx, held);
         assert!(result.is_ok());
         assert!(user_account.held() == Decimal::ZERO);
         assert!(!disputed_tx.in_dispute());
     }
 
+    #[test]
+    fn disputes_exceeding_available_funds_follow_the_policy() {
+        let deposit = Transaction::deposit(5, 1, Decimal::TEN);
+        let withdrawal = Transaction::withdrawal(5, 2, Decimal::from(6));
+        let dispute = Transaction::dispute(5, 1);
+        let account = |policy| {
+            let mut account = ClientState::new(5).with_dispute_policy(policy);
+            account.deposit(&deposit).unwrap();
+            account.withdraw(&withdrawal).unwrap();
+            (account, deposit.dispute_record().unwrap())
+        };
+
+        let (mut full, mut disputed) = account(DisputeFundsPolicy::HoldFullAllowNegative);
+        assert_eq!(full.dispute(&dispute, &mut disputed).unwrap(), Decimal::TEN);
+        assert_eq!(
+            (full.available(), full.held()),
+            (Decimal::from(-6), Decimal::TEN)
+        );
+
+        let (mut partial, mut disputed) = account(DisputeFundsPolicy::HoldPartial);
+        let held = partial.dispute(&dispute, &mut disputed).unwrap();
+        assert_eq!(held, Decimal::from(4));
+        assert_eq!((partial.available(), partial.held()), (Decimal::ZERO, held));
+        partial
+            .chargeback(&Transaction::chargeback(5, 1), &mut disputed, held)
+            .unwrap();
+        assert_eq!(
+            (partial.available(), partial.held()),
+            (Decimal::from(-6), Decimal::ZERO)
+        );
+
+        let (mut rejecting, mut disputed) = account(DisputeFundsPolicy::Reject);
+        assert_eq!(
+            rejecting
+                .dispute(&dispute, &mut disputed)
+                .unwrap_err()
+                .to_string(),
+            "Dispute failed due to insufficient funds in Client Account `5`"
+        );
+        assert!(!disputed.in_dispute());
+        assert_eq!(rejecting.available(), Decimal::from(4));
+    }
+
     #[test]
     fn chargeback_should_lock_account_when_invoked() {
         let mut user_account = ClientState {
@@ -500,43 +971,207 @@ mod test {
             available: Decimal::from_f64(100.).unwrap(),
             held: Decimal::ZERO,
             locked: false,
+            ..ClientState::new(123)
         };
-        let mut disputed_tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
-        let dispute_tx = Transaction {
-            tx_type: TransactionType::Dispute,
-            client_id: 123,
-            tx_id: 1,
-            amount: None,
-            in_dispute: false,
-        };
-        let chargeback_tx = Transaction {
-            tx_type: TransactionType::Chargeback,
-            client_id: 123,
-            tx_id: 1,
-            amount: None,
-            in_dispute: false,
-        };
+        let deposit_tx = Transaction::deposit(123, 1, Decimal::from_f64(100.).unwrap());
+        let mut disputed_tx = deposit_tx.dispute_record().unwrap();
+        let dispute_tx = Transaction::dispute(123, 1);
+        let chargeback_tx = Transaction::chargeback(123, 1);
 
-        let result = user_account.deposit(&disputed_tx);
+        let result = user_account.deposit(&deposit_tx);
         assert!(result.is_ok());
         let result = user_account.dispute(&dispute_tx, &mut disputed_tx);
         assert!(result.is_ok());
         assert!(disputed_tx.in_dispute());
-        let result = user_account.chargeback(&chargeback_tx, &disputed_tx);
+        let held = disputed_tx.amount();
+        let result = user_account.chargeback(&chargeback_tx, &mut disputed_tx, held);
         assert!(result.is_ok());
         assert!(user_account.is_locked());
+        assert!(disputed_tx.is_charged_back());
+        assert!(!disputed_tx.in_dispute());
 
-        let result = user_account.deposit(&disputed_tx);
+        let result = user_account.deposit(&deposit_tx);
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().to_string(),
             "Account '123' is locked".to_string()
         );
     }
+
+    #[test]
+    fn chargebacks_require_an_open_dispute() {
+        let mut user_account = ClientState::new(123);
+        let deposit_tx = Transaction::deposit(123, 1, Decimal::TEN);
+        let mut disputed_tx = deposit_tx.dispute_record().unwrap();
+        let chargeback_tx = Transaction::chargeback(123, 1);
+        user_account.deposit(&deposit_tx).unwrap();
+
+        // Never disputed, nothing is held to charge back
+        let result = user_account.chargeback(&chargeback_tx, &mut disputed_tx, Decimal::TEN);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Charging back Transaction failed as TxId `1` is not under dispute"
+        );
+        assert!(!user_account.is_locked());
+        assert_eq!(user_account.held(), Decimal::ZERO);
+        assert_eq!(user_account.chargebacks(), 0);
+
+        user_account
+            .dispute(&Transaction::dispute(123, 1), &mut disputed_tx)
+            .unwrap();
+        user_account
+            .chargeback(&chargeback_tx, &mut disputed_tx, Decimal::TEN)
+            .unwrap();
+        // Even were the account unlocked, the same transaction is not charged back again
+        user_account.locked = false;
+        let result = user_account.chargeback(&chargeback_tx, &mut disputed_tx, Decimal::TEN);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Transaction `1` has already been charged back"
+        );
+        assert_eq!(user_account.held(), Decimal::ZERO);
+        assert_eq!(user_account.chargebacks(), 1);
+    }
+
+    #[test]
+    fn reversal_undoes_a_transaction_once() {
+        let mut user_account = ClientState::new(123);
+        let deposit_tx = Transaction::deposit(123, 1, Decimal::TEN);
+        let withdrawal_tx = Transaction::withdrawal(123, 2, Decimal::from(4));
+        let mut reversed_deposit = deposit_tx.dispute_record().unwrap();
+        let mut reversed_withdrawal = withdrawal_tx.dispute_record().unwrap();
+        user_account.deposit(&deposit_tx).unwrap();
+        user_account.withdraw(&withdrawal_tx).unwrap();
+
+        // Only 6 of the 10 deposited are left, the record is untouched by the failure
+        let result = user_account.reverse(&Transaction::reversal(123, 1), &mut reversed_deposit);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Reversal failed due to insufficient funds in Client Account `123`"
+        );
+        assert!(!reversed_deposit.is_reversed());
+
+        let reversal_tx = Transaction::reversal(123, 2);
+        user_account
+            .reverse(&reversal_tx, &mut reversed_withdrawal)
+            .unwrap();
+        assert_eq!(user_account.available(), Decimal::TEN);
+        assert!(reversed_withdrawal.is_reversed());
+        assert!(user_account
+            .reverse(&reversal_tx, &mut reversed_withdrawal)
+            .is_err());
+        assert!(user_account
+            .dispute(&Transaction::dispute(123, 2), &mut reversed_withdrawal)
+            .is_err());
+
+        user_account
+            .reverse(&Transaction::reversal(123, 1), &mut reversed_deposit)
+            .unwrap();
+        assert_eq!(user_account.available(), Decimal::ZERO);
+        assert_eq!(user_account.held(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn extended_summary_reports_lifetime_counters() {
+        let mut user_account = ClientState::new(5);
+        let deposit_tx = Transaction::deposit(5, 1, Decimal::TEN);
+        let withdrawal_tx = Transaction::withdrawal(5, 2, Decimal::from(3));
+        let mut disputed_tx = deposit_tx.dispute_record().unwrap();
+        let mut reversed_tx = withdrawal_tx.dispute_record().unwrap();
+        user_account.deposit(&deposit_tx).unwrap();
+        user_account.withdraw(&withdrawal_tx).unwrap();
+        user_account
+            .withdraw(&Transaction::withdrawal(5, 3, Decimal::ONE))
+            .unwrap();
+        user_account
+            .reverse(&Transaction::reversal(5, 2), &mut reversed_tx)
+            .unwrap();
+        user_account
+            .dispute(&Transaction::dispute(5, 1), &mut disputed_tx)
+            .unwrap();
+
+        let columns = SummaryColumns {
+            extended: true,
+            ..SummaryColumns::default()
+        };
+        let summary = AccountSummary::with_columns(&user_account, columns);
+        assert_eq!(summary.deposited, Some(Decimal::TEN));
+        assert_eq!(summary.withdrawn, Some(Decimal::ONE));
+        let cents = SummaryColumns {
+            minor_units: Some(MinorUnits::new(2)),
+            ..columns
+        };
+        let summary = AccountSummary::with_columns(&user_account, cents);
+        assert_eq!(summary.deposited, Some(Decimal::ONE_THOUSAND));
+        assert_eq!(summary.withdrawn, Some(Decimal::ONE_HUNDRED));
+        assert_eq!(summary.disputes, Some(1));
+        assert_eq!(
+            AccountSummary::header(columns)[5..],
+            ["deposited", "withdrawn", "disputes"]
+        );
+        assert_eq!(
+            AccountSummary::with_columns(&user_account, SummaryColumns::default()).disputes,
+            None
+        );
+    }
+
+    #[test]
+    fn chargeback_ratio_counts_applied_deposits_and_withdrawals() {
+        let mut user_account = ClientState::new(7);
+        let deposit = Transaction::deposit(7, 1, Decimal::TEN);
+        let disputed_deposit = Transaction::deposit(7, 2, Decimal::ONE);
+        let mut disputed = disputed_deposit.dispute_record().unwrap();
+        user_account.deposit(&deposit).unwrap();
+        user_account.deposit(&disputed_deposit).unwrap();
+        assert!(user_account
+            .withdraw(&Transaction::withdrawal(7, 3, Decimal::ONE_HUNDRED))
+            .is_err());
+        assert_eq!(user_account.chargeback_ratio(), Decimal::ZERO);
+
+        user_account
+            .dispute(&Transaction::dispute(7, 2), &mut disputed)
+            .unwrap();
+        user_account
+            .chargeback(&Transaction::chargeback(7, 2), &mut disputed, Decimal::ONE)
+            .unwrap();
+        let limit = Decimal::from_f64(0.4).unwrap();
+        assert!(user_account.is_high_risk(limit));
+        assert!(!user_account.is_high_risk(Decimal::from_f64(0.5).unwrap()));
+        // Without a limit nothing is frozen
+        assert!(!user_account.is_frozen());
+        assert_eq!(
+            HighRiskAccount::from(&user_account),
+            HighRiskAccount {
+                client: 7,
+                label: ClientLabel::Id(7),
+                chargebacks: 1,
+                transactions: 2,
+                chargeback_ratio: Decimal::from_f64(0.5).unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn withdrawal_may_use_overdraft_up_to_limit() {
+        let mut user_account = ClientState::new(9).with_overdraft_limit(Decimal::TEN);
+        user_account
+            .deposit(&Transaction::deposit(9, 1, Decimal::ONE))
+            .unwrap();
+        user_account
+            .withdraw(&Transaction::withdrawal(9, 2, Decimal::from(8)))
+            .unwrap();
+        assert_eq!(user_account.available().to_string(), "-7");
+        assert_eq!(user_account.overdraft_used().to_string(), "7");
+
+        let result = user_account.withdraw(&Transaction::withdrawal(9, 3, Decimal::from(4)));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Withdrawal failed due to insufficient funds in Client Account `9`"
+        );
+        user_account
+            .withdraw(&Transaction::withdrawal(9, 4, Decimal::from(3)))
+            .unwrap();
+        assert_eq!(user_account.overdraft_used().to_string(), "10");
+        assert_eq!(ClientState::new(9).overdraft_used(), Decimal::ZERO);
+    }
 }
@@ -0,0 +1,296 @@
+//! Avro container files, for inputs and accounts exchanged with services which speak Avro
+//! rather than CSV
+//!
+//! An `.avro` input holds records with the fields of `Transaction::HEADER`, e.g. of
+//! `TRANSACTION_SCHEMA`. Each record is handed to the same deserialisation as a CSV record,
+//! so its fields may be strings or numbers and it is checked, skipped with `--lenient` and
+//! rejected as a CSV record would be. `--avro-out` writes the accounts as records of
+//! `ACCOUNT_SCHEMA`.
+//!
+//! Messages framed for a Confluent schema registry are not read: this engine has no message
+//! queue source yet, and container files carry their schema.
+
+use std::{
+    io::Cursor,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result};
+use apache_avro::{types::Value, Reader, Schema, Writer};
+use csv_async::StringRecord;
+use serde::Serialize;
+
+use crate::{
+    account::{AccountSummary, ClientState},
+    data::{Sequenced, Transaction},
+    io_ops::{read_transaction, CsvFormat, SOURCE_BATCH},
+    source::EventSource,
+};
+
+/// Schema of the records of an input, amounts are decimal strings to keep their digits
+pub const TRANSACTION_SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "Transaction",
+    "namespace": "effective_train",
+    "fields": [
+        {"name": "type", "type": "string"},
+        {"name": "client", "type": "int"},
+        {"name": "tx", "type": "long"},
+        {"name": "amount", "type": ["null", "string"], "default": null}
+    ]
+}"#;
+
+/// Schema of the accounts written by `--avro-out`, amounts rounded to four decimal places
+pub const ACCOUNT_SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "AccountSummary",
+    "namespace": "effective_train",
+    "fields": [
+        {"name": "client", "type": "int"},
+        {"name": "available", "type": "string"},
+        {"name": "held", "type": "string"},
+        {"name": "total", "type": "string"},
+        {"name": "locked", "type": "boolean"}
+    ]
+}"#;
+
+/// Whether the input at `file_path` is read as an Avro container file, by its extension
+pub fn is_container(file_path: &str) -> bool {
+    std::path::Path::new(file_path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("avro"))
+}
+
+/// Records of an Avro container file, tagged with their position in it
+pub struct AvroSource {
+    reader: Reader<'static, Cursor<Vec<u8>>>,
+    format: CsvFormat,
+    /// Records read so far
+    read: u64,
+    malformed: AtomicU64,
+}
+
+impl AvroSource {
+    /// Reads the whole file, whose blocks are then decoded as the events are routed. Only
+    /// `lenient` of `format` applies.
+    ///
+    /// # Errors
+    /// If the file cannot be read or is not an Avro container file
+    pub async fn open(file_path: &str, format: CsvFormat) -> Result<Self> {
+        let bytes = tokio::fs::read(file_path).await?;
+        let reader = Reader::new(Cursor::new(bytes))
+            .with_context(|| format!("{file_path} is not an Avro container file"))?;
+        Ok(Self {
+            reader,
+            format,
+            read: 0,
+            malformed: AtomicU64::new(0),
+        })
+    }
+}
+
+impl EventSource for AvroSource {
+    async fn next_batch(&mut self) -> Result<Vec<Sequenced>> {
+        let mut batch = Vec::new();
+        while batch.len() < SOURCE_BATCH {
+            let Some(value) = self.reader.next() else {
+                break;
+            };
+            self.read += 1;
+            let record = value.with_context(|| format!("Cannot decode record {}", self.read))?;
+            let tx = self
+                .format
+                .tolerate(read_transaction(&fields(&record)), &self.malformed)
+                .with_context(|| format!("Record {} of the input", self.read))?;
+            batch.extend(tx.map(|tx| Sequenced { seq: self.read, tx }));
+        }
+        Ok(batch)
+    }
+
+    fn malformed(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+}
+
+/// The `Transaction::HEADER` fields of a record as a CSV record holds them, empty where the
+/// record has no such field or it is not a single value
+fn fields(record: &Value) -> StringRecord {
+    let fields: &[(String, Value)] = match record {
+        Value::Record(fields) => fields.as_slice(),
+        _ => &[],
+    };
+    let mut csv = StringRecord::new();
+    for name in Transaction::HEADER {
+        let value = fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .and_then(|(_, value)| scalar(value));
+        csv.push_field(value.as_deref().unwrap_or_default());
+    }
+    csv
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => Some(String::new()),
+        Value::Boolean(value) => Some(value.to_string()),
+        Value::Int(value) => Some(value.to_string()),
+        Value::Long(value) => Some(value.to_string()),
+        Value::Float(value) => Some(value.to_string()),
+        Value::Double(value) => Some(value.to_string()),
+        Value::String(value) | Value::Enum(_, value) => Some(value.trim().to_owned()),
+        Value::Union(_, value) => scalar(value),
+        _ => None,
+    }
+}
+
+/// A record of `ACCOUNT_SCHEMA`
+#[derive(Serialize)]
+struct AvroAccount {
+    client: i32,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+}
+
+/// Encodes `accounts` in client id order as an Avro container file of `ACCOUNT_SCHEMA`
+///
+/// # Errors
+/// If an account cannot be encoded
+pub fn encode_accounts<'a>(accounts: impl IntoIterator<Item = &'a ClientState>) -> Result<Vec<u8>> {
+    let schema = Schema::parse_str(ACCOUNT_SCHEMA)?;
+    let mut summaries: Vec<_> = accounts.into_iter().map(AccountSummary::from).collect();
+    summaries.sort_unstable_by_key(|summary| summary.client);
+    let mut writer = Writer::new(&schema, Vec::new());
+    for summary in summaries {
+        writer.append_ser(AvroAccount {
+            client: summary.client.into(),
+            available: summary.available.to_string(),
+            held: summary.held.to_string(),
+            total: summary.total.to_string(),
+            locked: summary.locked,
+        })?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod test {
+    use apache_avro::{types::Value, Reader, Schema, Writer};
+    use rust_decimal::Decimal;
+    use serde::Serialize;
+
+    use crate::{
+        account::ClientState,
+        avro::{encode_accounts, is_container, AvroSource, TRANSACTION_SCHEMA},
+        io_ops::CsvFormat,
+        source::EventSource,
+    };
+
+    #[derive(Serialize)]
+    struct Record {
+        r#type: &'static str,
+        client: i32,
+        tx: i64,
+        amount: Option<&'static str>,
+    }
+
+    /// An input of a deposit, a dispute and a deposit to a client beyond `u16`
+    fn write_input(name: &str) -> String {
+        let schema = Schema::parse_str(TRANSACTION_SCHEMA).unwrap();
+        let mut writer = Writer::new(&schema, Vec::new());
+        let records = [
+            ("deposit", 1, 1, Some("10.50")),
+            ("dispute", 1, 1, None),
+            ("deposit", 70_000, 2, Some("1")),
+        ];
+        for (r#type, client, tx, amount) in records {
+            writer
+                .append_ser(Record {
+                    r#type,
+                    client,
+                    tx,
+                    amount,
+                })
+                .unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("effective-train-{name}.avro"));
+        std::fs::write(&path, writer.into_inner().unwrap()).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Position, type and amount of each transaction read, and the records skipped
+    async fn read_all(path: &str, format: CsvFormat) -> anyhow::Result<(Vec<String>, u64)> {
+        let mut source = AvroSource::open(path, format).await?;
+        let mut read = Vec::new();
+        loop {
+            let batch = source.next_batch().await?;
+            if batch.is_empty() {
+                return Ok((read, source.malformed()));
+            }
+            read.extend(batch.iter().map(|event| {
+                let tx = &event.tx;
+                format!("{} {} {:?}", event.seq, tx.tx_type(), tx.amount())
+            }));
+        }
+    }
+
+    #[tokio::test]
+    async fn records_are_read_as_csv_records() {
+        let path = write_input("avro-input");
+        let e = read_all(&path, CsvFormat::default()).await.unwrap_err();
+        assert!(format!("{e:#}").starts_with("Record 3 of the input"));
+
+        let lenient = CsvFormat {
+            lenient: true,
+            ..CsvFormat::default()
+        };
+        let (read, malformed) = read_all(&path, lenient).await.unwrap();
+        assert_eq!(read, ["1 deposit Some(10.50)", "2 dispute None"]);
+        assert_eq!(malformed, 1);
+
+        std::fs::write(&path, "type,client,tx,amount\n").unwrap();
+        assert!(read_all(&path, lenient).await.is_err());
+    }
+
+    #[test]
+    fn accounts_are_written_in_client_order() {
+        let accounts = [
+            ClientState::restore(2, Decimal::ONE, Decimal::ZERO, true),
+            ClientState::restore(1, Decimal::new(105_555, 5), Decimal::TWO, false),
+        ];
+        let bytes = encode_accounts(&accounts).unwrap();
+        let records: Vec<_> = Reader::new(bytes.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let string = |value: &str| Value::String(value.to_string());
+        assert_eq!(
+            records,
+            [
+                Value::Record(vec![
+                    ("client".to_string(), Value::Int(1)),
+                    ("available".to_string(), string("1.0556")),
+                    ("held".to_string(), string("2")),
+                    ("total".to_string(), string("3.0556")),
+                    ("locked".to_string(), Value::Boolean(false)),
+                ]),
+                Value::Record(vec![
+                    ("client".to_string(), Value::Int(2)),
+                    ("available".to_string(), string("1")),
+                    ("held".to_string(), string("0")),
+                    ("total".to_string(), string("1")),
+                    ("locked".to_string(), Value::Boolean(true)),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn containers_are_told_by_their_extension() {
+        assert!(is_container("transactions.avro"));
+        assert!(is_container("in/Transactions.AVRO"));
+        assert!(!is_container("transactions.csv"));
+    }
+}
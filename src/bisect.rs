@@ -0,0 +1,268 @@
+//! Bisection of an input for the records where a client's balance diverges from what was
+//! expected, replaying slices of the input as `--limit` does
+//!
+//! The expected trajectory gives the client's total after some records of the input. Each
+//! probe runs the engine over the input up to a checkpoint for the client alone, and the
+//! balance is assumed to stay diverged once it diverged, so only a logarithmic number of
+//! checkpoints is replayed.
+
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use csv_async::AsyncReaderBuilder;
+use futures::stream::StreamExt;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    cli::Args,
+    ledger::WorkerSinks,
+    pipeline::process_file,
+    shutdown::Shutdown,
+    statement::{run_statement, AmountFormat, StatementFormat, StatementOptions},
+};
+
+/// Record of the checkpoint after the last record of the input
+const END: u64 = u64::MAX;
+
+/// The client's expected total after a record of the input, counting from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Checkpoint {
+    pub record: u64,
+    pub total: Decimal,
+}
+
+/// What the client's balance is checked against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    /// The total after the last record
+    Final(Decimal),
+    /// Path of a CSV of `record,total` checkpoints
+    Trajectory(String),
+}
+
+/// An amount, or else the path of a trajectory
+impl FromStr for Expected {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(total) => Self::Final(total),
+            Err(_) => Self::Trajectory(s.to_owned()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BisectOptions {
+    pub client: u16,
+    pub expected: Expected,
+}
+
+/// Reads the checkpoints of a trajectory, which must be in increasing record order
+///
+/// # Errors
+/// If the file cannot be read, is malformed, empty or out of order
+pub async fn load_trajectory(file_path: &str) -> Result<Vec<Checkpoint>> {
+    let bytes = tokio::fs::read(file_path)
+        .await
+        .with_context(|| format!("Cannot read the trajectory {file_path}"))?;
+    let mut reader = AsyncReaderBuilder::new()
+        .trim(csv_async::Trim::All)
+        .create_deserializer(bytes.as_slice());
+    let mut rows = reader.deserialize::<Checkpoint>();
+    let mut checkpoints: Vec<Checkpoint> = Vec::new();
+    while let Some(checkpoint) = rows.next().await {
+        let checkpoint = checkpoint?;
+        let after = checkpoints.last().map_or(0, |last| last.record);
+        if checkpoint.record <= after {
+            bail!(
+                "Trajectory {file_path} expects increasing record numbers from 1, found {} \
+                 after {after}",
+                checkpoint.record
+            );
+        }
+        checkpoints.push(checkpoint);
+    }
+    if checkpoints.is_empty() {
+        bail!("Trajectory {file_path} holds no checkpoint");
+    }
+    Ok(checkpoints)
+}
+
+/// Index of the first of `len` checkpoints which `matches` fails for, if any
+///
+/// # Errors
+/// If a probe fails
+pub async fn first_mismatch(
+    len: usize,
+    mut matches: impl AsyncFnMut(usize) -> Result<bool>,
+) -> Result<Option<usize>> {
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if matches(mid).await? {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    Ok((low < len).then_some(low))
+}
+
+fn describe_record(record: u64) -> String {
+    match record {
+        END => "the last record".to_owned(),
+        record => format!("record {record}"),
+    }
+}
+
+/// The client's total once the engine applied the records up to `record`, zero if it has
+/// no account by then
+async fn computed_total(
+    client: u16,
+    record: u64,
+    file_path: &str,
+    args: &mut Args,
+    shutdown: &Shutdown,
+) -> Result<Decimal> {
+    args.limit = (record != END).then_some(record);
+    let ledger = process_file(file_path, args, WorkerSinks::default(), shutdown).await?;
+    if shutdown.is_triggered() {
+        bail!("Stopped bisecting {file_path} on shutdown");
+    }
+    Ok(ledger
+        .summary(client)
+        .map_or(Decimal::ZERO, |account| account.total))
+}
+
+/// Bisects the input for the first checkpoint of `options` the client's total differs at,
+/// and writes the statement of the records since the previous checkpoint to stdout
+///
+/// # Errors
+/// If the trajectory or the input cannot be read
+pub async fn run_bisect(
+    options: &BisectOptions,
+    file_path: &str,
+    mut args: Args,
+    shutdown: &Shutdown,
+) -> Result<()> {
+    let checkpoints = match &options.expected {
+        Expected::Final(total) => vec![Checkpoint {
+            record: END,
+            total: *total,
+        }],
+        Expected::Trajectory(path) => load_trajectory(path).await?,
+    };
+    let client = options.client;
+    args.skip = 0;
+    args.worker_stats = false;
+    args.filter.only_clients = Some([client].into());
+
+    let mut mismatch = None;
+    let first = first_mismatch(checkpoints.len(), async |i| {
+        let checkpoint = checkpoints[i];
+        let total =
+            computed_total(client, checkpoint.record, file_path, &mut args, shutdown).await?;
+        if total != checkpoint.total {
+            mismatch = Some((i, total));
+        }
+        Ok(total == checkpoint.total)
+    })
+    .await?;
+
+    let mut stdout = tokio::io::stdout();
+    // Bisection ends with the probe of the first mismatch as the last one to fail
+    let (Some(first), Some((_, computed))) = (first, mismatch) else {
+        let last = checkpoints[checkpoints.len() - 1].record;
+        let report = format!(
+            "Client {client} matches the expected total at every checkpoint, up to {}\n",
+            describe_record(last)
+        );
+        stdout.write_all(report.as_bytes()).await?;
+        stdout.flush().await?;
+        return Ok(());
+    };
+    let checkpoint = checkpoints[first];
+    let from = first
+        .checked_sub(1)
+        .map_or(1, |previous| checkpoints[previous].record + 1);
+    let report = format!(
+        "Client {client} diverges between record {from} and {}: expected a total of {} \
+         after it, computed {computed}\n\n",
+        describe_record(checkpoint.record),
+        checkpoint.total
+    );
+    stdout.write_all(report.as_bytes()).await?;
+    stdout.flush().await?;
+
+    let statement = StatementOptions {
+        client,
+        from,
+        to: checkpoint.record,
+        format: StatementFormat::Text,
+        amounts: AmountFormat::default(),
+        passthrough: Vec::new(),
+    };
+    run_statement(&statement, file_path, &args).await
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use crate::bisect::{first_mismatch, load_trajectory, Checkpoint, Expected};
+
+    #[tokio::test]
+    async fn finds_the_first_mismatch() {
+        for diverged in 0..=7 {
+            let mut probes = 0;
+            let first = first_mismatch(7, async |i| {
+                probes += 1;
+                Ok(i < diverged)
+            })
+            .await
+            .unwrap();
+            assert_eq!(first, (diverged < 7).then_some(diverged));
+            assert!(probes <= 3);
+        }
+        let unreadable = first_mismatch(3, async |_| Err(anyhow::anyhow!("unreadable")));
+        assert!(unreadable.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn loads_increasing_checkpoints() {
+        let path = std::env::temp_dir().join("effective-train-trajectory.csv");
+        std::fs::write(&path, "record,total\n10, 100.0\n20,95.5\n").unwrap();
+        let trajectory = load_trajectory(&path.to_string_lossy()).await.unwrap();
+        assert_eq!(
+            trajectory,
+            vec![
+                Checkpoint {
+                    record: 10,
+                    total: Decimal::new(1000, 1),
+                },
+                Checkpoint {
+                    record: 20,
+                    total: Decimal::new(955, 1),
+                },
+            ]
+        );
+
+        std::fs::write(&path, "record,total\n20,1\n10,2\n").unwrap();
+        let e = load_trajectory(&path.to_string_lossy()).await.unwrap_err();
+        assert!(e.to_string().contains("found 10 after 20"));
+        std::fs::write(&path, "record,total\n").unwrap();
+        assert!(load_trajectory(&path.to_string_lossy()).await.is_err());
+    }
+
+    #[test]
+    fn expects_an_amount_or_a_trajectory() {
+        assert_eq!("100.0".parse(), Ok(Expected::Final(Decimal::new(1000, 1))));
+        assert_eq!(
+            "expected.csv".parse(),
+            Ok(Expected::Trajectory("expected.csv".to_owned()))
+        );
+    }
+}
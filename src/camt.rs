@@ -0,0 +1,210 @@
+//! ISO 20022 camt.053 bank-to-customer statements of the final balances, written with
+//! `--camt-out`
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use rust_decimal::Decimal;
+
+use crate::account::{AccountSummary, ClientState};
+
+const NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:camt.053.001.02";
+
+/// ISO 4217 code the balances are stated in, as amounts carry no currency of their own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Currency(String);
+
+impl FromStr for Currency {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.len() != 3 || !s.bytes().all(|b| b.is_ascii_uppercase()) {
+            bail!("Expected a three letter ISO 4217 currency code, e.g. `EUR`, found `{s}`");
+        }
+        Ok(Self(s.to_owned()))
+    }
+}
+
+impl Currency {
+    /// Decimal places of its minor unit per ISO 4217, e.g. 2 for the cents of `EUR`. Codes
+    /// the table does not list are assumed to have cents.
+    pub fn exponent(&self) -> u32 {
+        match self.0.as_str() {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF"
+            | "UGX" | "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            "CLF" | "UYW" => 4,
+            _ => 2,
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Renders a camt.053 message with a statement per account, in client id order. Each
+/// statement holds the closing booked (`CLBD`) balance, the account's total, and the
+/// closing available (`CLAV`) one, both as of `created`.
+#[allow(clippy::implicit_hasher)]
+pub fn render_camt053(
+    results: &HashMap<u16, ClientState>,
+    currency: &Currency,
+    created: SystemTime,
+) -> String {
+    let secs = created
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (date, date_time) = utc_date_time(secs);
+    let mut accounts: Vec<_> = results.values().map(AccountSummary::from).collect();
+    accounts.sort_unstable_by_key(|account| account.client);
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(xml, "<Document xmlns=\"{NAMESPACE}\">");
+    xml.push_str("  <BkToCstmrStmt>\n    <GrpHdr>\n");
+    let _ = writeln!(
+        xml,
+        "      <MsgId>{}-{secs}</MsgId>",
+        env!("CARGO_PKG_NAME")
+    );
+    let _ = writeln!(xml, "      <CreDtTm>{date_time}</CreDtTm>\n    </GrpHdr>");
+    for account in &accounts {
+        let client = account.client;
+        xml.push_str("    <Stmt>\n");
+        let _ = writeln!(xml, "      <Id>{client}-{secs}</Id>");
+        let _ = writeln!(xml, "      <CreDtTm>{date_time}</CreDtTm>");
+        let _ = writeln!(
+            xml,
+            "      <Acct><Id><Othr><Id>{client}</Id></Othr></Id><Ccy>{}</Ccy></Acct>",
+            currency.0
+        );
+        write_balance(&mut xml, "CLBD", account.total, currency, &date);
+        write_balance(&mut xml, "CLAV", account.available, currency, &date);
+        if account.locked {
+            xml.push_str("      <AddtlStmtInf>Account locked</AddtlStmtInf>\n");
+        }
+        xml.push_str("    </Stmt>\n");
+    }
+    xml.push_str("  </BkToCstmrStmt>\n</Document>\n");
+    xml
+}
+
+/// Amounts are unsigned, a negative balance is a debit
+fn write_balance(xml: &mut String, code: &str, amount: Decimal, currency: &Currency, date: &str) {
+    let indicator = if amount.is_sign_negative() && !amount.is_zero() {
+        "DBIT"
+    } else {
+        "CRDT"
+    };
+    let _ = writeln!(
+        xml,
+        "      <Bal>\n        <Tp><CdOrPrtry><Cd>{code}</Cd></CdOrPrtry></Tp>\n        \
+         <Amt Ccy=\"{}\">{}</Amt>\n        <CdtDbtInd>{indicator}</CdtDbtInd>\n        \
+         <Dt><Dt>{date}</Dt></Dt>\n      </Bal>",
+        currency.0,
+        amount.abs()
+    );
+}
+
+/// The UTC date and date-time of `secs` since the Unix epoch, e.g. `2023-11-14` and
+/// `2023-11-14T22:13:20Z`
+pub(crate) fn utc_date_time(secs: u64) -> (String, String) {
+    // Days to a proleptic Gregorian date, with years starting on the 1st of March
+    let z = secs / 86_400 + 719_468;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = z / 146_097 * 400 + yoe + u64::from(month <= 2);
+
+    let date = format!("{year:04}-{month:02}-{day:02}");
+    let time = secs % 86_400;
+    let date_time = format!(
+        "{date}T{:02}:{:02}:{:02}Z",
+        time / 3_600,
+        time / 60 % 60,
+        time % 60
+    );
+    (date, date_time)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        account::ClientState,
+        camt::{render_camt053, utc_date_time, Currency},
+    };
+
+    #[test]
+    fn converts_timestamps_to_utc() {
+        assert_eq!(
+            utc_date_time(1_700_000_000),
+            ("2023-11-14".to_owned(), "2023-11-14T22:13:20Z".to_owned())
+        );
+        assert_eq!(utc_date_time(0).1, "1970-01-01T00:00:00Z");
+        assert_eq!(utc_date_time(951_782_400).0, "2000-02-29");
+    }
+
+    #[test]
+    fn renders_a_statement_per_account() {
+        let results = HashMap::from([
+            (
+                2,
+                ClientState::restore(2, Decimal::new(-5, 0), Decimal::TWO, false),
+            ),
+            (
+                1,
+                ClientState::restore(1, Decimal::TEN, Decimal::ZERO, true),
+            ),
+        ]);
+        let xml = render_camt053(
+            &results,
+            &"EUR".parse().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        );
+
+        assert!(xml.contains("<CreDtTm>2023-11-14T22:13:20Z</CreDtTm>"));
+        let first = xml.find("<Othr><Id>1</Id>").unwrap();
+        let second = xml.find("<Othr><Id>2</Id>").unwrap();
+        assert!(first < second);
+        assert_eq!(xml.matches("<Stmt>").count(), 2);
+        assert_eq!(xml.matches("Account locked").count(), 1);
+        let debit = &xml[second..];
+        assert!(debit.contains("<Amt Ccy=\"EUR\">3</Amt>\n        <CdtDbtInd>DBIT</CdtDbtInd>"));
+        assert!(debit.contains("<Amt Ccy=\"EUR\">5</Amt>\n        <CdtDbtInd>DBIT</CdtDbtInd>"));
+        assert!(xml.ends_with("</BkToCstmrStmt>\n</Document>\n"));
+    }
+
+    #[test]
+    fn currency_is_an_iso_code() {
+        assert!("USD".parse::<Currency>().is_ok());
+        assert!("usd".parse::<Currency>().is_err());
+        assert!("EURO".parse::<Currency>().is_err());
+    }
+
+    #[test]
+    fn currencies_know_their_minor_unit() {
+        let exponent = |code: &str| code.parse::<Currency>().unwrap().exponent();
+        assert_eq!(exponent("EUR"), 2);
+        assert_eq!(exponent("JPY"), 0);
+        assert_eq!(exponent("KWD"), 3);
+    }
+}
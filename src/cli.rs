@@ -0,0 +1,2481 @@
+use std::{
+    collections::{HashMap, HashSet},
+    process::ExitCode,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use crate::{
+    account::{DisputeFundsPolicy, LockedAccountPolicy, SummaryColumns},
+    bisect::{BisectOptions, Expected},
+    camt::Currency,
+    data::DisputableTypes,
+    determinism::Nondeterminism,
+    export::ExportFormat,
+    invariants::InvariantViolation,
+    io_ops::{CsvFormat, UnexpectedHeader},
+    partitioner::PartitionerKind,
+    pipeline::WorkersPanicked,
+    pseudonym::Pseudonymizer,
+    retry::RetryPolicy,
+    risk::{AnomalyPolicy, VelocityPolicy},
+    router::{ClientSample, RecordFilter},
+    snapshot::SnapshotKey,
+    statement::{AmountFormat, StatementFormat, StatementOptions},
+    units::{AmountUnit, MinorUnits},
+    webhook::WebhookUrl,
+    window,
+};
+
+const OPTIONS: &str = "Options:
+    --no-header             The input has no header row, columns are read by position
+    --delimiter <char>      Field delimiter of the input, e.g. `;` or `tab` (default `,`)
+    --quote <char>          Quote character of the input (default `\"`)
+    --no-quoting            Treat quote characters as part of the field
+    --lenient               Skip and count records which are not transactions instead
+                            of failing with exit status 3
+    --readers <N>           Parse the input in N concurrent line-aligned chunks
+    --fast-parse            Split plain single-reader lines without the CSV reader
+    --retries <N>           Retry failed transactions N times with backoff
+    --dead-letter <path>    Write transactions which failed every attempt to path
+    --warnings-out <path>   Write records skipped with a warning, e.g. duplicates or
+                            disputes of unknown transactions, to path
+    --io-retries <N>        Retry reads and writes failing with a transient error, e.g.
+                            a timeout on a network filesystem, N times with backoff
+    --io-backoff <ms>       Delay before the first IO retry, doubled on each further
+                            retry (default 100)
+    --reorder-window <N>    Hold a dispute, resolve or chargeback for an unknown
+                            transaction for up to N later records
+    --only-clients <ids|path>
+                            Only process the records of these clients, given as
+                            comma-separated ids or a file of ids, may be repeated
+    --exclude-clients <ids|path>
+                            Skip the records of these clients, as `--only-clients`
+    --only-types <types>    Only process records of these comma-separated types, e.g.
+                            `deposit,withdrawal` for the gross flows alone
+    --sample <fraction>     Only process the records of a random fraction of the clients,
+                            e.g. `0.01`, the same clients for the same `--seed`
+    --seed <N>              Seed choosing the clients of `--sample` (default 0)
+    --skip <N>              Skip the first N records of each input
+    --limit <M>             Process at most M records of each input after those skipped,
+                            and stop reading there
+    --audit-digest          Print a SHA-256 digest of every applied state transition
+                            to stderr
+    --dispute-webhook <url> POST each applied dispute, resolve and chargeback as JSON
+                            to an http:// endpoint
+    --webhook-outbox <path> Keep the notifications the webhook did not accept in path
+                            and deliver them again first on the next run
+    --velocity-limit <N>/<M>
+                            Flag a withdrawal making more than N of a client's last
+                            M transactions withdrawals, adds a `flags` column
+    --velocity-reject       Reject such withdrawals instead of flagging them
+    --withdrawal-limits <path>
+                            Reject withdrawals exceeding the limits of their client in
+                            a CSV of `client,window,max_withdrawals,max_withdrawn`
+    --amount-anomaly <Z>/<N>
+                            Flag a deposit or withdrawal more than Z standard deviations
+                            from the mean amount of the client's deposits, or
+                            withdrawals, among its last N transactions
+    --chargeback-limit <ratio>
+                            Freeze accounts a chargeback leaves with more chargebacks
+                            per deposit or withdrawal than ratio, and report them to
+                            high_risk_accounts.csv
+    --high-risk-out <path>  Write the report of `--chargeback-limit` to path instead
+    --overdraft <client>=<limit>
+                            Let withdrawals drive the client's available funds down
+                            to -limit, adds an `overdraft_used` column, may be repeated
+    --disputable-types <types>
+                            Comma-separated types of transaction a dispute may
+                            reference (default `deposit,withdrawal`)
+    --locked-account-policy <reject-all|allow-deposits>
+                            Whether accounts locked by a chargeback still accept
+                            deposits (default `reject-all`)
+    --dispute-funds-policy <hold-full-allow-negative|hold-partial|reject>
+                            What a dispute exceeding the funds available holds: the
+                            whole amount (default), only the funds available, or
+                            nothing as it is rejected
+    --max-amount <amount>   Reject any transaction whose amount exceeds amount either
+                            way, e.g. 1000000000000, as a mistyped record
+    --emit <snapshot|updates>
+                            Write every account once processed (default), or a row
+                            with the account's balances after every applied transaction
+    --latency-budget <ms>   Warn when the p99 latency from reading a record to applying
+                            it exceeds ms, requires `--emit updates`
+    --flow-windows <path>   Write the deposits, withdrawals and disputes applied in each
+                            window of time to path as it ends, requires `--emit updates`
+    --flow-window <secs>    Width of the windows of `--flow-windows` (default 60)
+    --max-memory <size>     Stop with an error before the accounts and transactions
+                            held for an input exceed size bytes, e.g. `512M` or `2G`
+    --timeout <duration>    Stop reading the inputs once the run took duration, e.g.
+                            `90s`, `30m` or `2h`, and write the accounts so far
+    --verify-invariants     Fail the run if the accounts of an input break an invariant of
+                            the ledger once it was processed
+    --verify-determinism    Process the input twice, with other worker counts and
+                            partitioners, and fail if the accounts of the runs differ
+    --worker-stats          Print the events and clients routed to each worker to stderr
+    --workers <N>           Apply the input with N workers (default: one per core)
+    --partitioner <sticky|modulo|rendezvous>
+                            Assign each client to the least loaded worker (default), by
+                            its id modulo the workers, or by rendezvous hashing
+    --health-addr <host:port>
+                            Serve /healthz and /readyz over HTTP while processing
+    --ready-backlog <N>     Report not ready while more than N routed events wait to be
+                            applied (default 100000)
+    --top-balances <N>      Print the N accounts with the highest totals to stderr
+    --top-dispute-clients <N>
+                            Print the N clients with the most disputes to stderr
+    --negative-balances     Print the accounts with negative available funds to stderr
+    --open-disputes-out <path>
+                            Write the disputes still holding funds, oldest first, to
+                            path
+    --holds-out <path>      Write every held amount with the dispute holding it to path
+    --locked-out <path>     Write the locked accounts with the chargeback which locked
+                            them and the balances before it to path
+    --camt-out <path>       Write the final balances as an ISO 20022 camt.053 statement
+                            per account to path, requires `--currency`
+    --avro-out <path>       Write the accounts as an Avro container file to path, in
+                            builds with the `avro` feature
+    --export <ofx|qif>      Write the transactions and balances of each client to
+                            client_<id>.ofx or .qif, ofx requires `--currency`
+    --currency <code>       ISO 4217 currency the exported amounts are stated in
+    --amount-unit <major|minor>
+                            Read and write the amounts of the input and the accounts
+                            in whole minor units of `--currency`, e.g. cents
+    --pseudonym-key <path>  Write and log an HMAC pseudonym keyed by the file at path in
+                            place of each client id, for reports shared with third
+                            parties; `reveal` turns pseudonyms back into client ids
+    --opening-balances <path>
+                            Start each account from its balances in a CSV of the
+                            accounts or a snapshot written by an earlier run
+    --snapshot-out <path>   Write the accounts and their deposits and withdrawals as a
+                            compact MessagePack snapshot to path, which `simulate`
+                            restores so disputes may reference an earlier batch,
+                            encrypted if EFFECTIVE_TRAIN_SNAPSHOT_KEY is set
+    --sign-key <path>       Sign each file of accounts written with the Ed25519 private
+                            key at path, a PKCS#8 PEM file, to <file>.sig, in builds
+                            with the `signing` feature; `verify` checks the signature
+    --signature-out <path>  Write the signature of the accounts written to stdout to
+                            path, required by `--sign-key` unless they go to files
+    --output-columns <standard|extended>
+                            Add the lifetime amounts deposited and withdrawn and the
+                            number of disputes of each account to the output (extended)
+    --sorted                Write the accounts in client id order
+    --sync                  Process a small input on the main thread without workers,
+                            with only the input, filter, account and column options
+    --output-partitions <N> Write the accounts to N files, accounts_<i>.csv, each holding
+                            a range of client ids, instead of stdout
+    --manifest <path>       Write a JSON summary of the run, with the hash, record
+                            and rejection counts of each input, to path
+    --processed-log <path>  Refuse inputs whose checksum is recorded in path, and record
+                            every input of a completed run
+    --skip-processed        Skip such inputs instead of refusing to run
+    --tenant <name>=<path>  Process path with its own isolated ledgers and write the
+                            accounts to accounts_<name>.csv, may be repeated";
+
+const STATEMENT_OPTIONS: &str = "Statement options:
+    --client <id>           Client whose transactions are listed, required
+    --from <N>              First record of the input listed, counting from 1; the
+                            records before it make up the opening balance (default 1)
+    --to <N>                Last record of the input listed (default the last one)
+    --format <text|csv>     Render aligned columns (default) or CSV
+    --thousands-separators  Separate the thousands of amounts with `,` (text only)
+    --decimal-places <N>    Round amounts to N decimal places (text only)
+    --currency-symbol <symbol>
+                            Add a column with the currency's symbol (text only)
+    --passthrough-columns <names>
+                            Add the input's comma-separated columns, e.g.
+                            `merchant,reference`, to each line, may be repeated";
+
+const BISECT_OPTIONS: &str = "Bisect options:
+    --client <id>           Client whose balance is checked, required
+    --expected <total|path> The client's total after the last record, or a CSV of
+                            `record,total` checkpoints of its expected balance, required";
+
+const VERIFY_OPTIONS: &str = "Verify options:
+    --public-key <path>     Ed25519 public key of the `--sign-key` of the run, a PEM
+                            file, required
+    --signature <path>      Signature of the accounts (default <accounts.csv>.sig)";
+
+/// The options `sync_options` keeps, named in the usage error of `--sync`
+const SYNC_OPTIONS: &[&str] = &[
+    "--no-header",
+    "--delimiter",
+    "--quote",
+    "--no-quoting",
+    "--lenient",
+    "--only-clients",
+    "--exclude-clients",
+    "--sample",
+    "--seed",
+    "--skip",
+    "--limit",
+    "--only-types",
+    "--velocity-limit",
+    "--velocity-reject",
+    "--withdrawal-limits",
+    "--amount-anomaly",
+    "--max-amount",
+    "--overdraft",
+    "--disputable-types",
+    "--locked-account-policy",
+    "--dispute-funds-policy",
+    "--output-columns",
+    "--currency",
+    "--amount-unit",
+    "--pseudonym-key",
+    "--opening-balances",
+    "--sorted",
+];
+
+const EXIT_STATUS: &str = "Exit status:
+    0  Every input was processed
+    1  Processing failed for another reason, e.g. the memory budget was exceeded
+    2  The command line was invalid
+    3  An input's header or a record could not be parsed, unless `--lenient`
+    4  A file could not be read or written
+    5  Interrupted, the accounts written only cover the records read until then
+    6  The accounts broke a ledger invariant, see `--verify-invariants`
+    7  Timed out, the accounts written only cover the records read until then
+    8  A worker panicked, the accounts written only cover the other workers' clients
+    9  The accounts of the runs of `--verify-determinism` differ";
+
+/// Process exit status of each class of failure, see `EXIT_STATUS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success = 0,
+    Failure = 1,
+    Usage = 2,
+    InvalidInput = 3,
+    Io = 4,
+    Interrupted = 5,
+    InvariantViolated = 6,
+    TimedOut = 7,
+    WorkerPanicked = 8,
+    NonDeterministic = 9,
+}
+
+impl ExitStatus {
+    /// Classifies `error` by the first cause in its chain which identifies the failure
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<csv_async::Error>() {
+                return match e.kind() {
+                    csv_async::ErrorKind::Io(_) => Self::Io,
+                    csv_async::ErrorKind::Serialize(_) => Self::Failure,
+                    _ => Self::InvalidInput,
+                };
+            } else if cause.is::<UnexpectedHeader>() {
+                return Self::InvalidInput;
+            } else if cause.is::<std::io::Error>() {
+                return Self::Io;
+            } else if cause.is::<InvariantViolation>() {
+                return Self::InvariantViolated;
+            } else if cause.is::<WorkersPanicked>() {
+                return Self::WorkerPanicked;
+            } else if cause.is::<Nondeterminism>() {
+                return Self::NonDeterministic;
+            }
+        }
+        Self::Failure
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        Self::from(status as u8)
+    }
+}
+
+/// What is written for each input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Emit {
+    /// Every account once the input was processed
+    #[default]
+    Snapshot,
+    /// A row for every applied transaction, as it is applied
+    Updates,
+}
+
+impl FromStr for Emit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "snapshot" => Ok(Self::Snapshot),
+            "updates" => Ok(Self::Updates),
+            _ => bail!("unknown output `{s}`"),
+        }
+    }
+}
+
+/// Which columns are written for each account, besides those of enabled features
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputColumns {
+    #[default]
+    Standard,
+    /// Adds the lifetime counters of each account
+    Extended,
+}
+
+impl FromStr for OutputColumns {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "standard" => Ok(Self::Standard),
+            "extended" => Ok(Self::Extended),
+            _ => bail!("unknown columns `{s}`"),
+        }
+    }
+}
+
+/// An input processed with its own ledgers, isolated from every other tenant
+#[derive(Debug, PartialEq, Eq)]
+pub struct Tenant {
+    pub name: String,
+    pub file_path: String,
+}
+
+impl FromStr for Tenant {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, file_path) = s
+            .split_once('=')
+            .context("`--tenant` expects `<name>=<path>`")?;
+        let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || !name.chars().all(valid_name) {
+            bail!("Tenant name `{name}` may only contain ASCII letters, digits, `-` and `_`");
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            file_path: file_path.to_owned(),
+        })
+    }
+}
+
+/// Files `verify` checks the accounts against
+#[derive(Debug, PartialEq, Eq)]
+pub struct VerifyOptions {
+    /// PEM public key of the `--sign-key` which signed the accounts
+    pub public_key: String,
+    /// Signature of the accounts
+    pub signature: String,
+}
+
+/// Options parsed from the command line
+#[derive(Debug, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Args {
+    /// Input whose accounts are written to `stdout`
+    pub file_path: Option<String>,
+    pub tenants: Vec<Tenant>,
+    /// Account output of a previous run the input is simulated against, see `simulate`
+    pub simulate: Option<String>,
+    /// Client and records of the input to write a statement of, see `statement`
+    pub statement: Option<StatementOptions>,
+    /// Client and expected balance to bisect the input for, see `bisect`
+    pub bisect: Option<BisectOptions>,
+    /// Transaction to explain the handling of, see `explain`
+    pub explain: Option<u32>,
+    /// Accounts browsed with the history of the input, if any, see `explorer`
+    pub tui: Option<String>,
+    /// Pseudonyms to turn back into client ids with the key of `pseudonyms`, see `reveal`
+    pub reveal: Vec<String>,
+    pub csv: CsvFormat,
+    /// Number of concurrent readers splitting the input file into byte ranges
+    pub readers: usize,
+    /// Parse plain records of the fixed schema without the CSV reader
+    pub fast_parse: bool,
+    /// Times a failed transaction is retried before it is dead-lettered
+    pub retries: u32,
+    /// File receiving transactions which failed every attempt
+    pub dead_letter: Option<String>,
+    /// File receiving records skipped with a warning rather than rejected
+    pub warnings_out: Option<String>,
+    /// Retries of reads and writes failing with a transient error
+    pub io_retry: RetryPolicy,
+    /// Records a dispute referencing an unknown transaction may wait for it
+    pub reorder_window: usize,
+    /// Records which are processed
+    pub filter: RecordFilter,
+    /// Records skipped at the start of each input
+    pub skip: u64,
+    /// Records processed of each input after those skipped
+    pub limit: Option<u64>,
+    /// Report a digest of every state transition applied by the run
+    pub audit_digest: bool,
+    /// Endpoint notified of every applied dispute, resolve and chargeback
+    pub dispute_webhook: Option<WebhookUrl>,
+    /// Where notifications the webhook did not accept are kept for the next run
+    pub webhook_outbox: Option<String>,
+    pub velocity: Option<VelocityPolicy>,
+    /// Limits on the withdrawals of each client over its recent transactions, read with
+    /// `LimitConfig::load` where the input is opened
+    pub withdrawal_limits: Option<String>,
+    /// Flags deposits and withdrawals of unusual amounts for their client
+    pub anomaly: Option<AnomalyPolicy>,
+    pub columns: SummaryColumns,
+    /// Chargebacks per deposit or withdrawal above which an account is frozen and reported as
+    /// high-risk
+    pub chargeback_limit: Option<Decimal>,
+    /// Where to report the high-risk accounts after each input, `high_risk_accounts.csv` if
+    /// not set
+    pub high_risk_out: Option<String>,
+    /// Overdraft limit of each client allowed to go below zero
+    pub overdrafts: HashMap<u16, Decimal>,
+    /// What accounts locked by a chargeback still accept
+    pub locked_policy: LockedAccountPolicy,
+    /// What disputes exceeding the funds available hold
+    pub dispute_policy: DisputeFundsPolicy,
+    /// Largest amount, either way, a transaction may carry
+    pub max_amount: Option<Decimal>,
+    /// The deposits or withdrawals a dispute may reference
+    pub disputable: DisputableTypes,
+    pub emit: Emit,
+    /// p99 latency of applying a record once read, above which the workers warn
+    pub latency_budget: Option<Duration>,
+    /// Where to write the payment flow of each window while processing
+    pub flow_windows: Option<String>,
+    /// Width of the windows of `flow_windows`
+    pub flow_window: Duration,
+    /// Check the accounts of each input against the ledger invariants once processed
+    pub verify_invariants: bool,
+    /// Process the input a second time with other workers and compare the accounts
+    pub verify_determinism: bool,
+    /// Report how events were spread over the workers and the memory they held
+    pub worker_stats: bool,
+    /// Number of workers, one per logical core if `None`
+    pub workers: Option<usize>,
+    /// How the router assigns clients to workers
+    pub partitioner: PartitionerKind,
+    /// Where to serve the health endpoints while processing
+    pub health_addr: Option<String>,
+    /// Events waiting to be applied above which the run reports not ready
+    pub ready_backlog: u64,
+    /// Accounts with the highest totals to print after each input
+    pub top_balances: Option<usize>,
+    /// Clients with the most disputes to print after each input
+    pub top_dispute_clients: Option<usize>,
+    /// Print the accounts with negative available funds after each input
+    pub negative_balances: bool,
+    /// Where to report the disputes left open after each input
+    pub open_disputes_out: Option<String>,
+    /// Where to export the amounts making up the held balances after each input
+    pub holds_out: Option<String>,
+    /// Where to report the locked accounts and their chargebacks after each input
+    pub locked_out: Option<String>,
+    /// Where to write the camt.053 statements of the final balances
+    pub camt_out: Option<String>,
+    /// Where to write the accounts as an Avro container file after each input
+    pub avro_out: Option<String>,
+    /// Format of the per-client files of account activity
+    pub export: Option<ExportFormat>,
+    /// Currency of the amounts in `camt_out` and `export`
+    pub currency: Option<Currency>,
+    /// Unit of the amounts of the input and of the accounts written, minor units of
+    /// `currency` converted with `columns.minor_units`
+    pub amount_unit: AmountUnit,
+    /// The key of the pseudonyms, read into `pseudonyms` by `Args::load_pseudonym_key`
+    pub pseudonym_key: Option<String>,
+    /// Written in place of client ids when set
+    pub pseudonyms: Option<Pseudonymizer>,
+    /// Accounts of an earlier run to start from, as a CSV of the accounts or a snapshot
+    pub opening_balances: Option<String>,
+    /// Where to write a MessagePack snapshot of the accounts after each input
+    pub snapshot_out: Option<String>,
+    /// Seals the snapshots written and opens those read, see `Args::load_snapshot_key`
+    pub snapshot_key: Option<SnapshotKey>,
+    /// Private key signing the accounts written
+    pub sign_key: Option<String>,
+    /// Where to write the signature of the accounts written to stdout
+    pub signature_out: Option<String>,
+    /// Check the accounts in `file_path` against their signature
+    pub verify: Option<VerifyOptions>,
+    /// Approximate bytes the ledgers of one input may hold
+    pub max_memory: Option<usize>,
+    /// How long the run may take before reading stops
+    pub timeout: Option<Duration>,
+    /// Write the accounts in client id order
+    pub sorted: bool,
+    /// Apply the input on the main thread, without a runtime or workers
+    pub sync: bool,
+    /// Files the accounts are split into by client id range
+    pub output_partitions: Option<usize>,
+    /// Where to write the summary of the run
+    pub manifest: Option<String>,
+    /// Checksums of the inputs processed by earlier runs
+    pub processed_log: Option<String>,
+    /// Skip inputs found in `processed_log` rather than failing
+    pub skip_processed: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            file_path: None,
+            tenants: Vec::new(),
+            simulate: None,
+            statement: None,
+            bisect: None,
+            explain: None,
+            tui: None,
+            reveal: Vec::new(),
+            csv: CsvFormat::default(),
+            readers: 1,
+            fast_parse: false,
+            retries: 0,
+            dead_letter: None,
+            warnings_out: None,
+            io_retry: RetryPolicy {
+                retries: 0,
+                backoff: Duration::from_millis(100),
+            },
+            reorder_window: 0,
+            filter: RecordFilter::default(),
+            skip: 0,
+            limit: None,
+            audit_digest: false,
+            dispute_webhook: None,
+            webhook_outbox: None,
+            velocity: None,
+            withdrawal_limits: None,
+            anomaly: None,
+            columns: SummaryColumns::default(),
+            chargeback_limit: None,
+            high_risk_out: None,
+            overdrafts: HashMap::new(),
+            locked_policy: LockedAccountPolicy::default(),
+            dispute_policy: DisputeFundsPolicy::default(),
+            max_amount: None,
+            disputable: DisputableTypes::default(),
+            emit: Emit::default(),
+            latency_budget: None,
+            flow_windows: None,
+            flow_window: window::DEFAULT_WIDTH,
+            verify_invariants: false,
+            verify_determinism: false,
+            worker_stats: false,
+            workers: None,
+            partitioner: PartitionerKind::default(),
+            health_addr: None,
+            ready_backlog: 100_000,
+            top_balances: None,
+            top_dispute_clients: None,
+            negative_balances: false,
+            open_disputes_out: None,
+            holds_out: None,
+            locked_out: None,
+            camt_out: None,
+            avro_out: None,
+            export: None,
+            currency: None,
+            amount_unit: AmountUnit::default(),
+            pseudonym_key: None,
+            pseudonyms: None,
+            opening_balances: None,
+            snapshot_out: None,
+            snapshot_key: None,
+            sign_key: None,
+            signature_out: None,
+            verify: None,
+            max_memory: None,
+            timeout: None,
+            sorted: false,
+            sync: false,
+            output_partitions: None,
+            manifest: None,
+            processed_log: None,
+            skip_processed: false,
+        }
+    }
+}
+
+impl Args {
+    /// Reads the key of `--pseudonym-key` into `pseudonyms`, once the arguments are parsed
+    /// so a key which cannot be read fails as an input does
+    ///
+    /// # Errors
+    /// If the key cannot be read or is too short
+    pub fn load_pseudonym_key(&mut self) -> Result<()> {
+        self.pseudonyms = self
+            .pseudonym_key
+            .as_deref()
+            .map(Pseudonymizer::load)
+            .transpose()?;
+        Ok(())
+    }
+
+    /// Reads the key of `SNAPSHOT_KEY_VAR` into `snapshot_key`, so a malformed key fails
+    /// before any input is read
+    ///
+    /// # Errors
+    /// If the variable is set to anything but 64 hex digits
+    pub fn load_snapshot_key(&mut self) -> Result<()> {
+        self.snapshot_key = SnapshotKey::from_env()?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// If the input path is missing or a flag has an invalid value
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut args = args.peekable();
+        let bin_name = args.next().context("Cannot parse executable name")?;
+        let usage = format!(
+            "Usage: {bin_name} <transactions.csv> [OPTIONS]\n       {bin_name} --tenant <name>=<path>... [OPTIONS]\n       {bin_name} simulate <accounts.csv> <transactions.csv> [OPTIONS]\n       {bin_name} statement <transactions.csv> --client <id> [STATEMENT OPTIONS] [OPTIONS]\n       {bin_name} bisect <transactions.csv> --client <id> --expected <total|path> [OPTIONS]\n       {bin_name} explain <transactions.csv> --tx <id> [OPTIONS]\n       {bin_name} reveal <pseudonym>... --pseudonym-key <path>\n       {bin_name} tui <accounts.csv> [<transactions.csv>] [OPTIONS]\n       {bin_name} verify <accounts.csv> --public-key <path> [VERIFY OPTIONS]\n\n{OPTIONS}\n\n{STATEMENT_OPTIONS}\n\n{BISECT_OPTIONS}\n\n{VERIFY_OPTIONS}\n\n{EXIT_STATUS}"
+        );
+
+        let simulate = if args.next_if_eq("simulate").is_some() {
+            let snapshot = args.next().filter(|arg| !arg.starts_with("--"));
+            Some(snapshot.with_context(|| usage.clone())?)
+        } else {
+            None
+        };
+        let statement = simulate.is_none() && args.next_if_eq("statement").is_some();
+        let bisect = simulate.is_none() && !statement && args.next_if_eq("bisect").is_some();
+        let explain =
+            simulate.is_none() && !statement && !bisect && args.next_if_eq("explain").is_some();
+        let reveal = simulate.is_none()
+            && !statement
+            && !bisect
+            && !explain
+            && args.next_if_eq("reveal").is_some();
+        let tui = if simulate.is_none()
+            && !statement
+            && !bisect
+            && !explain
+            && !reveal
+            && args.next_if_eq("tui").is_some()
+        {
+            let accounts = args.next().filter(|arg| !arg.starts_with("--"));
+            Some(accounts.with_context(|| usage.clone())?)
+        } else {
+            None
+        };
+        let verify = simulate.is_none()
+            && !statement
+            && !bisect
+            && !explain
+            && !reveal
+            && tui.is_none()
+            && args.next_if_eq("verify").is_some();
+
+        let mut parsed = Self {
+            simulate,
+            tui,
+            ..Self::default()
+        };
+        let mut velocity_reject = false;
+        let mut seed = None;
+        let (mut client, mut from, mut to) = (None, 1, u64::MAX);
+        let mut format = StatementFormat::default();
+        let mut amounts = AmountFormat::default();
+        let mut passthrough = Vec::new();
+        let mut expected = None;
+        let mut tx = None;
+        let (mut public_key, mut signature) = (None, None);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--velocity-reject" => velocity_reject = true,
+                "--seed" => seed = Some(parse_value(&arg, args.next(), "an unsigned integer")?),
+                "--public-key" if verify => {
+                    public_key = Some(parse_value(&arg, args.next(), "a path")?);
+                }
+                "--signature" if verify => {
+                    signature = Some(parse_value(&arg, args.next(), "a path")?);
+                }
+                "--tx" if explain => tx = Some(parse_value(&arg, args.next(), "a transaction id")?),
+                "--expected" if bisect => {
+                    expected = Some(parse_value(&arg, args.next(), "a total or a path")?);
+                }
+                "--client" if statement || bisect => {
+                    client = Some(parse_value(&arg, args.next(), "a client id")?);
+                }
+                "--from" if statement => from = parse_value(&arg, args.next(), "a record number")?,
+                "--to" if statement => to = parse_value(&arg, args.next(), "a record number")?,
+                "--format" if statement => {
+                    format = parse_value(&arg, args.next(), "`text` or `csv`")?;
+                }
+                "--thousands-separators" if statement => amounts.thousands_separators = true,
+                "--decimal-places" if statement => {
+                    let places = parse_value(&arg, args.next(), "a number of decimal places")?;
+                    if places > AmountFormat::MAX_DECIMAL_PLACES {
+                        bail!(
+                            "`--decimal-places` expects at most {}",
+                            AmountFormat::MAX_DECIMAL_PLACES
+                        );
+                    }
+                    amounts.decimal_places = Some(places);
+                }
+                "--currency-symbol" if statement => {
+                    amounts.currency = Some(
+                        args.next()
+                            .context("`--currency-symbol` expects a symbol")?,
+                    );
+                }
+                "--passthrough-columns" if statement => {
+                    let names = args
+                        .next()
+                        .context("`--passthrough-columns` expects column names")?;
+                    for name in names.split(',').map(str::trim) {
+                        if name.is_empty() {
+                            bail!("`--passthrough-columns` expects column names, found `{names}`");
+                        }
+                        passthrough.push(name.to_owned());
+                    }
+                }
+                flag if flag.starts_with("--") => {
+                    if !parsed.parse_flag(flag, &mut args)? {
+                        bail!("Unknown flag `{flag}`\n{usage}");
+                    }
+                }
+                _ if reveal => parsed.reveal.push(arg),
+                _ if parsed.file_path.is_none() => parsed.file_path = Some(arg),
+                _ => bail!("Unexpected argument `{arg}`\n{usage}"),
+            }
+        }
+
+        if reveal {
+            if parsed.reveal.is_empty() || parsed.pseudonym_key.is_none() {
+                bail!("`reveal` expects pseudonyms and `--pseudonym-key <path>`\n{usage}");
+            }
+            return Ok(parsed);
+        } else if parsed.tui.is_some() {
+            if !parsed.tenants.is_empty() {
+                bail!("`tui` expects at most a single input and no `--tenant`\n{usage}");
+            }
+        } else if parsed.file_path.is_none() && parsed.tenants.is_empty() {
+            bail!(usage);
+        } else if parsed.simulate.is_some()
+            && (parsed.file_path.is_none() || !parsed.tenants.is_empty())
+        {
+            bail!("`simulate` expects a single input and no `--tenant`\n{usage}");
+        }
+        if statement {
+            if parsed.file_path.is_none() || !parsed.tenants.is_empty() {
+                bail!("`statement` expects a single input and no `--tenant`\n{usage}");
+            } else if from == 0 || from > to {
+                bail!("`statement` expects `--from` between 1 and `--to`");
+            } else if format == StatementFormat::Csv && amounts != AmountFormat::default() {
+                bail!(
+                    "`--thousands-separators`, `--decimal-places` and `--currency-symbol` only \
+                     apply to `--format text`"
+                );
+            } else if !passthrough.is_empty() && !parsed.csv.has_header {
+                bail!(
+                    "`--passthrough-columns` names columns of the header, not with `--no-header`"
+                );
+            }
+            let client = client.context("`statement` expects `--client <id>`")?;
+            parsed.statement = Some(StatementOptions {
+                client,
+                from,
+                to,
+                format,
+                amounts,
+                passthrough,
+            });
+        }
+        if explain {
+            if parsed.file_path.is_none() || !parsed.tenants.is_empty() {
+                bail!("`explain` expects a single input and no `--tenant`\n{usage}");
+            }
+            parsed.explain = Some(tx.context("`explain` expects `--tx <id>`")?);
+        }
+        if verify {
+            if !cfg!(feature = "signing") {
+                bail!("`verify` needs a build with the `signing` feature");
+            }
+            let Some(file_path) = parsed
+                .file_path
+                .as_ref()
+                .filter(|_| parsed.tenants.is_empty())
+            else {
+                bail!("`verify` expects a single file of accounts and no `--tenant`\n{usage}");
+            };
+            parsed.verify = Some(VerifyOptions {
+                public_key: public_key.context("`verify` expects `--public-key <path>`")?,
+                signature: signature.unwrap_or_else(|| format!("{file_path}.sig")),
+            });
+        }
+        if parsed.verify_determinism && (parsed.file_path.is_none() || !parsed.tenants.is_empty()) {
+            bail!("`--verify-determinism` expects a single input and no `--tenant`\n{usage}");
+        }
+        if parsed.opening_balances.is_some() {
+            if parsed.file_path.is_none() || !parsed.tenants.is_empty() {
+                bail!("`--opening-balances` expects a single input and no `--tenant`\n{usage}");
+            } else if parsed.verify_invariants {
+                bail!(
+                    "`--verify-invariants` cannot check the accounts opened by \
+                     `--opening-balances`"
+                );
+            }
+        }
+        if bisect {
+            if parsed.file_path.is_none() || !parsed.tenants.is_empty() {
+                bail!("`bisect` expects a single input and no `--tenant`\n{usage}");
+            }
+            parsed.bisect = Some(BisectOptions {
+                client: client.context("`bisect` expects `--client <id>`")?,
+                expected: expected.context("`bisect` expects `--expected <total|path>`")?,
+            });
+        }
+        if parsed.currency.is_none() {
+            if parsed.camt_out.is_some() {
+                bail!("`--camt-out` requires `--currency`");
+            } else if parsed.export == Some(ExportFormat::Ofx) {
+                bail!("`--export ofx` requires `--currency`");
+            }
+        }
+        if parsed.amount_unit == AmountUnit::Minor {
+            if parsed.currency.is_none() {
+                bail!("`--amount-unit minor` requires `--currency`");
+            } else if parsed.simulate.is_some()
+                || statement
+                || explain
+                || bisect
+                || parsed.tui.is_some()
+            {
+                bail!(
+                    "`--amount-unit minor` cannot be combined with `simulate`, `statement`, \
+                     `explain`, `bisect` or `tui`"
+                );
+            } else if parsed.emit == Emit::Updates {
+                bail!("`--amount-unit minor` cannot be combined with `--emit updates`");
+            }
+        }
+        if parsed.pseudonym_key.is_some() {
+            if parsed.camt_out.is_some() || parsed.avro_out.is_some() || parsed.export.is_some() {
+                bail!(
+                    "`--pseudonym-key` cannot be combined with `--camt-out`, `--avro-out` or \
+                     `--export`"
+                );
+            } else if parsed.top_balances.is_some()
+                || parsed.top_dispute_clients.is_some()
+                || parsed.negative_balances
+            {
+                bail!(
+                    "`--pseudonym-key` cannot be combined with `--top-balances`, \
+                     `--top-dispute-clients` or `--negative-balances`"
+                );
+            }
+        }
+        if parsed.sign_key.is_some() {
+            let stdout = parsed.file_path.is_some() && parsed.output_partitions.is_none();
+            if parsed.emit == Emit::Updates {
+                bail!("`--sign-key` signs the accounts, not the updates of `--emit updates`");
+            } else if stdout && parsed.signature_out.is_none() {
+                bail!(
+                    "`--sign-key` requires `--signature-out <path>` for the accounts written to \
+                     stdout"
+                );
+            } else if !stdout && parsed.signature_out.is_some() {
+                bail!("`--signature-out` only applies to the accounts written to stdout");
+            }
+        } else if parsed.signature_out.is_some() {
+            bail!("`--signature-out` requires `--sign-key`");
+        }
+        if parsed.high_risk_out.is_some() && parsed.chargeback_limit.is_none() {
+            bail!("`--high-risk-out` requires `--chargeback-limit`");
+        }
+        if parsed.webhook_outbox.is_some() && parsed.dispute_webhook.is_none() {
+            bail!("`--webhook-outbox` requires `--dispute-webhook`");
+        }
+        if parsed.output_partitions.is_some() && parsed.emit == Emit::Updates {
+            bail!("`--output-partitions` cannot be combined with `--emit updates`");
+        }
+        if parsed.latency_budget.is_some() && parsed.emit != Emit::Updates {
+            bail!("`--latency-budget` requires `--emit updates`");
+        }
+        if parsed.flow_windows.is_some() && parsed.emit != Emit::Updates {
+            bail!("`--flow-windows` requires `--emit updates`");
+        }
+        match &mut parsed.filter.sample {
+            Some(sample) => sample.seed = seed.unwrap_or(0),
+            None if seed.is_some() => bail!("`--seed` requires `--sample`"),
+            None => {}
+        }
+        match &mut parsed.velocity {
+            Some(velocity) => velocity.reject = velocity_reject,
+            None if velocity_reject => bail!("`--velocity-reject` requires `--velocity-limit`"),
+            None => {}
+        }
+        parsed.columns = SummaryColumns {
+            flags: parsed.velocity.is_some() || parsed.anomaly.is_some(),
+            overdraft: !parsed.overdrafts.is_empty(),
+            minor_units: parsed
+                .currency
+                .as_ref()
+                .filter(|_| parsed.amount_unit == AmountUnit::Minor)
+                .map(|currency| MinorUnits::new(currency.exponent())),
+            ..parsed.columns
+        };
+        if parsed.sync && parsed != parsed.sync_options() {
+            bail!(
+                "`--sync` only supports a single input and the options {}\n{usage}",
+                SYNC_OPTIONS.join(", ")
+            );
+        }
+
+        Ok(parsed)
+    }
+
+    /// The options of `self` which `--sync` supports, every other one left as by default.
+    /// Their flags are listed in `SYNC_OPTIONS`.
+    fn sync_options(&self) -> Self {
+        Self {
+            file_path: self.file_path.clone(),
+            csv: self.csv,
+            filter: self.filter.clone(),
+            skip: self.skip,
+            limit: self.limit,
+            velocity: self.velocity,
+            withdrawal_limits: self.withdrawal_limits.clone(),
+            anomaly: self.anomaly,
+            columns: self.columns,
+            currency: self.currency.clone(),
+            amount_unit: self.amount_unit,
+            overdrafts: self.overdrafts.clone(),
+            opening_balances: self.opening_balances.clone(),
+            locked_policy: self.locked_policy,
+            dispute_policy: self.dispute_policy,
+            max_amount: self.max_amount,
+            disputable: self.disputable,
+            pseudonym_key: self.pseudonym_key.clone(),
+            pseudonyms: self.pseudonyms.clone(),
+            sorted: self.sorted,
+            sync: true,
+            ..Self::default()
+        }
+    }
+
+    /// Applies a flag and its value, returning false if the flag is unknown
+    fn parse_flag(&mut self, flag: &str, args: &mut impl Iterator<Item = String>) -> Result<bool> {
+        match flag {
+            "--no-header" => self.csv.has_header = false,
+            "--delimiter" => self.csv.delimiter = parse_byte(flag, args.next())?,
+            "--quote" => self.csv.quote = parse_byte(flag, args.next())?,
+            "--no-quoting" => self.csv.quoting = false,
+            "--lenient" => self.csv.lenient = true,
+            "--readers" => {
+                self.readers = parse_value(flag, args.next(), "a positive integer")?;
+                if self.readers == 0 {
+                    bail!("`--readers` expects a positive integer");
+                }
+            }
+            "--fast-parse" => self.fast_parse = true,
+            "--retries" => self.retries = parse_value(flag, args.next(), "a non-negative integer")?,
+            "--dead-letter" => self.dead_letter = Some(parse_value(flag, args.next(), "a path")?),
+            "--warnings-out" => self.warnings_out = Some(parse_value(flag, args.next(), "a path")?),
+            "--io-retries" => {
+                self.io_retry.retries = parse_value(flag, args.next(), "a non-negative integer")?;
+            }
+            "--io-backoff" => {
+                let millis = parse_value(flag, args.next(), "a number of milliseconds")?;
+                self.io_retry.backoff = Duration::from_millis(millis);
+            }
+            "--only-clients" => {
+                let ids = parse_client_ids(flag, args.next())?;
+                self.filter
+                    .only_clients
+                    .get_or_insert_with(HashSet::new)
+                    .extend(ids);
+            }
+            "--exclude-clients" => self
+                .filter
+                .exclude_clients
+                .extend(parse_client_ids(flag, args.next())?),
+            "--sample" => self.filter.sample = Some(parse_sample(args.next())?),
+            "--skip" => self.skip = parse_value(flag, args.next(), "a number of records")?,
+            "--limit" => self.limit = Some(parse_value(flag, args.next(), "a number of records")?),
+            "--only-types" => {
+                let types: String = parse_value(flag, args.next(), "transaction types")?;
+                let only = self.filter.only_types.get_or_insert_with(Vec::new);
+                for tx_type in types.split(',') {
+                    let tx_type = tx_type
+                        .parse()
+                        .map_err(|e| anyhow::anyhow!("`{flag}` expects transaction types: {e}"))?;
+                    if !only.contains(&tx_type) {
+                        only.push(tx_type);
+                    }
+                }
+            }
+            "--reorder-window" => {
+                self.reorder_window = parse_value(flag, args.next(), "a non-negative integer")?;
+            }
+            "--audit-digest" => self.audit_digest = true,
+            "--dispute-webhook" => {
+                self.dispute_webhook = Some(parse_value(flag, args.next(), "an http:// URL")?);
+            }
+            "--webhook-outbox" => {
+                self.webhook_outbox = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--velocity-limit" => self.velocity = Some(parse_velocity(args.next())?),
+            "--withdrawal-limits" => {
+                self.withdrawal_limits = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--amount-anomaly" => self.anomaly = Some(parse_anomaly(args.next())?),
+            "--chargeback-limit" => {
+                let limit: Decimal = parse_value(flag, args.next(), "a non-negative ratio")?;
+                if limit.is_sign_negative() {
+                    bail!("`--chargeback-limit` expects a non-negative ratio");
+                }
+                self.chargeback_limit = Some(limit);
+            }
+            "--max-amount" => {
+                let max: Decimal = parse_value(flag, args.next(), "a positive amount")?;
+                if max <= Decimal::ZERO {
+                    bail!("`--max-amount` expects a positive amount");
+                }
+                self.max_amount = Some(max);
+            }
+            "--overdraft" => {
+                let (client, limit) = parse_overdraft(args.next())?;
+                self.overdrafts.insert(client, limit);
+            }
+            "--disputable-types" => {
+                self.disputable = parse_value(flag, args.next(), "transaction types")?;
+            }
+            "--locked-account-policy" => {
+                self.locked_policy =
+                    parse_value(flag, args.next(), "`reject-all` or `allow-deposits`")?;
+            }
+            "--dispute-funds-policy" => {
+                self.dispute_policy = parse_value(
+                    flag,
+                    args.next(),
+                    "`hold-full-allow-negative`, `hold-partial` or `reject`",
+                )?;
+            }
+            "--emit" => self.emit = parse_value(flag, args.next(), "`snapshot` or `updates`")?,
+            "--latency-budget" => {
+                let millis = parse_value(flag, args.next(), "a number of milliseconds")?;
+                self.latency_budget = Some(Duration::from_millis(millis));
+            }
+            "--flow-windows" => self.flow_windows = Some(parse_value(flag, args.next(), "a path")?),
+            "--flow-window" => {
+                let secs = parse_value(flag, args.next(), "a positive number of seconds")?;
+                if secs == 0 {
+                    bail!("`--flow-window` expects a positive number of seconds");
+                }
+                self.flow_window = Duration::from_secs(secs);
+            }
+            "--output-columns" => {
+                let columns: OutputColumns =
+                    parse_value(flag, args.next(), "`standard` or `extended`")?;
+                self.columns.extended = columns == OutputColumns::Extended;
+            }
+            "--verify-invariants" => self.verify_invariants = true,
+            "--verify-determinism" => self.verify_determinism = true,
+            "--worker-stats" => self.worker_stats = true,
+            "--workers" => {
+                let workers = parse_value(flag, args.next(), "a positive integer")?;
+                if workers == 0 {
+                    bail!("`--workers` expects a positive integer");
+                }
+                self.workers = Some(workers);
+            }
+            "--partitioner" => {
+                self.partitioner =
+                    parse_value(flag, args.next(), "`sticky`, `modulo` or `rendezvous`")?;
+            }
+            "--health-addr" => {
+                self.health_addr = Some(parse_value(flag, args.next(), "a `<host>:<port>`")?);
+            }
+            "--ready-backlog" => {
+                self.ready_backlog = parse_value(flag, args.next(), "a non-negative integer")?;
+            }
+            "--top-balances" => {
+                self.top_balances = Some(parse_value(flag, args.next(), "a number of accounts")?);
+            }
+            "--top-dispute-clients" => {
+                let clients = parse_value(flag, args.next(), "a number of clients")?;
+                self.top_dispute_clients = Some(clients);
+            }
+            "--negative-balances" => self.negative_balances = true,
+            "--open-disputes-out" => {
+                self.open_disputes_out = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--holds-out" => self.holds_out = Some(parse_value(flag, args.next(), "a path")?),
+            "--locked-out" => self.locked_out = Some(parse_value(flag, args.next(), "a path")?),
+            "--high-risk-out" => {
+                self.high_risk_out = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--camt-out" => self.camt_out = Some(parse_value(flag, args.next(), "a path")?),
+            "--avro-out" => {
+                if !cfg!(feature = "avro") {
+                    bail!("`--avro-out` needs a build with the `avro` feature");
+                }
+                self.avro_out = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--export" => self.export = Some(parse_value(flag, args.next(), "`ofx` or `qif`")?),
+            "--currency" => {
+                self.currency = Some(parse_value(flag, args.next(), "an ISO 4217 code")?);
+            }
+            "--amount-unit" => {
+                self.amount_unit = parse_value(flag, args.next(), "`major` or `minor`")?;
+            }
+            "--pseudonym-key" => {
+                self.pseudonym_key = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--opening-balances" => {
+                self.opening_balances = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--snapshot-out" => {
+                self.snapshot_out = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--sign-key" => {
+                if !cfg!(feature = "signing") {
+                    bail!("`--sign-key` needs a build with the `signing` feature");
+                }
+                self.sign_key = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--signature-out" => {
+                self.signature_out = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--sorted" => self.sorted = true,
+            "--sync" => self.sync = true,
+            "--output-partitions" => {
+                let partitions = parse_value(flag, args.next(), "between 1 and 65536 files")?;
+                if !(1..=1 << 16).contains(&partitions) {
+                    bail!("`--output-partitions` expects between 1 and 65536 files");
+                }
+                self.output_partitions = Some(partitions);
+            }
+            "--manifest" => self.manifest = Some(parse_value(flag, args.next(), "a path")?),
+            "--processed-log" => {
+                self.processed_log = Some(parse_value(flag, args.next(), "a path")?);
+            }
+            "--skip-processed" => self.skip_processed = true,
+            "--max-memory" => self.max_memory = Some(parse_size(flag, args.next())?),
+            "--timeout" => self.timeout = Some(parse_duration(flag, args.next())?),
+            "--tenant" => self
+                .tenants
+                .push(parse_value(flag, args.next(), "`<name>=<path>`")?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+fn parse_velocity(limit: Option<String>) -> Result<VelocityPolicy> {
+    let limit = limit.context("`--velocity-limit` expects `<N>/<M>`")?;
+    let expects = || format!("`--velocity-limit` expects `<N>/<M>` with M > 0, found `{limit}`");
+    let (max_withdrawals, window) = limit.split_once('/').with_context(expects)?;
+    let (max_withdrawals, window) = (
+        max_withdrawals.parse().with_context(expects)?,
+        window.parse().with_context(expects)?,
+    );
+    if window == 0 {
+        bail!(expects());
+    }
+
+    Ok(VelocityPolicy {
+        max_withdrawals,
+        window,
+        reject: false,
+    })
+}
+
+fn parse_anomaly(limit: Option<String>) -> Result<AnomalyPolicy> {
+    let limit = limit.context("`--amount-anomaly` expects `<Z>/<N>`")?;
+    let expects =
+        || format!("`--amount-anomaly` expects `<Z>/<N>` with Z and N > 0, found `{limit}`");
+    let (max_zscore, window) = limit.split_once('/').with_context(expects)?;
+    let (max_zscore, window): (Decimal, usize) = (
+        max_zscore.parse().with_context(expects)?,
+        window.parse().with_context(expects)?,
+    );
+    if max_zscore <= Decimal::ZERO || window == 0 {
+        bail!(expects());
+    }
+
+    Ok(AnomalyPolicy { max_zscore, window })
+}
+
+fn parse_overdraft(overdraft: Option<String>) -> Result<(u16, Decimal)> {
+    let overdraft = overdraft.context("`--overdraft` expects `<client>=<limit>`")?;
+    let expects = || {
+        format!("`--overdraft` expects `<client>=<limit>` with a non-negative limit, found `{overdraft}`")
+    };
+    let (client, limit) = overdraft.split_once('=').with_context(expects)?;
+    let (client, limit): (u16, Decimal) = (
+        client.parse().with_context(expects)?,
+        limit.parse().with_context(expects)?,
+    );
+    if limit.is_sign_negative() {
+        bail!(expects());
+    }
+
+    Ok((client, limit))
+}
+
+/// A fraction of the clients between a millionth and 1, the seed is set once parsed
+fn parse_sample(fraction: Option<String>) -> Result<ClientSample> {
+    let fraction = fraction.context("`--sample` expects a fraction")?;
+    let expects =
+        || format!("`--sample` expects a fraction between 0.000001 and 1, found `{fraction}`");
+    let per_million = fraction
+        .parse::<Decimal>()
+        .ok()
+        .and_then(|fraction| (fraction * Decimal::from(1_000_000)).round().to_u32())
+        .filter(|per_million| (1..=1_000_000).contains(per_million))
+        .with_context(expects)?;
+
+    Ok(ClientSample {
+        per_million,
+        seed: 0,
+    })
+}
+
+/// Comma-separated client ids, or else the path of a file of ids separated by commas or
+/// whitespace
+fn parse_client_ids(flag: &str, value: Option<String>) -> Result<Vec<u16>> {
+    let value = value.with_context(|| format!("`{flag}` expects client ids or a path"))?;
+    if let Ok(ids) = value.split(',').map(str::parse).collect::<Result<_, _>>() {
+        return Ok(ids);
+    }
+    let ids = std::fs::read_to_string(&value)
+        .with_context(|| format!("`{flag}` expects client ids or a path, found `{value}`"))?;
+    ids.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse()
+                .with_context(|| format!("`{flag}` found `{id}` in {value}, expected a client id"))
+        })
+        .collect()
+}
+
+fn parse_value<T>(flag: &str, value: Option<String>, expects: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = value.with_context(|| format!("`{flag}` expects {expects}"))?;
+    value
+        .parse()
+        .map_err(|e| anyhow::anyhow!("`{flag}` expects {expects}: {e}"))
+}
+
+/// A number of bytes, optionally with a `K`, `M` or `G` binary suffix
+fn parse_size(flag: &str, value: Option<String>) -> Result<usize> {
+    let value = value.with_context(|| format!("`{flag}` expects a size"))?;
+    let (digits, shift) = match value.as_bytes().last() {
+        Some(b'K' | b'k') => (&value[..value.len() - 1], 10),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], 20),
+        Some(b'G' | b'g') => (&value[..value.len() - 1], 30),
+        _ => (value.as_str(), 0),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|size| size.checked_mul(1 << shift))
+        .with_context(|| format!("`{flag}` expects a size such as `512M`, found `{value}`"))
+}
+
+/// A positive number of seconds, or of minutes or hours with an `m` or `h` suffix
+fn parse_duration(flag: &str, value: Option<String>) -> Result<Duration> {
+    let value = value.with_context(|| format!("`{flag}` expects a duration"))?;
+    let (digits, unit) = match value.as_bytes().last() {
+        Some(b's') => (&value[..value.len() - 1], 1),
+        Some(b'm') => (&value[..value.len() - 1], 60),
+        Some(b'h') => (&value[..value.len() - 1], 60 * 60),
+        _ => (value.as_str(), 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .filter(|count| *count > 0)
+        .and_then(|count| count.checked_mul(unit))
+        .map(Duration::from_secs)
+        .with_context(|| format!("`{flag}` expects a duration such as `30m`, found `{value}`"))
+}
+
+/// A single ASCII character, or `tab`/`\t` for a tab
+fn parse_byte(flag: &str, value: Option<String>) -> Result<u8> {
+    let value = value.with_context(|| format!("`{flag}` expects a character"))?;
+    match value.as_str() {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => bail!("`{flag}` expects a single ASCII character, found `{value}`"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use anyhow::Context;
+    use futures::StreamExt;
+    use rust_decimal::Decimal;
+
+    use crate::{
+        account::{DisputeFundsPolicy, LockedAccountPolicy},
+        bisect::{BisectOptions, Expected},
+        cli::{Args, Emit, ExitStatus, Tenant, VerifyOptions},
+        data::{DisputableTypes, TransactionType},
+        determinism::Nondeterminism,
+        export::ExportFormat,
+        invariants::InvariantViolation,
+        io_ops::UnexpectedHeader,
+        ledger::WorkerPanic,
+        limits::LimitConfig,
+        partitioner::PartitionerKind,
+        pipeline::WorkersPanicked,
+        pseudonym::Pseudonymizer,
+        risk::{AnomalyPolicy, VelocityPolicy},
+        router::{ClientSample, RecordFilter},
+        statement::{AmountFormat, StatementFormat, StatementOptions},
+        units::{AmountUnit, MinorUnits},
+    };
+
+    fn parse(args: &[&str]) -> anyhow::Result<Args> {
+        Args::parse(args.iter().map(ToString::to_string))
+    }
+
+    #[test]
+    fn defaults_to_a_single_reader() {
+        let args = parse(&["bin", "tx.csv"]).unwrap();
+        assert_eq!(args.file_path.as_deref(), Some("tx.csv"));
+        assert!(args.tenants.is_empty());
+        assert_eq!(args.simulate, None);
+        assert_eq!(args.statement, None);
+        assert_eq!(args.bisect, None);
+        assert_eq!(args.explain, None);
+        assert_eq!(args.tui, None);
+        assert!(args.reveal.is_empty());
+        assert_eq!(args.readers, 1);
+        assert!(args.csv.has_header);
+        assert!(!args.csv.lenient);
+        assert_eq!(args.retries, 0);
+        assert_eq!(args.dead_letter, None);
+        assert_eq!(args.warnings_out, None);
+        assert_eq!(args.reorder_window, 0);
+        assert_eq!(args.filter, RecordFilter::default());
+        assert_eq!((args.skip, args.limit), (0, None));
+        assert!(!args.audit_digest);
+        assert_eq!(args.dispute_webhook, None);
+        assert_eq!(args.webhook_outbox, None);
+        assert_eq!(args.velocity, None);
+        assert_eq!(args.withdrawal_limits, None);
+        assert_eq!(args.anomaly, None);
+        assert!(!args.columns.flags);
+        assert_eq!(args.chargeback_limit, None);
+        assert_eq!(args.high_risk_out, None);
+        assert!(args.overdrafts.is_empty());
+        assert_eq!(args.disputable, DisputableTypes::default());
+        assert_eq!(
+            args.dispute_policy,
+            DisputeFundsPolicy::HoldFullAllowNegative
+        );
+        assert_eq!(args.max_amount, None);
+        assert!(!args.columns.overdraft);
+        assert_eq!(args.emit, Emit::Snapshot);
+        assert_eq!(args.latency_budget, None);
+        assert_eq!(args.flow_windows, None);
+        assert_eq!(args.flow_window, Duration::from_secs(60));
+        assert!(!args.verify_invariants);
+        assert!(!args.verify_determinism);
+        assert!(!args.worker_stats);
+        assert_eq!(args.workers, None);
+        assert_eq!(args.partitioner, PartitionerKind::Sticky);
+        assert_eq!(args.health_addr, None);
+        assert_eq!(args.ready_backlog, 100_000);
+        assert_eq!(args.top_balances, None);
+        assert_eq!(args.top_dispute_clients, None);
+        assert!(!args.negative_balances);
+        assert_eq!(args.open_disputes_out, None);
+        assert_eq!(args.holds_out, None);
+        assert_eq!(args.locked_out, None);
+        assert_eq!(args.camt_out, None);
+        assert_eq!(args.avro_out, None);
+        assert_eq!(args.sign_key, None);
+        assert_eq!(args.signature_out, None);
+        assert_eq!(args.verify, None);
+        assert_eq!(args.export, None);
+        assert_eq!(args.currency, None);
+        assert_eq!(args.amount_unit, AmountUnit::Major);
+        assert_eq!(args.columns.minor_units, None);
+        assert_eq!(args.pseudonyms, None);
+        assert_eq!(args.opening_balances, None);
+        assert_eq!(args.snapshot_out, None);
+        assert_eq!(args.snapshot_key, None);
+        assert_eq!(args.max_memory, None);
+        assert_eq!(args.timeout, None);
+        assert_eq!(args.manifest, None);
+        assert_eq!(args.output_partitions, None);
+        assert_eq!(args.processed_log, None);
+        assert!(!args.skip_processed);
+        assert!(!args.fast_parse);
+        assert!(!args.sorted);
+        assert!(!args.sync);
+    }
+
+    #[test]
+    fn parses_client_filters_from_ids_or_files() {
+        let path = std::env::temp_dir().join("effective-train-clients.txt");
+        std::fs::write(&path, "3\n4, 5\n\n").unwrap();
+        let file = path.to_string_lossy();
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--only-clients",
+            "1,2",
+            "--only-clients",
+            &file,
+            "--exclude-clients",
+            "4",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.filter,
+            RecordFilter {
+                only_clients: Some([1, 2, 3, 4, 5].into()),
+                exclude_clients: [4].into(),
+                ..RecordFilter::default()
+            }
+        );
+
+        std::fs::write(&path, "3\nx\n").unwrap();
+        let e = parse(&["bin", "tx.csv", "--exclude-clients", &file]).unwrap_err();
+        assert!(e.to_string().contains("found `x`"));
+        assert!(parse(&["bin", "tx.csv", "--only-clients", "/nonexistent/ids"]).is_err());
+    }
+
+    #[test]
+    fn parses_a_slice() {
+        let args = parse(&["bin", "tx.csv", "--skip", "1000", "--limit", "500"]).unwrap();
+        assert_eq!((args.skip, args.limit), (1000, Some(500)));
+        assert!(parse(&["bin", "tx.csv", "--limit", "-5"]).is_err());
+    }
+
+    #[test]
+    fn parses_a_seeded_sample() {
+        let args = parse(&["bin", "tx.csv", "--seed", "42", "--sample", "0.01"]).unwrap();
+        assert_eq!(
+            args.filter.sample,
+            Some(ClientSample {
+                per_million: 10_000,
+                seed: 42,
+            })
+        );
+        let args = parse(&["bin", "tx.csv", "--sample", "1"]).unwrap();
+        assert_eq!(args.filter.sample.unwrap().seed, 0);
+        for fraction in ["0", "1.5", "-0.1", "0.0000001", "half"] {
+            assert!(parse(&["bin", "tx.csv", "--sample", fraction]).is_err());
+        }
+        assert_eq!(
+            parse(&["bin", "tx.csv", "--seed", "42"])
+                .unwrap_err()
+                .to_string(),
+            "`--seed` requires `--sample`"
+        );
+    }
+
+    #[test]
+    fn parses_type_filter() {
+        let args = parse(&["bin", "tx.csv", "--only-types", "deposit,withdrawal"]).unwrap();
+        assert_eq!(
+            args.filter.only_types,
+            Some(vec![TransactionType::Deposit, TransactionType::Withdrawal])
+        );
+        assert_eq!(
+            parse(&["bin", "tx.csv", "--only-types", "deposit,refund"])
+                .unwrap_err()
+                .to_string(),
+            "`--only-types` expects transaction types: unknown transaction type `refund`"
+        );
+    }
+
+    #[test]
+    fn parses_partitioner() {
+        let args = parse(&["bin", "tx.csv", "--partitioner", "rendezvous"]).unwrap();
+        assert_eq!(args.partitioner, PartitionerKind::Rendezvous);
+        let result = parse(&["bin", "tx.csv", "--partitioner", "random"]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "`--partitioner` expects `sticky`, `modulo` or `rendezvous`: unknown partitioner \
+             `random`"
+        );
+    }
+
+    #[test]
+    fn parses_determinism_check() {
+        let args = parse(&["bin", "tx.csv", "--verify-determinism", "--workers", "8"]).unwrap();
+        assert!(args.verify_determinism);
+        assert_eq!(args.workers, Some(8));
+        assert!(parse(&["bin", "tx.csv", "--workers", "0"]).is_err());
+        assert_eq!(
+            parse(&["bin", "--tenant", "a=a.csv", "--verify-determinism"])
+                .unwrap_err()
+                .to_string()
+                .lines()
+                .next(),
+            Some("`--verify-determinism` expects a single input and no `--tenant`")
+        );
+    }
+
+    #[test]
+    fn parses_reader_count() {
+        let args = parse(&["bin", "--readers", "4", "tx.csv"]).unwrap();
+        assert_eq!(args.readers, 4);
+        assert!(parse(&["bin", "tx.csv", "--readers", "0"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--readers"]).is_err());
+    }
+
+    #[test]
+    fn parses_no_header_flag() {
+        let args = parse(&["bin", "tx.csv", "--no-header"]).unwrap();
+        assert!(!args.csv.has_header);
+    }
+
+    #[test]
+    fn failures_map_to_exit_statuses() {
+        let missing = std::fs::File::open("/nonexistent/tx.csv")
+            .context("Cannot open input")
+            .unwrap_err();
+        assert_eq!(ExitStatus::of(&missing), ExitStatus::Io);
+        let header = anyhow::Error::new(UnexpectedHeader {
+            found: "a,b".to_owned(),
+        });
+        assert_eq!(ExitStatus::of(&header), ExitStatus::InvalidInput);
+        assert_eq!(
+            ExitStatus::of(&anyhow::anyhow!("Worker 0 stopped")),
+            ExitStatus::Failure
+        );
+        let violation = anyhow::Error::new(InvariantViolation {
+            violations: vec!["client 1: locked without a chargeback".to_owned()],
+        });
+        assert_eq!(
+            ExitStatus::of(&violation.context("Tenant a")),
+            ExitStatus::InvariantViolated
+        );
+        let panicked = anyhow::Error::new(WorkersPanicked {
+            panics: vec![WorkerPanic {
+                file_path: "tx.csv".to_owned(),
+                worker: 3,
+                clients: 12,
+                message: "boom".to_owned(),
+            }],
+        });
+        assert_eq!(
+            panicked.to_string(),
+            "1 workers panicked, the output only holds the accounts of the others\n    worker 3 \
+             of tx.csv panicked with `boom`, its 12 clients are missing"
+        );
+        assert_eq!(ExitStatus::of(&panicked), ExitStatus::WorkerPanicked);
+        let differ = anyhow::Error::new(Nondeterminism {
+            runs: [
+                "4 workers, sticky".to_owned(),
+                "1 workers, modulo".to_owned(),
+            ],
+            differences: vec!["client 1: only in the first run".to_owned()],
+        });
+        assert_eq!(ExitStatus::of(&differ), ExitStatus::NonDeterministic);
+    }
+
+    #[tokio::test]
+    async fn unparseable_records_are_invalid_input() {
+        let mut reader = csv_async::AsyncReaderBuilder::new()
+            .has_headers(false)
+            .create_reader("deposit,1,x,1.0\n".as_bytes());
+        let record = reader.records().next().await.unwrap().unwrap();
+        let e = anyhow::Error::new(
+            record
+                .deserialize::<crate::data::Transaction>(None)
+                .unwrap_err(),
+        );
+        assert_eq!(ExitStatus::of(&e), ExitStatus::InvalidInput);
+    }
+
+    #[test]
+    fn parses_sorted_flag() {
+        let args = parse(&["bin", "tx.csv", "--sorted"]).unwrap();
+        assert!(args.sorted);
+    }
+
+    #[test]
+    fn parses_sync_flag() {
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--sync",
+            "--sorted",
+            "--only-types",
+            "deposit",
+            "--overdraft",
+            "1=10",
+        ])
+        .unwrap();
+        assert!(args.sync && args.sorted);
+        assert!(parse(&["bin", "tx.csv", "--sync", "--readers", "4"]).is_err());
+        assert!(parse(&["bin", "--sync", "--tenant", "acme=tx.csv"]).is_err());
+        assert!(parse(&["bin", "statement", "tx.csv", "--client", "1", "--sync"]).is_err());
+        let e = parse(&["bin", "tx.csv", "--sync", "--chargeback-limit", "0.1"]).unwrap_err();
+        let message = e.to_string();
+        let message = message.lines().next().unwrap();
+        assert!(message.contains("--velocity-limit") && message.contains("--max-amount"));
+        assert!(!message.contains("--chargeback-limit"));
+    }
+
+    #[test]
+    fn parses_delimiter_and_quoting() {
+        let args = parse(&["bin", "tx.csv", "--delimiter", ";", "--quote", "'"]).unwrap();
+        assert_eq!(args.csv.delimiter, b';');
+        assert_eq!(args.csv.quote, b'\'');
+        assert!(args.csv.quoting);
+
+        let args = parse(&["bin", "tx.csv", "--delimiter", "tab", "--no-quoting"]).unwrap();
+        assert_eq!(args.csv.delimiter, b'\t');
+        assert!(!args.csv.quoting);
+
+        let args = parse(&["bin", "tx.csv", "--lenient", "--sync"]).unwrap();
+        assert!(args.csv.lenient);
+
+        let result = parse(&["bin", "tx.csv", "--delimiter", "::"]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "`--delimiter` expects a single ASCII character, found `::`"
+        );
+    }
+
+    #[test]
+    fn parses_retry_options() {
+        let args = parse(&["bin", "tx.csv", "--retries", "3", "--dead-letter", "dl.csv"]).unwrap();
+        assert_eq!(args.retries, 3);
+        assert_eq!(args.dead_letter.as_deref(), Some("dl.csv"));
+        assert!(parse(&["bin", "tx.csv", "--retries", "-1"]).is_err());
+    }
+
+    #[test]
+    fn parses_warnings_file() {
+        let args = parse(&["bin", "tx.csv", "--warnings-out", "warnings.csv"]).unwrap();
+        assert_eq!(args.warnings_out.as_deref(), Some("warnings.csv"));
+        assert!(parse(&["bin", "tx.csv", "--warnings-out"]).is_err());
+    }
+
+    #[test]
+    fn parses_io_retry_options() {
+        let args = parse(&["bin", "tx.csv"]).unwrap();
+        assert_eq!(args.io_retry.retries, 0);
+        assert_eq!(args.io_retry.backoff, Duration::from_millis(100));
+        let args = parse(&["bin", "tx.csv", "--io-retries", "5", "--io-backoff", "20"]).unwrap();
+        assert_eq!(args.io_retry.retries, 5);
+        assert_eq!(args.io_retry.backoff, Duration::from_millis(20));
+        assert!(parse(&["bin", "tx.csv", "--io-backoff", "soon"]).is_err());
+    }
+
+    #[test]
+    fn parses_output_partitions() {
+        let args = parse(&["bin", "tx.csv", "--output-partitions", "4"]).unwrap();
+        assert_eq!(args.output_partitions, Some(4));
+        assert!(parse(&["bin", "tx.csv", "--output-partitions", "0"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--output-partitions", "65537"]).is_err());
+        assert_eq!(
+            parse(&[
+                "bin",
+                "tx.csv",
+                "--output-partitions",
+                "2",
+                "--emit",
+                "updates"
+            ])
+            .unwrap_err()
+            .to_string(),
+            "`--output-partitions` cannot be combined with `--emit updates`"
+        );
+    }
+
+    #[test]
+    fn webhook_outbox_requires_a_webhook() {
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--dispute-webhook",
+            "http://risk.local/disputes",
+            "--webhook-outbox",
+            "outbox.jsonl",
+        ])
+        .unwrap();
+        assert_eq!(args.webhook_outbox.as_deref(), Some("outbox.jsonl"));
+        assert_eq!(
+            parse(&["bin", "tx.csv", "--webhook-outbox", "outbox.jsonl"])
+                .unwrap_err()
+                .to_string(),
+            "`--webhook-outbox` requires `--dispute-webhook`"
+        );
+    }
+
+    #[test]
+    fn parses_health_flags() {
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--health-addr",
+            "0.0.0.0:8080",
+            "--ready-backlog",
+            "500",
+        ])
+        .unwrap();
+        assert_eq!(args.health_addr.as_deref(), Some("0.0.0.0:8080"));
+        assert_eq!(args.ready_backlog, 500);
+        assert!(parse(&["bin", "tx.csv", "--ready-backlog", "-1"]).is_err());
+    }
+
+    #[test]
+    fn parses_top_n_flags() {
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--top-balances",
+            "10",
+            "--top-dispute-clients",
+            "3",
+            "--negative-balances",
+        ])
+        .unwrap();
+        assert_eq!(args.top_balances, Some(10));
+        assert_eq!(args.top_dispute_clients, Some(3));
+        assert!(args.negative_balances);
+        assert!(parse(&["bin", "tx.csv", "--top-balances", "-1"]).is_err());
+    }
+
+    #[test]
+    fn parses_open_disputes_path() {
+        let args = parse(&["bin", "tx.csv", "--open-disputes-out", "open.csv"]).unwrap();
+        assert_eq!(args.open_disputes_out.as_deref(), Some("open.csv"));
+        assert!(parse(&["bin", "tx.csv", "--open-disputes-out"]).is_err());
+    }
+
+    #[test]
+    fn parses_holds_path() {
+        let args = parse(&["bin", "tx.csv", "--holds-out", "holds.csv"]).unwrap();
+        assert_eq!(args.holds_out.as_deref(), Some("holds.csv"));
+        assert!(parse(&["bin", "tx.csv", "--holds-out"]).is_err());
+    }
+
+    #[test]
+    fn parses_locked_accounts_path() {
+        let args = parse(&["bin", "tx.csv", "--locked-out", "locked.csv"]).unwrap();
+        assert_eq!(args.locked_out.as_deref(), Some("locked.csv"));
+        assert!(parse(&["bin", "tx.csv", "--locked-out"]).is_err());
+    }
+
+    #[test]
+    fn parses_camt_output() {
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--camt-out",
+            "camt.xml",
+            "--currency",
+            "EUR",
+        ])
+        .unwrap();
+        assert_eq!(args.camt_out.as_deref(), Some("camt.xml"));
+        assert_eq!(args.currency, Some("EUR".parse().unwrap()));
+        assert!(parse(&["bin", "tx.csv", "--camt-out", "camt.xml"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--currency", "euro"]).is_err());
+    }
+
+    #[test]
+    fn parses_avro_output() {
+        let parsed = parse(&["bin", "tx.csv", "--avro-out", "accounts.avro"]);
+        if cfg!(feature = "avro") {
+            assert_eq!(parsed.unwrap().avro_out.as_deref(), Some("accounts.avro"));
+            assert!(parse(&["bin", "tx.csv", "--avro-out"]).is_err());
+            let pseudonyms = [
+                "bin",
+                "tx.csv",
+                "--pseudonym-key",
+                "key",
+                "--avro-out",
+                "accounts.avro",
+            ];
+            assert!(parse(&pseudonyms).is_err());
+        } else {
+            let e = parsed.unwrap_err();
+            assert!(e.to_string().contains("`avro` feature"));
+        }
+    }
+
+    #[test]
+    fn parses_snapshot_path() {
+        let args = parse(&["bin", "tx.csv", "--snapshot-out", "accounts.msgpack"]).unwrap();
+        assert_eq!(args.snapshot_out.as_deref(), Some("accounts.msgpack"));
+        assert!(parse(&["bin", "tx.csv", "--snapshot-out"]).is_err());
+    }
+
+    #[test]
+    fn parses_opening_balances() {
+        let args = parse(&["bin", "tx.csv", "--opening-balances", "accounts.csv"]).unwrap();
+        assert_eq!(args.opening_balances.as_deref(), Some("accounts.csv"));
+        assert!(parse(&["bin", "tx.csv", "--opening-balances"]).is_err());
+        assert!(parse(&[
+            "bin",
+            "--tenant",
+            "eu=eu.csv",
+            "--opening-balances",
+            "a.csv"
+        ])
+        .is_err());
+        let e = parse(&[
+            "bin",
+            "tx.csv",
+            "--opening-balances",
+            "accounts.csv",
+            "--verify-invariants",
+        ])
+        .unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("`--verify-invariants` cannot check"));
+    }
+
+    #[test]
+    fn parses_export_format() {
+        let args = parse(&["bin", "tx.csv", "--export", "qif"]).unwrap();
+        assert_eq!(args.export, Some(ExportFormat::Qif));
+        let args = parse(&["bin", "tx.csv", "--export", "ofx", "--currency", "USD"]).unwrap();
+        assert_eq!(args.export, Some(ExportFormat::Ofx));
+        assert!(parse(&["bin", "tx.csv", "--export", "ofx"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--export", "csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_pseudonym_key_and_reveal() {
+        let path = std::env::temp_dir().join("effective-train-pseudonym.key");
+        std::fs::write(&path, "0123456789abcdef\n").unwrap();
+        let key = path.to_string_lossy();
+
+        let mut args = parse(&["bin", "tx.csv", "--pseudonym-key", &key]).unwrap();
+        assert_eq!(args.pseudonyms, None);
+        args.load_pseudonym_key().unwrap();
+        assert_eq!(
+            args.pseudonyms,
+            Some(Pseudonymizer::new(b"0123456789abcdef").unwrap())
+        );
+        let args = parse(&["bin", "reveal", "p1", "p2", "--pseudonym-key", &key]).unwrap();
+        assert_eq!(args.reveal, vec!["p1", "p2"]);
+        assert_eq!(args.file_path, None);
+
+        assert!(parse(&["bin", "reveal", "p1"]).is_err());
+        assert!(parse(&["bin", "reveal", "--pseudonym-key", &key]).is_err());
+        let mut args = parse(&["bin", "tx.csv", "--pseudonym-key", "/nonexistent/key"]).unwrap();
+        let e = args.load_pseudonym_key().unwrap_err();
+        assert_eq!(ExitStatus::of(&e), ExitStatus::Io);
+        assert!(parse(&[
+            "bin",
+            "tx.csv",
+            "--pseudonym-key",
+            &key,
+            "--top-balances",
+            "3"
+        ])
+        .is_err());
+        assert!(parse(&["bin", "tx.csv", "--pseudonym-key", &key, "--export", "qif"]).is_err());
+    }
+
+    #[test]
+    fn parses_sign_key_and_verify() {
+        let signed = [
+            "bin",
+            "tx.csv",
+            "--sign-key",
+            "key.pem",
+            "--signature-out",
+            "accounts.sig",
+        ];
+        let verify = [
+            "bin",
+            "verify",
+            "accounts.csv",
+            "--public-key",
+            "key.pub.pem",
+        ];
+        if !cfg!(feature = "signing") {
+            assert!(parse(&signed)
+                .unwrap_err()
+                .to_string()
+                .contains("`signing` feature"));
+            assert!(parse(&verify)
+                .unwrap_err()
+                .to_string()
+                .contains("`signing` feature"));
+            return;
+        }
+        let args = parse(&signed).unwrap();
+        assert_eq!(args.sign_key.as_deref(), Some("key.pem"));
+        assert_eq!(args.signature_out.as_deref(), Some("accounts.sig"));
+        // The accounts written to stdout need somewhere for their signature
+        assert!(parse(&signed[..4]).is_err());
+        let mut partitions = vec!["bin", "tx.csv", "--sign-key", "key.pem"];
+        partitions.extend(["--output-partitions", "2"]);
+        assert!(parse(&partitions).is_ok());
+        partitions.extend(["--signature-out", "accounts.sig"]);
+        assert!(parse(&partitions).is_err());
+        let mut updates = signed.to_vec();
+        updates.extend(["--emit", "updates"]);
+        assert!(parse(&updates).is_err());
+        assert!(parse(&["bin", "tx.csv", "--signature-out", "accounts.sig"]).is_err());
+
+        let args = parse(&verify).unwrap();
+        assert_eq!(args.file_path.as_deref(), Some("accounts.csv"));
+        assert_eq!(
+            args.verify,
+            Some(VerifyOptions {
+                public_key: "key.pub.pem".to_owned(),
+                signature: "accounts.csv.sig".to_owned(),
+            })
+        );
+        let mut signature = verify.to_vec();
+        signature.extend(["--signature", "accounts.sig"]);
+        let args = parse(&signature).unwrap();
+        assert_eq!(args.verify.unwrap().signature, "accounts.sig");
+        assert!(parse(&verify[..3]).is_err());
+        assert!(parse(&["bin", "verify", "--public-key", "key.pub.pem"]).is_err());
+        // Only `verify` takes its options
+        assert!(parse(&["bin", "accounts.csv", "--public-key", "key.pub.pem"]).is_err());
+    }
+
+    #[test]
+    fn parses_tui_subcommand() {
+        let args = parse(&["bin", "tui", "accounts.csv"]).unwrap();
+        assert_eq!(args.tui.as_deref(), Some("accounts.csv"));
+        assert_eq!(args.file_path, None);
+        let args = parse(&["bin", "tui", "accounts.csv", "tx.csv", "--overdraft", "1=5"]).unwrap();
+        assert_eq!(args.file_path.as_deref(), Some("tx.csv"));
+        assert!(parse(&["bin", "tui"]).is_err());
+        assert!(parse(&["bin", "tui", "accounts.csv", "--tenant", "a=tx.csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_manifest_path() {
+        let args = parse(&["bin", "tx.csv", "--manifest", "run.json"]).unwrap();
+        assert_eq!(args.manifest.as_deref(), Some("run.json"));
+        assert!(parse(&["bin", "tx.csv", "--manifest"]).is_err());
+    }
+
+    #[test]
+    fn parses_repeated_tenants() {
+        let args = parse(&[
+            "bin",
+            "--tenant",
+            "acme=a.csv",
+            "--tenant",
+            "globex=b=c.csv",
+        ])
+        .unwrap();
+        assert_eq!(args.file_path, None);
+        assert_eq!(
+            args.tenants,
+            vec![
+                Tenant {
+                    name: "acme".to_owned(),
+                    file_path: "a.csv".to_owned()
+                },
+                Tenant {
+                    name: "globex".to_owned(),
+                    file_path: "b=c.csv".to_owned()
+                },
+            ]
+        );
+        assert!(parse(&["bin", "--tenant", "a.csv"]).is_err());
+        assert!(parse(&["bin", "--tenant", "../acme=a.csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_withdrawal_limits() {
+        let path = std::env::temp_dir().join("effective-train-limits.csv");
+        std::fs::write(
+            &path,
+            "client,window,max_withdrawals,max_withdrawn\n7,100,5,\n",
+        )
+        .unwrap();
+        let path = path.to_string_lossy();
+        let args = parse(&["bin", "tx.csv", "--withdrawal-limits", &path]).unwrap();
+        let limits = LimitConfig::load(args.withdrawal_limits.as_deref())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            limits.get(7).map(|limit| limit.max_withdrawals),
+            Some(Some(5))
+        );
+
+        // The file is only read once the arguments are parsed, as an input
+        let args = parse(&["bin", "tx.csv", "--withdrawal-limits", "missing.csv"]).unwrap();
+        let e = LimitConfig::load(args.withdrawal_limits.as_deref()).unwrap_err();
+        assert_eq!(ExitStatus::of(&e), ExitStatus::Io);
+    }
+
+    #[test]
+    fn parses_amount_anomaly() {
+        let args = parse(&["bin", "tx.csv", "--amount-anomaly", "3.5/50"]).unwrap();
+        assert_eq!(
+            args.anomaly,
+            Some(AnomalyPolicy {
+                max_zscore: Decimal::new(35, 1),
+                window: 50,
+            })
+        );
+        assert!(args.columns.flags);
+        assert!(parse(&["bin", "tx.csv", "--amount-anomaly", "0/50"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--amount-anomaly", "3"]).is_err());
+    }
+
+    #[test]
+    fn parses_velocity_limit() {
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--velocity-limit",
+            "3/10",
+            "--velocity-reject",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.velocity,
+            Some(VelocityPolicy {
+                max_withdrawals: 3,
+                window: 10,
+                reject: true,
+            })
+        );
+        assert!(args.columns.flags);
+        assert!(parse(&["bin", "tx.csv", "--velocity-limit", "3/0"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--velocity-reject"]).is_err());
+    }
+
+    #[test]
+    fn parses_chargeback_limit() {
+        let args = parse(&["bin", "tx.csv", "--chargeback-limit", "0.05"]).unwrap();
+        assert_eq!(args.chargeback_limit, Some(Decimal::new(5, 2)));
+        assert!(parse(&["bin", "tx.csv", "--chargeback-limit", "-1"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--chargeback-limit", "high"]).is_err());
+
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--chargeback-limit",
+            "0.05",
+            "--high-risk-out",
+            "reports/risk.csv",
+        ])
+        .unwrap();
+        assert_eq!(args.high_risk_out.as_deref(), Some("reports/risk.csv"));
+        assert_eq!(
+            parse(&["bin", "tx.csv", "--high-risk-out", "risk.csv"])
+                .unwrap_err()
+                .to_string(),
+            "`--high-risk-out` requires `--chargeback-limit`"
+        );
+    }
+
+    #[test]
+    fn parses_repeated_overdrafts() {
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--overdraft",
+            "1=100",
+            "--overdraft",
+            "2=2.5",
+        ])
+        .unwrap();
+        assert_eq!(args.overdrafts.get(&1), Some(&Decimal::ONE_HUNDRED));
+        assert_eq!(args.overdrafts.get(&2), Some(&Decimal::new(25, 1)));
+        assert!(args.columns.overdraft);
+        assert!(parse(&["bin", "tx.csv", "--overdraft", "1=-5"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--overdraft", "70000=5"]).is_err());
+    }
+
+    #[test]
+    fn parses_simulate_subcommand() {
+        let args = parse(&[
+            "bin",
+            "simulate",
+            "accounts.csv",
+            "whatif.csv",
+            "--no-header",
+        ])
+        .unwrap();
+        assert_eq!(args.simulate.as_deref(), Some("accounts.csv"));
+        assert_eq!(args.file_path.as_deref(), Some("whatif.csv"));
+        assert!(!args.csv.has_header);
+        assert!(parse(&["bin", "simulate", "accounts.csv"]).is_err());
+        assert!(parse(&["bin", "simulate", "--tenant", "a=a.csv"]).is_err());
+        assert!(parse(&["bin", "simulate", "accounts.csv", "--tenant", "a=a.csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_statement_subcommand() {
+        let args = parse(&[
+            "bin",
+            "statement",
+            "tx.csv",
+            "--client",
+            "42",
+            "--from",
+            "10",
+            "--format",
+            "csv",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.statement,
+            Some(StatementOptions {
+                client: 42,
+                from: 10,
+                to: u64::MAX,
+                format: StatementFormat::Csv,
+                amounts: AmountFormat::default(),
+                passthrough: Vec::new(),
+            })
+        );
+        assert_eq!(args.file_path.as_deref(), Some("tx.csv"));
+        assert!(parse(&["bin", "statement", "tx.csv"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--client", "42"]).is_err());
+        assert!(parse(&["bin", "statement", "tx.csv", "--client", "1", "--from", "0"]).is_err());
+        assert!(parse(&[
+            "bin",
+            "statement",
+            "tx.csv",
+            "--client",
+            "1",
+            "--from",
+            "5",
+            "--to",
+            "4"
+        ])
+        .is_err());
+
+        let args = parse(&[
+            "bin",
+            "statement",
+            "tx.csv",
+            "--client",
+            "42",
+            "--thousands-separators",
+            "--decimal-places",
+            "2",
+            "--currency-symbol",
+            "EUR",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.statement.unwrap().amounts,
+            AmountFormat {
+                thousands_separators: true,
+                decimal_places: Some(2),
+                currency: Some("EUR".to_owned()),
+            }
+        );
+        assert!(parse(&[
+            "bin",
+            "statement",
+            "tx.csv",
+            "--client",
+            "1",
+            "--format",
+            "csv",
+            "--decimal-places",
+            "2"
+        ])
+        .is_err());
+        assert!(parse(&["bin", "tx.csv", "--currency-symbol", "EUR"]).is_err());
+
+        let args = parse(&[
+            "bin",
+            "statement",
+            "tx.csv",
+            "--client",
+            "42",
+            "--passthrough-columns",
+            "merchant, reference",
+            "--passthrough-columns",
+            "channel",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.statement.unwrap().passthrough,
+            ["merchant", "reference", "channel"]
+        );
+        assert!(parse(&[
+            "bin",
+            "statement",
+            "tx.csv",
+            "--client",
+            "42",
+            "--passthrough-columns",
+            "merchant,"
+        ])
+        .is_err());
+        assert!(parse(&[
+            "bin",
+            "statement",
+            "tx.csv",
+            "--client",
+            "42",
+            "--no-header",
+            "--passthrough-columns",
+            "merchant"
+        ])
+        .is_err());
+        assert!(parse(&[
+            "bin",
+            "statement",
+            "tx.csv",
+            "--client",
+            "1",
+            "--decimal-places",
+            "29"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn parses_bisect_subcommand() {
+        let args = parse(&[
+            "bin",
+            "bisect",
+            "tx.csv",
+            "--client",
+            "42",
+            "--expected",
+            "100.0",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.bisect,
+            Some(BisectOptions {
+                client: 42,
+                expected: Expected::Final(Decimal::new(1000, 1)),
+            })
+        );
+        assert_eq!(args.file_path.as_deref(), Some("tx.csv"));
+        let args = parse(&[
+            "bin",
+            "bisect",
+            "tx.csv",
+            "--client",
+            "42",
+            "--expected",
+            "t.csv",
+        ]);
+        assert_eq!(
+            args.unwrap().bisect.unwrap().expected,
+            Expected::Trajectory("t.csv".to_owned())
+        );
+        assert!(parse(&["bin", "bisect", "tx.csv", "--client", "42"]).is_err());
+        assert!(parse(&["bin", "bisect", "tx.csv", "--expected", "1"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--expected", "1"]).is_err());
+    }
+
+    #[test]
+    fn parses_explain_subcommand() {
+        let args = parse(&["bin", "explain", "tx.csv", "--tx", "12345"]).unwrap();
+        assert_eq!(args.explain, Some(12_345));
+        assert_eq!(args.file_path.as_deref(), Some("tx.csv"));
+        assert!(parse(&["bin", "explain", "tx.csv"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--tx", "1"]).is_err());
+    }
+
+    #[test]
+    fn parses_emit_mode() {
+        let args = parse(&["bin", "tx.csv", "--emit", "updates"]).unwrap();
+        assert_eq!(args.emit, Emit::Updates);
+        let result = parse(&["bin", "tx.csv", "--emit", "deltas"]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "`--emit` expects `snapshot` or `updates`: unknown output `deltas`"
+        );
+    }
+
+    #[test]
+    fn parses_flow_windows() {
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--emit",
+            "updates",
+            "--flow-windows",
+            "flow.csv",
+            "--flow-window",
+            "10",
+        ])
+        .unwrap();
+        assert_eq!(args.flow_windows.as_deref(), Some("flow.csv"));
+        assert_eq!(args.flow_window, Duration::from_secs(10));
+        assert!(parse(&["bin", "tx.csv", "--flow-windows", "flow.csv"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--flow-window", "0"]).is_err());
+    }
+
+    #[test]
+    fn latency_budget_requires_emit_updates() {
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--emit",
+            "updates",
+            "--latency-budget",
+            "50",
+        ]);
+        assert_eq!(
+            args.unwrap().latency_budget,
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(
+            parse(&["bin", "tx.csv", "--latency-budget", "50"])
+                .unwrap_err()
+                .to_string(),
+            "`--latency-budget` requires `--emit updates`"
+        );
+    }
+
+    #[test]
+    fn parses_disputable_types() {
+        let args = parse(&["bin", "tx.csv", "--disputable-types", "deposit"]).unwrap();
+        assert_eq!(
+            args.disputable,
+            DisputableTypes {
+                deposits: true,
+                withdrawals: false,
+            }
+        );
+        let result = parse(&["bin", "tx.csv", "--disputable-types", "deposit,chargeback"]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "`--disputable-types` expects transaction types: `chargeback` transactions cannot be disputed"
+        );
+    }
+
+    #[test]
+    fn parses_locked_account_policy() {
+        let args = parse(&["bin", "tx.csv", "--locked-account-policy", "allow-deposits"]).unwrap();
+        assert_eq!(args.locked_policy, LockedAccountPolicy::AllowDeposits);
+        let result = parse(&["bin", "tx.csv", "--locked-account-policy", "allow-all"]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "`--locked-account-policy` expects `reject-all` or `allow-deposits`: unknown policy `allow-all`"
+        );
+    }
+
+    #[test]
+    fn parses_dispute_funds_policy() {
+        let args = parse(&["bin", "tx.csv", "--dispute-funds-policy", "hold-partial"]).unwrap();
+        assert_eq!(args.dispute_policy, DisputeFundsPolicy::HoldPartial);
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--sync",
+            "--dispute-funds-policy",
+            "reject",
+        ])
+        .unwrap();
+        assert_eq!(args.dispute_policy, DisputeFundsPolicy::Reject);
+        assert!(parse(&["bin", "tx.csv", "--dispute-funds-policy", "hold"]).is_err());
+    }
+
+    #[test]
+    fn parses_amount_unit() {
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--sync",
+            "--amount-unit",
+            "minor",
+            "--currency",
+            "JPY",
+        ])
+        .unwrap();
+        assert_eq!(args.amount_unit, AmountUnit::Minor);
+        assert_eq!(args.columns.minor_units, Some(MinorUnits::new(0)));
+        let args = parse(&[
+            "bin",
+            "tx.csv",
+            "--amount-unit",
+            "major",
+            "--currency",
+            "EUR",
+        ])
+        .unwrap();
+        assert_eq!(args.columns.minor_units, None);
+
+        let result = parse(&["bin", "tx.csv", "--amount-unit", "minor"]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "`--amount-unit minor` requires `--currency`"
+        );
+        let minor = ["--amount-unit", "minor", "--currency", "EUR"];
+        let simulate = [
+            &["bin", "simulate", "accounts.msgpack", "tx.csv"][..],
+            &minor,
+        ]
+        .concat();
+        assert!(parse(&simulate).is_err());
+        let updates = [&["bin", "tx.csv", "--emit", "updates"][..], &minor].concat();
+        assert!(parse(&updates).is_err());
+        assert!(parse(&["bin", "tx.csv", "--amount-unit", "cents"]).is_err());
+    }
+
+    #[test]
+    fn parses_max_amount() {
+        let args = parse(&["bin", "tx.csv", "--sync", "--max-amount", "1000000000000"]).unwrap();
+        assert_eq!(args.max_amount, Some(Decimal::from(1_000_000_000_000_u64)));
+        assert!(parse(&["bin", "tx.csv", "--max-amount", "0"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--max-amount", "-5"]).is_err());
+        assert!(parse(&["bin", "tx.csv", "--max-amount", "lots"]).is_err());
+    }
+
+    #[test]
+    fn parses_output_columns() {
+        let args = parse(&["bin", "tx.csv", "--output-columns", "extended"]).unwrap();
+        assert!(args.columns.extended);
+        assert!(!parse(&["bin", "tx.csv"]).unwrap().columns.extended);
+        let result = parse(&["bin", "tx.csv", "--output-columns", "all"]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "`--output-columns` expects `standard` or `extended`: unknown columns `all`"
+        );
+    }
+
+    #[test]
+    fn parses_max_memory_sizes() {
+        let size =
+            |value: &str| parse(&["bin", "tx.csv", "--max-memory", value]).map(|a| a.max_memory);
+        assert_eq!(size("4096").unwrap(), Some(4096));
+        assert_eq!(size("512M").unwrap(), Some(512 << 20));
+        assert_eq!(size("2g").unwrap(), Some(2 << 30));
+        assert!(size("lots").is_err());
+        assert!(size("M").is_err());
+    }
+
+    #[test]
+    fn parses_timeouts() {
+        let timeout =
+            |value: &str| parse(&["bin", "tx.csv", "--timeout", value]).map(|a| a.timeout);
+        assert_eq!(timeout("90").unwrap(), Some(Duration::from_secs(90)));
+        assert_eq!(timeout("90s").unwrap(), Some(Duration::from_secs(90)));
+        assert_eq!(timeout("30m").unwrap(), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(
+            timeout("2h").unwrap(),
+            Some(Duration::from_secs(2 * 60 * 60))
+        );
+        assert!(timeout("0m").is_err());
+        assert!(timeout("soon").is_err());
+        assert!(timeout("m").is_err());
+    }
+
+    #[test]
+    fn missing_file_path_reports_usage() {
+        let result = parse(&["bin"]);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("Usage: bin <transactions.csv> [OPTIONS]"));
+    }
+}
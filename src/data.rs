@@ -1,3 +1,4 @@
+use anyhow::{bail, Result};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
@@ -9,30 +10,230 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Reversal,
+    /// A signed correction of the available funds, only created through
+    /// `Transaction::adjustment` so an input file cannot move funds without a deposit
+    #[serde(skip_deserializing)]
+    Adjustment(ReasonCode),
+    /// A kind registered with a `TransactionRegistry`, never read from an input file
+    #[serde(skip_deserializing)]
+    Custom(CustomKind),
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Transaction {
+/// Identifies a transaction kind registered with a `TransactionRegistry`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct CustomKind(pub(crate) u8);
+
+/// Why an adjustment was made, recorded with it in the audit chain
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ReasonCode {
+    /// An earlier transaction was applied with the wrong amount
+    Correction,
+    /// Funds returned to the client outside of a dispute
+    Refund,
+    Fee,
+    Goodwill,
+}
+
+impl ReasonCode {
+    /// Indexed by the code packed into `Transaction::detail`
+    const ALL: [Self; 4] = [Self::Correction, Self::Refund, Self::Fee, Self::Goodwill];
+}
+
+impl std::fmt::Display for ReasonCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ReasonCode::Correction => "correction",
+            ReasonCode::Refund => "refund",
+            ReasonCode::Fee => "fee",
+            ReasonCode::Goodwill => "goodwill",
+        })
+    }
+}
+
+impl std::fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Reversal => "reversal",
+            TransactionType::Adjustment(reason) => return write!(f, "adjustment:{reason}"),
+            TransactionType::Custom(CustomKind(kind)) => return write!(f, "custom:{kind}"),
+        })
+    }
+}
+
+/// One of the types read from an input file, by its name in the `type` column
+impl std::str::FromStr for TransactionType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Self::ALL
+            .into_iter()
+            .find(|tx_type| tx_type.to_string() == s)
+        {
+            Some(tx_type) => Ok(tx_type),
+            None => bail!("unknown transaction type `{s}`"),
+        }
+    }
+}
+
+impl TransactionType {
+    /// Indexed by the code packed into `Transaction::flags`
+    const ALL: [Self; 6] = [
+        Self::Deposit,
+        Self::Withdrawal,
+        Self::Dispute,
+        Self::Resolve,
+        Self::Chargeback,
+        Self::Reversal,
+    ];
+    /// Code of every custom kind, which is stored separately
+    const CUSTOM: u8 = 6;
+    /// Code of every adjustment, the reason is stored separately
+    const ADJUSTMENT: u8 = 7;
+
+    fn code(self) -> u8 {
+        match self {
+            Self::Deposit => 0,
+            Self::Withdrawal => 1,
+            Self::Dispute => 2,
+            Self::Resolve => 3,
+            Self::Chargeback => 4,
+            Self::Reversal => 5,
+            Self::Custom(_) => Self::CUSTOM,
+            Self::Adjustment(_) => Self::ADJUSTMENT,
+        }
+    }
+}
+
+/// Layout of an input record, packed into a `Transaction` once read
+#[derive(Deserialize)]
+struct Record {
     #[serde(rename = "type")]
-    pub tx_type: TransactionType,
-    #[serde(rename = "client")]
+    tx_type: TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+}
+
+impl From<Record> for Transaction {
+    fn from(record: Record) -> Self {
+        Self::new(record.tx_type, record.client, record.tx, record.amount)
+    }
+}
+
+/// A single input record, fields are only set on construction.
+///
+/// One is sent to a worker per record, so the type and whether an amount was given are
+/// packed into one byte and the amount is stored without an `Option`, keeping it at 24
+/// bytes along with the kind of a custom transaction or the reason of an adjustment.
+#[derive(Deserialize, Clone)]
+#[serde(from = "Record")]
+pub struct Transaction {
     /// Clients are represented by u16 integers
-    pub client_id: u16,
-    #[serde(rename = "tx")]
-    pub tx_id: u32,
-    #[serde(rename = "amount")]
-    pub amount: Option<Decimal>,
-    #[serde(skip_deserializing)]
-    pub in_dispute: bool,
+    client_id: u16,
+    tx_id: u32,
+    /// `TransactionType` code in `TYPE_MASK`, plus `HAS_AMOUNT`
+    flags: u8,
+    /// `CustomKind` of a `TransactionType::Custom` or `ReasonCode` of an adjustment, zero
+    /// otherwise
+    detail: u8,
+    /// Zero unless `HAS_AMOUNT` is set
+    amount: Decimal,
 }
 
 impl Transaction {
+    /// Column names of an input file, in the order the fields are deserialised
+    pub const HEADER: [&'static str; 4] = ["type", "client", "tx", "amount"];
+
+    const TYPE_MASK: u8 = 0b0111;
+    const HAS_AMOUNT: u8 = 0b1000;
+
+    pub(crate) fn new(
+        tx_type: TransactionType,
+        client_id: u16,
+        tx_id: u32,
+        amount: Option<Decimal>,
+    ) -> Self {
+        let has_amount = if amount.is_some() {
+            Self::HAS_AMOUNT
+        } else {
+            0
+        };
+        let detail = match tx_type {
+            TransactionType::Custom(CustomKind(kind)) => kind,
+            TransactionType::Adjustment(reason) => reason as u8,
+            _ => 0,
+        };
+        Self {
+            client_id,
+            tx_id,
+            flags: tx_type.code() | has_amount,
+            detail,
+            amount: amount.unwrap_or_default(),
+        }
+    }
+
+    pub fn deposit(client_id: u16, tx_id: u32, amount: Decimal) -> Self {
+        Self::new(TransactionType::Deposit, client_id, tx_id, Some(amount))
+    }
+
+    pub fn withdrawal(client_id: u16, tx_id: u32, amount: Decimal) -> Self {
+        Self::new(TransactionType::Withdrawal, client_id, tx_id, Some(amount))
+    }
+
+    /// Disputes the deposit or withdrawal `tx_id` of the client
+    pub fn dispute(client_id: u16, tx_id: u32) -> Self {
+        Self::new(TransactionType::Dispute, client_id, tx_id, None)
+    }
+
+    /// Releases the held funds of the disputed transaction `tx_id`
+    pub fn resolve(client_id: u16, tx_id: u32) -> Self {
+        Self::new(TransactionType::Resolve, client_id, tx_id, None)
+    }
+
+    /// Reverses the disputed transaction `tx_id` and locks the client's account
+    pub fn chargeback(client_id: u16, tx_id: u32) -> Self {
+        Self::new(TransactionType::Chargeback, client_id, tx_id, None)
+    }
+
+    /// Undoes the deposit or withdrawal `tx_id` of the client at once, without holding its
+    /// funds first. The transaction cannot be disputed afterwards.
+    pub fn reversal(client_id: u16, tx_id: u32) -> Self {
+        Self::new(TransactionType::Reversal, client_id, tx_id, None)
+    }
+
+    /// Adds `amount`, which may be negative, to the client's available funds
+    pub fn adjustment(client_id: u16, tx_id: u32, amount: Decimal, reason: ReasonCode) -> Self {
+        Self::new(
+            TransactionType::Adjustment(reason),
+            client_id,
+            tx_id,
+            Some(amount),
+        )
+    }
+
+    /// A transaction of a kind registered with the ledger's `TransactionRegistry`
+    pub fn custom(kind: CustomKind, client_id: u16, tx_id: u32, amount: Option<Decimal>) -> Self {
+        Self::new(TransactionType::Custom(kind), client_id, tx_id, amount)
+    }
+
     pub fn tx_id(&self) -> u32 {
         self.tx_id
     }
 
-    pub fn tx_type(&self) -> &TransactionType {
-        &self.tx_type
+    pub fn tx_type(&self) -> TransactionType {
+        match self.flags & Self::TYPE_MASK {
+            TransactionType::CUSTOM => TransactionType::Custom(CustomKind(self.detail)),
+            TransactionType::ADJUSTMENT => {
+                TransactionType::Adjustment(ReasonCode::ALL[usize::from(self.detail)])
+            }
+            code => TransactionType::ALL[usize::from(code)],
+        }
     }
 
     pub fn client_id(&self) -> u16 {
@@ -40,21 +241,432 @@ impl Transaction {
     }
 
     pub fn amount(&self) -> Option<Decimal> {
-        self.amount
+        (self.flags & Self::HAS_AMOUNT != 0).then_some(self.amount)
     }
 
-    pub fn dispute(&mut self) {
-        self.in_dispute = true;
-    }
-
-    pub fn in_dispute(&self) -> bool {
-        self.in_dispute
+    /// The same transaction with `amount` in place of its own, unless it has none
+    #[must_use]
+    pub fn with_amount(self, amount: Decimal) -> Self {
+        match self.amount() {
+            Some(_) => Self { amount, ..self },
+            None => self,
+        }
     }
 
     pub fn is_disputable(&self) -> bool {
         matches!(
-            self.tx_type,
+            self.tx_type(),
             TransactionType::Deposit | TransactionType::Withdrawal
         )
     }
+
+    /// What a later dispute needs to know about this transaction, if it can be disputed
+    pub fn dispute_record(&self) -> Option<DisputeRecord> {
+        let amount = self.amount().filter(|_| self.is_disputable())?;
+        Some(DisputeRecord {
+            client_id: self.client_id,
+            tx_type: self.flags & Self::TYPE_MASK,
+            state: RecordState::Settled,
+            amount,
+        })
+    }
+}
+
+/// Unpacks the flags into the fields they replaced
+#[allow(clippy::missing_fields_in_debug)]
+impl std::fmt::Debug for Transaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transaction")
+            .field("tx_type", &self.tx_type())
+            .field("client_id", &self.client_id)
+            .field("tx_id", &self.tx_id)
+            .field("amount", &self.amount())
+            .finish()
+    }
+}
+
+/// The parts of an approved deposit or withdrawal which a later dispute, resolve,
+/// chargeback or reversal needs, kept under its transaction id for the rest of the run. Its
+/// state can only change through `mark_disputed`, `clear_dispute`, `mark_charged_back` and
+/// `mark_reversed`, each of which fails unless the record is in a state it may leave.
+/// A record which was charged back or reversed keeps that state for good. A ledger keeps
+/// it packed into less than half its size, see `store::TransactionStore`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeRecord {
+    client_id: u16,
+    /// Code of a deposit or withdrawal, the only disputable types
+    tx_type: u8,
+    state: RecordState,
+    amount: Decimal,
+}
+
+/// Where a `DisputeRecord` stands in the life of its disputes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordState {
+    /// Applied and not under dispute, either never disputed or resolved since
+    Settled,
+    /// Under a dispute holding its funds
+    Disputed,
+    /// Undone by a reversal, so it can no longer be disputed
+    Reversed,
+    /// Undone by the chargeback ending its dispute, so it can no longer be disputed
+    ChargedBack,
+}
+
+impl DisputeRecord {
+    /// The record of a deposit or withdrawal of an earlier run, e.g. one saved in a snapshot
+    pub fn restore(
+        client_id: u16,
+        tx_type: TransactionType,
+        amount: Decimal,
+        state: RecordState,
+    ) -> Self {
+        debug_assert!(matches!(
+            tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ));
+        Self {
+            client_id,
+            tx_type: tx_type.code(),
+            state,
+            amount,
+        }
+    }
+
+    pub fn client_id(&self) -> u16 {
+        self.client_id
+    }
+
+    pub fn tx_type(&self) -> TransactionType {
+        TransactionType::ALL[usize::from(self.tx_type)]
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn state(&self) -> RecordState {
+        self.state
+    }
+
+    pub fn in_dispute(&self) -> bool {
+        self.state == RecordState::Disputed
+    }
+
+    pub fn is_reversed(&self) -> bool {
+        self.state == RecordState::Reversed
+    }
+
+    pub fn is_charged_back(&self) -> bool {
+        self.state == RecordState::ChargedBack
+    }
+
+    /// # Errors
+    /// If transaction `tx_id` is already under dispute, was reversed or was charged back
+    pub fn mark_disputed(&mut self, tx_id: u32) -> Result<()> {
+        match self.state {
+            RecordState::Settled => {}
+            RecordState::Disputed => bail!("Transaction `{}` is already under dispute", tx_id),
+            RecordState::Reversed => bail!("Transaction `{}` has been reversed", tx_id),
+            RecordState::ChargedBack => bail!("Transaction `{}` has been charged back", tx_id),
+        }
+
+        self.state = RecordState::Disputed;
+        Ok(())
+    }
+
+    /// # Errors
+    /// If transaction `tx_id` is not under dispute
+    pub fn clear_dispute(&mut self, tx_id: u32) -> Result<()> {
+        if self.state != RecordState::Disputed {
+            bail!(
+                "Resolving Transaction failed as TxId `{}` is not under dispute",
+                tx_id
+            )
+        }
+
+        self.state = RecordState::Settled;
+        Ok(())
+    }
+
+    /// # Errors
+    /// If transaction `tx_id` is not under dispute, e.g. it was already charged back
+    pub fn mark_charged_back(&mut self, tx_id: u32) -> Result<()> {
+        match self.state {
+            RecordState::Disputed => {}
+            RecordState::ChargedBack => {
+                bail!("Transaction `{}` has already been charged back", tx_id)
+            }
+            RecordState::Settled | RecordState::Reversed => {
+                bail!(
+                    "Charging back Transaction failed as TxId `{}` is not under dispute",
+                    tx_id
+                )
+            }
+        }
+
+        self.state = RecordState::ChargedBack;
+        Ok(())
+    }
+
+    /// # Errors
+    /// If transaction `tx_id` is under dispute, was already reversed or was charged back
+    pub fn mark_reversed(&mut self, tx_id: u32) -> Result<()> {
+        match self.state {
+            RecordState::Settled => {}
+            RecordState::Disputed => {
+                bail!(
+                    "Transaction `{}` is under dispute and cannot be reversed",
+                    tx_id
+                )
+            }
+            RecordState::Reversed => bail!("Transaction `{}` has already been reversed", tx_id),
+            RecordState::ChargedBack => bail!("Transaction `{}` has been charged back", tx_id),
+        }
+
+        self.state = RecordState::Reversed;
+        Ok(())
+    }
+}
+
+/// Which of the transactions kept as a `DisputeRecord` a dispute may reference, as
+/// integrations differ on whether withdrawals can be disputed. Both can by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputableTypes {
+    pub deposits: bool,
+    pub withdrawals: bool,
+}
+
+impl Default for DisputableTypes {
+    fn default() -> Self {
+        Self {
+            deposits: true,
+            withdrawals: true,
+        }
+    }
+}
+
+impl DisputableTypes {
+    pub fn allows(self, tx_type: TransactionType) -> bool {
+        match tx_type {
+            TransactionType::Deposit => self.deposits,
+            TransactionType::Withdrawal => self.withdrawals,
+            _ => false,
+        }
+    }
+}
+
+/// Comma-separated types, e.g. `deposit` or `deposit,withdrawal`
+impl std::str::FromStr for DisputableTypes {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut types = Self {
+            deposits: false,
+            withdrawals: false,
+        };
+        for tx_type in s.split(',') {
+            match tx_type.parse()? {
+                TransactionType::Deposit => types.deposits = true,
+                TransactionType::Withdrawal => types.withdrawals = true,
+                tx_type => bail!("`{tx_type}` transactions cannot be disputed"),
+            }
+        }
+        Ok(types)
+    }
+}
+
+/// A transaction tagged with the byte offset of its record in the input file.
+///
+/// Offsets increase monotonically through the file regardless of how it was split
+/// between readers, so a worker can verify it applies each client's transactions in
+/// the order they were written (a dispute must never overtake its deposit).
+#[derive(Debug, Clone)]
+pub struct Sequenced {
+    pub seq: u64,
+    pub tx: Transaction,
+}
+
+/// Step of a dispute's lifecycle, published once it has been applied to the account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeStage {
+    Opened,
+    Resolved,
+    ChargedBack,
+}
+
+/// Notification that a dispute changed state, `amount` is that of the disputed transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisputeEvent {
+    pub stage: DisputeStage,
+    pub client_id: u16,
+    pub tx_id: u32,
+    pub amount: Option<Decimal>,
+}
+
+/// How much a transaction changed its account's total funds, recorded for `--export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Activity {
+    pub tx_id: u32,
+    pub tx_type: TransactionType,
+    /// Negative if it took funds out of the account
+    pub amount: Decimal,
+}
+
+/// A dispute still holding the amount of its transaction, see `Ledger::open_dispute_ages`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenDispute {
+    pub client_id: u16,
+    pub tx_id: u32,
+    pub held: Decimal,
+    /// Sequence number of the event which opened it, `None` if it came without one
+    pub opened_seq: Option<u64>,
+}
+
+/// The chargeback which locked an account, with the account's balances just before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockingChargeback {
+    pub tx_id: u32,
+    pub amount: Decimal,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+}
+
+/// A locked account, see `Ledger::locked_accounts`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedAccount {
+    pub client_id: u16,
+    /// `None` if the account was locked by an earlier run, e.g. restored from a snapshot
+    pub chargeback: Option<LockingChargeback>,
+}
+
+/// What became of a transaction, published once it was applied or its worker gave up on it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxOutcome {
+    pub tx_id: u32,
+    pub client_id: u16,
+    pub status: TxStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxStatus {
+    Applied,
+    /// Rejected with the error of its final attempt
+    Rejected(String),
+    /// Skipped with a warning, e.g. as it was a duplicate
+    Skipped(String),
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use crate::data::{DisputableTypes, DisputeRecord, ReasonCode, Transaction, TransactionType};
+
+    #[test]
+    fn transactions_are_packed() {
+        assert_eq!(std::mem::size_of::<Transaction>(), 24);
+
+        let tx = Transaction::withdrawal(3, 9, Decimal::ZERO);
+        assert_eq!(tx.tx_type(), TransactionType::Withdrawal);
+        assert_eq!(tx.amount(), Some(Decimal::ZERO));
+        let tx = Transaction::chargeback(3, 9);
+        assert_eq!(tx.tx_type(), TransactionType::Chargeback);
+        assert_eq!((tx.client_id(), tx.tx_id(), tx.amount()), (3, 9, None));
+    }
+
+    #[test]
+    fn dispute_state_transitions_are_checked() {
+        let mut record = Transaction::deposit(1, 1, Decimal::ONE)
+            .dispute_record()
+            .unwrap();
+        assert!(record.clear_dispute(1).is_err());
+        record.mark_disputed(1).unwrap();
+        assert_eq!(
+            record.mark_disputed(1).unwrap_err().to_string(),
+            "Transaction `1` is already under dispute"
+        );
+        record.clear_dispute(1).unwrap();
+        assert!(!record.in_dispute());
+
+        assert!(Transaction::dispute(1, 1).dispute_record().is_none());
+
+        record.mark_disputed(1).unwrap();
+        assert_eq!(
+            record.mark_reversed(1).unwrap_err().to_string(),
+            "Transaction `1` is under dispute and cannot be reversed"
+        );
+        record.clear_dispute(1).unwrap();
+        record.mark_reversed(1).unwrap();
+        assert!(record.is_reversed() && !record.in_dispute());
+        assert_eq!(
+            record.mark_disputed(1).unwrap_err().to_string(),
+            "Transaction `1` has been reversed"
+        );
+        assert!(record.mark_charged_back(1).is_err());
+
+        let mut record = Transaction::deposit(1, 2, Decimal::ONE)
+            .dispute_record()
+            .unwrap();
+        assert_eq!(
+            record.mark_charged_back(2).unwrap_err().to_string(),
+            "Charging back Transaction failed as TxId `2` is not under dispute"
+        );
+        record.mark_disputed(2).unwrap();
+        record.mark_charged_back(2).unwrap();
+        assert!(record.is_charged_back() && !record.in_dispute());
+        assert_eq!(
+            record.mark_charged_back(2).unwrap_err().to_string(),
+            "Transaction `2` has already been charged back"
+        );
+        assert!(record.mark_disputed(2).is_err());
+        assert!(record.clear_dispute(2).is_err());
+        assert!(record.mark_reversed(2).is_err());
+    }
+
+    #[test]
+    fn adjustments_keep_their_reason_but_cannot_be_read() {
+        let tx = Transaction::adjustment(3, 9, -Decimal::TEN, ReasonCode::Goodwill);
+        assert_eq!(
+            tx.tx_type(),
+            TransactionType::Adjustment(ReasonCode::Goodwill)
+        );
+        assert_eq!(tx.tx_type().to_string(), "adjustment:goodwill");
+        assert_eq!(tx.amount(), Some(-Decimal::TEN));
+        assert!(tx.dispute_record().is_none());
+
+        let record = csv_async::StringRecord::from(vec!["adjustment", "3", "9", "-10"]);
+        assert!(record.deserialize::<Transaction>(None).is_err());
+    }
+
+    #[test]
+    fn parses_disputable_types() {
+        let types: DisputableTypes = "deposit".parse().unwrap();
+        assert!(types.allows(TransactionType::Deposit));
+        assert!(!types.allows(TransactionType::Withdrawal));
+        assert_eq!(
+            "withdrawal,deposit".parse::<DisputableTypes>().unwrap(),
+            DisputableTypes::default()
+        );
+        assert_eq!(
+            "deposit,dispute"
+                .parse::<DisputableTypes>()
+                .unwrap_err()
+                .to_string(),
+            "`dispute` transactions cannot be disputed"
+        );
+    }
+
+    #[test]
+    fn dispute_records_are_smaller_than_transactions() {
+        assert_eq!(std::mem::size_of::<DisputeRecord>(), 20);
+        let record = Transaction::withdrawal(4, 2, Decimal::TEN)
+            .dispute_record()
+            .unwrap();
+        assert_eq!(
+            (record.client_id(), record.tx_type(), record.amount()),
+            (4, TransactionType::Withdrawal, Decimal::TEN)
+        );
+    }
 }
@@ -0,0 +1,214 @@
+//! Checks with `--verify-determinism` that the accounts of an input do not depend on how its
+//! clients were spread over the workers
+//!
+//! The input is processed twice, the second time with another number of workers and
+//! another partitioner, and the final balances of the runs are compared. As each client's
+//! events are applied in input order by a single worker, any difference means a change to
+//! the reader, the router or the workers let concurrency reorder them.
+
+use std::{collections::HashMap, fmt};
+
+use anyhow::{bail, Result};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    account::{AccountSummary, ClientState},
+    cli::Args,
+    ledger::WorkerSinks,
+    partitioner::PartitionerKind,
+    pipeline::{process_file, WorkersPanicked},
+    shutdown::Shutdown,
+};
+
+/// Differences listed in the error, the rest are only counted
+const LISTED: usize = 10;
+
+/// The accounts of two runs over the same input differ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nondeterminism {
+    /// How each run spread the clients over its workers
+    pub runs: [String; 2],
+    pub differences: Vec<String>,
+}
+
+impl fmt::Display for Nondeterminism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} accounts differ between runs with {} and {}",
+            self.differences.len(),
+            self.runs[0],
+            self.runs[1]
+        )?;
+        for difference in self.differences.iter().take(LISTED) {
+            write!(f, "\n    {difference}")?;
+        }
+        if self.differences.len() > LISTED {
+            write!(f, "\n    ...")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Nondeterminism {}
+
+/// A line per client whose account is missing from one of the runs or whose balances
+/// differ, in client id order
+pub fn compare_accounts(
+    first: &HashMap<u16, ClientState>,
+    second: &HashMap<u16, ClientState>,
+) -> Vec<String> {
+    let mut clients: Vec<_> = first.keys().chain(second.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+
+    let describe = |account: &AccountSummary| {
+        format!(
+            "available {}, held {}, total {}, locked {}",
+            account.available, account.held, account.total, account.locked
+        )
+    };
+    let mut differences = Vec::new();
+    for client in clients {
+        let summaries = (
+            first.get(&client).map(AccountSummary::from),
+            second.get(&client).map(AccountSummary::from),
+        );
+        let difference = match summaries {
+            (Some(first), Some(second)) if first != second => Some(format!(
+                "{} against {}",
+                describe(&first),
+                describe(&second)
+            )),
+            (Some(_), None) => Some("only in the first run".to_owned()),
+            (None, Some(_)) => Some("only in the second run".to_owned()),
+            _ => None,
+        };
+        if let Some(difference) = difference {
+            differences.push(format!("client {client}: {difference}"));
+        }
+    }
+    differences
+}
+
+/// The partitioner of the second run
+fn other_partitioner(partitioner: PartitionerKind) -> PartitionerKind {
+    match partitioner {
+        PartitionerKind::Sticky => PartitionerKind::Modulo,
+        PartitionerKind::Modulo => PartitionerKind::Rendezvous,
+        PartitionerKind::Rendezvous => PartitionerKind::Sticky,
+    }
+}
+
+/// Processes the input twice and writes how many accounts the runs agree on to `stdout`.
+/// The second run has a single worker, so no concurrency at all, unless the first one did,
+/// and the next partitioner.
+///
+/// # Errors
+/// With `Nondeterminism` if the accounts of the runs differ, or if a run fails, had a
+/// worker panic or was stopped on shutdown
+pub async fn run_determinism_check(
+    file_path: &str,
+    mut args: Args,
+    shutdown: &Shutdown,
+) -> Result<()> {
+    let workers = args.workers.unwrap_or_else(num_cpus::get);
+    let plans = [
+        (workers, args.partitioner),
+        (
+            if workers == 1 { 4 } else { 1 },
+            other_partitioner(args.partitioner),
+        ),
+    ];
+    let runs = plans.map(|(workers, partitioner)| format!("{workers} workers, {partitioner}"));
+
+    let mut accounts = Vec::with_capacity(plans.len());
+    for (workers, partitioner) in plans {
+        args.workers = Some(workers);
+        args.partitioner = partitioner;
+        let ledger = process_file(file_path, &args, WorkerSinks::default(), shutdown).await?;
+        if shutdown.is_triggered() {
+            bail!("Stopped verifying {file_path} on shutdown");
+        }
+        if !ledger.worker_panics().is_empty() {
+            let panics = ledger.worker_panics().to_vec();
+            return Err(WorkersPanicked { panics }.into());
+        }
+        accounts.push(ledger.into_accounts());
+    }
+
+    let differences = compare_accounts(&accounts[0], &accounts[1]);
+    if !differences.is_empty() {
+        return Err(Nondeterminism { runs, differences }.into());
+    }
+    let mut stdout = tokio::io::stdout();
+    stdout
+        .write_all(
+            format!(
+                "{} accounts identical between runs with {} and {}\n",
+                accounts[0].len(),
+                runs[0],
+                runs[1]
+            )
+            .as_bytes(),
+        )
+        .await?;
+    stdout.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        account::ClientState,
+        determinism::{compare_accounts, Nondeterminism},
+    };
+
+    #[test]
+    fn lists_the_accounts_which_differ() {
+        let account = |client, available| {
+            (
+                client,
+                ClientState::restore(client, available, Decimal::ZERO, false),
+            )
+        };
+        let first = HashMap::from([
+            account(1, Decimal::TEN),
+            account(2, Decimal::ONE),
+            account(3, Decimal::TWO),
+        ]);
+        let second = HashMap::from([
+            account(1, Decimal::TEN),
+            account(2, Decimal::TWO),
+            account(4, Decimal::ONE),
+        ]);
+        assert!(compare_accounts(&first, &first).is_empty());
+
+        let differences = compare_accounts(&first, &second);
+        assert_eq!(
+            differences,
+            vec![
+                "client 2: available 1, held 0, total 1, locked false against available 2, \
+                 held 0, total 2, locked false"
+                    .to_owned(),
+                "client 3: only in the first run".to_owned(),
+                "client 4: only in the second run".to_owned(),
+            ]
+        );
+        let e = Nondeterminism {
+            runs: [
+                "8 workers, sticky".to_owned(),
+                "1 workers, modulo".to_owned(),
+            ],
+            differences,
+        };
+        assert!(e.to_string().starts_with(
+            "3 accounts differ between runs with 8 workers, sticky and 1 workers, modulo\n    \
+             client 2"
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+//! SHA-256 hash chains proving which state transitions a run applied
+
+use std::{collections::HashMap, fmt::Write};
+
+use sha2::{Digest, Sha256};
+#[cfg(feature = "runtime")]
+use tokio::{fs::File, io::AsyncReadExt};
+
+use crate::{account::ClientState, data::Transaction};
+
+/// SHA-256 of `data`
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// SHA-256 of the file at `path`, read in 1 MiB chunks
+///
+/// # Errors
+/// If the file cannot be read
+#[cfg(feature = "runtime")]
+pub async fn sha256_file(path: &str) -> std::io::Result<[u8; 32]> {
+    let mut file = File::open(path).await?;
+    let mut buffer = vec![0; 1 << 20];
+    let mut hasher = Sha256::new();
+    loop {
+        match file.read(&mut buffer).await? {
+            0 => return Ok(hasher.finalize().into()),
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+}
+
+/// Lowercase hex encoding of a digest
+pub fn to_hex(digest: &[u8; 32]) -> String {
+    digest
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Extends a client's chain with a transaction and the balances it resulted in
+pub fn chain(head: &[u8; 32], tx: &Transaction, state: &ClientState) -> [u8; 32] {
+    let link = format!(
+        "{},{},{},{},{},{},{}",
+        tx.tx_type(),
+        tx.client_id(),
+        tx.tx_id(),
+        tx.amount().map(|a| a.to_string()).unwrap_or_default(),
+        state.available(),
+        state.held(),
+        state.is_locked()
+    );
+    let mut data = head.to_vec();
+    data.extend_from_slice(link.as_bytes());
+    sha256(&data)
+}
+
+/// Combines every client's chain head in client id order, so the digest does not depend
+/// on how clients were partitioned between workers
+#[allow(clippy::implicit_hasher)]
+pub fn run_digest(results: &HashMap<u16, ClientState>) -> [u8; 32] {
+    let mut clients: Vec<_> = results.values().collect();
+    clients.sort_unstable_by_key(|client| client.id());
+
+    let mut data = Vec::with_capacity(clients.len() * 34);
+    for client in clients {
+        data.extend_from_slice(&client.id().to_be_bytes());
+        data.extend_from_slice(client.audit_head());
+    }
+    sha256(&data)
+}
+
+#[cfg(test)]
+mod test {
+    use sha2::{Digest, Sha256};
+
+    use crate::digest::{sha256, to_hex};
+
+    #[test]
+    fn sha256_matches_known_vectors() {
+        assert_eq!(
+            to_hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            to_hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            to_hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn sha256_is_the_same_over_pieces() {
+        let data: Vec<u8> = (0..1000_u32).map(|i| (i % 251) as u8).collect();
+        for piece in [1, 7, 63, 64, 65, 200] {
+            let mut hasher = Sha256::new();
+            for chunk in data.chunks(piece) {
+                hasher.update(chunk);
+            }
+            let digest: [u8; 32] = hasher.finalize().into();
+            assert_eq!(digest, sha256(&data), "pieces of {piece} bytes");
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! How one transaction was handled, replayed from an input file: the record of the
+//! transaction and of every dispute, resolve, chargeback or reversal referencing it
+
+use std::{collections::HashMap, fmt::Write as _};
+
+use anyhow::{bail, Result};
+use futures::stream::StreamExt;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    account::{AccountSummary, ClientState},
+    cli::Args,
+    data::Transaction,
+    io_ops::{async_read_csv, read_transaction},
+    ledger::Ledger,
+    limits::LimitConfig,
+    statement::{build_statement, replay_ledger, AmountFormat, StatementFormat, StatementOptions},
+};
+
+/// A record with the transaction's id and what applying it did to the account
+#[derive(Debug)]
+pub struct ExplainStep {
+    /// Position of the record in the input, counting from 1
+    pub record: u64,
+    pub tx: Transaction,
+    /// Why the record was rejected, `None` if it applied
+    pub rejected: Option<String>,
+    pub before: AccountSummary,
+    pub after: AccountSummary,
+}
+
+/// Replays the history of each client, as `statement` does, and lists the records with the
+/// id `tx_id` in input order
+pub fn explain(
+    tx_id: u32,
+    histories: HashMap<u16, Vec<(u64, Transaction)>>,
+    new_ledger: impl Fn() -> Ledger,
+) -> Vec<ExplainStep> {
+    let mut steps = Vec::new();
+    for (client, history) in histories {
+        let options = StatementOptions {
+            client,
+            from: 1,
+            to: u64::MAX,
+            format: StatementFormat::Text,
+            amounts: AmountFormat::default(),
+            passthrough: Vec::new(),
+        };
+        let statement = build_statement(new_ledger(), history, options);
+        let mut before = AccountSummary::from(&ClientState::new(client));
+        for line in statement.lines {
+            if line.tx.tx_id() == tx_id {
+                steps.push(ExplainStep {
+                    record: line.record,
+                    tx: line.tx,
+                    rejected: line.rejected,
+                    before: before.clone(),
+                    after: line.balance.clone(),
+                });
+            }
+            before = line.balance;
+        }
+    }
+    steps.sort_unstable_by_key(|step| step.record);
+    steps
+}
+
+/// A paragraph per step with the balances which changed
+pub fn render_explanation(tx_id: u32, steps: &[ExplainStep]) -> String {
+    let mut text = format!("Transaction {tx_id}, {} records\n", steps.len());
+    for step in steps {
+        let amount = step
+            .tx
+            .amount()
+            .map(|amount| format!(" of {amount}"))
+            .unwrap_or_default();
+        let outcome = match &step.rejected {
+            Some(reason) => format!("rejected: {reason}"),
+            None => "applied".to_owned(),
+        };
+        let _ = writeln!(
+            text,
+            "\nrecord {}, client {}: {}{amount} {outcome}",
+            step.record,
+            step.tx.client_id(),
+            step.tx.tx_type()
+        );
+
+        let (before, after) = (&step.before, &step.after);
+        let changes: Vec<_> = [
+            ("available", before.available, after.available),
+            ("held", before.held, after.held),
+            ("total", before.total, after.total),
+        ]
+        .into_iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|(balance, before, after)| format!("{balance} {before} -> {after}"))
+        .collect();
+        if changes.is_empty() {
+            text.push_str("    no balance changed\n");
+        } else {
+            let _ = writeln!(text, "    {}", changes.join(", "));
+        }
+        if after.locked && !before.locked {
+            text.push_str("    account locked\n");
+        }
+    }
+    text
+}
+
+/// Replays the input of `args` and writes how the transaction `tx_id` was handled to
+/// `stdout`
+///
+/// # Errors
+/// If the input cannot be read or holds no record with the id
+pub async fn run_explain(tx_id: u32, file_path: &str, args: &Args) -> Result<()> {
+    let limits = LimitConfig::load(args.withdrawal_limits.as_deref())?;
+    // The clients with records of the transaction, whose histories are replayed
+    let mut clients = Vec::new();
+    let mut reader = async_read_csv(file_path, args.csv, args.io_retry).await?;
+    let mut records = reader.records();
+    while let Some(row) = records.next().await {
+        let tx = read_transaction(&row?)?;
+        if tx.tx_id() == tx_id && !clients.contains(&tx.client_id()) {
+            clients.push(tx.client_id());
+        }
+    }
+    if clients.is_empty() {
+        bail!("Transaction `{tx_id}` is not in {file_path}");
+    }
+
+    let mut histories: HashMap<_, Vec<_>> = HashMap::new();
+    let mut reader = async_read_csv(file_path, args.csv, args.io_retry).await?;
+    let mut records = reader.records();
+    let mut record = 0;
+    while let Some(row) = records.next().await {
+        record += 1;
+        let tx = read_transaction(&row?)?;
+        if clients.contains(&tx.client_id()) {
+            histories
+                .entry(tx.client_id())
+                .or_default()
+                .push((record, tx));
+        }
+    }
+
+    let steps = explain(tx_id, histories, || replay_ledger(args, limits.clone()));
+    let mut stdout = tokio::io::stdout();
+    stdout
+        .write_all(render_explanation(tx_id, &steps).as_bytes())
+        .await?;
+    stdout.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::Transaction,
+        explain::{explain, render_explanation},
+        ledger::Ledger,
+    };
+
+    #[test]
+    fn explains_a_transaction_and_its_disputes() {
+        let histories = HashMap::from([
+            (
+                1,
+                vec![
+                    (1, Transaction::deposit(1, 1, Decimal::TEN)),
+                    (3, Transaction::deposit(1, 2, Decimal::TWO)),
+                    (4, Transaction::dispute(1, 2)),
+                    (5, Transaction::chargeback(1, 2)),
+                    (6, Transaction::resolve(1, 2)),
+                ],
+            ),
+            (2, vec![(2, Transaction::dispute(2, 2))]),
+        ]);
+        let steps = explain(2, histories, Ledger::new);
+        let records: Vec<_> = steps.iter().map(|step| step.record).collect();
+        assert_eq!(records, vec![2, 3, 4, 5, 6]);
+
+        let text = render_explanation(2, &steps);
+        assert!(text.starts_with("Transaction 2, 5 records\n"));
+        assert!(text.contains(
+            "\nrecord 3, client 1: deposit of 2 applied\n    available 10 -> 12, total 10 -> 12\n"
+        ));
+        assert!(text.contains(
+            "\nrecord 4, client 1: dispute applied\n    available 12 -> 10, held 0 -> 2\n"
+        ));
+        assert!(text.contains("    held 2 -> 0, total 12 -> 10\n    account locked\n"));
+        assert!(text.contains("\nrecord 2, client 2: dispute rejected: "));
+        assert!(text.contains("\nrecord 6, client 1: resolve rejected: "));
+        assert!(text.ends_with("\n    no balance changed\n"));
+    }
+}
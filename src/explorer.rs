@@ -0,0 +1,436 @@
+//! Read-only terminal browser of the accounts of a snapshot or output CSV, and of each
+//! client's transactions in an input
+
+use std::{collections::HashMap, io, sync::Arc};
+
+use anyhow::Result;
+use futures::stream::StreamExt;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table, TableState},
+    Frame,
+};
+use rust_decimal::Decimal;
+
+use crate::{
+    account::AccountSummary,
+    cli::Args,
+    data::Transaction,
+    io_ops::{async_read_csv, read_transaction},
+    ledger::Ledger,
+    limits::LimitConfig,
+    risk::risk_policy,
+    snapshot,
+    statement::{
+        build_statement, describe_balance, AmountFormat, Statement, StatementFormat,
+        StatementOptions,
+    },
+};
+
+/// Rows moved by page up and page down
+const PAGE: isize = 20;
+const HELP: &str = "↑/↓ move  PgUp/PgDn page  s sort  enter history  q quit";
+const HISTORY_HELP: &str = "↑/↓ move  PgUp/PgDn page  esc back  q quit";
+
+/// Order of the accounts, balances highest first and ties in client id order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Client,
+    Total,
+    Available,
+    Held,
+}
+
+impl SortKey {
+    /// The order `s` switches to
+    fn next(self) -> Self {
+        match self {
+            Self::Client => Self::Total,
+            Self::Total => Self::Available,
+            Self::Available => Self::Held,
+            Self::Held => Self::Client,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::Total => "total",
+            Self::Available => "available",
+            Self::Held => "held",
+        }
+    }
+}
+
+/// What the explorer shows, and what its keys do
+pub struct Explorer {
+    accounts: Vec<AccountSummary>,
+    sort: SortKey,
+    table: TableState,
+    /// Each client's `(record, transaction)`s in the input, `None` without an input
+    transactions: Option<HashMap<u16, Vec<(u64, Transaction)>>>,
+    /// Replays a client's transactions as `statement` would
+    new_ledger: Box<dyn Fn() -> Ledger>,
+    /// The client whose history is shown, if any
+    inspected: Option<Statement>,
+    history: TableState,
+    status: Option<String>,
+}
+
+impl Explorer {
+    pub fn new(
+        accounts: Vec<AccountSummary>,
+        transactions: Option<HashMap<u16, Vec<(u64, Transaction)>>>,
+        new_ledger: Box<dyn Fn() -> Ledger>,
+    ) -> Self {
+        let mut explorer = Self {
+            accounts,
+            sort: SortKey::default(),
+            table: TableState::default(),
+            transactions,
+            new_ledger,
+            inspected: None,
+            history: TableState::default(),
+            status: None,
+        };
+        explorer.sort_by(SortKey::default());
+        explorer
+    }
+
+    /// The accounts in the order shown
+    pub fn accounts(&self) -> &[AccountSummary] {
+        &self.accounts
+    }
+
+    /// The highlighted account
+    pub fn selected(&self) -> Option<&AccountSummary> {
+        self.table.selected().and_then(|i| self.accounts.get(i))
+    }
+
+    /// The history being shown, if any
+    pub fn inspected(&self) -> Option<&Statement> {
+        self.inspected.as_ref()
+    }
+
+    /// Sorts the accounts, keeping the highlighted one
+    pub fn sort_by(&mut self, sort: SortKey) {
+        let selected = self.selected().map(|account| account.client);
+        self.sort = sort;
+        let key = |account: &AccountSummary| match sort {
+            SortKey::Client => Decimal::ZERO,
+            SortKey::Total => -account.total,
+            SortKey::Available => -account.available,
+            SortKey::Held => -account.held,
+        };
+        self.accounts
+            .sort_unstable_by_key(|account| (key(account), account.client));
+        let index = selected
+            .and_then(|client| self.accounts.iter().position(|a| a.client == client))
+            .or_else(|| (!self.accounts.is_empty()).then_some(0));
+        self.table.select(index);
+    }
+
+    /// Applies a key press, returning false once the explorer should quit
+    pub fn handle(&mut self, key: KeyCode) -> bool {
+        self.status = None;
+        let (table, rows) = match &self.inspected {
+            Some(statement) => (&mut self.history, statement.lines.len()),
+            None => (&mut self.table, self.accounts.len()),
+        };
+        match key {
+            KeyCode::Char('q') => return false,
+            KeyCode::Up | KeyCode::Char('k') => move_selection(table, rows, -1),
+            KeyCode::Down | KeyCode::Char('j') => move_selection(table, rows, 1),
+            KeyCode::PageUp => move_selection(table, rows, -PAGE),
+            KeyCode::PageDown => move_selection(table, rows, PAGE),
+            KeyCode::Home => move_selection(table, rows, isize::MIN),
+            KeyCode::End => move_selection(table, rows, isize::MAX),
+            KeyCode::Esc | KeyCode::Backspace => self.inspected = None,
+            KeyCode::Char('s') if self.inspected.is_none() => self.sort_by(self.sort.next()),
+            KeyCode::Enter if self.inspected.is_none() => self.inspect(),
+            _ => {}
+        }
+        true
+    }
+
+    /// Shows the history of the highlighted account
+    fn inspect(&mut self) {
+        let Some(client) = self.selected().map(|account| account.client) else {
+            return;
+        };
+        let Some(transactions) = &self.transactions else {
+            self.status = Some("No input given to read the history from".to_owned());
+            return;
+        };
+        let options = StatementOptions {
+            client,
+            from: 1,
+            to: u64::MAX,
+            format: StatementFormat::Text,
+            amounts: AmountFormat::default(),
+            passthrough: Vec::new(),
+        };
+        let history = transactions.get(&client).cloned().unwrap_or_default();
+        let statement = build_statement((self.new_ledger)(), history, options);
+        self.history =
+            TableState::default().with_selected((!statement.lines.is_empty()).then_some(0));
+        self.inspected = Some(statement);
+    }
+
+    pub fn render(&mut self, frame: &mut Frame<'_>) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(2)]).areas(frame.area());
+        let highlight = Style::new().reversed();
+        let help = match &self.inspected {
+            Some(statement) => {
+                let rows = statement.lines.iter().map(|line| {
+                    let status = match &line.rejected {
+                        Some(reason) => format!("rejected: {reason}"),
+                        None => "applied".to_owned(),
+                    };
+                    Row::new([
+                        line.record.to_string(),
+                        line.tx.tx_type().to_string(),
+                        line.tx.tx_id().to_string(),
+                        line.tx.amount().map(|a| a.to_string()).unwrap_or_default(),
+                        line.balance.available.to_string(),
+                        line.balance.held.to_string(),
+                        line.balance.total.to_string(),
+                        status,
+                    ])
+                });
+                let widths = [8, 12, 10, 14, 14, 14, 14].map(Constraint::Length);
+                let table = Table::new(rows, widths.into_iter().chain([Constraint::Min(0)]))
+                    .header(Row::new([
+                        "record",
+                        "type",
+                        "tx",
+                        "amount",
+                        "available",
+                        "held",
+                        "total",
+                        "status",
+                    ]))
+                    .block(Block::bordered().title(format!(
+                        " client {}, closing balance: {} ",
+                        statement.options.client,
+                        describe_balance(statement.closing(), &statement.options.amounts)
+                    )))
+                    .row_highlight_style(highlight);
+                frame.render_stateful_widget(table, main, &mut self.history);
+                HISTORY_HELP
+            }
+            None => {
+                let rows = self.accounts.iter().map(|account| {
+                    Row::new([
+                        account.client.to_string(),
+                        account.available.to_string(),
+                        account.held.to_string(),
+                        account.total.to_string(),
+                        account.locked.to_string(),
+                    ])
+                });
+                let widths = [8, 16, 16, 16, 8].map(Constraint::Length);
+                let table = Table::new(rows, widths)
+                    .header(Row::new(AccountSummary::HEADER))
+                    .block(Block::bordered().title(format!(
+                        " {} accounts by {} ",
+                        self.accounts.len(),
+                        self.sort.name()
+                    )))
+                    .row_highlight_style(highlight);
+                frame.render_stateful_widget(table, main, &mut self.table);
+                HELP
+            }
+        };
+        let status = self.status.as_deref().unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(vec![Line::from(status), Line::from(help).dim()]),
+            footer,
+        );
+    }
+}
+
+/// Moves the highlighted row of `rows` by `delta`, stopping at the first and last
+fn move_selection(table: &mut TableState, rows: usize, delta: isize) {
+    if rows == 0 {
+        return;
+    }
+    let current = table.selected().unwrap_or_default();
+    let next = current.saturating_add_signed(delta).min(rows - 1);
+    table.select(Some(next));
+}
+
+/// Groups the `(record, transaction)`s of the input at `file_path` by client, records
+/// numbered from 1
+async fn read_transactions(
+    file_path: &str,
+    args: &Args,
+) -> Result<HashMap<u16, Vec<(u64, Transaction)>>> {
+    let mut reader = async_read_csv(file_path, args.csv, args.io_retry).await?;
+    let mut records = reader.records();
+    let mut transactions: HashMap<_, Vec<_>> = HashMap::new();
+    let mut record = 0;
+    while let Some(row) = records.next().await {
+        record += 1;
+        let tx = read_transaction(&row?)?;
+        transactions
+            .entry(tx.client_id())
+            .or_default()
+            .push((record, tx));
+    }
+    Ok(transactions)
+}
+
+/// Browses the accounts at `accounts_path`, restored as `simulate` restores them, and the
+/// history of each client in `file_path` if given, until the operator quits. Nothing is
+/// ever written.
+///
+/// # Errors
+/// If the accounts or the input cannot be read, or the terminal cannot be drawn to
+pub async fn run_explorer(accounts_path: &str, file_path: Option<&str>, args: &Args) -> Result<()> {
+    let accounts = snapshot::load(accounts_path, args.snapshot_key.as_ref())
+        .await?
+        .accounts
+        .values()
+        .map(AccountSummary::from)
+        .collect();
+    let transactions = match file_path {
+        Some(file_path) => Some(read_transactions(file_path, args).await?),
+        None => None,
+    };
+    let (locked_policy, velocity, anomaly) = (args.locked_policy, args.velocity, args.anomaly);
+    let (dispute_policy, max_amount) = (args.dispute_policy, args.max_amount);
+    let chargeback_limit = args.chargeback_limit;
+    let overdrafts = Arc::new(args.overdrafts.clone());
+    let limits = LimitConfig::load(args.withdrawal_limits.as_deref())?;
+    let disputable = args.disputable;
+    let new_ledger = Box::new(move || {
+        let ledger = Ledger::new()
+            .with_locked_policy(locked_policy)
+            .with_dispute_policy(dispute_policy)
+            .with_chargeback_limit(chargeback_limit)
+            .with_max_amount(max_amount)
+            .with_overdrafts(Arc::clone(&overdrafts))
+            .with_withdrawal_limits(limits.clone())
+            .with_disputable_types(disputable);
+        match risk_policy(velocity, anomaly) {
+            Some(policy) => ledger.with_risk_policy(policy),
+            None => ledger,
+        }
+    });
+    let explorer = Explorer::new(accounts, transactions, new_ledger);
+    tokio::task::block_in_place(|| explore(explorer))?;
+    Ok(())
+}
+
+/// Draws the explorer and applies key presses until it quits, restoring the terminal after
+fn explore(mut explorer: Explorer) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = (|| loop {
+        terminal.draw(|frame| explorer.render(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !explorer.handle(key.code) {
+                return Ok(());
+            }
+        }
+    })();
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use ratatui::{backend::TestBackend, crossterm::event::KeyCode, Terminal};
+    use rust_decimal::Decimal;
+
+    use crate::{
+        account::{AccountSummary, ClientState},
+        data::Transaction,
+        explorer::{Explorer, SortKey},
+        ledger::Ledger,
+    };
+
+    fn open(transactions: Option<HashMap<u16, Vec<(u64, Transaction)>>>) -> Explorer {
+        let accounts = [
+            ClientState::restore(1, Decimal::TEN, Decimal::ZERO, false),
+            ClientState::restore(2, Decimal::ONE, Decimal::ONE_HUNDRED, false),
+            ClientState::restore(3, Decimal::ONE_HUNDRED, Decimal::ZERO, true),
+        ];
+        let accounts = accounts.iter().rev().map(AccountSummary::from).collect();
+        Explorer::new(accounts, transactions, Box::new(Ledger::new))
+    }
+
+    fn clients(explorer: &Explorer) -> Vec<u16> {
+        explorer.accounts().iter().map(|a| a.client).collect()
+    }
+
+    #[test]
+    fn sorts_by_balance_keeping_the_selection() {
+        let mut explorer = open(None);
+        assert_eq!(clients(&explorer), vec![1, 2, 3]);
+        assert!(explorer.handle(KeyCode::Down));
+        assert_eq!(explorer.selected().unwrap().client, 2);
+
+        explorer.handle(KeyCode::Char('s'));
+        assert_eq!(clients(&explorer), vec![2, 3, 1]);
+        assert_eq!(explorer.selected().unwrap().client, 2);
+        explorer.sort_by(SortKey::Available);
+        assert_eq!(clients(&explorer), vec![3, 1, 2]);
+        explorer.sort_by(SortKey::Held);
+        assert_eq!(clients(&explorer), vec![2, 1, 3]);
+
+        explorer.handle(KeyCode::End);
+        assert_eq!(explorer.selected().unwrap().client, 3);
+        explorer.handle(KeyCode::Down);
+        assert_eq!(explorer.selected().unwrap().client, 3);
+        assert!(!explorer.handle(KeyCode::Char('q')));
+    }
+
+    #[test]
+    fn inspects_a_clients_history() {
+        let history = HashMap::from([(
+            1,
+            vec![
+                (1, Transaction::deposit(1, 1, Decimal::TEN)),
+                (3, Transaction::withdrawal(1, 2, Decimal::ONE_HUNDRED)),
+            ],
+        )]);
+        let mut explorer = open(Some(history));
+        explorer.handle(KeyCode::Enter);
+        let statement = explorer.inspected().unwrap();
+        assert_eq!(statement.options.client, 1);
+        let records: Vec<_> = statement.lines.iter().map(|line| line.record).collect();
+        assert_eq!(records, vec![1, 3]);
+        assert!(statement.lines[1].rejected.is_some());
+
+        explorer.handle(KeyCode::Esc);
+        assert!(explorer.inspected().is_none());
+        let mut without_input = open(None);
+        without_input.handle(KeyCode::Enter);
+        assert!(without_input.inspected().is_none());
+    }
+
+    #[test]
+    fn renders_the_accounts() {
+        let mut explorer = open(None);
+        let mut terminal = Terminal::new(TestBackend::new(80, 10)).unwrap();
+        terminal.draw(|frame| explorer.render(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content
+            .iter()
+            .map(ratatui::buffer::Cell::symbol)
+            .collect();
+        assert!(screen.contains("3 accounts by client"));
+        assert!(screen.contains("available"));
+        assert!(screen.contains("s sort"));
+    }
+}
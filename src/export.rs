@@ -0,0 +1,213 @@
+//! Per-client files of account activity for accounting software, written with `--export`
+
+use std::{
+    fmt::Write,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    account::{AccountSummary, ClientState},
+    camt::{utc_date_time, Currency},
+    data::Activity,
+};
+
+/// The ledger is no bank, importers only match accounts by their id
+const BANK_ID: &str = "0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// OFX 2.2 bank statement
+    Ofx,
+    /// Quicken Interchange Format, which carries no balance or currency
+    Qif,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Ofx => "ofx",
+            Self::Qif => "qif",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ofx" => Ok(Self::Ofx),
+            "qif" => Ok(Self::Qif),
+            _ => bail!("unknown export format `{s}`"),
+        }
+    }
+}
+
+/// Renders the client's account and its activity, see `Ledger::activity`. Records carry no
+/// timestamps, so every transaction is dated `created`.
+pub fn render_export(
+    format: ExportFormat,
+    client: &ClientState,
+    activity: &[Activity],
+    currency: Option<&Currency>,
+    created: SystemTime,
+) -> String {
+    let secs = created
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (date, date_time) = utc_date_time(secs);
+    match format {
+        ExportFormat::Ofx => {
+            let timestamp: String = date_time.chars().filter(char::is_ascii_digit).collect();
+            let currency = currency.map_or_else(String::new, ToString::to_string);
+            render_ofx(client, activity, &currency, &timestamp)
+        }
+        ExportFormat::Qif => {
+            let us_date = format!("{}/{}/{}", &date[5..7], &date[8..10], &date[..4]);
+            render_qif(activity, &us_date)
+        }
+    }
+}
+
+fn render_ofx(client: &ClientState, activity: &[Activity], currency: &str, date: &str) -> String {
+    let account = AccountSummary::from(client);
+    let mut ofx = String::new();
+    ofx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    ofx.push_str(
+        "<?OFX OFXHEADER=\"200\" VERSION=\"220\" SECURITY=\"NONE\" OLDFILEUID=\"NONE\" \
+         NEWFILEUID=\"NONE\"?>\n",
+    );
+    ofx.push_str("<OFX>\n  <SIGNONMSGSRSV1>\n    <SONRS>\n");
+    ofx.push_str("      <STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>\n");
+    let _ = writeln!(ofx, "      <DTSERVER>{date}</DTSERVER>");
+    ofx.push_str("      <LANGUAGE>ENG</LANGUAGE>\n    </SONRS>\n  </SIGNONMSGSRSV1>\n");
+    ofx.push_str("  <BANKMSGSRSV1>\n    <STMTTRNRS>\n      <TRNUID>0</TRNUID>\n");
+    ofx.push_str("      <STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>\n");
+    ofx.push_str("      <STMTRS>\n");
+    let _ = writeln!(ofx, "        <CURDEF>{currency}</CURDEF>");
+    let _ = writeln!(
+        ofx,
+        "        <BANKACCTFROM><BANKID>{BANK_ID}</BANKID><ACCTID>{}</ACCTID>\
+         <ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>",
+        account.client
+    );
+    let _ = writeln!(
+        ofx,
+        "        <BANKTRANLIST>\n          <DTSTART>{date}</DTSTART>\n          \
+         <DTEND>{date}</DTEND>"
+    );
+    for entry in activity {
+        let kind = if entry.amount.is_sign_negative() {
+            "DEBIT"
+        } else {
+            "CREDIT"
+        };
+        let _ = writeln!(
+            ofx,
+            "          <STMTTRN><TRNTYPE>{kind}</TRNTYPE><DTPOSTED>{date}</DTPOSTED>\
+             <TRNAMT>{}</TRNAMT><FITID>{}</FITID><NAME>{}</NAME></STMTTRN>",
+            entry.amount, entry.tx_id, entry.tx_type
+        );
+    }
+    ofx.push_str("        </BANKTRANLIST>\n");
+    let _ = writeln!(
+        ofx,
+        "        <LEDGERBAL><BALAMT>{}</BALAMT><DTASOF>{date}</DTASOF></LEDGERBAL>",
+        account.total
+    );
+    let _ = writeln!(
+        ofx,
+        "        <AVAILBAL><BALAMT>{}</BALAMT><DTASOF>{date}</DTASOF></AVAILBAL>",
+        account.available
+    );
+    ofx.push_str("      </STMTRS>\n    </STMTTRNRS>\n  </BANKMSGSRSV1>\n</OFX>\n");
+    ofx
+}
+
+fn render_qif(activity: &[Activity], date: &str) -> String {
+    let mut qif = String::from("!Type:Bank\n");
+    for entry in activity {
+        let _ = write!(
+            qif,
+            "D{date}\nT{}\nN{}\nP{}\n^\n",
+            entry.amount, entry.tx_id, entry.tx_type
+        );
+    }
+    qif
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::Transaction,
+        export::{render_export, ExportFormat},
+        ledger::Ledger,
+    };
+
+    fn exported(format: ExportFormat) -> String {
+        let mut ledger = Ledger::new().with_activity(true);
+        let transactions = [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::deposit(1, 2, Decimal::TWO),
+            Transaction::withdrawal(1, 3, Decimal::ONE),
+            Transaction::dispute(1, 2),
+            Transaction::chargeback(1, 2),
+            Transaction::deposit(2, 4, Decimal::ONE),
+        ];
+        for tx in transactions {
+            ledger.apply(tx).unwrap();
+        }
+        let client = ledger.accounts().find(|client| client.id() == 1).unwrap();
+        render_export(
+            format,
+            client,
+            ledger.activity(1),
+            Some(&"EUR".parse().unwrap()),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        )
+    }
+
+    #[test]
+    fn exports_money_movements_as_ofx() {
+        let ofx = exported(ExportFormat::Ofx);
+        assert!(ofx.contains("<CURDEF>EUR</CURDEF>"));
+        assert!(ofx.contains("<ACCTID>1</ACCTID>"));
+        let transactions: Vec<_> = ofx
+            .lines()
+            .filter(|line| line.contains("<STMTTRN>"))
+            .map(str::trim)
+            .collect();
+        assert_eq!(transactions.len(), 4);
+        assert_eq!(
+            transactions[3],
+            "<STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20231114221320</DTPOSTED>\
+             <TRNAMT>-2</TRNAMT><FITID>2</FITID><NAME>chargeback</NAME></STMTTRN>"
+        );
+        assert!(ofx.contains("<LEDGERBAL><BALAMT>9</BALAMT>"));
+    }
+
+    #[test]
+    fn exports_money_movements_as_qif() {
+        let qif = exported(ExportFormat::Qif);
+        assert!(qif.starts_with("!Type:Bank\nD11/14/2023\nT10\nN1\nPdeposit\n^\n"));
+        assert!(qif.ends_with("D11/14/2023\nT-2\nN2\nPchargeback\n^\n"));
+        assert_eq!(qif.matches('^').count(), 4);
+    }
+
+    #[test]
+    fn activity_is_only_recorded_when_enabled() {
+        let mut ledger = Ledger::new();
+        ledger
+            .apply(Transaction::deposit(1, 1, Decimal::TEN))
+            .unwrap();
+        assert!(ledger.activity(1).is_empty());
+    }
+}
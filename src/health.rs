@@ -0,0 +1,281 @@
+//! Liveness and readiness endpoints for orchestrators, served over HTTP with
+//! `--health-addr` while inputs are processed
+//!
+//! `/healthz` fails once a worker has events waiting but has not applied one for
+//! `STALL_TIMEOUT`, so a wedged instance is restarted. `/readyz` fails while the events
+//! routed to the workers but not yet applied exceed the backlog limit.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "runtime")]
+use anyhow::Result;
+#[cfg(feature = "runtime")]
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+#[cfg(feature = "runtime")]
+use tracing::warn;
+
+/// How long a worker with events waiting may go without applying one before it is
+/// considered wedged
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Progress of one worker, shared between the router counting the events sent to it and the
+/// worker counting those it took off its channel
+#[derive(Debug)]
+pub struct WorkerProbe {
+    /// When the `Health` the worker is registered with was created
+    started: Instant,
+    routed: AtomicU64,
+    applied: AtomicU64,
+    /// Milliseconds after `started` when the worker last took an event
+    progressed: AtomicU64,
+}
+
+impl WorkerProbe {
+    pub fn routed(&self) {
+        self.routed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that the worker took an event off its channel
+    pub fn progressed(&self) {
+        self.applied.fetch_add(1, Ordering::Relaxed);
+        let now = elapsed_ms(self.started);
+        self.progressed.store(now, Ordering::Relaxed);
+    }
+
+    /// Events routed to the worker it has not taken yet
+    pub fn backlog(&self) -> u64 {
+        let applied = self.applied.load(Ordering::Relaxed);
+        self.routed.load(Ordering::Relaxed).saturating_sub(applied)
+    }
+}
+
+/// The state of the running workers, reported by the endpoints. Cloned handles share it.
+#[derive(Debug, Clone)]
+pub struct Health {
+    started: Instant,
+    /// Dropped by a worker and the router once it finished
+    workers: Arc<Mutex<Vec<Weak<WorkerProbe>>>>,
+    backlog_limit: u64,
+}
+
+/// What an endpoint reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthReport {
+    pub ok: bool,
+    pub workers: usize,
+    pub backlog: u64,
+    /// Workers with events waiting which made no progress for `STALL_TIMEOUT`
+    pub stalled: usize,
+}
+
+impl Health {
+    /// Reports ready while at most `backlog_limit` events are waiting to be applied
+    pub fn new(backlog_limit: u64) -> Self {
+        Self {
+            started: Instant::now(),
+            workers: Arc::new(Mutex::new(Vec::new())),
+            backlog_limit,
+        }
+    }
+
+    /// Registers a worker, reported on until the returned probe is dropped
+    pub fn probe(&self) -> Arc<WorkerProbe> {
+        let probe = Arc::new(WorkerProbe {
+            started: self.started,
+            routed: AtomicU64::new(0),
+            applied: AtomicU64::new(0),
+            progressed: AtomicU64::new(elapsed_ms(self.started)),
+        });
+        let mut workers = self.workers.lock().unwrap_or_else(PoisonError::into_inner);
+        workers.retain(|worker| worker.strong_count() > 0);
+        workers.push(Arc::downgrade(&probe));
+        probe
+    }
+
+    fn report(&self, stall_timeout: Duration) -> HealthReport {
+        let now = elapsed_ms(self.started);
+        let stall_ms = u64::try_from(stall_timeout.as_millis()).unwrap_or(u64::MAX);
+        let workers: Vec<_> = self
+            .workers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        let stalled = workers
+            .iter()
+            .filter(|worker| {
+                let idle = now.saturating_sub(worker.progressed.load(Ordering::Relaxed));
+                worker.backlog() > 0 && idle >= stall_ms
+            })
+            .count();
+        HealthReport {
+            ok: true,
+            workers: workers.len(),
+            backlog: workers.iter().map(|worker| worker.backlog()).sum(),
+            stalled,
+        }
+    }
+
+    /// Fails once a worker is wedged, after `stall_timeout` without progress
+    pub fn liveness(&self, stall_timeout: Duration) -> HealthReport {
+        let report = self.report(stall_timeout);
+        HealthReport {
+            ok: report.stalled == 0,
+            ..report
+        }
+    }
+
+    /// Fails while more than the backlog limit of events are waiting to be applied
+    pub fn readiness(&self) -> HealthReport {
+        let report = self.report(STALL_TIMEOUT);
+        HealthReport {
+            ok: report.backlog <= self.backlog_limit,
+            ..report
+        }
+    }
+}
+
+fn elapsed_ms(since: Instant) -> u64 {
+    u64::try_from(since.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Answers `GET /healthz` and `GET /readyz` on `listener` until the task is aborted
+///
+/// # Errors
+/// If the listener fails to accept connections
+#[cfg(feature = "runtime")]
+pub async fn serve_health(listener: TcpListener, health: Health) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let health = health.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, &health).await {
+                warn!("Health check failed to respond: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(feature = "runtime")]
+async fn respond(stream: TcpStream, health: &Health) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line).await?;
+    let path = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", path, _] => path,
+        _ => "",
+    };
+    let report = match path {
+        "/healthz" => Some(health.liveness(STALL_TIMEOUT)),
+        "/readyz" => Some(health.readiness()),
+        _ => None,
+    };
+    let (status, body) = match report {
+        Some(report) => {
+            let status = if report.ok {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            (status, to_json(&report))
+        }
+        None => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.get_mut().write_all(response.as_bytes()).await?;
+    stream.get_mut().shutdown().await
+}
+
+#[cfg(feature = "runtime")]
+fn to_json(report: &HealthReport) -> String {
+    format!(
+        "{{\"status\":\"{}\",\"workers\":{},\"backlog\":{},\"stalled\":{}}}",
+        if report.ok { "ok" } else { "unavailable" },
+        report.workers,
+        report.backlog,
+        report.stalled
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::health::{serve_health, Health};
+
+    #[test]
+    fn stalled_workers_fail_liveness() {
+        let health = Health::new(1);
+        let probe = health.probe();
+        let idle = health.probe();
+        probe.routed();
+        probe.routed();
+        probe.progressed();
+
+        let live = health.liveness(Duration::from_secs(30));
+        assert!(live.ok);
+        assert_eq!((live.workers, live.backlog), (2, 1));
+        let wedged = health.liveness(Duration::ZERO);
+        assert!(!wedged.ok);
+        assert_eq!(wedged.stalled, 1);
+        assert!(health.readiness().ok);
+
+        probe.routed();
+        assert!(!health.readiness().ok);
+        drop((probe, idle));
+        let finished = health.liveness(Duration::ZERO);
+        assert!(finished.ok);
+        assert_eq!((finished.workers, finished.backlog), (0, 0));
+    }
+
+    async fn get(port: u16, path: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_the_endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let health = Health::new(0);
+        let probe = health.probe();
+        let server = tokio::spawn(serve_health(listener, health));
+
+        let healthz = get(port, "/healthz").await;
+        assert!(healthz.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(healthz.ends_with("{\"status\":\"ok\",\"workers\":1,\"backlog\":0,\"stalled\":0}"));
+        probe.routed();
+        assert!(get(port, "/readyz")
+            .await
+            .starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(get(port, "/metrics")
+            .await
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+        server.abort();
+    }
+}
@@ -0,0 +1,140 @@
+//! Invariants the accounts of a run keep, checked with `--verify-invariants` once an input
+//! was processed
+//!
+//! They hold for accounts opened by the run, not for those restored from a previous one,
+//! whose history is unknown.
+
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::account::ClientState;
+
+/// Violations listed in the error, the rest are only counted
+const LISTED: usize = 10;
+
+/// The invariants an input's accounts broke
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantViolation {
+    pub violations: Vec<String>,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ledger invariants violated", self.violations.len())?;
+        for violation in self.violations.iter().take(LISTED) {
+            write!(f, "\n    {violation}")?;
+        }
+        if self.violations.len() > LISTED {
+            write!(f, "\n    ...")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+/// Checks that
+/// - the deposits less the withdrawals and chargebacks, plus the adjustments, of every
+///   account sum to its total, and so over all the accounts
+/// - no account holds negative funds
+/// - every locked account had a chargeback
+///
+/// # Errors
+/// With every violation found
+pub fn verify_invariants<'a>(
+    accounts: impl IntoIterator<Item = &'a ClientState>,
+) -> Result<(), InvariantViolation> {
+    let mut violations = Vec::new();
+    let (mut flows, mut totals) = (Decimal::ZERO, Decimal::ZERO);
+    for account in accounts {
+        let client = account.id();
+        let flow =
+            account.deposited() - account.withdrawn() - account.charged_back() + account.adjusted();
+        if flow != account.total() {
+            violations.push(format!(
+                "client {client}: deposits less withdrawals and chargebacks, plus adjustments, \
+                 come to {flow} but the total is {}",
+                account.total()
+            ));
+        }
+        if account.held() < Decimal::ZERO {
+            violations.push(format!(
+                "client {client}: {} held is negative",
+                account.held()
+            ));
+        }
+        if account.is_locked() && account.chargebacks() == 0 {
+            violations.push(format!("client {client}: locked without a chargeback"));
+        }
+        flows += flow;
+        totals += account.total();
+    }
+    if flows != totals {
+        violations.push(format!(
+            "all clients: deposits less withdrawals and chargebacks, plus adjustments, come to \
+             {flows} but the totals sum to {totals}"
+        ));
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(InvariantViolation { violations })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use crate::{
+        account::ClientState,
+        data::{ReasonCode, Transaction},
+        invariants::verify_invariants,
+        ledger::Ledger,
+    };
+
+    #[test]
+    fn processed_accounts_keep_the_invariants() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::withdrawal(1, 2, Decimal::ONE),
+            Transaction::adjustment(1, 3, -Decimal::TWO, ReasonCode::Goodwill),
+            Transaction::deposit(2, 4, Decimal::TEN),
+            Transaction::dispute(2, 4),
+            Transaction::chargeback(2, 4),
+            Transaction::deposit(3, 5, Decimal::ONE),
+            Transaction::dispute(3, 5),
+        ] {
+            ledger.apply(tx).unwrap();
+        }
+        verify_invariants(ledger.accounts()).unwrap();
+    }
+
+    #[test]
+    fn reports_each_violation() {
+        let restored = [
+            ClientState::restore(1, Decimal::TEN, -Decimal::ONE, true),
+            ClientState::new(2),
+        ];
+        let e = verify_invariants(&restored).unwrap_err();
+        assert_eq!(
+            e.violations,
+            vec![
+                "client 1: deposits less withdrawals and chargebacks, plus adjustments, come to \
+                 0 but the total is 9"
+                    .to_owned(),
+                "client 1: -1 held is negative".to_owned(),
+                "client 1: locked without a chargeback".to_owned(),
+                "all clients: deposits less withdrawals and chargebacks, plus adjustments, come \
+                 to 0 but the totals sum to 9"
+                    .to_owned(),
+            ]
+        );
+        assert!(e
+            .to_string()
+            .starts_with("4 ledger invariants violated\n    client 1"));
+    }
+}
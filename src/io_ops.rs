@@ -1,64 +1,1766 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    io::{self, SeekFrom},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+};
 
-use csv_async::{AsyncReader, Trim};
+use csv_async::{
+    AsyncReader, AsyncReaderBuilder, AsyncSerializer, AsyncWriter, AsyncWriterBuilder, Position,
+    StringRecord, Trim,
+};
 use futures::stream::StreamExt;
-use rust_decimal::{Decimal, RoundingStrategy};
-use tokio::{fs::File, sync::mpsc::UnboundedSender};
+use rust_decimal::Decimal;
+use tokio::{
+    fs::File,
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite,
+        AsyncWriteExt, BufReader, BufWriter, ReadBuf,
+    },
+    sync::mpsc::{self, UnboundedReceiver},
+    task::JoinHandle,
+    time::{sleep, Sleep},
+};
+use tracing::warn;
 
-use crate::{account::ClientState, data::Transaction};
+use crate::{
+    account::{AccountSummary, AccountUpdate, ClientState, HighRiskAccount, SummaryColumns},
+    data::{LockedAccount, OpenDispute, Sequenced, Transaction},
+    pseudonym::{ClientLabel, Pseudonymizer},
+    retry::{rejection_reason, DeadLetter, RetryPolicy},
+    source::EventSource,
+    warning::SkippedRecord,
+};
 
+/// Records a chunk reader may parse ahead of the dispatcher before it waits
+const CHUNK_BUFFER: usize = 100_000;
+/// Most events a CSV source yields per batch, bar the fast path's
+pub(crate) const SOURCE_BATCH: usize = 1024;
+/// Bytes read at a time by the fast path
+const FAST_READ_SIZE: usize = 1 << 20;
+/// Finished accounts a worker may hand to a partition's writer before it waits
+const ACCOUNT_BUFFER: usize = 1024;
+/// Bytes of accounts held back before they are written to the output
+const OUTPUT_BUFFER: usize = 1 << 16;
+/// Accounts written between flushes of the output, so its reader sees the accounts arrive
+const FLUSH_EVERY: usize = 10_000;
+
+/// How the input CSV is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvFormat {
+    /// Without a header row the columns are assumed to be in `Transaction::HEADER` order
+    pub has_header: bool,
+    pub delimiter: u8,
+    pub quote: u8,
+    /// When disabled quote characters are read as part of the field
+    pub quoting: bool,
+    /// Records which are not transactions are skipped and counted rather than failing the
+    /// input
+    pub lenient: bool,
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        Self {
+            has_header: true,
+            delimiter: b',',
+            quote: b'"',
+            quoting: true,
+            lenient: false,
+        }
+    }
+}
+
+impl CsvFormat {
+    /// Records of any length are read, callers skip those without as many fields as the
+    /// input has columns, see `record_width`. Otherwise the first record of a chunk or a
+    /// headerless input would set the length every later record is held to.
+    fn reader_builder(self) -> AsyncReaderBuilder {
+        let mut builder = AsyncReaderBuilder::new();
+        builder
+            .flexible(true)
+            .trim(Trim::All)
+            .has_headers(self.has_header)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .quoting(self.quoting);
+        builder
+    }
+
+    /// `parsed`, or with `lenient` nothing for a record which is not a transaction, which
+    /// is logged and counted in `malformed`
+    ///
+    /// # Errors
+    /// If the record could not be read, or is not a transaction and `lenient` is not set
+    pub(crate) fn tolerate<T>(
+        self,
+        parsed: csv_async::Result<T>,
+        malformed: &AtomicU64,
+    ) -> csv_async::Result<Option<T>> {
+        match parsed {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(e) if self.lenient && !matches!(e.kind(), csv_async::ErrorKind::Io(_)) => {
+                warn!("Skipped a malformed record: {}", e);
+                malformed.fetch_add(1, Ordering::Relaxed);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Errors which may succeed when retried, e.g. a network filesystem or object store which
+/// timed out or dropped its connection
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::StaleNetworkFileHandle
+    )
+}
+
+/// Delay before retry `attempt`, doubled on each subsequent attempt
+fn backoff(policy: RetryPolicy, attempt: u32) -> std::time::Duration {
+    policy.backoff.saturating_mul(1 << attempt.min(16))
+}
+
+/// Retries reads and writes of `T` failing with a transient error up to `policy.retries`
+/// times in a row, waiting with exponential backoff in between. Seeks are passed through.
+#[derive(Debug)]
+pub struct RetryingIo<T> {
+    inner: T,
+    policy: RetryPolicy,
+    /// Consecutive transient failures of the current operation
+    attempt: u32,
+    backoff: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> RetryingIo<T> {
+    pub fn new(inner: T, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            attempt: 0,
+            backoff: None,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Unpin> RetryingIo<T> {
+    fn poll_retrying<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut operation: impl FnMut(Pin<&mut T>, &mut Context<'_>) -> Poll<io::Result<R>>,
+    ) -> Poll<io::Result<R>> {
+        loop {
+            if let Some(backoff) = &mut self.backoff {
+                ready!(backoff.as_mut().poll(cx));
+                self.backoff = None;
+            }
+            match ready!(operation(Pin::new(&mut self.inner), cx)) {
+                Err(e) if is_transient(&e) && self.attempt < self.policy.retries => {
+                    warn!("Retrying IO after transient error `{}`", e);
+                    self.backoff = Some(Box::pin(sleep(backoff(self.policy, self.attempt))));
+                    self.attempt += 1;
+                }
+                result => {
+                    self.attempt = 0;
+                    return Poll::Ready(result);
+                }
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for RetryingIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .poll_retrying(cx, |inner, cx| inner.poll_read(cx, buf))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for RetryingIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_retrying(cx, |inner, cx| inner.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_retrying(cx, AsyncWrite::poll_flush)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_retrying(cx, AsyncWrite::poll_shutdown)
+    }
+}
+
+impl<T: AsyncSeek + Unpin> AsyncSeek for RetryingIo<T> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.get_mut().inner).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.get_mut().inner).poll_complete(cx)
+    }
+}
+
+/// Opens `file_path` for reading, retrying transient failures per `policy` as its reads are
+///
+/// # Errors
+/// If the file cannot be opened within the retries
+pub async fn open_retrying(file_path: &str, policy: RetryPolicy) -> io::Result<RetryingIo<File>> {
+    let mut attempt = 0;
+    loop {
+        match File::open(file_path).await {
+            Ok(file) => return Ok(RetryingIo::new(file, policy)),
+            Err(e) if is_transient(&e) && attempt < policy.retries => {
+                warn!(
+                    "Retrying opening {} after transient error `{}`",
+                    file_path, e
+                );
+                sleep(backoff(policy, attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Creates `file_path` for writing, retrying transient failures per `policy` as its writes are
+///
+/// # Errors
+/// If the file cannot be created within the retries
+pub async fn create_retrying(file_path: &str, policy: RetryPolicy) -> io::Result<RetryingIo<File>> {
+    let mut attempt = 0;
+    loop {
+        match File::create(file_path).await {
+            Ok(file) => return Ok(RetryingIo::new(file, policy)),
+            Err(e) if is_transient(&e) && attempt < policy.retries => {
+                warn!(
+                    "Retrying creating {} after transient error `{}`",
+                    file_path, e
+                );
+                sleep(backoff(policy, attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Creates `file_path` and writes `contents` to it, waiting until they reached the file
+///
+/// # Errors
+/// If the file cannot be created or written within the retries
+pub async fn write_file_retrying(
+    file_path: &str,
+    contents: &[u8],
+    policy: RetryPolicy,
+) -> io::Result<()> {
+    let mut file = create_retrying(file_path, policy).await?;
+    file.write_all(contents).await?;
+    file.shutdown().await
+}
+
+/// Writes out the records `writer` buffered and shuts down the writer under it, which for a
+/// file waits until the last write reached it. Dropping the writer instead would swallow an
+/// error of that write, and the process could exit before a file received it.
+///
+/// # Errors
+/// If the buffered records or the shutdown cannot be written
+pub async fn finish_csv<W: AsyncWrite + Unpin>(writer: AsyncWriter<W>) -> io::Result<()> {
+    let mut inner = writer
+        .into_inner()
+        .await
+        .map_err(|e| io::Error::new(e.error().kind(), e.error().to_string()))?;
+    inner.shutdown().await
+}
+
+/// `finish_csv` for a writer of serialized records
+///
+/// # Errors
+/// If the buffered records or the shutdown cannot be written
+pub async fn finish_serializer<W: AsyncWrite + Unpin>(
+    writer: AsyncSerializer<W>,
+) -> io::Result<()> {
+    let mut inner = writer
+        .into_inner()
+        .await
+        .map_err(|e| io::Error::new(e.error().kind(), e.error().to_string()))?;
+    inner.shutdown().await
+}
+
+/// # Errors
+/// If the `file_path` provided does not exist or its header is not the expected columns
+pub async fn async_read_csv(
+    file_path: &str,
+    format: CsvFormat,
+    io_retry: RetryPolicy,
+) -> anyhow::Result<AsyncReader<RetryingIo<File>>> {
+    let file = open_retrying(file_path, io_retry).await?;
+    let mut reader = format.reader_builder().create_reader(file);
+    if format.has_header {
+        check_header(reader.headers().await?)?;
+    }
+    Ok(reader)
+}
+
+/// Records are deserialised by position, so the columns must start with those of
+/// `Transaction::HEADER`. Any further columns, e.g. a merchant or reference, are ignored.
+pub(crate) fn check_header(header: &StringRecord) -> anyhow::Result<()> {
+    if !header
+        .iter()
+        .take(Transaction::HEADER.len())
+        .eq(Transaction::HEADER)
+    {
+        return Err(UnexpectedHeader {
+            found: header.iter().collect::<Vec<_>>().join(","),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// The header of an input is not the expected columns
+#[derive(Debug)]
+pub struct UnexpectedHeader {
+    pub found: String,
+}
+
+impl fmt::Display for UnexpectedHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Expected columns `{}`, found `{}`",
+            Transaction::HEADER.join(","),
+            self.found
+        )
+    }
+}
+
+impl std::error::Error for UnexpectedHeader {}
+
+/// Fields of every record of `reader`: as many as its header has columns, or the four of
+/// `Transaction::HEADER` without a header
+async fn record_width(
+    reader: &mut AsyncReader<RetryingIo<File>>,
+    format: CsvFormat,
+) -> anyhow::Result<usize> {
+    if format.has_header {
+        Ok(reader.headers().await?.len())
+    } else {
+        Ok(Transaction::HEADER.len())
+    }
+}
+
+/// Deserialises the `Transaction::HEADER` fields of a record, ignoring any further columns
+///
+/// # Errors
+/// If the fields are not a transaction
+pub fn read_transaction(record: &StringRecord) -> csv_async::Result<Transaction> {
+    if record.len() <= Transaction::HEADER.len() {
+        return record.deserialize(None);
+    }
+    let mut fields = record.clone();
+    fields.truncate(Transaction::HEADER.len());
+    fields.deserialize(None)
+}
+
+/// Reads the transactions of an input held in memory as `CsvSource` reads a file, polling
+/// the CSV reader on the calling thread so no runtime is needed
+///
+/// # Errors
+/// If the header is not the expected columns or a record cannot be deserialised, unless
+/// `format.lenient` skips it
+pub fn read_transactions_blocking(
+    contents: &[u8],
+    format: CsvFormat,
+) -> anyhow::Result<Vec<Transaction>> {
+    futures::executor::block_on(async {
+        let mut reader = format.reader_builder().create_reader(contents);
+        let width = if format.has_header {
+            let header = reader.headers().await?;
+            check_header(header)?;
+            header.len()
+        } else {
+            Transaction::HEADER.len()
+        };
+        let (mut transactions, malformed) = (Vec::new(), AtomicU64::new(0));
+        let mut records = reader.records();
+        while let Some(record) = records.next().await {
+            match record {
+                Ok(record) if record.len() == width => {
+                    transactions.extend(format.tolerate(read_transaction(&record), &malformed)?);
+                }
+                _ => {}
+            }
+        }
+        let malformed = malformed.into_inner();
+        if malformed > 0 {
+            warn!("Skipped {} malformed records", malformed);
+        }
+        Ok(transactions)
+    })
+}
+
+/// Deserialises a record, tagging it with its absolute byte offset in the input file.
+/// `None` for a record `format.lenient` skips.
+fn sequence_record(
+    record: &StringRecord,
+    offset: u64,
+    format: CsvFormat,
+    malformed: &AtomicU64,
+) -> anyhow::Result<Option<Sequenced>> {
+    let tx = format.tolerate(read_transaction(record), malformed)?;
+    Ok(tx.map(|tx| Sequenced {
+        seq: offset + record.position().map_or(0, Position::byte),
+        tx,
+    }))
+}
+
+/// Records read by the CSV reader
+pub struct CsvSource {
+    reader: AsyncReader<RetryingIo<File>>,
+    format: CsvFormat,
+    width: usize,
+    record: StringRecord,
+    malformed: AtomicU64,
+}
+
+impl CsvSource {
+    /// # Errors
+    /// If the file does not exist or its header is not the expected columns
+    pub async fn open(
+        file_path: &str,
+        format: CsvFormat,
+        io_retry: RetryPolicy,
+    ) -> anyhow::Result<Self> {
+        let mut reader = async_read_csv(file_path, format, io_retry).await?;
+        let width = record_width(&mut reader, format).await?;
+        Ok(Self {
+            reader,
+            format,
+            width,
+            record: StringRecord::new(),
+            malformed: AtomicU64::new(0),
+        })
+    }
+}
+
+impl EventSource for CsvSource {
+    async fn next_batch(&mut self) -> anyhow::Result<Vec<Sequenced>> {
+        let mut batch = Vec::new();
+        while batch.len() < SOURCE_BATCH {
+            match self.reader.read_record(&mut self.record).await {
+                Ok(false) => break,
+                Ok(true) if self.record.len() == self.width => {
+                    let event = sequence_record(&self.record, 0, self.format, &self.malformed)?;
+                    batch.extend(event);
+                }
+                _ => {}
+            }
+        }
+        Ok(batch)
+    }
+
+    fn malformed(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+}
+
+/// Reads the fixed `type,client,tx,amount` schema without the CSV reader, splitting plain
+/// lines on the delimiter. Lines with quotes or values the fast path does not recognise are
+/// parsed by the CSV reader, so the events are the same as those of `CsvSource`.
+pub struct FastSource {
+    file: RetryingIo<File>,
+    format: CsvFormat,
+    width: usize,
+    /// Read but not yet parsed, starting at a line
+    buffer: Vec<u8>,
+    /// Offset of the start of `buffer` in the file
+    offset: u64,
+    /// The header line is still to be skipped
+    header: bool,
+    ended: bool,
+    malformed: AtomicU64,
+}
+
+impl FastSource {
+    /// # Errors
+    /// If the file does not exist or its header is not the expected columns
+    pub async fn open(
+        file_path: &str,
+        format: CsvFormat,
+        io_retry: RetryPolicy,
+    ) -> anyhow::Result<Self> {
+        let width = record_width(
+            &mut async_read_csv(file_path, format, io_retry).await?,
+            format,
+        )
+        .await?;
+        Ok(Self {
+            file: open_retrying(file_path, io_retry).await?,
+            format,
+            width,
+            buffer: Vec::new(),
+            offset: 0,
+            header: format.has_header,
+            ended: false,
+            malformed: AtomicU64::new(0),
+        })
+    }
+}
+
+impl EventSource for FastSource {
+    /// The records of the complete lines of the next `FAST_READ_SIZE` bytes, or more if
+    /// those hold none
+    async fn next_batch(&mut self) -> anyhow::Result<Vec<Sequenced>> {
+        let mut batch = Vec::new();
+        while batch.is_empty() && !self.ended {
+            let start = self.buffer.len();
+            self.buffer.resize(start + FAST_READ_SIZE, 0);
+            let read = self.file.read(&mut self.buffer[start..]).await?;
+            self.buffer.truncate(start + read);
+            self.ended = read == 0;
+
+            // Only complete lines are parsed, unless the file has ended
+            let parsed = match self.buffer.iter().rposition(|b| *b == b'\n') {
+                Some(end) if !self.ended => end + 1,
+                _ if self.ended => self.buffer.len(),
+                _ => continue,
+            };
+            for line in self.buffer[..parsed].split_inclusive(|b| *b == b'\n') {
+                if std::mem::take(&mut self.header) {
+                    self.offset += line.len() as u64;
+                    continue;
+                }
+                let tx = match parse_plain_record(line, self.format, self.width) {
+                    Some(tx) => Some(tx),
+                    None => parse_record(line, self.format, self.width, &self.malformed).await?,
+                };
+                if let Some(tx) = tx {
+                    batch.push(Sequenced {
+                        seq: self.offset,
+                        tx,
+                    });
+                }
+                self.offset += line.len() as u64;
+            }
+            self.buffer.drain(..parsed);
+        }
+        Ok(batch)
+    }
+
+    fn malformed(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+}
+
+/// A record of `width` unquoted fields, or `None` to leave the line to the CSV reader
+fn parse_plain_record(line: &[u8], format: CsvFormat, width: usize) -> Option<Transaction> {
+    if format.quoting && line.contains(&format.quote) {
+        return None;
+    }
+    let mut fields = line
+        .split(|b| *b == format.delimiter)
+        .map(<[u8]>::trim_ascii);
+    let (tx_type, client, tx, amount) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    if fields.count() != width - Transaction::HEADER.len() {
+        return None;
+    }
+
+    let (client, tx) = (parse_field(client)?, parse_field(tx)?);
+    match (tx_type, amount) {
+        (b"deposit", amount) => Some(Transaction::deposit(client, tx, parse_amount(amount)?)),
+        (b"withdrawal", amount) => Some(Transaction::withdrawal(client, tx, parse_amount(amount)?)),
+        (b"dispute", b"") => Some(Transaction::dispute(client, tx)),
+        (b"resolve", b"") => Some(Transaction::resolve(client, tx)),
+        (b"chargeback", b"") => Some(Transaction::chargeback(client, tx)),
+        (b"reversal", b"") => Some(Transaction::reversal(client, tx)),
+        _ => None,
+    }
+}
+
+fn parse_field<T: std::str::FromStr>(field: &[u8]) -> Option<T> {
+    std::str::from_utf8(field).ok()?.parse().ok()
+}
+
+/// Mirrors how the CSV reader infers a number before handing it to `Decimal`, which goes
+/// through `f64` for anything with a fraction
+fn parse_amount(field: &[u8]) -> Option<Decimal> {
+    let field = std::str::from_utf8(field).ok()?;
+    if let Ok(amount) = field.parse::<u64>() {
+        return Some(amount.into());
+    } else if let Ok(amount) = field.parse::<i64>() {
+        return Some(amount.into());
+    } else if field.parse::<i128>().is_ok() || field.parse::<u128>().is_ok() {
+        return None;
+    }
+    field.parse::<f64>().ok()?.to_string().parse().ok()
+}
+
+/// Parses a single line with the CSV reader, skipping it as `CsvSource` would
+async fn parse_record(
+    line: &[u8],
+    format: CsvFormat,
+    width: usize,
+    malformed: &AtomicU64,
+) -> anyhow::Result<Option<Transaction>> {
+    let mut reader = format
+        .reader_builder()
+        .has_headers(false)
+        .create_reader(line);
+    let record = reader.records().next().await;
+    match record {
+        Some(core::result::Result::Ok(record)) if record.len() == width => {
+            Ok(format.tolerate(read_transaction(&record), malformed)?)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Offset just past the first newline at or after `pos`, or the end of the file
+async fn next_line_start(file: &mut RetryingIo<File>, pos: u64, len: u64) -> anyhow::Result<u64> {
+    file.seek(SeekFrom::Start(pos)).await?;
+    let mut line = Vec::new();
+    let read = BufReader::new(file).read_until(b'\n', &mut line).await?;
+    Ok((pos + read as u64).min(len))
+}
+
+/// Splits the records of the CSV (everything after the header) into at most `chunks`
+/// byte ranges, each starting at the beginning of a line so no record is split
+/// between two readers. Assumes no quoted field spans multiple lines.
+///
 /// # Errors
 /// If the `file_path` provided does not exist
-pub async fn async_read_csv(file_path: &str) -> anyhow::Result<AsyncReader<File>> {
-    let file = File::open(file_path).await?;
-    Ok(csv_async::AsyncReaderBuilder::new()
-        .trim(Trim::All)
-        .create_reader(file))
+pub async fn chunk_ranges(
+    file_path: &str,
+    chunks: usize,
+    format: CsvFormat,
+    io_retry: RetryPolicy,
+) -> anyhow::Result<Vec<(u64, u64)>> {
+    let mut file = open_retrying(file_path, io_retry).await?;
+    let len = file.inner.metadata().await?.len();
+    let body_start = if format.has_header {
+        next_line_start(&mut file, 0, len).await?
+    } else {
+        0
+    };
+    let span = (len - body_start) / chunks.max(1) as u64;
+
+    let mut starts = vec![body_start];
+    let mut last_start = body_start;
+    for i in 1..chunks as u64 {
+        let start = next_line_start(&mut file, body_start + span * i, len).await?;
+        if start > last_start && start < len {
+            starts.push(start);
+            last_start = start;
+        }
+    }
+
+    let ends = starts.iter().skip(1).copied().chain([len]);
+    Ok(starts.iter().copied().zip(ends).collect())
 }
 
-pub async fn partition_csv_events(
-    mut reader: AsyncReader<File>,
-    event_senders: Vec<UnboundedSender<Transaction>>,
-    num: usize,
+async fn read_chunk(
+    file_path: String,
+    (start, end): (u64, u64),
+    format: CsvFormat,
+    width: usize,
+    io_retry: RetryPolicy,
+    sender: mpsc::Sender<Sequenced>,
+    malformed: Arc<AtomicU64>,
 ) -> anyhow::Result<()> {
+    let mut file = open_retrying(&file_path, io_retry).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut reader = format
+        .reader_builder()
+        .has_headers(false)
+        .create_reader(file.take(end - start));
+
     let mut records = reader.records();
     while let Some(record) = records.next().await {
-        if let core::result::Result::Ok(record) = record {
-            let tx = record.deserialize::<Transaction>(None)?;
-            event_senders[tx.client_id() as usize % num]
-                .send(tx)
-                .unwrap();
+        let event = match record {
+            Ok(record) if record.len() == width => {
+                match sequence_record(&record, start, format, &malformed)? {
+                    Some(event) => event,
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        if sender.send(event).await.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Byte ranges of the file parsed concurrently, their events yielded strictly in file order
+/// so each client's transactions keep their sequence
+pub struct ChunkedSource {
+    /// The events of each range in order, and the task reading them
+    chunks: VecDeque<(mpsc::Receiver<Sequenced>, JoinHandle<anyhow::Result<()>>)>,
+    /// Shared by the tasks reading the ranges
+    malformed: Arc<AtomicU64>,
+}
+
+impl ChunkedSource {
+    /// Starts parsing at most `readers` ranges of the file
+    ///
+    /// # Errors
+    /// If the file does not exist or its header is not the expected columns
+    pub async fn open(
+        file_path: &str,
+        readers: usize,
+        format: CsvFormat,
+        io_retry: RetryPolicy,
+    ) -> anyhow::Result<Self> {
+        let width = record_width(
+            &mut async_read_csv(file_path, format, io_retry).await?,
+            format,
+        )
+        .await?;
+
+        let mut chunks = VecDeque::with_capacity(readers);
+        let malformed = Arc::new(AtomicU64::new(0));
+        for range in chunk_ranges(file_path, readers, format, io_retry).await? {
+            let (sender, receiver) = mpsc::channel(CHUNK_BUFFER);
+            let reader = tokio::spawn(read_chunk(
+                file_path.to_owned(),
+                range,
+                format,
+                width,
+                io_retry,
+                sender,
+                Arc::clone(&malformed),
+            ));
+            chunks.push_back((receiver, reader));
+        }
+        Ok(Self { chunks, malformed })
+    }
+}
+
+impl EventSource for ChunkedSource {
+    async fn next_batch(&mut self) -> anyhow::Result<Vec<Sequenced>> {
+        while let Some((receiver, reader)) = self.chunks.front_mut() {
+            if let Some(event) = receiver.recv().await {
+                let mut batch = vec![event];
+                while batch.len() < SOURCE_BATCH {
+                    match receiver.try_recv() {
+                        Ok(event) => batch.push(event),
+                        Err(_) => break,
+                    }
+                }
+                return Ok(batch);
+            }
+            reader.await??;
+            self.chunks.pop_front();
         }
+        Ok(Vec::new())
+    }
+
+    fn malformed(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+}
+
+/// Writes transactions which exhausted their retries to `file_path` as they arrive. With
+/// `pseudonyms`, errors are written as their `rejection_reason`, without any ids.
+///
+/// # Errors
+/// If the dead-letter file cannot be created or written
+pub async fn write_dead_letters(
+    file_path: String,
+    io_retry: RetryPolicy,
+    mut dead_letters: UnboundedReceiver<DeadLetter>,
+    pseudonyms: Option<Pseudonymizer>,
+) -> anyhow::Result<()> {
+    let file = create_retrying(&file_path, io_retry).await?;
+    let mut writer = csv_async::AsyncWriter::from_writer(file);
+    writer
+        .write_record(&["type", "client", "tx", "amount", "seq", "error"])
+        .await?;
+
+    while let Some(DeadLetter { event, reason }) = dead_letters.recv().await {
+        let client = ClientLabel::new(event.tx.client_id(), pseudonyms.as_ref());
+        let reason = match pseudonyms {
+            Some(_) => rejection_reason(&reason),
+            None => reason,
+        };
+        writer
+            .write_record(&[
+                event.tx.tx_type().to_string(),
+                client.to_string(),
+                event.tx.tx_id().to_string(),
+                event.tx.amount().map(|a| a.to_string()).unwrap_or_default(),
+                event.seq.to_string(),
+                reason,
+            ])
+            .await?;
     }
+    finish_csv(writer).await?;
 
     Ok(())
 }
 
-fn round_decimal(v: Decimal) -> String {
-    v.round_dp_with_strategy(4, RoundingStrategy::MidpointAwayFromZero)
-        .to_string()
+/// Writes records skipped with a warning to `file_path` as they arrive, with the kind of
+/// warning. With `pseudonyms`, messages are written as their `rejection_reason`.
+///
+/// # Errors
+/// If the warnings file cannot be created or written
+pub async fn write_warnings(
+    file_path: String,
+    io_retry: RetryPolicy,
+    mut skipped: UnboundedReceiver<SkippedRecord>,
+    pseudonyms: Option<Pseudonymizer>,
+) -> anyhow::Result<()> {
+    let file = create_retrying(&file_path, io_retry).await?;
+    let mut writer = csv_async::AsyncWriter::from_writer(file);
+    writer
+        .write_record(&[
+            "type", "client", "tx", "amount", "seq", "warning", "message",
+        ])
+        .await?;
+
+    while let Some(SkippedRecord { event, warning }) = skipped.recv().await {
+        let client = ClientLabel::new(event.tx.client_id(), pseudonyms.as_ref());
+        let message = match pseudonyms {
+            Some(_) => rejection_reason(&warning.message),
+            None => warning.message,
+        };
+        writer
+            .write_record(&[
+                event.tx.tx_type().to_string(),
+                client.to_string(),
+                event.tx.tx_id().to_string(),
+                event.tx.amount().map(|a| a.to_string()).unwrap_or_default(),
+                event.seq.to_string(),
+                warning.kind.to_string(),
+                message,
+            ])
+            .await?;
+    }
+    finish_csv(writer).await?;
+
+    Ok(())
 }
 
 #[allow(clippy::implicit_hasher)]
 /// # Errors
 /// Can fail to write to `stdout`
-pub async fn display_results(results: HashMap<u16, ClientState>) -> anyhow::Result<()> {
-    let mut writer = csv_async::AsyncWriter::from_writer(tokio::io::stdout());
+pub async fn display_results(
+    results: HashMap<u16, ClientState>,
+    columns: SummaryColumns,
+    sorted: bool,
+    pseudonyms: Option<&Pseudonymizer>,
+) -> anyhow::Result<()> {
+    write_results(results, columns, sorted, pseudonyms, tokio::io::stdout()).await
+}
+
+#[allow(clippy::implicit_hasher)]
+/// Writes every account, in client id order if `sorted` and in no particular order otherwise,
+/// with the client's pseudonym in place of its id if `pseudonyms` are enabled
+///
+/// # Errors
+/// Can fail to write to `writer`
+pub async fn write_results<W: AsyncWrite + Unpin>(
+    results: HashMap<u16, ClientState>,
+    columns: SummaryColumns,
+    sorted: bool,
+    pseudonyms: Option<&Pseudonymizer>,
+    writer: W,
+) -> anyhow::Result<()> {
+    let mut writer = account_serializer(writer);
+    writer.serialize(AccountSummary::header(columns)).await?;
+
+    let mut clients: Vec<_> = results.into_values().collect();
+    if sorted {
+        clients.sort_unstable_by_key(ClientState::id);
+    }
+    for (i, client) in clients.into_iter().enumerate() {
+        let summary = AccountSummary::with_columns(&client, columns).pseudonymized(pseudonyms);
+        writer.serialize(summary).await?;
+        if (i + 1) % FLUSH_EVERY == 0 {
+            writer.flush().await?;
+        }
+    }
+    finish_serializer(writer).await?;
+
+    Ok(())
+}
+
+/// Serializes accounts to `writer` through a buffer of `OUTPUT_BUFFER` bytes. Writing waits
+/// whenever the buffer is full until `writer` takes it, so a slow reader of the output holds
+/// the accounts back rather than them piling up in memory.
+fn account_serializer<W: AsyncWrite + Unpin>(writer: W) -> AsyncSerializer<BufWriter<W>> {
+    AsyncWriterBuilder::new()
+        .has_headers(false)
+        .create_serializer(BufWriter::with_capacity(OUTPUT_BUFFER, writer))
+}
+
+/// Index of the partition holding `client_id` when the client ids are split into
+/// `partitions` contiguous ranges of equal width
+pub fn client_partition(client_id: u16, partitions: usize) -> usize {
+    (usize::from(client_id) * partitions) >> 16
+}
+
+/// Splits the accounts into `partitions` ranges of client ids, as `client_partition` does
+#[allow(clippy::implicit_hasher)]
+pub fn partition_by_client(
+    results: HashMap<u16, ClientState>,
+    partitions: usize,
+) -> Vec<HashMap<u16, ClientState>> {
+    let mut split: Vec<_> = (0..partitions).map(|_| HashMap::new()).collect();
+    for (client_id, state) in results {
+        split[client_partition(client_id, partitions)].insert(client_id, state);
+    }
+    split
+}
+
+/// Hands the accounts finished by the workers to one writer per range of client ids, as
+/// `client_partition` splits them
+#[derive(Debug, Clone)]
+pub struct AccountSink {
+    partitions: Vec<mpsc::Sender<ClientState>>,
+}
+
+impl AccountSink {
+    /// A sink over `partitions` channels, along with the receiving end of each for
+    /// `stream_results` to write
+    pub fn new(partitions: usize) -> (Self, Vec<mpsc::Receiver<ClientState>>) {
+        let (partitions, receivers) = (0..partitions)
+            .map(|_| mpsc::channel(ACCOUNT_BUFFER))
+            .unzip();
+        (Self { partitions }, receivers)
+    }
+
+    /// Waits for room in the channel of the account's partition
+    ///
+    /// # Errors
+    /// Hands the account back if the partition's writer stopped
+    pub async fn send(&self, state: ClientState) -> Result<(), ClientState> {
+        let partition = client_partition(state.id(), self.partitions.len());
+        self.partitions[partition]
+            .send(state)
+            .await
+            .map_err(|e| e.0)
+    }
+}
+
+/// Writes every account to `writer` as it arrives, in no particular order, pseudonymized as
+/// by `write_results`
+///
+/// # Errors
+/// Can fail to write to `writer`
+pub async fn stream_results<W: AsyncWrite + Unpin>(
+    mut results: mpsc::Receiver<ClientState>,
+    columns: SummaryColumns,
+    pseudonyms: Option<Pseudonymizer>,
+    writer: W,
+) -> anyhow::Result<()> {
+    let mut writer = account_serializer(writer);
+    writer.serialize(AccountSummary::header(columns)).await?;
+    let mut written = 0;
+    while let Some(client) = results.recv().await {
+        let summary =
+            AccountSummary::with_columns(&client, columns).pseudonymized(pseudonyms.as_ref());
+        writer.serialize(summary).await?;
+        written += 1;
+        if written % FLUSH_EVERY == 0 {
+            writer.flush().await?;
+        }
+    }
+    finish_serializer(writer).await?;
+
+    Ok(())
+}
+
+/// Writes every account update to `writer` as it arrives
+///
+/// # Errors
+/// Can fail to write to `writer`
+pub async fn write_account_updates<W: AsyncWrite + Unpin>(
+    mut updates: UnboundedReceiver<AccountUpdate>,
+    pseudonyms: Option<Pseudonymizer>,
+    writer: W,
+) -> anyhow::Result<()> {
+    let mut writer = AsyncWriterBuilder::new()
+        .has_headers(false)
+        .create_serializer(writer);
+    writer.serialize(AccountUpdate::HEADER).await?;
+    while let Some(update) = updates.recv().await {
+        writer
+            .serialize(update.pseudonymized(pseudonyms.as_ref()))
+            .await?;
+    }
+    finish_serializer(writer).await?;
+
+    Ok(())
+}
+
+/// Writes the accounts whose chargeback ratio exceeds `chargeback_limit`, in client order
+///
+/// # Errors
+/// Can fail to write to `writer`
+#[allow(clippy::implicit_hasher)]
+pub async fn write_high_risk<W: AsyncWrite + Unpin>(
+    results: &HashMap<u16, ClientState>,
+    chargeback_limit: Decimal,
+    pseudonyms: Option<&Pseudonymizer>,
+    writer: W,
+) -> anyhow::Result<()> {
+    let mut high_risk: Vec<_> = results
+        .values()
+        .filter(|client| client.is_high_risk(chargeback_limit))
+        .collect();
+    high_risk.sort_unstable_by_key(|client| client.id());
+
+    let mut writer = AsyncWriterBuilder::new()
+        .has_headers(false)
+        .create_serializer(writer);
+    writer.serialize(HighRiskAccount::HEADER).await?;
+    for client in high_risk {
+        let account = HighRiskAccount::from(client).pseudonymized(pseudonyms);
+        writer.serialize(account).await?;
+    }
+    finish_serializer(writer).await?;
+
+    Ok(())
+}
+
+/// Writes open disputes in the order given, with the sequence number which opened each,
+/// i.e. the byte offset of its record in the input
+///
+/// # Errors
+/// Can fail to write to `writer`
+pub async fn write_open_disputes<W: AsyncWrite + Unpin>(
+    disputes: &[OpenDispute],
+    pseudonyms: Option<&Pseudonymizer>,
+    writer: W,
+) -> anyhow::Result<()> {
+    let header = ["client", "tx", "held", "seq"];
+    write_disputes(header, disputes, pseudonyms, writer).await
+}
+
+/// Writes a row per amount held by a dispute, for the `held` column of the accounts to be
+/// tied back to the disputes making it up
+///
+/// # Errors
+/// Can fail to write to `writer`
+pub async fn write_holds<W: AsyncWrite + Unpin>(
+    holds: &[OpenDispute],
+    pseudonyms: Option<&Pseudonymizer>,
+    writer: W,
+) -> anyhow::Result<()> {
+    let header = ["client", "tx", "amount", "disputed_seq"];
+    write_disputes(header, holds, pseudonyms, writer).await
+}
+
+/// Writes a row per locked account with the chargeback which locked it and the balances
+/// just before it. The chargeback columns are empty for an account locked by an earlier
+/// run.
+///
+/// # Errors
+/// Can fail to write to `writer`
+pub async fn write_locked_accounts<W: AsyncWrite + Unpin>(
+    locked: &[LockedAccount],
+    pseudonyms: Option<&Pseudonymizer>,
+    writer: W,
+) -> anyhow::Result<()> {
+    let mut writer = csv_async::AsyncWriter::from_writer(writer);
     writer
-        .write_record(&["client", "available", "held", "total", "locked"])
+        .write_record(&[
+            "client",
+            "tx",
+            "amount",
+            "available_before",
+            "held_before",
+            "total_before",
+        ])
         .await?;
+    for account in locked {
+        let client = ClientLabel::new(account.client_id, pseudonyms).to_string();
+        let chargeback = match account.chargeback {
+            Some(chargeback) => [
+                chargeback.tx_id.to_string(),
+                chargeback.amount.to_string(),
+                chargeback.available.to_string(),
+                chargeback.held.to_string(),
+                chargeback.total.to_string(),
+            ],
+            None => Default::default(),
+        };
+        writer
+            .write_record(std::iter::once(client).chain(chargeback))
+            .await?;
+    }
+    finish_csv(writer).await?;
 
-    for (_, client) in results {
+    Ok(())
+}
+
+async fn write_disputes<W: AsyncWrite + Unpin>(
+    header: [&str; 4],
+    disputes: &[OpenDispute],
+    pseudonyms: Option<&Pseudonymizer>,
+    writer: W,
+) -> anyhow::Result<()> {
+    let mut writer = csv_async::AsyncWriter::from_writer(writer);
+    writer.write_record(&header).await?;
+    for dispute in disputes {
         writer
             .write_record(&[
-                client.id().to_string(),
-                round_decimal(client.available()),
-                round_decimal(client.held()),
-                round_decimal(client.total()),
-                client.is_locked().to_string(),
+                ClientLabel::new(dispute.client_id, pseudonyms).to_string(),
+                dispute.tx_id.to_string(),
+                dispute.held.to_string(),
+                dispute
+                    .opened_seq
+                    .map(|seq| seq.to_string())
+                    .unwrap_or_default(),
             ])
             .await?;
     }
+    finish_csv(writer).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fmt::Write,
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+        sync::mpsc,
+    };
+
+    use rust_decimal::Decimal;
+
+    use crate::account::{ClientState, SummaryColumns};
+    use crate::data::{LockedAccount, LockingChargeback, OpenDispute, Sequenced};
+    use crate::io_ops::{
+        async_read_csv, chunk_ranges, client_partition, partition_by_client,
+        read_transactions_blocking, stream_results, write_holds, write_locked_accounts,
+        write_results, AccountSink, ChunkedSource, CsvFormat, CsvSource, FastSource, RetryingIo,
+        FLUSH_EVERY,
+    };
+    use crate::pseudonym::Pseudonymizer;
+    use crate::retry::RetryPolicy;
+    use crate::source::EventSource;
+
+    /// Fails its first `failures` reads and writes with `kind`, then reads `ok` once and
+    /// accepts every write
+    struct Flaky {
+        failures: u32,
+        kind: io::ErrorKind,
+        read: bool,
+        written: Vec<u8>,
+    }
+
+    impl Flaky {
+        fn new(failures: u32, kind: io::ErrorKind) -> Self {
+            Self {
+                failures,
+                kind,
+                read: false,
+                written: Vec::new(),
+            }
+        }
+
+        fn fail(&mut self) -> Option<io::Error> {
+            let fail = self.failures > 0;
+            self.failures = self.failures.saturating_sub(1);
+            fail.then(|| io::Error::from(self.kind))
+        }
+    }
+
+    impl AsyncRead for Flaky {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            if let Some(e) = self.fail() {
+                return Poll::Ready(Err(e));
+            }
+            if !std::mem::replace(&mut self.read, true) {
+                buf.put_slice(b"ok");
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for Flaky {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if let Some(e) = self.fail() {
+                return Poll::Ready(Err(e));
+            }
+            self.written.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Accepts every write, noting how many bytes it had been given at each flush and at
+    /// shutdown
+    #[derive(Default)]
+    struct Flushes {
+        written: usize,
+        flushed_at: Vec<usize>,
+        shut_down_at: Option<usize>,
+    }
+
+    impl AsyncWrite for Flushes {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.written += buf.len();
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let written = self.written;
+            self.flushed_at.push(written);
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.shut_down_at = Some(self.written);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn io_retry(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_io_errors_are_retried() {
+        let mut reader = RetryingIo::new(Flaky::new(2, io::ErrorKind::TimedOut), io_retry(2));
+        let mut read = String::new();
+        reader.read_to_string(&mut read).await.unwrap();
+        assert_eq!(read, "ok");
+
+        let mut writer =
+            RetryingIo::new(Flaky::new(1, io::ErrorKind::ConnectionReset), io_retry(1));
+        writer.write_all(b"client").await.unwrap();
+        assert_eq!(writer.into_inner().written, b"client");
+    }
+
+    #[tokio::test]
+    async fn io_errors_fail_once_retries_are_exhausted_or_not_transient() {
+        let mut reader = RetryingIo::new(Flaky::new(3, io::ErrorKind::TimedOut), io_retry(2));
+        let e = reader.read_to_string(&mut String::new()).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+        let mut reader =
+            RetryingIo::new(Flaky::new(1, io::ErrorKind::PermissionDenied), io_retry(5));
+        let e = reader.read_to_string(&mut String::new()).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    /// Every event of `source`, in order
+    async fn read_all(mut source: impl EventSource) -> Vec<Sequenced> {
+        let mut events = Vec::new();
+        loop {
+            let batch = source.next_batch().await.unwrap();
+            if batch.is_empty() {
+                return events;
+            }
+            events.extend(batch);
+        }
+    }
+
+    fn write_fixture(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("effective-train-{name}.csv"));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn chunk_ranges_align_to_line_starts() {
+        let contents = "type,client,tx,amount\n\
+            deposit,1,1,1.0\n\
+            deposit,2,2,2.0\n\
+            deposit,1,3,3.0\n\
+            withdrawal,2,4,1.5\n";
+        let path = write_fixture("chunk-ranges", contents);
+
+        let ranges = chunk_ranges(&path, 3, CsvFormat::default(), RetryPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(ranges.first().unwrap().0, 22);
+        assert_eq!(ranges.last().unwrap().1, contents.len() as u64);
+        for (start, end) in &ranges {
+            assert!(start < end);
+            let start = usize::try_from(*start).unwrap();
+            assert_eq!(contents.as_bytes()[start - 1], b'\n');
+        }
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].1, pair[1].0);
+        }
+    }
+
+    #[tokio::test]
+    async fn chunked_reading_preserves_file_order() {
+        let mut contents = String::from("type,client,tx,amount\n");
+        for tx in 1..=200 {
+            writeln!(contents, "deposit,{},{tx},1.0", tx % 3).unwrap();
+        }
+        let path = write_fixture("chunked-order", &contents);
+
+        let source = ChunkedSource::open(&path, 7, CsvFormat::default(), RetryPolicy::default())
+            .await
+            .unwrap();
+
+        let (mut seen, mut last_seq) = (Vec::new(), 0);
+        for event in read_all(source).await {
+            assert!(event.seq > last_seq);
+            last_seq = event.seq;
+            seen.push(event.tx.tx_id());
+        }
+        assert_eq!(seen, (1..=200).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn misnamed_columns_are_rejected() {
+        let path = write_fixture(
+            "misnamed-header",
+            "type, client, txid, amount\ndeposit,1,1,1.0\n",
+        );
+        let result = async_read_csv(&path, CsvFormat::default(), RetryPolicy::default()).await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Expected columns `type,client,tx,amount`, found `type,client,txid,amount`"
+        );
+
+        let path = write_fixture("missing-header", "deposit,1,1,1.0\n");
+        let result =
+            ChunkedSource::open(&path, 2, CsvFormat::default(), RetryPolicy::default()).await;
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Expected columns `type,client,tx,amount`, found `deposit,1,1,1.0`"
+        );
+    }
+
+    #[tokio::test]
+    async fn headerless_files_are_read_positionally() {
+        let contents = "deposit,1,1,1.0\nwithdrawal,2,2,2.0\ndispute,1,1,\n";
+        let path = write_fixture("headerless", contents);
+        let format = CsvFormat {
+            has_header: false,
+            ..CsvFormat::default()
+        };
+
+        let source = CsvSource::open(&path, format, RetryPolicy::default())
+            .await
+            .unwrap();
+        let seen: Vec<_> = read_all(source)
+            .await
+            .iter()
+            .map(|event| (event.tx.client_id(), event.tx.tx_id()))
+            .collect();
+        assert_eq!(seen, vec![(1, 1), (2, 2), (1, 1)]);
+
+        let ranges = chunk_ranges(&path, 2, format, RetryPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(ranges.first().unwrap().0, 0);
+        let source = ChunkedSource::open(&path, 2, format, RetryPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(read_all(source).await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn lenient_sources_skip_and_count_malformed_records() {
+        let contents = "type,client,tx,amount\n\
+            deposit,1,1,1.0\n\
+            deposit,1,two,5.0\n\
+            deposit,x,3,5.0\n\
+            withdrawal,1,4,0.5\n";
+        let path = write_fixture("lenient", contents);
+        let strict = CsvSource::open(&path, CsvFormat::default(), RetryPolicy::default())
+            .await
+            .unwrap();
+        assert!(strict_fails(strict).await);
+
+        let format = CsvFormat {
+            lenient: true,
+            ..CsvFormat::default()
+        };
+        let csv = CsvSource::open(&path, format, RetryPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(read_counted(csv).await, (vec![1, 4], 2));
+        let fast = FastSource::open(&path, format, RetryPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(read_counted(fast).await, (vec![1, 4], 2));
+        let chunked = ChunkedSource::open(&path, 2, format, RetryPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(read_counted(chunked).await, (vec![1, 4], 2));
+
+        let blocking = read_transactions_blocking(contents.as_bytes(), format).unwrap();
+        assert_eq!(blocking.len(), 2);
+        assert!(read_transactions_blocking(contents.as_bytes(), CsvFormat::default()).is_err());
+    }
+
+    /// The transaction ids read and the records skipped as malformed
+    async fn read_counted(mut source: impl EventSource) -> (Vec<u32>, u64) {
+        let mut tx_ids = Vec::new();
+        loop {
+            let batch = source.next_batch().await.unwrap();
+            if batch.is_empty() {
+                return (tx_ids, source.malformed());
+            }
+            tx_ids.extend(batch.iter().map(|event| event.tx.tx_id()));
+        }
+    }
+
+    async fn strict_fails(mut source: impl EventSource) -> bool {
+        loop {
+            match source.next_batch().await {
+                Ok(batch) if batch.is_empty() => return false,
+                Ok(_) => {}
+                Err(_) => return true,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn semicolon_separated_files_are_read() {
+        let contents = "type;client;tx;amount\n'deposit';1;1;1.5\nwithdrawal;1;2;0.5\n";
+        let path = write_fixture("semicolon", contents);
+        let format = CsvFormat {
+            delimiter: b';',
+            quote: b'\'',
+            ..CsvFormat::default()
+        };
+
+        let source = CsvSource::open(&path, format, RetryPolicy::default())
+            .await
+            .unwrap();
+        let events = read_all(source).await;
+        assert_eq!(events[0].tx.amount().unwrap().to_string(), "1.5");
+        assert_eq!(events[1].tx.tx_id(), 2);
+
+        let result = async_read_csv(&path, CsvFormat::default(), RetryPolicy::default()).await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Expected columns `type,client,tx,amount`, found `type;client;tx;amount`"
+        );
+    }
+
+    #[tokio::test]
+    async fn fast_path_routes_the_same_events_as_the_csv_reader() {
+        let contents = "type,client,tx,amount\n\
+            deposit, 1, 1, 1.50\n\
+            \"withdrawal\",1,2,0.5\r\n\
+            dispute,1,1,\n\
+            deposit,2,3\n\
+            \n\
+            chargeback,1,1,  \n\
+            deposit,2,4,1e2\n\
+            resolve,2,4";
+        let path = write_fixture("fast-path", contents);
+
+        let read = |fast: bool| {
+            let path = path.clone();
+            async move {
+                let (format, retry) = (CsvFormat::default(), RetryPolicy::default());
+                let events = if fast {
+                    read_all(FastSource::open(&path, format, retry).await.unwrap()).await
+                } else {
+                    read_all(CsvSource::open(&path, format, retry).await.unwrap()).await
+                };
+                // Offsets after a `\r\n` differ by one, the CSV reader counts it as one byte
+                assert!(events.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+                events
+                    .into_iter()
+                    .map(|event| format!("{:?}", event.tx))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let expected = read(false).await;
+        assert_eq!(expected.len(), 5);
+        assert_eq!(read(true).await, expected);
+    }
+
+    #[tokio::test]
+    async fn extra_columns_are_ignored() {
+        let contents = "type,client,tx,amount,merchant,reference\n\
+            deposit,1,1,1.5,Acme,\"INV-1, INV-2\"\n\
+            withdrawal,1,2,0.5,Acme,INV-3\n\
+            deposit,1,3,2.0\n\
+            dispute,1,1,,,\n";
+        let path = write_fixture("extra-columns", contents);
+        let (format, retry) = (CsvFormat::default(), RetryPolicy::default());
+
+        let read = [
+            read_all(CsvSource::open(&path, format, retry).await.unwrap()).await,
+            read_all(FastSource::open(&path, format, retry).await.unwrap()).await,
+            read_all(ChunkedSource::open(&path, 2, format, retry).await.unwrap()).await,
+        ];
+        for events in read {
+            let seen: Vec<_> = events
+                .iter()
+                .map(|event| (event.tx.tx_type().to_string(), event.tx.tx_id()))
+                .collect();
+            assert_eq!(
+                seen,
+                [("deposit", 1), ("withdrawal", 2), ("dispute", 1)]
+                    .map(|(tx_type, tx)| (tx_type.to_owned(), tx))
+            );
+        }
+    }
+
+    #[test]
+    fn partitions_are_contiguous_client_ranges() {
+        assert_eq!(client_partition(0, 4), 0);
+        assert_eq!(client_partition(16_383, 4), 0);
+        assert_eq!(client_partition(16_384, 4), 1);
+        assert_eq!(client_partition(u16::MAX, 4), 3);
+        assert_eq!(client_partition(u16::MAX, 1), 0);
+        assert_eq!(client_partition(u16::MAX, 1 << 16), usize::from(u16::MAX));
+
+        let results = [1, 30_000, 40_000, 65_000]
+            .map(|client_id| (client_id, ClientState::new(client_id)))
+            .into();
+        let split = partition_by_client(results, 3);
+        let clients: Vec<Vec<u16>> = split
+            .iter()
+            .map(|part| {
+                let mut clients: Vec<_> = part.keys().copied().collect();
+                clients.sort_unstable();
+                clients
+            })
+            .collect();
+        assert_eq!(clients, vec![vec![1], vec![30_000, 40_000], vec![65_000]]);
+    }
+
+    #[tokio::test]
+    async fn sorted_results_are_in_client_order() {
+        let results = (1..=20)
+            .rev()
+            .map(|id| (id, ClientState::new(id)))
+            .collect();
+        let mut output = Vec::new();
+        write_results(results, SummaryColumns::default(), true, None, &mut output)
+            .await
+            .unwrap();
+
+        let clients: Vec<_> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().parse::<u16>().unwrap())
+            .collect();
+        assert_eq!(clients, (1..=20).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn holds_are_written_with_their_dispute_seq() {
+        let holds = [
+            OpenDispute {
+                client_id: 1,
+                tx_id: 2,
+                held: Decimal::TEN,
+                opened_seq: Some(40),
+            },
+            OpenDispute {
+                client_id: 3,
+                tx_id: 4,
+                held: Decimal::ONE,
+                opened_seq: None,
+            },
+        ];
+        let mut output = Vec::new();
+        write_holds(&holds, None, &mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,amount,disputed_seq\n1,2,10,40\n3,4,1,\n"
+        );
+
+        let pseudonyms = Pseudonymizer::new(b"0123456789abcdef").unwrap();
+        let mut output = Vec::new();
+        write_holds(&holds, Some(&pseudonyms), &mut output)
+            .await
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!("\n{},2,10,40\n", pseudonyms.pseudonym(1))));
+    }
+
+    #[tokio::test]
+    async fn locked_accounts_are_written_with_their_chargeback() {
+        let locked = [
+            LockedAccount {
+                client_id: 1,
+                chargeback: Some(LockingChargeback {
+                    tx_id: 2,
+                    amount: Decimal::TWO,
+                    available: Decimal::ONE,
+                    held: Decimal::TWO,
+                    total: Decimal::from(3),
+                }),
+            },
+            LockedAccount {
+                client_id: 3,
+                chargeback: None,
+            },
+        ];
+        let mut output = Vec::new();
+        write_locked_accounts(&locked, None, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,amount,available_before,held_before,total_before
+1,2,2,1,2,3
+3,,,,,
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn pseudonymized_results_hide_client_ids() {
+        let pseudonyms = Pseudonymizer::new(b"0123456789abcdef").unwrap();
+        let results = (1..=3).map(|id| (id, ClientState::new(id))).collect();
+        let mut output = Vec::new();
+        write_results(
+            results,
+            SummaryColumns::default(),
+            true,
+            Some(&pseudonyms),
+            &mut output,
+        )
+        .await
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let clients: Vec<_> = output
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(
+            output.lines().next(),
+            Some("client,available,held,total,locked")
+        );
+        let revealed: Vec<_> = clients
+            .iter()
+            .map(|client| pseudonyms.reveal(client))
+            .collect();
+        assert_eq!(revealed, vec![Some(1), Some(2), Some(3)]);
+    }
+
+    #[tokio::test]
+    async fn sink_streams_accounts_to_their_partition() {
+        let (sink, mut receivers) = AccountSink::new(2);
+        let upper = receivers.pop().unwrap();
+        let writer = tokio::spawn(async move {
+            let mut output = Vec::new();
+            stream_results(upper, SummaryColumns::default(), None, &mut output).await?;
+            anyhow::Ok(output)
+        });
+        for id in [40_000, 7, 50_000] {
+            sink.send(ClientState::new(id)).await.unwrap();
+        }
+        drop(sink);
+
+        let mut lower = receivers.pop().unwrap();
+        let output = String::from_utf8(writer.await.unwrap().unwrap()).unwrap();
+        let clients: Vec<_> = output
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().parse::<u16>().unwrap())
+            .collect();
+        assert_eq!(clients, vec![40_000, 50_000]);
+        assert_eq!(lower.try_recv().unwrap().id(), 7);
+
+        let (sink, receivers) = AccountSink::new(1);
+        drop(receivers);
+        assert_eq!(sink.send(ClientState::new(3)).await.unwrap_err().id(), 3);
+    }
+
+    #[tokio::test]
+    async fn accounts_are_flushed_as_they_are_written_and_shut_down_at_the_end() {
+        let (sender, receiver) = mpsc::channel(16);
+        let writer = tokio::spawn(async move {
+            let mut output = Flushes::default();
+            stream_results(receiver, SummaryColumns::default(), None, &mut output).await?;
+            anyhow::Ok(output)
+        });
+        for id in 0..=FLUSH_EVERY {
+            let id = u16::try_from(id).unwrap();
+            sender.send(ClientState::new(id)).await.unwrap();
+        }
+        drop(sender);
+        let output = writer.await.unwrap().unwrap();
+        // A flush after the first `FLUSH_EVERY` accounts, the shutdown once all were written
+        assert!(output.flushed_at[0] < output.written);
+        assert_eq!(output.shut_down_at, Some(output.written));
+
+        let results = (1..=3).map(|id| (id, ClientState::new(id))).collect();
+        let mut output = Flushes::default();
+        write_results(results, SummaryColumns::default(), true, None, &mut output)
+            .await
+            .unwrap();
+        assert!(output.written > 0);
+        assert_eq!(output.shut_down_at, Some(output.written));
+    }
+}
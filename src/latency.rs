@@ -0,0 +1,128 @@
+//! Latency budget of `--emit updates`, where each applied transaction is streamed out as
+//! soon as it applied
+//!
+//! The latency of a transaction runs from the moment its record was read to the moment its
+//! worker applied it, or gave up on its first attempt. Each worker takes the p99 of every
+//! `WINDOW` latencies, and alerts when it exceeds the budget.
+
+use std::time::Duration;
+
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::warn;
+
+/// Latencies the p99 is taken over
+pub const WINDOW: usize = 1000;
+
+/// A window of transactions whose p99 latency exceeded the budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyAlert {
+    pub p99: Duration,
+    pub budget: Duration,
+    /// Transactions in the window, fewer than `WINDOW` for the last one of a worker
+    pub samples: usize,
+}
+
+/// Collects the latencies of one worker
+#[derive(Debug)]
+pub struct LatencyTracker {
+    budget: Duration,
+    samples: Vec<Duration>,
+    alerts: Option<UnboundedSender<LatencyAlert>>,
+}
+
+impl LatencyTracker {
+    pub fn new(budget: Duration, alerts: Option<UnboundedSender<LatencyAlert>>) -> Self {
+        Self {
+            budget,
+            samples: Vec::with_capacity(WINDOW),
+            alerts,
+        }
+    }
+
+    /// Records the latency of a transaction, checking the budget once a window is full
+    pub fn record(&mut self, latency: Duration) -> Option<LatencyAlert> {
+        self.samples.push(latency);
+        if self.samples.len() < WINDOW {
+            return None;
+        }
+        self.check()
+    }
+
+    /// Checks the budget against the latencies of a partial window, e.g. once the input was
+    /// read
+    pub fn flush(&mut self) -> Option<LatencyAlert> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        self.check()
+    }
+
+    fn check(&mut self) -> Option<LatencyAlert> {
+        let samples = self.samples.len();
+        let rank = (samples * 99).div_ceil(100) - 1;
+        let (_, &mut p99, _) = self.samples.select_nth_unstable(rank);
+        self.samples.clear();
+        if p99 <= self.budget {
+            return None;
+        }
+
+        let alert = LatencyAlert {
+            p99,
+            budget: self.budget,
+            samples,
+        };
+        warn!(
+            "Latency p99 of {:?} over the last {} transactions exceeds the budget of {:?}",
+            p99, samples, self.budget
+        );
+        if let Some(alerts) = &self.alerts {
+            alerts.send(alert).ok();
+        }
+        Some(alert)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use crate::latency::{LatencyAlert, LatencyTracker, WINDOW};
+
+    #[test]
+    fn alerts_when_p99_exceeds_the_budget() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut tracker = LatencyTracker::new(Duration::from_millis(10), Some(sender));
+
+        // One slow transaction in a hundred stays within the p99
+        for i in 0..WINDOW {
+            let millis = if i % 100 == 0 { 50 } else { 1 };
+            assert_eq!(tracker.record(Duration::from_millis(millis)), None);
+        }
+        assert!(receiver.try_recv().is_err());
+
+        for i in 0..WINDOW {
+            let millis = if i % 50 == 0 { 50 } else { 1 };
+            let alert = tracker.record(Duration::from_millis(millis));
+            assert_eq!(alert.is_some(), i == WINDOW - 1);
+        }
+        let alert = LatencyAlert {
+            p99: Duration::from_millis(50),
+            budget: Duration::from_millis(10),
+            samples: WINDOW,
+        };
+        assert_eq!(receiver.try_recv().unwrap(), alert);
+    }
+
+    #[test]
+    fn flushes_a_partial_window() {
+        let mut tracker = LatencyTracker::new(Duration::from_millis(10), None);
+        assert_eq!(tracker.flush(), None);
+        tracker.record(Duration::from_millis(1));
+        tracker.record(Duration::from_millis(20));
+        let alert = tracker.flush().unwrap();
+        assert_eq!((alert.p99, alert.samples), (Duration::from_millis(20), 2));
+        assert_eq!(tracker.flush(), None);
+    }
+}
@@ -1,109 +1,1393 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::{bail, Ok, Result};
-use tokio::sync::mpsc::UnboundedReceiver;
-use tracing::error;
+use anyhow::{bail, Result};
+use rust_decimal::Decimal;
+use tokio::{
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    time::{sleep_until, Instant},
+};
+use tracing::warn;
 
+#[cfg(feature = "runtime")]
+use crate::io_ops::AccountSink;
 use crate::{
-    account::ClientState,
+    account::{
+        AccountSummary, AccountUpdate, ClientState, DisputeFundsPolicy, LockedAccountPolicy,
+        SummaryColumns,
+    },
     data::{
-        Transaction,
-        TransactionType::{Chargeback, Deposit, Dispute, Resolve, Withdrawal},
+        Activity, DisputableTypes, DisputeEvent, DisputeRecord, DisputeStage, LockedAccount,
+        LockingChargeback, OpenDispute, Sequenced, Transaction,
+        TransactionType::{
+            Adjustment, Chargeback, Custom, Deposit, Dispute, Resolve, Reversal, Withdrawal,
+        },
+        TxOutcome, TxStatus,
     },
+    hasher::IdMap,
+    health::{Health, WorkerProbe},
+    latency::{LatencyAlert, LatencyTracker},
+    limits::{LimitConfig, Limits},
+    memory::{
+        MemoryBudget, MemoryBudgetExceeded, ACCOUNT_BYTES, ACTIVITY_BYTES, EVENT_BYTES,
+        HISTORY_BYTES, HISTORY_ENTRY_BYTES, OPEN_DISPUTE_BYTES, PARTIAL_HOLD_BYTES,
+        TRANSACTION_BYTES,
+    },
+    pseudonym::Pseudonymizer,
+    registry::TransactionRegistry,
+    retry::{DeadLetter, RetryPolicy, RetryQueue},
+    risk::{risk_policy, AnomalyPolicy, ClientHistory, RiskDecision, RiskPolicy, VelocityPolicy},
+    router::AccountQueries,
+    settlement::Settlement,
+    shutdown::{Cancellation, Cancelled},
+    store::TransactionStore,
+    warning::{SkippedRecord, Warning, WarningKind},
 };
 
+/// Balance changes applied to an account for each transaction type
+///
+/// Every operation errors if the account is locked, the client ids do not match or the
+/// transaction cannot take part in the operation. A dispute returns the amount it held,
+/// which the resolve or chargeback closing it is given back.
+#[allow(clippy::missing_errors_doc)]
 pub trait Transact {
-    fn chargeback(&mut self, tx: &Transaction, chargeback_tx: &Transaction) -> Result<()>;
+    fn adjust(&mut self, tx: &Transaction) -> Result<()>;
+    fn chargeback(
+        &mut self,
+        tx: &Transaction,
+        chargeback_tx: &mut DisputeRecord,
+        held: Decimal,
+    ) -> Result<()>;
     fn deposit(&mut self, tx: &Transaction) -> Result<()>;
-    fn dispute(&mut self, tx: &Transaction, disputed_tx: &mut Transaction) -> Result<()>;
-    fn resolve(&mut self, tx: &Transaction, disputed_tx: &mut Transaction) -> Result<()>;
+    fn dispute(&mut self, tx: &Transaction, disputed_tx: &mut DisputeRecord) -> Result<Decimal>;
+    fn resolve(
+        &mut self,
+        tx: &Transaction,
+        disputed_tx: &mut DisputeRecord,
+        held: Decimal,
+    ) -> Result<()>;
+    fn reverse(&mut self, tx: &Transaction, reversed_tx: &mut DisputeRecord) -> Result<()>;
     fn withdraw(&mut self, tx: &Transaction) -> Result<()>;
 }
 
-pub async fn event_handler(mut rx: UnboundedReceiver<Transaction>) -> HashMap<u16, ClientState> {
-    let mut ledger = Ledger::new();
+/// Behaviour of each worker and its ledger
+#[derive(Debug, Clone, Default)]
+pub struct WorkerOptions {
+    pub retry: RetryPolicy,
+    /// Events a dispute referencing an unknown transaction is held back for
+    pub reorder_window: usize,
+    /// Maintain a hash chain per account over every applied transaction
+    pub audit: bool,
+    /// Record every transaction changing an account's total, see `Ledger::activity`
+    pub activity: bool,
+    pub velocity: Option<VelocityPolicy>,
+    pub anomaly: Option<AnomalyPolicy>,
+    /// Overdraft limit of each client allowed to go below zero
+    pub overdrafts: Arc<HashMap<u16, Decimal>>,
+    pub locked_policy: LockedAccountPolicy,
+    pub dispute_policy: DisputeFundsPolicy,
+    /// Chargeback ratio beyond which accounts are frozen
+    pub chargeback_limit: Option<Decimal>,
+    /// Largest amount, either way, a transaction may carry
+    pub max_amount: Option<Decimal>,
+    pub withdrawal_limits: Option<Arc<LimitConfig>>,
+    pub disputable: DisputableTypes,
+    /// Budget shared by every worker processing the same input
+    pub memory: Option<Arc<MemoryBudget>>,
+    /// Shared with the reader and the other workers of the same input
+    pub cancel: Cancellation,
+    /// Keeps client ids out of the log with `--pseudonym-key`
+    pub pseudonyms: Option<Pseudonymizer>,
+    /// p99 latency of the `WorkerMsg::Timed` events above which the worker alerts
+    pub latency_budget: Option<Duration>,
+}
 
-    while let Some(mut tx) = rx.recv().await {
-        ledger
-            .process_transaction(&mut tx)
-            .map_err(|e| error!("Processing transaction error `{}`", e))
-            .ok();
+/// Channels a worker publishes to besides its returned accounts
+#[derive(Debug, Clone, Default)]
+pub struct WorkerSinks {
+    /// Transactions which failed every attempt
+    pub dead_letters: Option<UnboundedSender<DeadLetter>>,
+    /// Records skipped with a warning, kept apart from `dead_letters`
+    pub warnings: Option<UnboundedSender<SkippedRecord>>,
+    /// Disputes, resolves and chargebacks once applied
+    pub dispute_events: Option<UnboundedSender<DisputeEvent>>,
+    /// The client's account after every applied transaction
+    pub account_updates: Option<UnboundedSender<AccountUpdate>>,
+    /// Every transaction once applied or given up on
+    pub tx_outcomes: Option<UnboundedSender<TxOutcome>>,
+    /// Attached to the workers of an input by `pipeline::process_file` while it is read
+    pub queries: Option<AccountQueries>,
+    /// Takes the worker's accounts once its channel closed, so the returned ledger holds none
+    #[cfg(feature = "runtime")]
+    pub finished_accounts: Option<AccountSink>,
+    /// Reported on by the health endpoints, each worker of an input is registered with it by
+    /// `pipeline::process_file`
+    pub health: Option<Health>,
+    /// The worker's registration with `health`
+    pub probe: Option<Arc<WorkerProbe>>,
+    /// Windows of events over `WorkerOptions::latency_budget`, once logged
+    pub latency_alerts: Option<UnboundedSender<LatencyAlert>>,
+    /// Every transaction once applied
+    pub applied_transactions: Option<UnboundedSender<Transaction>>,
+}
+
+/// A message on a worker's channel
+#[derive(Debug)]
+pub enum WorkerMsg {
+    /// An event to apply to the worker's ledger
+    Tx(Sequenced),
+    /// An event with when its record was read, to track its latency
+    Timed(Sequenced, Instant),
+    /// Asks for the client's account with every column, or `None` if the worker does not
+    /// hold it. Answered after the events sent before it were processed, but before any
+    /// pending retry or buffered event for them is.
+    Query(u16, oneshot::Sender<Option<AccountSummary>>),
+    /// An account carried over from an earlier run, sent before any event of its client
+    Open(ClientState),
+    /// A deposit or withdrawal of an earlier run by its id, with what its dispute holds if
+    /// less than its amount, sent before any event of its client
+    Restore(u32, DisputeRecord, Option<Decimal>),
+    /// An event the router rejected or skipped before it reached any ledger, reported as the
+    /// worker reports its own
+    Reject(Sequenced, anyhow::Error),
+}
+
+impl WorkerMsg {
+    pub fn into_event(self) -> Option<Sequenced> {
+        match self {
+            Self::Tx(event) | Self::Timed(event, _) => Some(event),
+            Self::Query(..) | Self::Open(_) | Self::Restore(..) | Self::Reject(..) => None,
+        }
     }
+}
 
-    ledger.accounts
+/// Applies events until the channel closes, retrying failures per `options.retry` while new
+/// events keep arriving. Retries bypass the ordering check as they are expected to be late.
+/// Returns the ledger, to be merged with those of the other workers.
+///
+/// Stops early once `options.cancel` is cancelled, and cancels it on failing.
+///
+/// # Errors
+/// If the ledger would exceed its memory budget, or with `Cancelled`
+pub async fn event_handler(
+    mut rx: UnboundedReceiver<WorkerMsg>,
+    options: WorkerOptions,
+    sinks: WorkerSinks,
+) -> Result<Ledger> {
+    let mut ledger = Ledger::new()
+        .with_reorder_window(options.reorder_window)
+        .with_audit(options.audit)
+        .with_activity(options.activity)
+        .with_dispute_events(sinks.dispute_events)
+        .with_account_updates(sinks.account_updates)
+        .with_tx_outcomes(sinks.tx_outcomes.clone())
+        .with_applied_transactions(sinks.applied_transactions)
+        .with_overdrafts(options.overdrafts)
+        .with_locked_policy(options.locked_policy)
+        .with_dispute_policy(options.dispute_policy)
+        .with_chargeback_limit(options.chargeback_limit)
+        .with_max_amount(options.max_amount)
+        .with_withdrawal_limits(options.withdrawal_limits)
+        .with_disputable_types(options.disputable)
+        .with_memory_budget(options.memory);
+    if let Some(policy) = risk_policy(options.velocity, options.anomaly) {
+        ledger = ledger.with_risk_policy(policy);
+    }
+    let mut retries = RetryQueue::new(options.retry, sinks.dead_letters)
+        .with_tx_outcomes(sinks.tx_outcomes)
+        .with_warnings(sinks.warnings)
+        .with_pseudonyms(options.pseudonyms);
+    let mut latency = options
+        .latency_budget
+        .map(|budget| LatencyTracker::new(budget, sinks.latency_alerts));
+    let probe = sinks.probe.as_deref();
+    let mut open = true;
+    let cancelled = options.cancel.cancelled();
+    tokio::pin!(cancelled);
+
+    let applied = async {
+        while open || !retries.is_empty() {
+            let next_due = retries.next_due();
+            tokio::select! {
+                msg = rx.recv(), if open => match msg {
+                    Some(WorkerMsg::Tx(event)) => {
+                        take_event(&mut ledger, &mut retries, probe, event)?;
+                    }
+                    Some(WorkerMsg::Timed(event, read)) => {
+                        take_event(&mut ledger, &mut retries, probe, event)?;
+                        if let Some(latency) = &mut latency {
+                            latency.record(read.elapsed());
+                        }
+                    }
+                    Some(WorkerMsg::Query(client_id, reply)) => {
+                        reply.send(ledger.summary(client_id)).ok();
+                    }
+                    Some(WorkerMsg::Open(state)) => ledger.open_account(state),
+                    Some(WorkerMsg::Restore(tx_id, record, held)) => {
+                        ledger.restore_transaction(tx_id, record, held);
+                    }
+                    Some(WorkerMsg::Reject(event, e)) => retries.dead_letter(event, &e),
+                    None => {
+                        if let Some(latency) = &mut latency {
+                            latency.flush();
+                        }
+                        ledger.expire_buffered();
+                        for (event, e) in ledger.release_quarantine() {
+                            retries.dead_letter(event, &e);
+                        }
+                        open = false;
+                    }
+                },
+                () = &mut cancelled => return Err(Cancelled.into()),
+                () = sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                    for (attempt, event) in retries.take_due() {
+                        if let Err((event, e)) = ledger.process_sequenced(event) {
+                            if e.is::<MemoryBudgetExceeded>() {
+                                return Err(e);
+                            }
+                            retries.failed(attempt, event, &e);
+                        }
+                    }
+                }
+            }
+            for (event, e) in ledger.unmatched.drain(..) {
+                if e.is::<MemoryBudgetExceeded>() {
+                    return Err(e);
+                }
+                retries.failed(0, event, &e);
+            }
+            ledger.report_memory();
+        }
+        anyhow::Ok(())
+    };
+    if let Err(e) = applied.await {
+        // A failure of this worker stops the others, unless it was their failure
+        if !e.is::<Cancelled>() {
+            options.cancel.cancel();
+        }
+        return Err(e);
+    }
+
+    ledger.rejected.clone_from(retries.rejected());
+    ledger.warnings.clone_from(retries.warnings());
+    #[cfg(feature = "runtime")]
+    if let Some(sink) = sinks.finished_accounts {
+        for state in ledger.drain_accounts() {
+            // The writer failed, its error is reported once it is awaited
+            if sink.send(state).await.is_err() {
+                break;
+            }
+        }
+    }
+    Ok(ledger)
+}
+
+/// Applies an event taken off the worker's channel, queueing it for a retry if it failed
+fn take_event(
+    ledger: &mut Ledger,
+    retries: &mut RetryQueue,
+    probe: Option<&WorkerProbe>,
+    event: Sequenced,
+) -> Result<()> {
+    if let Some(probe) = probe {
+        probe.progressed();
+    }
+    if let Err((event, e)) = ledger.process_event(event) {
+        if e.is::<MemoryBudgetExceeded>() {
+            return Err(e);
+        }
+        retries.failed(0, event, &e);
+    }
+    Ok(())
+}
+
+/// State held by both ledgers passed to `Ledger::merge`, which would be lost by combining them
+#[derive(Debug, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// Both ledgers hold an account of the client
+    Client(u16),
+    /// Both ledgers approved a transaction with the id
+    Transaction(u32),
+}
+
+impl std::fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Client(client_id) => write!(f, "Client '{client_id}' is held by both ledgers"),
+            Self::Transaction(tx_id) => {
+                write!(f, "Transaction `{tx_id}` was approved by both ledgers")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeConflict {}
+
+/// An amount beyond the bound of `Ledger::with_max_amount`, most likely mistyped, e.g. with
+/// extra digits. No later attempt can apply it, so it is dead-lettered without retries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmountOutOfBounds {
+    pub tx_id: u32,
+    pub amount: Decimal,
+    pub max: Decimal,
+}
+
+impl std::fmt::Display for AmountOutOfBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Amount `{}` of transaction `{}` exceeds the bound of {}",
+            self.amount, self.tx_id, self.max
+        )
+    }
+}
+
+impl std::error::Error for AmountOutOfBounds {}
+
+/// A worker of an input which panicked, whose clients have no account in the output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerPanic {
+    pub file_path: String,
+    pub worker: usize,
+    /// Clients assigned to the worker
+    pub clients: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for WorkerPanic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "worker {} of {} panicked with `{}`, its {} clients are missing",
+            self.worker, self.file_path, self.message, self.clients
+        )
+    }
 }
 
 pub struct Ledger {
-    accounts: HashMap<u16, ClientState>,
-    approved_tx: HashMap<u32, Transaction>,
+    accounts: IdMap<u16, ClientState>,
+    /// Accounts taken out by `drain_accounts`
+    drained: usize,
+    approved_tx: TransactionStore,
+    /// Transactions currently under dispute per client, in the order they were disputed
+    open_disputes: IdMap<u16, Vec<u32>>,
+    /// Entries held in `open_disputes`
+    open_disputes_len: usize,
+    /// Sequence number of the event which opened each dispute in `open_disputes`, if it
+    /// came with one
+    dispute_seqs: IdMap<u32, u64>,
+    /// Amount held by each dispute in `open_disputes` holding less than its transaction's,
+    /// see `DisputeFundsPolicy::HoldPartial`
+    partial_holds: IdMap<u32, Decimal>,
+    /// Sequence number of the last event applied, events must arrive in increasing order
+    last_seq: Option<u64>,
+    /// How many subsequent events a dispute, resolve, chargeback or reversal referencing an
+    /// unknown transaction is held back for, waiting for that transaction to arrive
+    reorder_window: u64,
+    /// Events received so far, used to expire buffered events
+    received: u64,
+    /// Transactions applied, including retries and replayed events
+    applied: u64,
+    /// Transactions given up on by the worker, by `retry::rejection_reason`
+    rejected: BTreeMap<String, u64>,
+    /// Records the worker skipped with a warning, by kind
+    warnings: BTreeMap<WarningKind, u64>,
+    /// Money moved into and out of the client accounts
+    settlement: Settlement,
+    /// Workers whose ledgers are missing from this merged one as they panicked
+    worker_panics: Vec<WorkerPanic>,
+    /// Buffered events in arrival order, with the event count after which each expires
+    buffered: VecDeque<(u64, Sequenced)>,
+    /// Buffered events which expired or failed once replayed
+    unmatched: Vec<(Sequenced, anyhow::Error)>,
+    /// Events for locked accounts in arrival order, replayed if `unlock` is called
+    quarantine: IdMap<u16, Vec<Sequenced>>,
+    /// Events held in `quarantine`
+    quarantine_len: usize,
+    /// Whether applied transactions extend their account's audit chain
+    audit: bool,
+    /// Transactions which changed each client's total in the order applied, only kept when
+    /// enabled by `with_activity`
+    activity: Option<IdMap<u16, Vec<Activity>>>,
+    /// Entries held in `activity`
+    activity_len: usize,
+    /// The last chargeback applied to each client, which locked its account
+    chargebacks: IdMap<u16, LockingChargeback>,
+    dispute_events: Option<UnboundedSender<DisputeEvent>>,
+    account_updates: Option<UnboundedSender<AccountUpdate>>,
+    tx_outcomes: Option<UnboundedSender<TxOutcome>>,
+    applied_transactions: Option<UnboundedSender<Transaction>>,
+    risk_policy: Option<Box<dyn RiskPolicy>>,
+    limits: Option<Limits>,
+    disputable: DisputableTypes,
+    registry: Option<Arc<TransactionRegistry>>,
+    /// Recently applied transactions per client, only kept for the risk policy
+    histories: IdMap<u16, ClientHistory>,
+    overdrafts: Arc<HashMap<u16, Decimal>>,
+    locked_policy: LockedAccountPolicy,
+    dispute_policy: DisputeFundsPolicy,
+    /// Chargeback ratio beyond which accounts are frozen
+    chargeback_limit: Option<Decimal>,
+    /// Transactions with a larger amount, either way, are rejected before being applied
+    max_amount: Option<Decimal>,
+    memory: Option<Arc<MemoryBudget>>,
+    /// Bytes of `memory_usage` already added to the budget
+    reported_memory: usize,
 }
 
 impl Ledger {
     pub fn new() -> Self {
         Self {
-            accounts: HashMap::new(),
-            approved_tx: HashMap::new(),
+            accounts: IdMap::default(),
+            drained: 0,
+            approved_tx: TransactionStore::default(),
+            open_disputes: IdMap::default(),
+            open_disputes_len: 0,
+            dispute_seqs: IdMap::default(),
+            partial_holds: IdMap::default(),
+            last_seq: None,
+            reorder_window: 0,
+            received: 0,
+            applied: 0,
+            rejected: BTreeMap::new(),
+            warnings: BTreeMap::new(),
+            settlement: Settlement::default(),
+            worker_panics: Vec::new(),
+            buffered: VecDeque::new(),
+            unmatched: Vec::new(),
+            quarantine: IdMap::default(),
+            quarantine_len: 0,
+            audit: false,
+            activity: None,
+            activity_len: 0,
+            chargebacks: IdMap::default(),
+            dispute_events: None,
+            account_updates: None,
+            tx_outcomes: None,
+            applied_transactions: None,
+            risk_policy: None,
+            limits: None,
+            disputable: DisputableTypes::default(),
+            registry: None,
+            histories: IdMap::default(),
+            overdrafts: Arc::default(),
+            locked_policy: LockedAccountPolicy::default(),
+            dispute_policy: DisputeFundsPolicy::default(),
+            chargeback_limit: None,
+            max_amount: None,
+            memory: None,
+            reported_memory: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn with_reorder_window(mut self, reorder_window: usize) -> Self {
+        self.reorder_window = reorder_window as u64;
+        self
+    }
+
+    #[must_use]
+    pub fn with_audit(mut self, audit: bool) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Records the change each applied transaction made to its account's total, e.g. to
+    /// export the account activity
+    #[must_use]
+    pub fn with_activity(mut self, activity: bool) -> Self {
+        self.activity = activity.then(IdMap::default);
+        self
+    }
+
+    /// Assesses every transaction with `policy` before it is applied
+    #[must_use]
+    pub fn with_risk_policy(mut self, policy: Box<dyn RiskPolicy>) -> Self {
+        self.risk_policy = Some(policy);
+        self
+    }
+
+    /// Rejects withdrawals exceeding their client's limit in `limits`
+    #[must_use]
+    pub fn with_withdrawal_limits(mut self, limits: Option<Arc<LimitConfig>>) -> Self {
+        self.limits = limits.map(Limits::new);
+        self
+    }
+
+    /// Rejects disputes of the deposits or withdrawals which `disputable` excludes
+    #[must_use]
+    pub fn with_disputable_types(mut self, disputable: DisputableTypes) -> Self {
+        self.disputable = disputable;
+        self
+    }
+
+    /// Applies custom transaction kinds with the handlers registered in `registry`
+    #[must_use]
+    pub fn with_registry(mut self, registry: Arc<TransactionRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Starts from existing accounts instead of empty ones
+    #[must_use]
+    pub fn with_accounts(mut self, accounts: HashMap<u16, ClientState>) -> Self {
+        let (policy, dispute_policy) = (self.locked_policy, self.dispute_policy);
+        let chargeback_limit = self.chargeback_limit;
+        self.accounts = accounts
+            .into_iter()
+            .map(|(client_id, state)| {
+                let state = state
+                    .with_locked_policy(policy)
+                    .with_dispute_policy(dispute_policy)
+                    .with_chargeback_limit(chargeback_limit);
+                (client_id, state)
+            })
+            .collect();
+        self
+    }
+
+    /// Holds the deposits and withdrawals of earlier runs, so disputes, resolves, chargebacks
+    /// and reversals of this one can reference them. Those still under dispute are reopened
+    /// in transaction id order, while those charged back stay closed.
+    #[must_use]
+    pub fn with_transactions(mut self, transactions: HashMap<u32, DisputeRecord>) -> Self {
+        let mut disputed: Vec<_> = transactions
+            .iter()
+            .filter(|(_, record)| record.in_dispute())
+            .map(|(tx_id, record)| (record.client_id(), *tx_id))
+            .collect();
+        disputed.sort_unstable();
+        self.open_disputes_len += disputed.len();
+        for (client_id, tx_id) in disputed {
+            self.open_disputes.entry(client_id).or_default().push(tx_id);
         }
+        self.approved_tx.extend(transactions);
+        self
+    }
+
+    /// Holds less than the whole amount for the disputes of `with_transactions` listed, as
+    /// an earlier run held them with `DisputeFundsPolicy::HoldPartial`. Holds of
+    /// transactions not under dispute are ignored, so to be called after `with_transactions`.
+    #[must_use]
+    pub fn with_partial_holds(mut self, holds: HashMap<u32, Decimal>) -> Self {
+        let approved_tx = &self.approved_tx;
+        let open = holds.into_iter().filter(|(tx_id, _)| {
+            approved_tx
+                .get(*tx_id)
+                .is_some_and(|record| record.in_dispute())
+        });
+        self.partial_holds.extend(open);
+        self
+    }
+
+    /// Lets locked accounts, existing and opened later, accept what `policy` allows
+    #[must_use]
+    pub fn with_locked_policy(mut self, policy: LockedAccountPolicy) -> Self {
+        self.locked_policy = policy;
+        self.accounts = std::mem::take(&mut self.accounts)
+            .into_iter()
+            .map(|(client_id, state)| (client_id, state.with_locked_policy(policy)))
+            .collect();
+        self
+    }
+
+    /// Decides what disputes of accounts, existing and opened later, hold when the amount
+    /// exceeds the funds available
+    #[must_use]
+    pub fn with_dispute_policy(mut self, policy: DisputeFundsPolicy) -> Self {
+        self.dispute_policy = policy;
+        self.accounts = std::mem::take(&mut self.accounts)
+            .into_iter()
+            .map(|(client_id, state)| (client_id, state.with_dispute_policy(policy)))
+            .collect();
+        self
+    }
+
+    /// Freezes accounts, existing and opened later, once a chargeback drives their
+    /// chargeback ratio past `limit`
+    #[must_use]
+    pub fn with_chargeback_limit(mut self, limit: Option<Decimal>) -> Self {
+        self.chargeback_limit = limit;
+        self.accounts = std::mem::take(&mut self.accounts)
+            .into_iter()
+            .map(|(client_id, state)| (client_id, state.with_chargeback_limit(limit)))
+            .collect();
+        self
     }
 
-    fn record_tx(&mut self, tx: &Transaction) -> Result<()> {
-        self.approved_tx.insert(tx.tx_id(), tx.clone());
+    /// Rejects any transaction whose amount exceeds `max` either way, guarding the balances
+    /// against a mistyped record. This is the only check of the bound: the workers of the
+    /// pipeline, `--sync`, `simulate`, `statement`, `explain` and `tui` all rely on it.
+    #[must_use]
+    pub fn with_max_amount(mut self, max: Option<Decimal>) -> Self {
+        self.max_amount = max;
+        self
+    }
+
+    /// Opens the accounts of the listed clients with an overdraft facility
+    #[must_use]
+    pub fn with_overdrafts(mut self, overdrafts: Arc<HashMap<u16, Decimal>>) -> Self {
+        self.overdrafts = overdrafts;
+        self
+    }
+
+    /// Publishes every applied dispute, resolve and chargeback to `sender`
+    #[must_use]
+    pub fn with_dispute_events(mut self, sender: Option<UnboundedSender<DisputeEvent>>) -> Self {
+        self.dispute_events = sender;
+        self
+    }
+
+    /// Fails any transaction which could take the usage of the shared budget over its limit
+    #[must_use]
+    pub fn with_memory_budget(mut self, memory: Option<Arc<MemoryBudget>>) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Approximate bytes held by the accounts and approved transactions, the open disputes,
+    /// the events held back and the histories kept for the risk policy
+    pub fn memory_usage(&self) -> usize {
+        let history_len = self
+            .risk_policy
+            .as_ref()
+            .map_or(0, |policy| policy.history_len());
+        let events = self.quarantine_len + self.buffered.len() + self.unmatched.len();
+        self.accounts.len() * ACCOUNT_BYTES
+            + self.approved_tx.len() * TRANSACTION_BYTES
+            + self.activity_len * ACTIVITY_BYTES
+            + self.open_disputes_len * OPEN_DISPUTE_BYTES
+            + self.partial_holds.len() * PARTIAL_HOLD_BYTES
+            + events * EVENT_BYTES
+            + self.histories.len() * (HISTORY_BYTES + history_len * HISTORY_ENTRY_BYTES)
+    }
+
+    /// Brings the usage of the shared budget in step with `memory_usage`, which shrinks as
+    /// disputes close and events held back are let go
+    fn report_memory(&mut self) {
+        let Some(memory) = &self.memory else {
+            return;
+        };
+        let usage = self.memory_usage();
+        if usage >= self.reported_memory {
+            memory.grow(usage - self.reported_memory);
+        } else {
+            memory.shrink(self.reported_memory - usage);
+        }
+        self.reported_memory = usage;
+    }
+
+    /// Publishes the client's account to `sender` after every applied transaction
+    #[must_use]
+    pub fn with_account_updates(mut self, sender: Option<UnboundedSender<AccountUpdate>>) -> Self {
+        self.account_updates = sender;
+        self
+    }
+
+    /// Publishes every applied transaction to `sender`. Rejections are published by the
+    /// worker once it gives up on them, as they may still be retried.
+    #[must_use]
+    pub fn with_tx_outcomes(mut self, sender: Option<UnboundedSender<TxOutcome>>) -> Self {
+        self.tx_outcomes = sender;
+        self
+    }
+
+    /// Publishes a copy of every applied transaction to `sender`
+    #[must_use]
+    pub fn with_applied_transactions(
+        mut self,
+        sender: Option<UnboundedSender<Transaction>>,
+    ) -> Self {
+        self.applied_transactions = sender;
+        self
+    }
+
+    /// Hands the event back along with the error if it was not applied
+    fn process_event(&mut self, event: Sequenced) -> Result<(), (Sequenced, anyhow::Error)> {
+        if let Some(last_seq) = self.last_seq.filter(|last_seq| event.seq <= *last_seq) {
+            let e = anyhow::anyhow!(
+                "Transaction `{}` arrived out of order (sequence {} after {})",
+                event.tx.tx_id(),
+                event.seq,
+                last_seq
+            );
+            return Err((event, e));
+        }
+        self.last_seq = Some(event.seq);
+        self.received += 1;
+        self.expire_buffered_before(self.received);
+        let Some(event) = self.quarantine_if_locked(event) else {
+            return Ok(());
+        };
+
+        let tx = &event.tx;
+        if self.reorder_window > 0
+            && matches!(tx.tx_type(), Dispute | Resolve | Chargeback | Reversal)
+            && !self.approved_tx.contains(tx.tx_id())
+        {
+            self.buffered
+                .push_back((self.received + self.reorder_window, event));
+            return Ok(());
+        }
+
+        let replay = event.tx.is_disputable().then_some(event.tx.tx_id());
+        self.process_sequenced(event)?;
+        if let Some(tx_id) = replay {
+            self.replay_buffered(tx_id);
+        }
         Ok(())
     }
 
-    fn process_transaction(&mut self, tx: &mut Transaction) -> Result<()> {
-        let state = self
+    /// Applies buffered events waiting on `tx_id`, in the order they arrived
+    fn replay_buffered(&mut self, tx_id: u32) {
+        let (waiting, others) = self
+            .buffered
+            .drain(..)
+            .partition(|(_, event)| event.tx.tx_id() == tx_id);
+        self.buffered = others;
+
+        for (_, event) in waiting {
+            if let Err(failed) = self.process_sequenced(event) {
+                self.unmatched.push(failed);
+            }
+        }
+    }
+
+    fn expire_buffered_before(&mut self, received: u64) {
+        while let Some((expires, _)) = self.buffered.front() {
+            if *expires >= received {
+                break;
+            }
+            let (_, event) = self.buffered.pop_front().unwrap();
+            let e = Warning::unknown_transaction(&event.tx).into();
+            self.unmatched.push((event, e));
+        }
+    }
+
+    /// Gives up on every buffered event, e.g. once the input is exhausted
+    fn expire_buffered(&mut self) {
+        self.expire_buffered_before(u64::MAX);
+    }
+
+    /// Holds back the event if its account is locked and does not accept it, otherwise hands
+    /// it back
+    fn quarantine_if_locked(&mut self, event: Sequenced) -> Option<Sequenced> {
+        let client_id = event.tx.client_id();
+        let state = self.accounts.get(&client_id);
+        if state.is_none_or(|state| state.accepts(event.tx.tx_type())) {
+            return Some(event);
+        }
+        self.quarantine.entry(client_id).or_default().push(event);
+        self.quarantine_len += 1;
+        None
+    }
+
+    /// Unlocks the client's account through the admin flow and replays the events
+    /// quarantined while it was locked in the order they arrived. Replayed events which fail
+    /// are reported like any other, and those after one locking the account again go back
+    /// into quarantine.
+    ///
+    /// # Errors
+    /// If the client has no locked account
+    pub fn unlock(&mut self, client_id: u16) -> Result<()> {
+        match self.accounts.get_mut(&client_id) {
+            Some(state) if state.is_locked() => state.unlock(),
+            _ => bail!("Account '{}' is not locked", client_id),
+        }
+
+        let quarantined = self.quarantine.remove(&client_id).unwrap_or_default();
+        self.quarantine_len -= quarantined.len();
+        for event in quarantined {
+            let Some(event) = self.quarantine_if_locked(event) else {
+                continue;
+            };
+            if let Err(failed) = self.process_sequenced(event) {
+                self.unmatched.push(failed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives up on every quarantined event, in input order, e.g. once the input is exhausted
+    fn release_quarantine(&mut self) -> Vec<(Sequenced, anyhow::Error)> {
+        self.quarantine_len = 0;
+        let mut released: Vec<_> = self
+            .quarantine
+            .drain()
+            .flat_map(|(_, events)| events)
+            .collect();
+        released.sort_unstable_by_key(|event| event.seq);
+        released
+            .into_iter()
+            .map(|event| {
+                let e = anyhow::anyhow!("Account '{}' is locked", event.tx.client_id());
+                (event, e)
+            })
+            .collect()
+    }
+
+    /// Every account opened, in no particular order
+    pub fn accounts(&self) -> impl ExactSizeIterator<Item = &ClientState> {
+        self.accounts.values()
+    }
+
+    /// The client's account with every column, if it was opened
+    pub fn summary(&self, client_id: u16) -> Option<AccountSummary> {
+        let columns = SummaryColumns {
+            flags: true,
+            overdraft: true,
+            extended: true,
+            minor_units: None,
+        };
+        self.accounts
+            .get(&client_id)
+            .map(|state| AccountSummary::with_columns(state, columns))
+    }
+
+    /// The deposit or withdrawal `tx_id` if it was applied, with whether it is under dispute
+    pub fn transaction(&self, tx_id: u32) -> Option<DisputeRecord> {
+        self.approved_tx.get(tx_id)
+    }
+
+    /// Every applied deposit and withdrawal, in no particular order
+    pub fn transactions(&self) -> impl Iterator<Item = (u32, DisputeRecord)> + '_ {
+        self.approved_tx.records()
+    }
+
+    /// The amount held by each open dispute holding less than its transaction's, see
+    /// `DisputeFundsPolicy::HoldPartial`
+    pub fn partial_holds(&self) -> impl Iterator<Item = (u32, Decimal)> + '_ {
+        self.partial_holds
+            .iter()
+            .map(|(tx_id, held)| (*tx_id, *held))
+    }
+
+    /// Events received in order, whether they were applied or not
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    /// Money moved into and out of the client accounts, drained ones included
+    pub fn settlement(&self) -> Settlement {
+        self.settlement
+    }
+
+    pub fn worker_panics(&self) -> &[WorkerPanic] {
+        &self.worker_panics
+    }
+
+    pub(crate) fn record_worker_panic(&mut self, panic: WorkerPanic) {
+        self.worker_panics.push(panic);
+    }
+
+    /// Transactions the worker gave up on, counted by reason with ids replaced by `*`
+    pub fn rejected(&self) -> &BTreeMap<String, u64> {
+        &self.rejected
+    }
+
+    /// Records the worker skipped with a warning rather than rejected, by kind
+    pub fn warnings(&self) -> &BTreeMap<WarningKind, u64> {
+        &self.warnings
+    }
+
+    /// Transactions of the client currently under dispute, in the order they were disputed
+    pub fn open_disputes(&self, client_id: u16) -> &[u32] {
+        self.open_disputes
+            .get(&client_id)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Transactions which changed the client's total, in the order they were applied. Empty
+    /// unless enabled by `with_activity`, disputes and resolves only move funds between
+    /// available and held so they are never recorded.
+    pub fn activity(&self, client_id: u16) -> &[Activity] {
+        self.activity
+            .as_ref()
+            .and_then(|activity| activity.get(&client_id))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Every amount held by an open dispute, in client and dispute order. The amounts of a
+    /// client add up to the `held` balance of its account.
+    pub fn holds(&self) -> Vec<OpenDispute> {
+        let mut clients: Vec<_> = self.open_disputes.iter().collect();
+        clients.sort_unstable_by_key(|(client_id, _)| **client_id);
+        clients
+            .into_iter()
+            .flat_map(|(client_id, tx_ids)| {
+                tx_ids.iter().map(|tx_id| OpenDispute {
+                    client_id: *client_id,
+                    tx_id: *tx_id,
+                    held: self.partial_holds.get(tx_id).copied().unwrap_or_else(|| {
+                        self.approved_tx
+                            .get(*tx_id)
+                            .map_or(Decimal::ZERO, |record| record.amount())
+                    }),
+                    opened_seq: self.dispute_seqs.get(tx_id).copied(),
+                })
+            })
+            .collect()
+    }
+
+    /// Every open dispute with the amount it holds, oldest first. Disputes opened without a
+    /// sequence number, e.g. through `apply`, come last in client and dispute order.
+    pub fn open_dispute_ages(&self) -> Vec<OpenDispute> {
+        let mut disputes = self.holds();
+        disputes.sort_by_key(|dispute| (dispute.opened_seq.is_none(), dispute.opened_seq));
+        disputes
+    }
+
+    /// Every locked account in client order, with the chargeback which locked it if it was
+    /// applied by this ledger
+    pub fn locked_accounts(&self) -> Vec<LockedAccount> {
+        let mut locked: Vec<_> = self
+            .accounts
+            .values()
+            .filter(|state| state.is_locked())
+            .map(|state| LockedAccount {
+                client_id: state.id(),
+                chargeback: self.chargebacks.get(&state.id()).copied(),
+            })
+            .collect();
+        locked.sort_unstable_by_key(|account| account.client_id);
+        locked
+    }
+
+    /// Moves the accounts and transactions of `other` into this ledger, whose configuration
+    /// is kept. Ledgers of disjoint sets of clients, such as those of different workers, can
+    /// always be merged.
+    ///
+    /// # Errors
+    /// If both ledgers hold the same client or transaction, in which case neither changed
+    pub fn merge(&mut self, mut other: Ledger) -> Result<(), MergeConflict> {
+        if let Some(client_id) = other
             .accounts
+            .keys()
+            .find(|c| self.accounts.contains_key(c))
+        {
+            return Err(MergeConflict::Client(*client_id));
+        }
+        // Only the smaller set of transactions is looked up and moved
+        let other_is_larger = other.approved_tx.len() > self.approved_tx.len();
+        let (smaller, larger) = if other_is_larger {
+            (&self.approved_tx, &other.approved_tx)
+        } else {
+            (&other.approved_tx, &self.approved_tx)
+        };
+        if let Some(tx_id) = smaller.ids().find(|tx_id| larger.contains(*tx_id)) {
+            return Err(MergeConflict::Transaction(tx_id));
+        }
+        if other_is_larger {
+            std::mem::swap(&mut self.approved_tx, &mut other.approved_tx);
+        }
+
+        self.accounts.extend(other.accounts);
+        self.approved_tx.append(other.approved_tx);
+        self.open_disputes.extend(other.open_disputes);
+        self.open_disputes_len += other.open_disputes_len;
+        self.dispute_seqs.extend(other.dispute_seqs);
+        self.partial_holds.extend(other.partial_holds);
+        if let (Some(activity), Some(other_activity)) = (&mut self.activity, other.activity) {
+            activity.extend(other_activity);
+            self.activity_len += other.activity_len;
+        }
+        self.chargebacks.extend(other.chargebacks);
+        self.histories.extend(other.histories);
+        self.quarantine.extend(other.quarantine);
+        self.quarantine_len += other.quarantine_len;
+        self.unmatched.extend(other.unmatched);
+        self.reported_memory += other.reported_memory;
+        self.drained += other.drained;
+        self.received += other.received;
+        self.applied += other.applied;
+        self.settlement += other.settlement;
+        self.worker_panics.extend(other.worker_panics);
+        for (reason, count) in other.rejected {
+            *self.rejected.entry(reason).or_default() += count;
+        }
+        for (kind, count) in other.warnings {
+            *self.warnings.entry(kind).or_default() += count;
+        }
+        Ok(())
+    }
+
+    pub fn into_accounts(self) -> HashMap<u16, ClientState> {
+        self.accounts.into_iter().collect()
+    }
+
+    /// Accounts opened, including those taken out by `drain_accounts`
+    pub fn account_count(&self) -> usize {
+        self.accounts.len() + self.drained
+    }
+
+    /// Takes every account out of the ledger, for them to be written without the ledger
+    /// keeping them, giving back what they held to the memory budget
+    pub fn drain_accounts(&mut self) -> impl Iterator<Item = ClientState> + '_ {
+        self.drained += self.accounts.len();
+        let bytes = (self.accounts.len() * ACCOUNT_BYTES).min(self.reported_memory);
+        self.reported_memory -= bytes;
+        if let Some(memory) = &self.memory {
+            memory.shrink(bytes);
+        }
+        self.accounts.drain().map(|(_, state)| state)
+    }
+
+    /// Opens the account with the balances of an earlier run, e.g. from `--opening-balances`,
+    /// in place of any the ledger holds. Its overdraft limit and locked policy are the
+    /// ledger's.
+    pub fn open_account(&mut self, state: ClientState) {
+        let limit = self.overdrafts.get(&state.id()).copied();
+        let state = state
+            .with_overdraft_limit(limit.unwrap_or_default())
+            .with_locked_policy(self.locked_policy)
+            .with_dispute_policy(self.dispute_policy)
+            .with_chargeback_limit(self.chargeback_limit);
+        self.chargebacks.remove(&state.id());
+        self.accounts.insert(state.id(), state);
+    }
+
+    /// Holds a deposit or withdrawal of an earlier run, e.g. from `--opening-balances`, as
+    /// `with_transactions` does. A dispute it is still under is reopened, after those
+    /// reopened before it, holding `held` if that is less than the amount.
+    pub fn restore_transaction(
+        &mut self,
+        tx_id: u32,
+        record: DisputeRecord,
+        held: Option<Decimal>,
+    ) {
+        if record.in_dispute() {
+            let open = self.open_disputes.entry(record.client_id()).or_default();
+            open.push(tx_id);
+            self.open_disputes_len += 1;
+            if let Some(held) = held {
+                self.partial_holds.insert(tx_id, held);
+            }
+        }
+        self.approved_tx.insert(tx_id, record);
+    }
+
+    /// Applies a single transaction, returning its account's new state
+    ///
+    /// # Errors
+    /// If the transaction is rejected, in which case no account changed
+    pub fn apply(&mut self, tx: Transaction) -> Result<AccountUpdate> {
+        let (client_id, tx_id) = (tx.client_id(), tx.tx_id());
+        self.process_transaction(tx).map_err(|(_, e)| e)?;
+        match self.accounts.get(&client_id) {
+            Some(state) => Ok(AccountUpdate::new(state, tx_id)),
+            None => bail!("Client account '{}' was not opened", client_id),
+        }
+    }
+
+    /// Applies the event like `process_transaction`, noting the sequence number of a dispute
+    /// it opens
+    fn process_sequenced(&mut self, event: Sequenced) -> Result<(), (Sequenced, anyhow::Error)> {
+        let Sequenced { seq, tx } = event;
+        let opens = matches!(tx.tx_type(), Dispute).then_some(tx.tx_id());
+        self.process_transaction(tx)
+            .map_err(|(tx, e)| (Sequenced { seq, tx }, e))?;
+        if let Some(tx_id) = opens {
+            self.dispute_seqs.insert(tx_id, seq);
+        }
+        Ok(())
+    }
+
+    /// Applies `tx`, keeping a `DisputeRecord` of deposits and withdrawals in `approved_tx`
+    /// so they can be disputed later. Hands the transaction back along with the error if it
+    /// was rejected.
+    pub(crate) fn process_transaction(
+        &mut self,
+        tx: Transaction,
+    ) -> Result<(), (Transaction, anyhow::Error)> {
+        let total = |ledger: &Self| {
+            ledger
+                .accounts
+                .get(&tx.client_id())
+                .map_or(Decimal::ZERO, ClientState::total)
+        };
+        let before = total(self);
+        let balances = match (tx.tx_type(), self.accounts.get(&tx.client_id())) {
+            (Chargeback, Some(state)) => Some((state.available(), state.held(), state.total())),
+            _ => None,
+        };
+        let flagged = match self.try_apply(&tx) {
+            Ok(flagged) => flagged,
+            Err(e) => return Err((tx, e)),
+        };
+        self.applied += 1;
+        if let (Some((available, held, total)), Some(record)) =
+            (balances, self.approved_tx.get(tx.tx_id()))
+        {
+            let chargeback = LockingChargeback {
+                tx_id: tx.tx_id(),
+                amount: record.amount(),
+                available,
+                held,
+                total,
+            };
+            self.chargebacks.insert(tx.client_id(), chargeback);
+        }
+        let change = total(self).saturating_sub(before);
+        if !change.is_zero() {
+            // A reversal moves money back the way the transaction it undoes came
+            let flow = match tx.tx_type() {
+                Reversal => self
+                    .approved_tx
+                    .get(tx.tx_id())
+                    .map(|record| record.tx_type()),
+                tx_type => Some(tx_type),
+            };
+            if let Some(flow) = flow {
+                self.settlement.record(flow, change);
+            }
+            if let Some(activity) = &mut self.activity {
+                activity.entry(tx.client_id()).or_default().push(Activity {
+                    tx_id: tx.tx_id(),
+                    tx_type: tx.tx_type(),
+                    amount: change,
+                });
+                self.activity_len += 1;
+            }
+        }
+        if let Some(history) = self.histories.get_mut(&tx.client_id()) {
+            history.push(&tx);
+        }
+        if let Some(limits) = &mut self.limits {
+            limits.record(&tx);
+        }
+        if let Some(state) = self.accounts.get_mut(&tx.client_id()) {
+            if self.audit {
+                state.record_applied(&tx);
+            }
+            if flagged {
+                state.flag_risk();
+            }
+            if let Some(sender) = &self.account_updates {
+                sender.send(AccountUpdate::new(state, tx.tx_id())).ok();
+            }
+        }
+        if let Some(sender) = &self.tx_outcomes {
+            sender
+                .send(TxOutcome {
+                    tx_id: tx.tx_id(),
+                    client_id: tx.client_id(),
+                    status: TxStatus::Applied,
+                })
+                .ok();
+        }
+        if let Some(sender) = &self.applied_transactions {
+            sender.send(tx.clone()).ok();
+        }
+        let stage = match tx.tx_type() {
+            Dispute => Some(DisputeStage::Opened),
+            Resolve => Some(DisputeStage::Resolved),
+            Chargeback => Some(DisputeStage::ChargedBack),
+            Deposit | Withdrawal | Reversal | Adjustment(_) | Custom(_) => None,
+        };
+        if let Some(stage) = stage {
+            self.index_dispute(&tx, stage);
+        }
+        if let Some(sender) = &self.dispute_events {
+            if let Some(stage) = stage {
+                sender
+                    .send(DisputeEvent {
+                        stage,
+                        client_id: tx.client_id(),
+                        tx_id: tx.tx_id(),
+                        amount: self
+                            .approved_tx
+                            .get(tx.tx_id())
+                            .map(|record| record.amount()),
+                    })
+                    .ok();
+            }
+        }
+        if let Some(record) = tx.dispute_record() {
+            self.approved_tx.insert(tx.tx_id(), record);
+        }
+        self.report_memory();
+        Ok(())
+    }
+
+    /// Keeps `open_disputes` in step with a dispute, resolve or chargeback applied to `tx`
+    fn index_dispute(&mut self, tx: &Transaction, stage: DisputeStage) {
+        let open = self.open_disputes.entry(tx.client_id()).or_default();
+        if stage == DisputeStage::Opened {
+            open.push(tx.tx_id());
+            self.open_disputes_len += 1;
+        } else if let Some(position) = open.iter().position(|tx_id| *tx_id == tx.tx_id()) {
+            open.remove(position);
+            self.open_disputes_len -= 1;
+            self.dispute_seqs.remove(&tx.tx_id());
+            self.partial_holds.remove(&tx.tx_id());
+        }
+        if open.is_empty() {
+            self.open_disputes.remove(&tx.client_id());
+        }
+    }
+
+    /// Applies `tx` within the memory budget and risk policy, returning whether it should be
+    /// flagged
+    fn try_apply(&mut self, tx: &Transaction) -> Result<bool> {
+        if let Some(max) = self.max_amount {
+            if let Some(amount) = tx.amount().filter(|amount| amount.abs() > max) {
+                let tx_id = tx.tx_id();
+                return Err(AmountOutOfBounds { tx_id, amount, max }.into());
+            }
+        }
+        if let Some(memory) = &self.memory {
+            memory.ensure(ACCOUNT_BYTES + TRANSACTION_BYTES)?;
+        }
+        // Ids are unique across clients, the first deposit or withdrawal with one keeps it.
+        // Across workers the router skips those of another client before they get here.
+        if tx.is_disputable() && self.approved_tx.contains(tx.tx_id()) {
+            return Err(Warning::duplicate_transaction(tx).into());
+        }
+        let flagged = self.assess_risk(tx)?;
+        self.apply_transaction(tx)?;
+        Ok(flagged)
+    }
+
+    /// Whether the transaction should be flagged once applied
+    fn assess_risk(&mut self, tx: &Transaction) -> Result<bool> {
+        let Some(policy) = &self.risk_policy else {
+            return Ok(false);
+        };
+        let history = self
+            .histories
             .entry(tx.client_id())
-            .or_insert_with(|| ClientState::new(tx.client_id()));
+            .or_insert_with(|| ClientHistory::new(policy.history_len()));
+
+        match policy.assess(tx, history) {
+            RiskDecision::Allow => Ok(false),
+            RiskDecision::Flag(reason) => {
+                warn!(
+                    "Transaction `{}` flagged by risk policy: {}",
+                    tx.tx_id(),
+                    reason
+                );
+                Ok(true)
+            }
+            RiskDecision::Reject(reason) => {
+                bail!(
+                    "Transaction `{}` rejected by risk policy: {}",
+                    tx.tx_id(),
+                    reason
+                )
+            }
+        }
+    }
+
+    fn apply_transaction(&mut self, tx: &Transaction) -> Result<()> {
+        let state = self.accounts.entry(tx.client_id()).or_insert_with(|| {
+            let limit = self.overdrafts.get(&tx.client_id()).copied();
+            ClientState::new(tx.client_id())
+                .with_overdraft_limit(limit.unwrap_or_default())
+                .with_locked_policy(self.locked_policy)
+                .with_dispute_policy(self.dispute_policy)
+                .with_chargeback_limit(self.chargeback_limit)
+        });
 
-        match (tx.tx_type(), self.approved_tx.get_mut(&tx.tx_id())) {
-            (Deposit, _) => state.deposit(tx).and_then(|_| self.record_tx(tx)),
-            (Withdrawal, _) => state.withdraw(tx).and_then(|_| self.record_tx(tx)),
-            (Dispute, Some(disputed_tx)) => state.dispute(tx, disputed_tx),
-            (Resolve, Some(disputed_tx)) => state.resolve(tx, disputed_tx),
-            (Chargeback, Some(chargeback_tx)) => state.chargeback(tx, chargeback_tx),
-            _ => bail!("Unmatched transaction `{:?}`", tx),
+        // The record is a copy, put back if the transaction changed its state
+        let stored = self.approved_tx.get(tx.tx_id());
+        let mut record = stored;
+        let applied = match (tx.tx_type(), record.as_mut()) {
+            (Deposit, _) => state.deposit(tx),
+            (Withdrawal, _) => {
+                if let Some(limits) = &self.limits {
+                    limits.check(tx)?;
+                }
+                state.withdraw(tx)
+            }
+            (Adjustment(_), _) => state.adjust(tx),
+            (Dispute, Some(disputed_tx)) if !self.disputable.allows(disputed_tx.tx_type()) => {
+                bail!(
+                    "Transaction `{}` is a {} which cannot be disputed",
+                    tx.tx_id(),
+                    disputed_tx.tx_type()
+                )
+            }
+            (Dispute, Some(disputed_tx)) => state.dispute(tx, disputed_tx).map(|held| {
+                if held != disputed_tx.amount() {
+                    self.partial_holds.insert(tx.tx_id(), held);
+                }
+            }),
+            (Resolve, Some(disputed_tx)) => {
+                let held = self.partial_holds.get(&tx.tx_id());
+                let held = held.copied().unwrap_or(disputed_tx.amount());
+                state.resolve(tx, disputed_tx, held)
+            }
+            (Chargeback, Some(chargeback_tx)) => {
+                let held = self.partial_holds.get(&tx.tx_id());
+                let held = held.copied().unwrap_or(chargeback_tx.amount());
+                state.chargeback(tx, chargeback_tx, held)
+            }
+            (Reversal, Some(reversed_tx)) => state.reverse(tx, reversed_tx),
+            (Custom(kind), _) => match &self.registry {
+                Some(registry) => registry.apply(kind, state, tx),
+                None => bail!("Transaction kind `{}` is not registered", tx.tx_type()),
+            },
+            _ => Err(Warning::unknown_transaction(tx).into()),
+        };
+        if let Some(record) = record.filter(|record| Some(*record) != stored) {
+            self.approved_tx.insert(tx.tx_id(), record);
         }
+        applied
+    }
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
     use rust_decimal::{prelude::FromPrimitive, Decimal};
+    use tokio::{sync::mpsc, time::Instant};
 
     use crate::{
-        data::{Transaction, TransactionType},
-        ledger::Ledger,
+        account::{ClientState, DisputeFundsPolicy, LockedAccountPolicy},
+        data::{
+            DisputableTypes, DisputeEvent, DisputeRecord, DisputeStage, LockedAccount,
+            LockingChargeback, ReasonCode, RecordState, Sequenced, Transaction, TransactionType,
+            TxStatus,
+        },
+        ledger::{
+            event_handler, AmountOutOfBounds, Ledger, MergeConflict, WorkerMsg, WorkerOptions,
+            WorkerSinks,
+        },
+        limits::{LimitConfig, WithdrawalLimit},
+        memory::{MemoryBudget, ACCOUNT_BYTES, OPEN_DISPUTE_BYTES, TRANSACTION_BYTES},
+        registry::TransactionRegistry,
+        retry::RetryPolicy,
+        risk::VelocityPolicy,
+        shutdown::Cancelled,
+        warning::{Warning, WarningKind},
     };
 
     #[test]
     fn load_and_record_transaction() {
         let mut test_ledger = Ledger::new();
-        let mut deposit_tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client_id: 123,
-            tx_id: 1,
-            amount: Some(Decimal::from_f64(200.).unwrap()),
-            in_dispute: false,
-        };
-        let mut withdrawal_tx = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            client_id: 123,
-            tx_id: 2,
-            amount: Some(Decimal::from_f64(100.).unwrap()),
-            in_dispute: false,
-        };
-        let mut tx = Transaction {
-            tx_type: TransactionType::Dispute,
-            client_id: 123,
-            tx_id: 2,
-            amount: None,
-            in_dispute: false,
-        };
-
-        test_ledger.process_transaction(&mut deposit_tx).unwrap();
-        test_ledger.process_transaction(&mut withdrawal_tx).unwrap();
+        let deposit_tx = Transaction::deposit(123, 1, Decimal::from_f64(200.).unwrap());
+        let withdrawal_tx = Transaction::withdrawal(123, 2, Decimal::from_f64(100.).unwrap());
+        let tx = Transaction::dispute(123, 2);
+
+        test_ledger.process_transaction(deposit_tx).unwrap();
+        test_ledger.process_transaction(withdrawal_tx).unwrap();
 
         assert_eq!(test_ledger.accounts.len(), 1);
         assert_eq!(test_ledger.approved_tx.len(), 2);
@@ -113,23 +1397,987 @@ mod test {
         assert_eq!(user_account.held().to_string(), "0");
         assert_eq!(user_account.total().to_string(), "100");
 
-        test_ledger.process_transaction(&mut tx).unwrap();
+        test_ledger.process_transaction(tx).unwrap();
         assert_eq!(test_ledger.approved_tx.len(), 2);
-        let disputed_tx = test_ledger.approved_tx.get(&2).unwrap();
+        let disputed_tx = test_ledger.approved_tx.get(2).unwrap();
         assert!(disputed_tx.in_dispute());
 
-        let disputed_tx = test_ledger.approved_tx.get(&2).unwrap();
+        let disputed_tx = test_ledger.approved_tx.get(2).unwrap();
         assert!(disputed_tx.in_dispute());
 
-        let mut resolve_tx = Transaction {
-            tx_type: TransactionType::Resolve,
-            client_id: 123,
-            tx_id: 2,
-            amount: None,
-            in_dispute: false,
-        };
-        test_ledger.process_transaction(&mut resolve_tx).unwrap();
-        let disputed_tx = test_ledger.approved_tx.get(&2).unwrap();
+        let resolve_tx = Transaction::resolve(123, 2);
+        test_ledger.process_transaction(resolve_tx).unwrap();
+        let disputed_tx = test_ledger.approved_tx.get(2).unwrap();
         assert!(!disputed_tx.in_dispute());
     }
+
+    #[test]
+    fn out_of_order_events_are_rejected() {
+        let mut test_ledger = Ledger::new();
+        let deposit_tx = Transaction::deposit(123, 1, Decimal::from_f64(200.).unwrap());
+        let dispute_tx = Transaction::dispute(123, 1);
+
+        let dispute = Sequenced {
+            seq: 40,
+            tx: dispute_tx,
+        };
+        let deposit = Sequenced {
+            seq: 20,
+            tx: deposit_tx,
+        };
+        assert!(test_ledger.process_event(dispute).is_err());
+        let result = test_ledger.process_event(deposit);
+        assert_eq!(
+            result.unwrap_err().1.to_string(),
+            "Transaction `1` arrived out of order (sequence 20 after 40)".to_string()
+        );
+        assert!(test_ledger.approved_tx.is_empty());
+    }
+
+    fn dispute_before_deposit() -> [Sequenced; 2] {
+        [
+            Sequenced {
+                seq: 1,
+                tx: Transaction::dispute(7, 1),
+            },
+            Sequenced {
+                seq: 2,
+                tx: Transaction::deposit(7, 1, Decimal::from_f64(50.).unwrap()),
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn failed_transactions_are_retried() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (dl_sender, mut dl_receiver) = mpsc::unbounded_channel();
+        for event in dispute_before_deposit() {
+            sender.send(WorkerMsg::Tx(event)).unwrap();
+        }
+        drop(sender);
+
+        let options = WorkerOptions {
+            retry: RetryPolicy {
+                retries: 2,
+                ..RetryPolicy::default()
+            },
+            ..WorkerOptions::default()
+        };
+        let sinks = WorkerSinks {
+            dead_letters: Some(dl_sender),
+            ..WorkerSinks::default()
+        };
+        let accounts = event_handler(receiver, options, sinks)
+            .await
+            .unwrap()
+            .into_accounts();
+        let user_account = accounts.get(&7).unwrap();
+        assert_eq!(user_account.held().to_string(), "50");
+        assert!(dl_receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn exhausted_transactions_are_dead_lettered() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (dl_sender, mut dl_receiver) = mpsc::unbounded_channel();
+        let (warning_sender, mut warning_receiver) = mpsc::unbounded_channel();
+        let overdrawn = Sequenced {
+            seq: 3,
+            tx: Transaction::withdrawal(7, 2, Decimal::ONE_HUNDRED),
+        };
+        for event in dispute_before_deposit().into_iter().chain([overdrawn]) {
+            sender.send(WorkerMsg::Tx(event)).unwrap();
+        }
+        drop(sender);
+
+        let ledger = event_handler(
+            receiver,
+            WorkerOptions::default(),
+            WorkerSinks {
+                dead_letters: Some(dl_sender),
+                warnings: Some(warning_sender),
+                ..WorkerSinks::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            ledger.warnings().get(&WarningKind::UnknownTransaction),
+            Some(&1)
+        );
+        assert_eq!(ledger.rejected().values().sum::<u64>(), 1);
+        assert_eq!(ledger.accounts[&7].held().to_string(), "0");
+        // The unmatched dispute is a warning, only the failed withdrawal is dead-lettered
+        let skipped = warning_receiver.recv().await.unwrap();
+        assert_eq!(skipped.event.seq, 1);
+        assert!(skipped.warning.message.starts_with("Unmatched transaction"));
+        let dead_letter = dl_receiver.recv().await.unwrap();
+        assert_eq!(dead_letter.event.seq, 3);
+        assert!(dead_letter.reason.contains("insufficient funds"));
+        assert!(dl_receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn amounts_out_of_bounds_are_dead_lettered_without_retries() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (dl_sender, mut dl_receiver) = mpsc::unbounded_channel();
+        let max = Decimal::from(1_000_000_000_000_u64);
+        let events = [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::deposit(1, 2, max * Decimal::TEN),
+            Transaction::deposit(1, 3, max),
+        ];
+        for (seq, tx) in (1..).zip(events) {
+            sender.send(WorkerMsg::Tx(Sequenced { seq, tx })).unwrap();
+        }
+        drop(sender);
+
+        let options = WorkerOptions {
+            retry: RetryPolicy {
+                retries: 3,
+                ..RetryPolicy::default()
+            },
+            max_amount: Some(max),
+            ..WorkerOptions::default()
+        };
+        let sinks = WorkerSinks {
+            dead_letters: Some(dl_sender),
+            ..WorkerSinks::default()
+        };
+        let ledger = event_handler(receiver, options, sinks).await.unwrap();
+        assert_eq!(ledger.applied(), 2);
+        let dead_letter = dl_receiver.recv().await.unwrap();
+        assert_eq!(dead_letter.event.seq, 2);
+        assert_eq!(
+            dead_letter.reason,
+            "Amount `10000000000000` of transaction `2` exceeds the bound of 1000000000000"
+        );
+        assert!(dl_receiver.recv().await.is_none());
+    }
+
+    #[test]
+    fn amounts_out_of_bounds_are_rejected() {
+        let max = Decimal::from(1_000_000_000_000_u64);
+        let mut test_ledger = Ledger::new().with_max_amount(Some(max));
+        test_ledger
+            .apply(Transaction::deposit(1, 1, Decimal::TEN))
+            .unwrap();
+        let e = test_ledger
+            .apply(Transaction::deposit(1, 2, max * Decimal::TEN))
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<AmountOutOfBounds>(),
+            Some(&AmountOutOfBounds {
+                tx_id: 2,
+                amount: max * Decimal::TEN,
+                max
+            })
+        );
+        assert_eq!(
+            e.to_string(),
+            "Amount `10000000000000` of transaction `2` exceeds the bound of 1000000000000"
+        );
+        assert!(test_ledger
+            .apply(Transaction::withdrawal(1, 3, -max - Decimal::ONE))
+            .is_err());
+        // An amount of exactly the bound is applied
+        test_ledger.apply(Transaction::deposit(1, 4, max)).unwrap();
+        assert_eq!(test_ledger.applied(), 2);
+        assert_eq!(test_ledger.accounts[&1].available(), max + Decimal::TEN);
+    }
+
+    #[test]
+    fn duplicate_transactions_are_skipped() {
+        let mut test_ledger = Ledger::new();
+        test_ledger
+            .apply(Transaction::deposit(1, 1, Decimal::TEN))
+            .unwrap();
+        let e = test_ledger
+            .apply(Transaction::deposit(1, 1, Decimal::TEN))
+            .unwrap_err();
+        let warning = e.downcast_ref::<Warning>().unwrap();
+        assert_eq!(warning.kind, WarningKind::DuplicateTransaction);
+        assert_eq!(
+            warning.message,
+            "Transaction `1` was already applied, the deposit was skipped"
+        );
+        assert_eq!(test_ledger.accounts[&1].available(), Decimal::TEN);
+
+        // The id stays with its first client
+        let e = test_ledger
+            .apply(Transaction::deposit(2, 1, Decimal::TEN))
+            .unwrap_err();
+        let warning = e.downcast_ref::<Warning>().unwrap();
+        assert_eq!(warning.kind, WarningKind::DuplicateTransaction);
+        assert!(!test_ledger.accounts.contains_key(&2));
+        assert_eq!(test_ledger.transaction(1).unwrap().client_id(), 1);
+        assert_eq!(test_ledger.applied(), 1);
+    }
+
+    #[tokio::test]
+    async fn every_transaction_publishes_its_outcome() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (outcome_sender, mut outcome_receiver) = mpsc::unbounded_channel();
+        let transactions = [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::withdrawal(1, 2, Decimal::ONE_HUNDRED),
+        ];
+        for (seq, tx) in (0..).zip(transactions) {
+            sender.send(WorkerMsg::Tx(Sequenced { seq, tx })).unwrap();
+        }
+        drop(sender);
+
+        let sinks = WorkerSinks {
+            tx_outcomes: Some(outcome_sender),
+            ..WorkerSinks::default()
+        };
+        event_handler(receiver, WorkerOptions::default(), sinks)
+            .await
+            .unwrap();
+        let applied = outcome_receiver.recv().await.unwrap();
+        assert_eq!(
+            (applied.tx_id, applied.client_id, applied.status),
+            (1, 1, TxStatus::Applied)
+        );
+        let rejected = outcome_receiver.recv().await.unwrap();
+        assert_eq!((rejected.tx_id, rejected.client_id), (2, 1));
+        let TxStatus::Rejected(reason) = rejected.status else {
+            panic!("the withdrawal should be rejected");
+        };
+        assert!(reason.contains("insufficient funds"), "{reason}");
+        assert!(outcome_receiver.recv().await.is_none());
+    }
+
+    #[test]
+    fn buffered_dispute_is_applied_when_its_deposit_arrives() {
+        let mut test_ledger = Ledger::new().with_reorder_window(1);
+        let [dispute, deposit] = dispute_before_deposit();
+
+        test_ledger.process_event(dispute).unwrap();
+        assert_eq!(test_ledger.buffered.len(), 1);
+        test_ledger.process_event(deposit).unwrap();
+
+        assert!(test_ledger.buffered.is_empty());
+        assert!(test_ledger.unmatched.is_empty());
+        let user_account = test_ledger.accounts.get(&7).unwrap();
+        assert_eq!(user_account.held().to_string(), "50");
+        assert!(test_ledger.approved_tx.get(1).unwrap().in_dispute());
+    }
+
+    #[test]
+    fn buffered_dispute_expires_after_the_reorder_window() {
+        let mut test_ledger = Ledger::new().with_reorder_window(1);
+        let [dispute, mut deposit] = dispute_before_deposit();
+        let other_deposit = Sequenced {
+            seq: 2,
+            tx: Transaction::deposit(7, 2, Decimal::from_f64(50.).unwrap()),
+        };
+        deposit.seq = 3;
+
+        test_ledger.process_event(dispute).unwrap();
+        test_ledger.process_event(other_deposit).unwrap();
+        test_ledger.process_event(deposit).unwrap();
+
+        assert!(test_ledger.buffered.is_empty());
+        assert_eq!(test_ledger.unmatched.len(), 1);
+        assert!(test_ledger.unmatched[0]
+            .1
+            .to_string()
+            .starts_with("Unmatched transaction"));
+        assert_eq!(
+            test_ledger.accounts.get(&7).unwrap().held().to_string(),
+            "0"
+        );
+    }
+
+    #[test]
+    fn audit_chain_only_covers_applied_transactions() {
+        let mut audited = Ledger::new().with_audit(true);
+        let mut unaudited = Ledger::new();
+        let [dispute, deposit] = dispute_before_deposit();
+
+        for ledger in [&mut audited, &mut unaudited] {
+            ledger.process_transaction(deposit.tx.clone()).unwrap();
+        }
+        let after_deposit = *audited.accounts.get(&7).unwrap().audit_head();
+        assert_ne!(after_deposit, [0; 32]);
+        assert_eq!(unaudited.accounts.get(&7).unwrap().audit_head(), &[0; 32]);
+
+        // A failed withdrawal leaves the chain untouched, the dispute extends it
+        let withdrawal = Transaction::withdrawal(7, 2, Decimal::from_f64(500.).unwrap());
+        assert!(audited.process_transaction(withdrawal).is_err());
+        assert_eq!(
+            audited.accounts.get(&7).unwrap().audit_head(),
+            &after_deposit
+        );
+        audited.process_transaction(dispute.tx).unwrap();
+        assert_ne!(
+            audited.accounts.get(&7).unwrap().audit_head(),
+            &after_deposit
+        );
+    }
+
+    #[test]
+    fn adjustments_are_audited_with_their_reason() {
+        let mut refunded = Ledger::new().with_audit(true);
+        let mut corrected = Ledger::new().with_audit(true);
+        for (ledger, reason) in [
+            (&mut refunded, ReasonCode::Refund),
+            (&mut corrected, ReasonCode::Correction),
+        ] {
+            ledger
+                .process_transaction(Transaction::deposit(7, 1, Decimal::TEN))
+                .unwrap();
+            ledger
+                .process_transaction(Transaction::adjustment(7, 2, -Decimal::ONE, reason))
+                .unwrap();
+            assert_eq!(ledger.accounts[&7].available(), Decimal::from(9));
+            // Only deposits and withdrawals can be disputed
+            assert!(ledger
+                .process_transaction(Transaction::dispute(7, 2))
+                .is_err());
+        }
+        assert_ne!(
+            refunded.accounts[&7].audit_head(),
+            corrected.accounts[&7].audit_head()
+        );
+    }
+
+    /// Client 7's account locked by a chargeback, followed by its `later` transactions
+    fn locked_then(later: Vec<Transaction>) -> Vec<Sequenced> {
+        [
+            Transaction::deposit(7, 1, Decimal::TEN),
+            Transaction::dispute(7, 1),
+            Transaction::chargeback(7, 1),
+        ]
+        .into_iter()
+        .chain(later)
+        .zip(1..)
+        .map(|(tx, seq)| Sequenced { seq, tx })
+        .collect()
+    }
+
+    #[test]
+    fn unlock_replays_quarantined_transactions_in_order() {
+        let mut test_ledger = Ledger::new();
+        assert!(test_ledger.unlock(7).is_err());
+        for event in locked_then(vec![
+            Transaction::deposit(7, 2, Decimal::TEN),
+            Transaction::withdrawal(7, 3, Decimal::from(4)),
+            Transaction::withdrawal(7, 4, Decimal::from(40)),
+        ]) {
+            test_ledger.process_event(event).unwrap();
+        }
+        assert_eq!(test_ledger.quarantine[&7].len(), 3);
+        assert_eq!(test_ledger.accounts[&7].available(), Decimal::ZERO);
+
+        test_ledger.unlock(7).unwrap();
+        assert!(!test_ledger.accounts[&7].is_locked());
+        assert_eq!(test_ledger.accounts[&7].available(), Decimal::from(6));
+        assert!(test_ledger.quarantine.is_empty());
+        assert_eq!(test_ledger.unmatched.len(), 1);
+        assert_eq!(test_ledger.unmatched[0].0.seq, 6);
+    }
+
+    #[test]
+    fn chargebacks_past_the_limit_freeze_the_account() {
+        let mut test_ledger = Ledger::new()
+            .with_locked_policy(LockedAccountPolicy::AllowDeposits)
+            .with_chargeback_limit(Some(Decimal::from_f64(0.4).unwrap()));
+        let events = [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::deposit(1, 2, Decimal::TEN),
+            Transaction::deposit(1, 3, Decimal::TEN),
+            Transaction::dispute(1, 3),
+            Transaction::chargeback(1, 3),
+            Transaction::deposit(2, 4, Decimal::TEN),
+            Transaction::deposit(2, 5, Decimal::TEN),
+            Transaction::dispute(2, 5),
+            Transaction::chargeback(2, 5),
+        ];
+        for tx in events {
+            test_ledger.process_transaction(tx).unwrap();
+        }
+        // A ratio of 1/3 is within the limit: the account is only locked, and still takes
+        // deposits under the policy
+        assert!(test_ledger.accounts[&1].is_locked());
+        assert!(!test_ledger.accounts[&1].is_frozen());
+        test_ledger
+            .process_transaction(Transaction::deposit(1, 6, Decimal::ONE))
+            .unwrap();
+        assert_eq!(test_ledger.accounts[&1].available(), Decimal::from(21));
+
+        // A ratio of 1/2 is past it: the account accepts nothing, deposits included
+        assert!(test_ledger.accounts[&2].is_frozen());
+        let e = test_ledger
+            .process_transaction(Transaction::deposit(2, 7, Decimal::ONE))
+            .unwrap_err()
+            .1;
+        assert_eq!(e.to_string(), "Account '2' is frozen");
+        assert_eq!(test_ledger.accounts[&2].available(), Decimal::TEN);
+
+        test_ledger.unlock(2).unwrap();
+        assert!(!test_ledger.accounts[&2].is_frozen());
+    }
+
+    #[tokio::test]
+    async fn quarantined_transactions_are_dead_lettered_without_unlock() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (dl_sender, mut dl_receiver) = mpsc::unbounded_channel();
+        for event in locked_then(vec![Transaction::deposit(7, 2, Decimal::TEN)]) {
+            sender.send(WorkerMsg::Tx(event)).unwrap();
+        }
+        drop(sender);
+
+        let options = WorkerOptions {
+            retry: RetryPolicy {
+                retries: 3,
+                ..RetryPolicy::default()
+            },
+            ..WorkerOptions::default()
+        };
+        let sinks = WorkerSinks {
+            dead_letters: Some(dl_sender),
+            ..WorkerSinks::default()
+        };
+        let ledger = event_handler(receiver, options, sinks).await.unwrap();
+        let dead_letter = dl_receiver.recv().await.unwrap();
+        assert_eq!(dead_letter.event.seq, 4);
+        assert_eq!(dead_letter.reason, "Account '7' is locked");
+        assert_eq!(ledger.rejected().get("Account '*' is locked"), Some(&1));
+        assert!(dl_receiver.recv().await.is_none());
+    }
+
+    #[test]
+    fn merge_rejects_shared_clients_and_transactions() {
+        let ledger_of = |txs: Vec<Transaction>| {
+            let mut ledger = Ledger::new();
+            for tx in txs {
+                ledger.process_transaction(tx).unwrap();
+            }
+            ledger
+        };
+        let mut merged = ledger_of(vec![Transaction::deposit(1, 1, Decimal::TEN)]);
+        merged
+            .merge(ledger_of(vec![
+                Transaction::deposit(2, 2, Decimal::ONE),
+                Transaction::deposit(2, 3, Decimal::ONE),
+                Transaction::dispute(2, 3),
+            ]))
+            .unwrap();
+        assert_eq!(merged.accounts.len(), 2);
+        assert_eq!(merged.open_disputes(2), &[3]);
+        merged
+            .process_transaction(Transaction::dispute(2, 2))
+            .unwrap();
+        assert_eq!(merged.applied(), 5);
+
+        let shared_client = ledger_of(vec![Transaction::deposit(1, 4, Decimal::ONE)]);
+        assert_eq!(
+            merged.merge(shared_client).unwrap_err().to_string(),
+            "Client '1' is held by both ledgers"
+        );
+        let shared_tx = ledger_of(vec![Transaction::deposit(3, 2, Decimal::ONE)]);
+        assert_eq!(merged.merge(shared_tx), Err(MergeConflict::Transaction(2)));
+        assert_eq!(merged.accounts.len(), 2);
+        assert_eq!(merged.approved_tx.len(), 3);
+    }
+
+    #[test]
+    fn applied_disputes_are_published() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut test_ledger = Ledger::new().with_dispute_events(Some(sender));
+        let [dispute, deposit] = dispute_before_deposit();
+
+        // Unmatched disputes and deposits publish nothing
+        assert!(test_ledger.process_transaction(dispute.tx.clone()).is_err());
+        test_ledger.process_transaction(deposit.tx).unwrap();
+        test_ledger.process_transaction(dispute.tx).unwrap();
+        test_ledger
+            .process_transaction(Transaction::chargeback(7, 1))
+            .unwrap();
+
+        let opened = receiver.try_recv().unwrap();
+        assert_eq!(
+            opened,
+            DisputeEvent {
+                stage: DisputeStage::Opened,
+                client_id: 7,
+                tx_id: 1,
+                amount: Some(Decimal::from_f64(50.).unwrap()),
+            }
+        );
+        assert_eq!(
+            receiver.try_recv().unwrap().stage,
+            DisputeStage::ChargedBack
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn open_disputes_are_indexed_per_client() {
+        let mut test_ledger = Ledger::new();
+        for tx_id in 1..=3 {
+            test_ledger
+                .process_transaction(Transaction::deposit(7, tx_id, Decimal::TEN))
+                .unwrap();
+            test_ledger
+                .process_transaction(Transaction::dispute(7, tx_id))
+                .unwrap();
+        }
+        test_ledger
+            .process_transaction(Transaction::deposit(8, 4, Decimal::TEN))
+            .unwrap();
+        assert_eq!(test_ledger.open_disputes(7), &[1, 2, 3]);
+        assert!(test_ledger.open_disputes(8).is_empty());
+
+        test_ledger
+            .process_transaction(Transaction::resolve(7, 2))
+            .unwrap();
+        assert!(test_ledger
+            .process_transaction(Transaction::resolve(7, 2))
+            .is_err());
+        assert_eq!(test_ledger.open_disputes(7), &[1, 3]);
+        test_ledger
+            .process_transaction(Transaction::resolve(7, 3))
+            .unwrap();
+        test_ledger
+            .process_transaction(Transaction::chargeback(7, 1))
+            .unwrap();
+        assert!(test_ledger.open_disputes(7).is_empty());
+        assert!(test_ledger.open_disputes.is_empty());
+    }
+
+    #[test]
+    fn open_disputes_are_listed_oldest_first() {
+        let mut test_ledger = Ledger::new();
+        let events = [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::deposit(2, 2, Decimal::ONE),
+            Transaction::deposit(1, 3, Decimal::TWO),
+            Transaction::dispute(2, 2),
+            Transaction::dispute(1, 3),
+            Transaction::dispute(1, 1),
+            Transaction::resolve(1, 3),
+        ];
+        for (seq, tx) in (0..).zip(events) {
+            test_ledger.process_event(Sequenced { seq, tx }).unwrap();
+        }
+        test_ledger
+            .process_transaction(Transaction::deposit(3, 4, Decimal::ONE_HUNDRED))
+            .unwrap();
+        test_ledger
+            .process_transaction(Transaction::dispute(3, 4))
+            .unwrap();
+
+        let open: Vec<_> = test_ledger
+            .open_dispute_ages()
+            .into_iter()
+            .map(|dispute| {
+                (
+                    dispute.client_id,
+                    dispute.tx_id,
+                    dispute.held,
+                    dispute.opened_seq,
+                )
+            })
+            .collect();
+        assert_eq!(
+            open,
+            vec![
+                (2, 2, Decimal::ONE, Some(3)),
+                (1, 1, Decimal::TEN, Some(5)),
+                (3, 4, Decimal::ONE_HUNDRED, None),
+            ]
+        );
+        assert!(!test_ledger.dispute_seqs.contains_key(&3));
+    }
+
+    #[test]
+    fn holds_add_up_to_held_balances() {
+        let mut test_ledger = Ledger::new();
+        let events = [
+            Transaction::deposit(2, 1, Decimal::TEN),
+            Transaction::deposit(1, 2, Decimal::ONE),
+            Transaction::deposit(2, 3, Decimal::TWO),
+            Transaction::withdrawal(2, 4, Decimal::ONE),
+            Transaction::dispute(2, 3),
+            Transaction::dispute(1, 2),
+            Transaction::dispute(2, 4),
+            Transaction::dispute(2, 1),
+            Transaction::chargeback(2, 1),
+        ];
+        for (seq, tx) in (0..).zip(events) {
+            test_ledger.process_event(Sequenced { seq, tx }).unwrap();
+        }
+
+        let holds: Vec<_> = test_ledger
+            .holds()
+            .into_iter()
+            .map(|hold| (hold.client_id, hold.tx_id, hold.held, hold.opened_seq))
+            .collect();
+        assert_eq!(
+            holds,
+            vec![
+                (1, 2, Decimal::ONE, Some(5)),
+                (2, 3, Decimal::TWO, Some(4)),
+                (2, 4, Decimal::ONE, Some(6)),
+            ]
+        );
+        for state in test_ledger.accounts() {
+            let held: Decimal = holds
+                .iter()
+                .filter(|(client_id, ..)| *client_id == state.id())
+                .map(|(_, _, held, _)| *held)
+                .sum();
+            assert_eq!(state.held(), held);
+        }
+    }
+
+    #[test]
+    fn partial_holds_are_reported_and_released() {
+        let mut test_ledger = Ledger::new().with_dispute_policy(DisputeFundsPolicy::HoldPartial);
+        for tx in [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::withdrawal(1, 2, Decimal::from(7)),
+            Transaction::dispute(1, 1),
+        ] {
+            test_ledger.process_transaction(tx).unwrap();
+        }
+        assert_eq!(test_ledger.holds()[0].held, Decimal::from(3));
+        assert_eq!(
+            test_ledger.partial_holds().collect::<Vec<_>>(),
+            vec![(1, Decimal::from(3))]
+        );
+
+        test_ledger
+            .process_transaction(Transaction::resolve(1, 1))
+            .unwrap();
+        let state = test_ledger.accounts.get(&1).unwrap();
+        assert_eq!(
+            (state.available(), state.held()),
+            (Decimal::from(3), Decimal::ZERO)
+        );
+        assert!(test_ledger.holds().is_empty());
+        assert_eq!(test_ledger.partial_holds().count(), 0);
+    }
+
+    #[test]
+    fn restored_chargebacks_stay_closed() {
+        let record =
+            |amount, state| DisputeRecord::restore(1, TransactionType::Deposit, amount, state);
+        let transactions = HashMap::from([
+            (1, record(Decimal::TEN, RecordState::ChargedBack)),
+            (2, record(Decimal::TWO, RecordState::Disputed)),
+        ]);
+        let holds = HashMap::from([(1, Decimal::ONE), (2, Decimal::ONE)]);
+        let restored = ClientState::restore(1, Decimal::ZERO, Decimal::ONE, false);
+        let mut test_ledger = Ledger::new()
+            .with_accounts(HashMap::from([(1, restored)]))
+            .with_transactions(transactions)
+            .with_partial_holds(holds);
+        assert_eq!(test_ledger.open_disputes(1), &[2]);
+        assert_eq!(
+            test_ledger.partial_holds().collect::<Vec<_>>(),
+            vec![(2, Decimal::ONE)]
+        );
+
+        for tx in [Transaction::resolve(1, 1), Transaction::dispute(1, 1)] {
+            assert!(test_ledger.process_transaction(tx).is_err());
+        }
+        let state = test_ledger.accounts.get(&1).unwrap();
+        assert_eq!(
+            (state.available(), state.held()),
+            (Decimal::ZERO, Decimal::ONE)
+        );
+    }
+
+    #[test]
+    fn locked_accounts_keep_the_chargeback_which_locked_them() {
+        let restored = ClientState::restore(3, Decimal::ONE, Decimal::ZERO, true);
+        let mut test_ledger = Ledger::new().with_accounts(HashMap::from([(3, restored)]));
+        let events = [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::deposit(1, 2, Decimal::TWO),
+            Transaction::deposit(2, 3, Decimal::ONE),
+            Transaction::dispute(1, 2),
+            Transaction::dispute(2, 3),
+            Transaction::chargeback(1, 2),
+            Transaction::resolve(2, 3),
+            Transaction::chargeback(1, 1),
+        ];
+        for tx in events {
+            let _ = test_ledger.process_transaction(tx);
+        }
+
+        assert_eq!(
+            test_ledger.locked_accounts(),
+            vec![
+                LockedAccount {
+                    client_id: 1,
+                    chargeback: Some(LockingChargeback {
+                        tx_id: 2,
+                        amount: Decimal::TWO,
+                        available: Decimal::TEN,
+                        held: Decimal::TWO,
+                        total: Decimal::from(12),
+                    }),
+                },
+                LockedAccount {
+                    client_id: 3,
+                    chargeback: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_kinds_are_applied_by_their_handler() {
+        let mut registry = TransactionRegistry::new();
+        let bonus = registry
+            .register("bonus", |state, tx| {
+                state.adjust_available(tx, tx.amount().unwrap_or_default())
+            })
+            .unwrap();
+
+        let mut unregistered = Ledger::new();
+        let e = unregistered
+            .process_transaction(Transaction::custom(bonus, 7, 1, Some(Decimal::TEN)))
+            .unwrap_err()
+            .1;
+        assert_eq!(
+            e.to_string(),
+            "Transaction kind `custom:0` is not registered"
+        );
+
+        let mut test_ledger = Ledger::new().with_registry(Arc::new(registry));
+        test_ledger
+            .process_transaction(Transaction::deposit(7, 1, Decimal::ONE))
+            .unwrap();
+        test_ledger
+            .process_transaction(Transaction::custom(bonus, 7, 2, Some(Decimal::TEN)))
+            .unwrap();
+        assert_eq!(test_ledger.accounts[&7].available(), Decimal::from(11));
+        // Only deposits and withdrawals can be disputed
+        assert!(test_ledger
+            .process_transaction(Transaction::dispute(7, 2))
+            .is_err());
+    }
+
+    #[test]
+    fn applied_transactions_publish_account_updates() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut test_ledger = Ledger::new().with_account_updates(Some(sender));
+        let [dispute, deposit] = dispute_before_deposit();
+
+        assert!(test_ledger.process_transaction(dispute.tx.clone()).is_err());
+        test_ledger.process_transaction(deposit.tx).unwrap();
+        test_ledger.process_transaction(dispute.tx).unwrap();
+
+        assert_eq!(receiver.try_recv().unwrap().available.to_string(), "50");
+        let disputed = receiver.try_recv().unwrap();
+        assert_eq!(
+            (disputed.tx, disputed.held.to_string()),
+            (1, "50".to_owned())
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn worker_stops_once_over_its_memory_budget() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        for (seq, client) in (0..3).zip(1..) {
+            let tx = Transaction::deposit(client, u32::from(client), Decimal::ONE);
+            sender.send(WorkerMsg::Tx(Sequenced { seq, tx })).unwrap();
+        }
+        drop(sender);
+
+        // Room for two clients with a deposit each, the third is refused
+        let memory = Arc::new(MemoryBudget::new(
+            3 * (ACCOUNT_BYTES + TRANSACTION_BYTES) - 1,
+        ));
+        let options = WorkerOptions {
+            memory: Some(Arc::clone(&memory)),
+            ..WorkerOptions::default()
+        };
+        let cancel = options.cancel.clone();
+        let Err(e) = event_handler(receiver, options, WorkerSinks::default()).await else {
+            panic!("worker should exceed its memory budget");
+        };
+        assert!(e.to_string().starts_with("Memory budget of"));
+        assert_eq!(memory.used(), 2 * (ACCOUNT_BYTES + TRANSACTION_BYTES));
+        // The other workers of the input stop too
+        assert!(cancel.is_cancelled());
+    }
+
+    #[test]
+    fn memory_budget_follows_disputes_and_drained_accounts() {
+        let memory = Arc::new(MemoryBudget::new(usize::MAX));
+        let mut test_ledger = Ledger::new().with_memory_budget(Some(Arc::clone(&memory)));
+        test_ledger
+            .apply(Transaction::deposit(1, 1, Decimal::TEN))
+            .unwrap();
+        let settled = ACCOUNT_BYTES + TRANSACTION_BYTES;
+        assert_eq!(memory.used(), settled);
+        test_ledger.apply(Transaction::dispute(1, 1)).unwrap();
+        assert_eq!(memory.used(), settled + OPEN_DISPUTE_BYTES);
+        assert_eq!(test_ledger.memory_usage(), memory.used());
+
+        // Closing the dispute and draining the account give their bytes back
+        test_ledger.apply(Transaction::resolve(1, 1)).unwrap();
+        assert_eq!(memory.used(), settled);
+        assert_eq!(test_ledger.drain_accounts().count(), 1);
+        assert_eq!(memory.used(), TRANSACTION_BYTES);
+    }
+
+    #[tokio::test]
+    async fn worker_alerts_on_latency_over_budget() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let read = Instant::now() - Duration::from_millis(20);
+        for seq in 0..2 {
+            let tx = Transaction::deposit(1, u32::try_from(seq).unwrap(), Decimal::ONE);
+            sender
+                .send(WorkerMsg::Timed(Sequenced { seq, tx }, read))
+                .unwrap();
+        }
+        drop(sender);
+
+        let (alert_sender, mut alerts) = mpsc::unbounded_channel();
+        let options = WorkerOptions {
+            latency_budget: Some(Duration::from_millis(10)),
+            ..WorkerOptions::default()
+        };
+        let sinks = WorkerSinks {
+            latency_alerts: Some(alert_sender),
+            ..WorkerSinks::default()
+        };
+        let ledger = event_handler(receiver, options, sinks).await.unwrap();
+        assert_eq!(ledger.summary(1).unwrap().total, Decimal::TWO);
+        let alert = alerts.try_recv().unwrap();
+        assert!(alert.p99 >= Duration::from_millis(20));
+        assert_eq!(alert.samples, 2);
+    }
+
+    #[tokio::test]
+    async fn cancelled_worker_stops_while_its_channel_is_open() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let tx = Transaction::deposit(1, 1, Decimal::ONE);
+        sender
+            .send(WorkerMsg::Tx(Sequenced { seq: 0, tx }))
+            .unwrap();
+        let options = WorkerOptions::default();
+        let cancel = options.cancel.clone();
+        let worker = tokio::spawn(event_handler(receiver, options, WorkerSinks::default()));
+
+        cancel.cancel();
+        let Err(e) = worker.await.unwrap() else {
+            panic!("worker should stop once cancelled");
+        };
+        assert!(e.is::<Cancelled>());
+        drop(sender);
+    }
+
+    #[test]
+    fn risk_policy_flags_and_rejects() {
+        let policy = VelocityPolicy {
+            max_withdrawals: 1,
+            window: 2,
+            reject: false,
+        };
+        let mut test_ledger = Ledger::new().with_risk_policy(Box::new(policy));
+        let amount = Decimal::from_f64(10.).unwrap();
+        test_ledger
+            .process_transaction(Transaction::deposit(5, 1, amount))
+            .unwrap();
+        test_ledger
+            .process_transaction(Transaction::withdrawal(5, 2, Decimal::ONE))
+            .unwrap();
+        test_ledger
+            .process_transaction(Transaction::withdrawal(5, 3, Decimal::ONE))
+            .unwrap();
+        assert_eq!(test_ledger.accounts.get(&5).unwrap().risk_flags(), 1);
+
+        let mut rejecting = Ledger::new().with_risk_policy(Box::new(VelocityPolicy {
+            reject: true,
+            ..policy
+        }));
+        rejecting
+            .process_transaction(Transaction::deposit(5, 1, amount))
+            .unwrap();
+        rejecting
+            .process_transaction(Transaction::withdrawal(5, 2, Decimal::ONE))
+            .unwrap();
+        let result = rejecting.process_transaction(Transaction::withdrawal(5, 3, Decimal::ONE));
+        assert_eq!(
+            result.unwrap_err().1.to_string(),
+            "Transaction `3` rejected by risk policy: 2 withdrawals in the last 2 transactions"
+        );
+        assert_eq!(
+            rejecting.accounts.get(&5).unwrap().available().to_string(),
+            "9"
+        );
+    }
+
+    #[test]
+    fn withdrawal_limits_reject_before_withdrawing() {
+        let limits = LimitConfig {
+            clients: [(
+                5,
+                WithdrawalLimit {
+                    window: 3,
+                    max_withdrawals: None,
+                    max_withdrawn: Some(Decimal::TEN),
+                },
+            )]
+            .into(),
+            ..LimitConfig::default()
+        };
+        let mut test_ledger = Ledger::new().with_withdrawal_limits(Some(Arc::new(limits)));
+        for client in [5, 6] {
+            test_ledger
+                .process_transaction(Transaction::deposit(client, u32::from(client), 100.into()))
+                .unwrap();
+            test_ledger
+                .process_transaction(Transaction::withdrawal(
+                    client,
+                    10 + u32::from(client),
+                    8.into(),
+                ))
+                .unwrap();
+        }
+        let result = test_ledger.process_transaction(Transaction::withdrawal(5, 20, 3.into()));
+        assert_eq!(
+            result.unwrap_err().1.to_string(),
+            "Withdrawal `20` exceeds the limit of 10 withdrawn in 3 transactions of client `5`"
+        );
+        // Clients without a limit withdraw freely
+        test_ledger
+            .process_transaction(Transaction::withdrawal(6, 21, 3.into()))
+            .unwrap();
+        assert_eq!(test_ledger.accounts.get(&5).unwrap().available(), 92.into());
+    }
+
+    #[test]
+    fn disputes_reference_disputable_types_only() {
+        let deposits_only = DisputableTypes {
+            deposits: true,
+            withdrawals: false,
+        };
+        let mut test_ledger = Ledger::new().with_disputable_types(deposits_only);
+        test_ledger
+            .process_transaction(Transaction::deposit(1, 1, Decimal::TEN))
+            .unwrap();
+        test_ledger
+            .process_transaction(Transaction::withdrawal(1, 2, Decimal::ONE))
+            .unwrap();
+        let result = test_ledger.process_transaction(Transaction::dispute(1, 2));
+        assert_eq!(
+            result.unwrap_err().1.to_string(),
+            "Transaction `2` is a withdrawal which cannot be disputed"
+        );
+        test_ledger
+            .process_transaction(Transaction::dispute(1, 1))
+            .unwrap();
+        assert_eq!(test_ledger.accounts.get(&1).unwrap().held(), Decimal::TEN);
+    }
 }
@@ -0,0 +1,75 @@
+#![deny(rust_2018_idioms)]
+#![deny(clippy::correctness)]
+#![deny(clippy::perf)]
+#![deny(clippy::all)]
+#![deny(clippy::pedantic)]
+#![allow(clippy::must_use_candidate)]
+
+// Both would be the global allocator of the binary
+#[cfg(all(feature = "mimalloc", feature = "jemalloc"))]
+compile_error!("The `mimalloc` and `jemalloc` features each set the global allocator, enable one");
+
+pub mod account;
+#[cfg(feature = "avro")]
+pub mod avro;
+#[cfg(feature = "runtime")]
+pub mod bisect;
+pub mod camt;
+#[cfg(feature = "runtime")]
+pub mod cli;
+pub mod data;
+#[cfg(feature = "runtime")]
+pub mod determinism;
+pub mod digest;
+#[cfg(feature = "runtime")]
+pub mod explain;
+#[cfg(feature = "runtime")]
+pub mod explorer;
+pub mod export;
+pub mod hasher;
+pub mod health;
+pub mod invariants;
+#[cfg(feature = "runtime")]
+pub mod io_ops;
+pub mod latency;
+pub mod ledger;
+pub mod limits;
+#[cfg(feature = "runtime")]
+pub mod manifest;
+pub mod memory;
+pub mod partitioner;
+#[cfg(feature = "runtime")]
+pub mod pipeline;
+#[cfg(feature = "runtime")]
+pub mod processed;
+#[cfg(feature = "proto")]
+pub mod proto;
+pub mod pseudonym;
+pub mod ranking;
+pub mod registry;
+pub mod retry;
+pub mod risk;
+pub mod router;
+pub mod settlement;
+pub mod shutdown;
+#[cfg(feature = "signing")]
+pub mod signing;
+#[cfg(feature = "runtime")]
+pub mod simulate;
+#[cfg(feature = "runtime")]
+pub mod snapshot;
+pub mod source;
+#[cfg(feature = "runtime")]
+pub mod statement;
+pub mod store;
+pub mod units;
+pub mod validate;
+pub mod warning;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "runtime")]
+pub mod webhook;
+#[cfg(feature = "runtime")]
+pub mod window;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
@@ -0,0 +1,272 @@
+//! Per-client limits on withdrawals, checked by the ledger before `Transact::withdraw`
+//!
+//! Records have no timestamps, so a limit applies over a number of the client's most recent
+//! transactions rather than a period of time: e.g. at most 5 withdrawals, or 10000 withdrawn,
+//! in any 1000 consecutive transactions of the client.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use rust_decimal::Decimal;
+
+use crate::{
+    data::{Transaction, TransactionType},
+    hasher::IdMap,
+};
+
+/// Limits on the withdrawals among `window` consecutive applied transactions of a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WithdrawalLimit {
+    pub window: usize,
+    pub max_withdrawals: Option<usize>,
+    pub max_withdrawn: Option<Decimal>,
+}
+
+/// The limit of each listed client, and of every other client if `default` is set
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LimitConfig {
+    pub default: Option<WithdrawalLimit>,
+    pub clients: HashMap<u16, WithdrawalLimit>,
+}
+
+impl LimitConfig {
+    /// Column names of a limits file
+    pub const HEADER: [&'static str; 4] = ["client", "window", "max_withdrawals", "max_withdrawn"];
+
+    pub fn get(&self, client_id: u16) -> Option<&WithdrawalLimit> {
+        self.clients.get(&client_id).or(self.default.as_ref())
+    }
+
+    /// Reads the limits file at `path` of `--withdrawal-limits`, if one was given
+    ///
+    /// # Errors
+    /// If the file cannot be read or parsed
+    pub fn load(path: Option<&str>) -> Result<Option<Arc<Self>>> {
+        let Some(path) = path else {
+            return Ok(None);
+        };
+        let limits = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read the withdrawal limits {path}"))?;
+        let limits = limits
+            .parse()
+            .with_context(|| format!("Cannot parse the withdrawal limits {path}"))?;
+        Ok(Some(Arc::new(limits)))
+    }
+}
+
+/// A CSV with a `client,window,max_withdrawals,max_withdrawn` header and a row per client,
+/// `*` standing for every client without a row of its own. An empty maximum is no limit.
+impl FromStr for LimitConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut lines = s.lines().map(str::trim).filter(|line| !line.is_empty());
+        let header = lines.next().unwrap_or_default();
+        if header.split(',').map(str::trim).ne(Self::HEADER) {
+            bail!(
+                "Expected columns `{}`, found `{header}`",
+                Self::HEADER.join(",")
+            );
+        }
+
+        let mut config = Self::default();
+        for line in lines {
+            let fields: Vec<_> = line.split(',').map(str::trim).collect();
+            let [client, window, max_withdrawals, max_withdrawn] = fields[..] else {
+                bail!("Expected 4 columns, found `{line}`");
+            };
+            let limit = WithdrawalLimit {
+                window: window
+                    .parse()
+                    .ok()
+                    .filter(|window| *window > 0)
+                    .with_context(|| format!("Expected a positive window, found `{line}`"))?,
+                max_withdrawals: parse_max(max_withdrawals, line)?,
+                max_withdrawn: parse_max(max_withdrawn, line)?,
+            };
+            let previous = match client {
+                "*" => config.default.replace(limit),
+                client => {
+                    let client = client
+                        .parse()
+                        .with_context(|| format!("Expected a client id or `*`, found `{line}`"))?;
+                    config.clients.insert(client, limit)
+                }
+            };
+            if previous.is_some() {
+                bail!("Client `{client}` has more than one limit");
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_max<T: FromStr>(max: &str, line: &str) -> Result<Option<T>> {
+    if max.is_empty() {
+        return Ok(None);
+    }
+    let max = max
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Expected a maximum or nothing, found `{line}`"))?;
+    Ok(Some(max))
+}
+
+/// The recent transactions of each client with a limit, as held by a ledger
+#[derive(Debug)]
+pub struct Limits {
+    config: Arc<LimitConfig>,
+    /// The amount of each withdrawal among a client's last transactions, oldest first, up to
+    /// one less than its window
+    recent: IdMap<u16, VecDeque<Option<Decimal>>>,
+}
+
+impl Limits {
+    pub fn new(config: Arc<LimitConfig>) -> Self {
+        Self {
+            config,
+            recent: IdMap::default(),
+        }
+    }
+
+    /// Rejects a withdrawal which would exceed its client's limit
+    ///
+    /// # Errors
+    /// If the withdrawal exceeds the limit
+    pub fn check(&self, tx: &Transaction) -> Result<()> {
+        let Some(limit) = self.config.get(tx.client_id()) else {
+            return Ok(());
+        };
+        let recent = self.recent.get(&tx.client_id());
+        let withdrawals = recent.into_iter().flatten().flatten();
+        let count = 1 + withdrawals.clone().count();
+        if limit.max_withdrawals.is_some_and(|max| count > max) {
+            bail!(
+                "Withdrawal `{}` exceeds the limit of {} withdrawals in {} transactions of \
+                 client `{}`",
+                tx.tx_id(),
+                limit.max_withdrawals.unwrap_or_default(),
+                limit.window,
+                tx.client_id()
+            );
+        }
+        let withdrawn = withdrawals.fold(tx.amount().unwrap_or_default(), |sum, amount| {
+            sum.saturating_add(*amount)
+        });
+        if limit.max_withdrawn.is_some_and(|max| withdrawn > max) {
+            bail!(
+                "Withdrawal `{}` exceeds the limit of {} withdrawn in {} transactions of \
+                 client `{}`",
+                tx.tx_id(),
+                limit.max_withdrawn.unwrap_or_default(),
+                limit.window,
+                tx.client_id()
+            );
+        }
+        Ok(())
+    }
+
+    /// Records an applied transaction of a client with a limit
+    pub fn record(&mut self, tx: &Transaction) {
+        let Some(limit) = self
+            .config
+            .get(tx.client_id())
+            .filter(|limit| limit.window > 1)
+        else {
+            return;
+        };
+        let recent = self.recent.entry(tx.client_id()).or_default();
+        if recent.len() == limit.window - 1 {
+            recent.pop_front();
+        }
+        let withdrawn = tx
+            .amount()
+            .filter(|_| tx.tx_type() == TransactionType::Withdrawal);
+        recent.push_back(withdrawn);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::Transaction,
+        limits::{LimitConfig, Limits, WithdrawalLimit},
+    };
+
+    #[test]
+    fn parses_a_limits_file() {
+        let config: LimitConfig =
+            "client,window,max_withdrawals,max_withdrawn\n*,1000,5,\n42, 10, , 10000.50\n"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            config.get(1),
+            Some(&WithdrawalLimit {
+                window: 1000,
+                max_withdrawals: Some(5),
+                max_withdrawn: None,
+            })
+        );
+        assert_eq!(
+            config.get(42),
+            Some(&WithdrawalLimit {
+                window: 10,
+                max_withdrawals: None,
+                max_withdrawn: Some(Decimal::new(1_000_050, 2)),
+            })
+        );
+        assert_eq!(
+            "client,window,max_withdrawals,max_withdrawn\n1,10,5,\n"
+                .parse::<LimitConfig>()
+                .unwrap()
+                .get(2),
+            None
+        );
+        assert!("client,window,max_withdrawals,max_withdrawn\n1,0,5,\n"
+            .parse::<LimitConfig>()
+            .is_err());
+        assert!(
+            "client,window,max_withdrawals,max_withdrawn\n1,10,5,\n1,10,,5\n"
+                .parse::<LimitConfig>()
+                .is_err()
+        );
+        assert!("client,max\n1,5\n".parse::<LimitConfig>().is_err());
+    }
+
+    #[test]
+    fn limits_withdrawals_over_a_window() {
+        let config = LimitConfig {
+            default: Some(WithdrawalLimit {
+                window: 3,
+                max_withdrawals: Some(2),
+                max_withdrawn: Some(Decimal::TEN),
+            }),
+            ..LimitConfig::default()
+        };
+        let mut limits = Limits::new(Arc::new(config));
+        let withdrawal = |tx, amount| Transaction::withdrawal(1, tx, Decimal::from(amount));
+
+        limits.record(&withdrawal(1, 4));
+        limits.check(&withdrawal(2, 6)).unwrap();
+        let e = limits.check(&withdrawal(2, 7)).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Withdrawal `2` exceeds the limit of 10 withdrawn in 3 transactions of client `1`"
+        );
+        limits.record(&withdrawal(2, 1));
+        let e = limits.check(&withdrawal(3, 1)).unwrap_err();
+        assert!(e.to_string().contains("limit of 2 withdrawals"));
+
+        // The first withdrawal falls out of the window
+        limits.record(&Transaction::deposit(1, 3, Decimal::ONE));
+        limits.check(&withdrawal(4, 9)).unwrap();
+        assert!(limits.check(&withdrawal(4, 10)).is_err());
+    }
+}
@@ -4,57 +4,831 @@
 #![deny(clippy::all)]
 #![deny(clippy::pedantic)]
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    io::Write,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
-use anyhow::Context;
-use tokio::sync::mpsc;
+use anyhow::{bail, Context};
+use futures::future::try_join_all;
+use tokio::{io::AsyncWrite, net::TcpListener, sync::mpsc, task::JoinHandle};
+use tracing::error;
 
-use crate::{
-    io_ops::{async_read_csv, display_results, partition_csv_events},
-    ledger::event_handler,
+#[cfg(feature = "signing")]
+use effective_train::signing::{OutputSigner, Recorder, Recording};
+use effective_train::{
+    account::ClientState,
+    bisect::run_bisect,
+    camt::render_camt053,
+    cli::{Args, Emit, ExitStatus},
+    determinism::run_determinism_check,
+    digest::{run_digest, sha256_file, to_hex},
+    explain::run_explain,
+    explorer::run_explorer,
+    export::{render_export, ExportFormat},
+    health::{serve_health, Health},
+    invariants::verify_invariants,
+    io_ops::{
+        create_retrying, partition_by_client, read_transactions_blocking, stream_results,
+        write_account_updates, write_dead_letters, write_file_retrying, write_high_risk,
+        write_holds, write_locked_accounts, write_open_disputes, write_results, write_warnings,
+        AccountSink, RetryingIo,
+    },
+    ledger::{Ledger, WorkerSinks},
+    limits::LimitConfig,
+    manifest::{InputManifest, RunManifest},
+    pipeline::{process_file, process_iter, OpeningBalances, WorkersPanicked},
+    processed::ProcessedLog,
+    pseudonym::write_revealed,
+    ranking::{NegativeBalances, TopBalances, TopDisputeClients},
+    shutdown::Shutdown,
+    simulate::run_simulation,
+    snapshot,
+    statement::{replay_ledger, run_statement},
+    webhook::publish_dispute_events,
+    window::write_flow_windows,
 };
 
-pub(crate) mod account;
-pub(crate) mod data;
-pub(crate) mod io_ops;
-pub(crate) mod ledger;
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// How long a run which timed out may take to write what it processed before it exits
+const TIMEOUT_GRACE: Duration = Duration::from_secs(60);
 
-// https://docs.rs/tokio/latest/tokio/attr.main.html
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> anyhow::Result<()> {
+fn main() -> ExitCode {
     let file_appender = tracing_appender::rolling::never("", "transaction_processor.log");
     tracing_subscriber::fmt()
         .with_ansi(false)
         .with_writer(file_appender)
         .init();
 
-    // Parse CLI Argument
-    let mut args = std::env::args();
-    let bin_name = args.next().context("Cannot parse executable name")?;
+    // Parse CLI Arguments
+    let mut args = match Args::parse(std::env::args()) {
+        Ok(args) => args,
+        Err(e) => return report(&e, ExitStatus::Usage),
+    };
+    if let Err(e) = args
+        .load_pseudonym_key()
+        .and_then(|()| args.load_snapshot_key())
+    {
+        return report(&e, ExitStatus::of(&e));
+    }
+
+    // A small input is processed on this thread, without starting a runtime
+    if args.sync {
+        return match run_sync(&args) {
+            Ok(()) => ExitStatus::Success.into(),
+            Err(e) => report(&e, ExitStatus::of(&e)),
+        };
+    }
+    match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime.block_on(start(args)),
+        Err(e) => report(&anyhow::Error::from(e), ExitStatus::Failure),
+    }
+}
+
+async fn start(args: Args) -> ExitCode {
+    // The first Ctrl-C stops reading and writes what was processed, a second one exits
+    let shutdown = Shutdown::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                shutdown.trigger();
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    std::process::exit(130);
+                }
+            }
+        }
+    });
+
+    // Once `--timeout` elapsed reading stops as on Ctrl-C, and the run exits if writing what
+    // it processed is stuck too
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let timeout = args.timeout.unwrap_or_default();
+    if args.timeout.is_some() {
+        tokio::spawn({
+            let (shutdown, timed_out) = (shutdown.clone(), timed_out.clone());
+            async move {
+                tokio::time::sleep(timeout).await;
+                timed_out.store(true, Ordering::SeqCst);
+                shutdown.trigger();
+                tokio::time::sleep(TIMEOUT_GRACE).await;
+                eprintln!(
+                    "Error: Timed out after {}s and still writing the accounts {}s later",
+                    timeout.as_secs(),
+                    TIMEOUT_GRACE.as_secs()
+                );
+                std::process::exit(ExitStatus::TimedOut as i32);
+            }
+        });
+    }
+
+    match run(args, &shutdown).await {
+        Ok(()) if timed_out.load(Ordering::SeqCst) => {
+            let e = anyhow::anyhow!(
+                "Timed out after {}s, the accounts written only cover the records read until then",
+                timeout.as_secs()
+            );
+            report(&e, ExitStatus::TimedOut)
+        }
+        Ok(()) if shutdown.is_triggered() => ExitStatus::Interrupted.into(),
+        Ok(()) => ExitStatus::Success.into(),
+        Err(e) => report(&e, ExitStatus::of(&e)),
+    }
+}
+
+fn report(error: &anyhow::Error, status: ExitStatus) -> ExitCode {
+    eprintln!("Error: {error:?}");
+    status.into()
+}
+
+/// Processes the input of `--sync`: the whole file is read, its transactions applied in order
+/// to one ledger and the accounts written to `stdout`, without a runtime, channels or workers
+fn run_sync(args: &Args) -> anyhow::Result<()> {
     let file_path = args
-        .next()
-        .context(format!("Usage: {bin_name} <transactions.csv>"))?;
+        .file_path
+        .as_deref()
+        .context("`--sync` expects an input")?;
+    let contents = std::fs::read(file_path).with_context(|| format!("Cannot read {file_path}"))?;
+    let transactions = read_transactions_blocking(&contents, args.csv)?;
+    // Sliced and filtered as the router does
+    let skip = usize::try_from(args.skip).unwrap_or(usize::MAX);
+    let limit = args.limit.map_or(usize::MAX, |limit| {
+        usize::try_from(limit).unwrap_or(usize::MAX)
+    });
+    let transactions = transactions
+        .into_iter()
+        .skip(skip)
+        .take(limit)
+        .filter(|tx| args.filter.allows(tx))
+        // A record the router would reject is rejected on its own, the others still applied
+        .filter_map(|tx| match args.columns.minor_units {
+            Some(units) => units.to_major(tx).map_err(|e| error!("{}", e)).ok(),
+            None => Some(tx),
+        });
+    let limits = LimitConfig::load(args.withdrawal_limits.as_deref())?;
+    let mut ledger = replay_ledger(args, limits);
+    if let Some(path) = &args.opening_balances {
+        let opening = std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| {
+                futures::executor::block_on(snapshot::parse(&contents, args.snapshot_key.as_ref()))
+            })
+            .with_context(|| format!("Cannot read the opening balances {path}"))?;
+        let opening = OpeningBalances::from(opening);
+        for state in opening.accounts {
+            if args.filter.keeps_client(state.id()) {
+                ledger.open_account(state);
+            }
+        }
+        for (tx_id, record, held) in opening.transactions {
+            if args.filter.keeps_client(record.client_id()) {
+                ledger.restore_transaction(tx_id, record, held);
+            }
+        }
+    }
+    let ledger = process_iter(ledger, transactions);
+
+    let mut output = Vec::new();
+    futures::executor::block_on(write_results(
+        ledger.into_accounts(),
+        args.columns,
+        args.sorted,
+        args.pseudonyms.as_ref(),
+        &mut output,
+    ))?;
+    std::io::stdout().write_all(&output)?;
+    Ok(())
+}
+
+async fn run(mut args: Args, shutdown: &Shutdown) -> anyhow::Result<()> {
+    #[cfg(feature = "signing")]
+    if let (Some(verify), Some(file_path)) = (&args.verify, &args.file_path) {
+        return effective_train::signing::run_verify(file_path, verify).await;
+    }
+    if let Some(pseudonyms) = args.pseudonyms.as_ref().filter(|_| !args.reveal.is_empty()) {
+        return write_revealed(&args.reveal, pseudonyms, tokio::io::stdout()).await;
+    }
+    if let (Some(snapshot), Some(file_path)) = (&args.simulate, &args.file_path) {
+        return run_simulation(snapshot, file_path, &args).await;
+    }
+    if let (Some(statement), Some(file_path)) = (&args.statement, &args.file_path) {
+        return run_statement(statement, file_path, &args).await;
+    }
+    if let (Some(tx_id), Some(file_path)) = (args.explain, &args.file_path) {
+        return run_explain(tx_id, file_path, &args).await;
+    }
+    if let (Some(bisect), Some(file_path)) = (args.bisect.take(), args.file_path.clone()) {
+        return run_bisect(&bisect, &file_path, args, shutdown).await;
+    }
+    if let (true, Some(file_path)) = (args.verify_determinism, args.file_path.clone()) {
+        return run_determinism_check(&file_path, args, shutdown).await;
+    }
+    if let Some(accounts) = &args.tui {
+        return run_explorer(accounts, args.file_path.as_deref(), &args).await;
+    }
+
+    let processed = match args.processed_log.clone() {
+        Some(path) => Some(unprocessed_inputs(&path, &mut args).await?),
+        None => None,
+    };
+    process_inputs(&args, shutdown).await?;
+    // An interrupted run may have applied only part of an input
+    if let Some((mut log, inputs)) = processed.filter(|_| !shutdown.is_triggered()) {
+        for (digest, file_path) in inputs {
+            log.record(&digest, &file_path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes the inputs found in the `--processed-log` at `path` from `args` with
+/// `--skip-processed`, or fails naming the first one. Returns the log with the checksum of
+/// each input left to process.
+async fn unprocessed_inputs(
+    path: &str,
+    args: &mut Args,
+) -> anyhow::Result<(ProcessedLog, Vec<([u8; 32], String)>)> {
+    let log = ProcessedLog::load(path).await?;
+    let mut unprocessed = Vec::new();
+    if let Some(file_path) = args.file_path.take() {
+        let digest = sha256_file(&file_path).await?;
+        if is_unprocessed(&log, &digest, &file_path, args.skip_processed)? {
+            unprocessed.push((digest, file_path.clone()));
+            args.file_path = Some(file_path);
+        }
+    }
+    let mut tenants = Vec::new();
+    for tenant in std::mem::take(&mut args.tenants) {
+        let digest = sha256_file(&tenant.file_path).await?;
+        if is_unprocessed(&log, &digest, &tenant.file_path, args.skip_processed)? {
+            unprocessed.push((digest, tenant.file_path.clone()));
+            tenants.push(tenant);
+        }
+    }
+    args.tenants = tenants;
+    Ok((log, unprocessed))
+}
 
-    // count logical cores this process could try to use
-    let num = num_cpus::get();
+fn is_unprocessed(
+    log: &ProcessedLog,
+    digest: &[u8; 32],
+    file_path: &str,
+    skip: bool,
+) -> anyhow::Result<bool> {
+    match log.find(digest) {
+        None => Ok(true),
+        Some(previous) if skip => {
+            eprintln!("Skipping {file_path}: already processed as {previous}");
+            Ok(false)
+        }
+        Some(previous) => bail!(
+            "{file_path} was already processed as {previous} (sha256:{}), \
+             pass `--skip-processed` to skip it",
+            to_hex(digest)
+        ),
+    }
+}
+
+async fn process_inputs(args: &Args, shutdown: &Shutdown) -> anyhow::Result<()> {
+    let (started, running) = (SystemTime::now(), Instant::now());
+    // A key which cannot be read fails the run before any input is
+    #[cfg(feature = "signing")]
+    let signer = args
+        .sign_key
+        .as_deref()
+        .map(OutputSigner::load)
+        .transpose()?;
+    let stdout = RetryingIo::new(tokio::io::stdout(), args.io_retry);
+    #[cfg(feature = "signing")]
+    let (stdout, stdout_copy) = Recorder::new(stdout, signer.is_some());
 
-    // Instantiate workers and senders
-    let (mut event_senders, mut workers) = (Vec::with_capacity(num), Vec::with_capacity(num));
-    for _ in 0..num {
-        let (client_sender, client_receiver) = mpsc::unbounded_channel();
-        event_senders.push(client_sender);
-        workers.push(tokio::spawn(event_handler(client_receiver)));
+    // Transactions which exhaust their retries are written to the dead-letter file
+    let (dead_letter_sender, dead_letter_writer) = match &args.dead_letter {
+        Some(path) => {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            (
+                Some(sender),
+                Some(tokio::spawn(write_dead_letters(
+                    path.clone(),
+                    args.io_retry,
+                    receiver,
+                    args.pseudonyms.clone(),
+                ))),
+            )
+        }
+        None => (None, None),
+    };
+    // Records skipped with a warning are written apart from the dead letters
+    let (warning_sender, warning_writer) = match &args.warnings_out {
+        Some(path) => {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            (
+                Some(sender),
+                Some(tokio::spawn(write_warnings(
+                    path.clone(),
+                    args.io_retry,
+                    receiver,
+                    args.pseudonyms.clone(),
+                ))),
+            )
+        }
+        None => (None, None),
+    };
+
+    // Applied disputes are POSTed to the webhook in the background
+    let (dispute_sender, dispute_publisher) = match &args.dispute_webhook {
+        Some(url) => {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            (
+                Some(sender),
+                Some(tokio::spawn(publish_dispute_events(
+                    url.clone(),
+                    receiver,
+                    args.webhook_outbox.clone(),
+                ))),
+            )
+        }
+        None => (None, None),
+    };
+    // Orchestrators probe the workers of every input while they run
+    let (health, health_server) = match &args.health_addr {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Cannot serve the health endpoints on {addr}"))?;
+            let health = Health::new(args.ready_backlog);
+            let server = tokio::spawn(serve_health(listener, health.clone()));
+            (Some(health), Some(server))
+        }
+        None => (None, None),
+    };
+    // The payment flow of each window of time is written as it ends
+    let (flow_sender, flow_writer) = match &args.flow_windows {
+        Some(path) => {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            (
+                Some(sender),
+                Some(tokio::spawn(write_flow_windows(
+                    path.clone(),
+                    args.io_retry,
+                    args.flow_window,
+                    receiver,
+                ))),
+            )
+        }
+        None => (None, None),
+    };
+    let sinks = WorkerSinks {
+        dead_letters: dead_letter_sender,
+        warnings: warning_sender,
+        dispute_events: dispute_sender,
+        applied_transactions: flow_sender,
+        health,
+        ..WorkerSinks::default()
+    };
+
+    // Each tenant is processed concurrently with its own ledgers and output file
+    let tenants = args.tenants.iter().map(|tenant| {
+        let sinks = sinks.clone();
+        async move {
+            let processing = Instant::now();
+            let output_path = output_path(Some(&tenant.name), args);
+            let output = match args.output_partitions {
+                Some(_) => None,
+                None => Some(create_retrying(&output_path, args.io_retry).await?),
+            };
+            let input = (Some(tenant.name.as_str()), tenant.file_path.as_str());
+            let (ledger, output) = process_input(input, args, sinks, shutdown, output).await?;
+            let manifest =
+                describe_input(args, input, &output_path, &ledger, processing.elapsed()).await?;
+            let panics = ledger.worker_panics().to_vec();
+            finish_input(Some(&tenant.name), ledger, output, args).await?;
+            anyhow::Ok((manifest, panics))
+        }
+    });
+    let results = async {
+        match &args.file_path {
+            Some(file_path) => {
+                let processing = Instant::now();
+                let (ledger, output) = process_input(
+                    (None, file_path),
+                    args,
+                    sinks.clone(),
+                    shutdown,
+                    args.output_partitions.is_none().then_some(stdout),
+                )
+                .await?;
+                Ok(Some((ledger, output, processing.elapsed())))
+            }
+            None => Ok(None),
+        }
+    };
+    let (results, tenant_results) = futures::try_join!(results, try_join_all(tenants))?;
+    drop(sinks);
+    if let Some(health_server) = health_server {
+        health_server.abort();
     }
 
-    // Read each line of CSV and push parsed records to Event Router
-    let reader = async_read_csv(&file_path).await?;
-    partition_csv_events(reader, event_senders, num).await?;
+    if let Some(dead_letter_writer) = dead_letter_writer {
+        dead_letter_writer.await??;
+    }
+    if let Some(warning_writer) = warning_writer {
+        warning_writer.await??;
+    }
+    if let Some(dispute_publisher) = dispute_publisher {
+        dispute_publisher.await??;
+    }
+    if let Some(flow_writer) = flow_writer {
+        flow_writer.await??;
+    }
 
-    let mut results = HashMap::new();
-    for event_handler in workers {
-        let client_results = event_handler.await?;
-        results.extend(client_results);
+    let (mut inputs, mut panics) = (Vec::new(), Vec::new());
+    if let (Some((ledger, output, elapsed)), Some(file_path)) = (results, &args.file_path) {
+        let output_path = output_path(None, args);
+        let input = (None, file_path.as_str());
+        inputs.extend(describe_input(args, input, &output_path, &ledger, elapsed).await?);
+        panics.extend_from_slice(ledger.worker_panics());
+        finish_input(None, ledger, output, args).await?;
+    }
+    for (manifest, tenant_panics) in tenant_results {
+        inputs.extend(manifest);
+        panics.extend(tenant_panics);
+    }
+    if let Some(path) = &args.manifest {
+        let manifest = RunManifest {
+            started,
+            elapsed: running.elapsed(),
+            filter: args.filter.clone(),
+            inputs,
+        };
+        write_file_retrying(path, manifest.to_json().as_bytes(), args.io_retry).await?;
+    }
+    // The accounts of the other workers were written all the same
+    if !panics.is_empty() {
+        return Err(WorkersPanicked { panics }.into());
     }
+    #[cfg(feature = "signing")]
+    if let Some(signer) = &signer {
+        sign_outputs(signer, stdout_copy, args).await?;
+    }
+    Ok(())
+}
+
+/// Signs the file of accounts of each input, or each of its partitions, and the accounts
+/// written to stdout to `--signature-out`
+#[cfg(feature = "signing")]
+async fn sign_outputs(
+    signer: &OutputSigner,
+    stdout: Option<Recording>,
+    args: &Args,
+) -> anyhow::Result<()> {
+    if let (Some(path), Some(stdout)) = (&args.signature_out, stdout) {
+        signer.sign_recording(&stdout, path, args.io_retry).await?;
+    }
+    let tenants = args.tenants.iter().map(|tenant| Some(tenant.name.as_str()));
+    let inputs = args
+        .file_path
+        .as_ref()
+        .map(|_| None)
+        .into_iter()
+        .chain(tenants);
+    for tenant in inputs {
+        let pattern = output_path(tenant, args);
+        match args.output_partitions {
+            Some(partitions) => {
+                for partition in 0..partitions {
+                    let path = pattern.replace('*', &partition.to_string());
+                    signer.sign_file(&path, args.io_retry).await?;
+                }
+            }
+            None if tenant.is_some() => signer.sign_file(&pattern, args.io_retry).await?,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// The manifest entry of a processed `(tenant, file_path)` input, if `--manifest` was given
+async fn describe_input(
+    args: &Args,
+    (tenant, file_path): (Option<&str>, &str),
+    output: &str,
+    ledger: &Ledger,
+    elapsed: Duration,
+) -> std::io::Result<Option<InputManifest>> {
+    if args.manifest.is_none() {
+        return Ok(None);
+    }
+    InputManifest::new(tenant, file_path, output, ledger, elapsed)
+        .await
+        .map(Some)
+}
+
+/// Reports on the accounts of a processed input and writes them to `output`, unless they
+/// were already streamed
+async fn finish_input<W>(
+    tenant: Option<&str>,
+    ledger: Ledger,
+    output: Option<W>,
+    args: &Args,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    if args.verify_invariants {
+        let verified = verify_invariants(ledger.accounts());
+        match tenant {
+            Some(name) => verified.with_context(|| format!("Tenant {name}"))?,
+            None => verified?,
+        }
+    }
+    if let Some(path) = &args.open_disputes_out {
+        let report = create_retrying(&tenant_path(path, tenant), args.io_retry).await?;
+        let disputes = ledger.open_dispute_ages();
+        write_open_disputes(&disputes, args.pseudonyms.as_ref(), report).await?;
+    }
+    if let Some(path) = &args.holds_out {
+        let export = create_retrying(&tenant_path(path, tenant), args.io_retry).await?;
+        write_holds(&ledger.holds(), args.pseudonyms.as_ref(), export).await?;
+    }
+    if let Some(path) = &args.locked_out {
+        let report = create_retrying(&tenant_path(path, tenant), args.io_retry).await?;
+        let locked = ledger.locked_accounts();
+        write_locked_accounts(&locked, args.pseudonyms.as_ref(), report).await?;
+    }
+    // The workers wrote the accounts as they finished
+    if streams_accounts(args) {
+        return Ok(());
+    }
+    if let Some(format) = args.export {
+        export_activity(tenant, &ledger, format, args).await?;
+    }
+    // The snapshot also holds the deposits and withdrawals, which `into_accounts` drops
+    let (transactions, partial_holds): (Vec<_>, Vec<_>) = match &args.snapshot_out {
+        Some(_) => (
+            ledger.transactions().collect(),
+            ledger.partial_holds().collect(),
+        ),
+        None => (Vec::new(), Vec::new()),
+    };
+    let results = ledger.into_accounts();
+    if args.audit_digest {
+        let digest = to_hex(&run_digest(&results));
+        match tenant {
+            Some(name) => eprintln!("audit digest {name}: sha256:{digest}"),
+            None => eprintln!("audit digest: sha256:{digest}"),
+        }
+    }
+    if let Some(n) = args.top_balances {
+        print_section("top balances", tenant, &TopBalances::new(&results, n));
+    }
+    if let Some(n) = args.top_dispute_clients {
+        print_section(
+            "top dispute clients",
+            tenant,
+            &TopDisputeClients::new(&results, n),
+        );
+    }
+    if args.negative_balances {
+        print_section(
+            "negative balances",
+            tenant,
+            &NegativeBalances::new(&results),
+        );
+    }
+    if let Some(path) = &args.snapshot_out {
+        let mut snapshot = snapshot::encode(&results, transactions, partial_holds)?;
+        if let Some(key) = &args.snapshot_key {
+            snapshot = key.seal(&snapshot)?;
+        }
+        write_file_retrying(&tenant_path(path, tenant), &snapshot, args.io_retry).await?;
+    }
+    #[cfg(feature = "avro")]
+    if let Some(path) = &args.avro_out {
+        let accounts = effective_train::avro::encode_accounts(results.values())?;
+        write_file_retrying(&tenant_path(path, tenant), &accounts, args.io_retry).await?;
+    }
+    if let (Some(path), Some(currency)) = (&args.camt_out, &args.currency) {
+        let statements = render_camt053(&results, currency, SystemTime::now());
+        let path = tenant_path(path, tenant);
+        write_file_retrying(&path, statements.as_bytes(), args.io_retry).await?;
+    }
+    if let Some(limit) = args.chargeback_limit {
+        let path = args
+            .high_risk_out
+            .as_deref()
+            .unwrap_or("high_risk_accounts.csv");
+        let report = create_retrying(&tenant_path(path, tenant), args.io_retry).await?;
+        write_high_risk(&results, limit, args.pseudonyms.as_ref(), report).await?;
+    }
+    match (args.output_partitions, output) {
+        (Some(partitions), _) => write_partitions(tenant, results, partitions, args).await,
+        (None, Some(output)) => {
+            let pseudonyms = args.pseudonyms.as_ref();
+            write_results(results, args.columns, args.sorted, pseudonyms, output).await
+        }
+        (None, None) => Ok(()),
+    }
+}
+
+/// Writes the activity of each client of `ledger` to its own file, `client_<id>.<format>`
+async fn export_activity(
+    tenant: Option<&str>,
+    ledger: &Ledger,
+    format: ExportFormat,
+    args: &Args,
+) -> anyhow::Result<()> {
+    let created = SystemTime::now();
+    for client in ledger.accounts() {
+        let id = client.id();
+        let path = match tenant {
+            Some(name) => format!("client_{id}_{name}.{}", format.extension()),
+            None => format!("client_{id}.{}", format.extension()),
+        };
+        let export = render_export(
+            format,
+            client,
+            ledger.activity(id),
+            args.currency.as_ref(),
+            created,
+        );
+        write_file_retrying(&path, export.as_bytes(), args.io_retry).await?;
+    }
+    Ok(())
+}
+
+/// Prints a ranked section of the stats to stderr, headed by the tenant it is about
+fn print_section(title: &str, tenant: Option<&str>, section: &impl std::fmt::Display) {
+    match tenant {
+        Some(name) => eprint!("{title} {name}:\n{section}"),
+        None => eprint!("{title}:\n{section}"),
+    }
+}
+
+/// `path` with the tenant's name before its extension, e.g. `open_acme.csv` for `open.csv`
+fn tenant_path(path: &str, tenant: Option<&str>) -> String {
+    let Some(name) = tenant else {
+        return path.to_owned();
+    };
+    let file_name = path.rfind('/').map_or(0, |i| i + 1);
+    match path[file_name..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = path.split_at(file_name + dot);
+            format!("{stem}_{name}{extension}")
+        }
+        _ => format!("{path}_{name}"),
+    }
+}
+
+/// Where the accounts of an input are written, `-` for stdout and with a `*` in place of
+/// the partition index with `--output-partitions`
+fn output_path(tenant: Option<&str>, args: &Args) -> String {
+    let partition = args.output_partitions.map(|_| "*");
+    match (tenant, partition) {
+        (Some(name), Some(partition)) => format!("accounts_{name}_{partition}.csv"),
+        (Some(name), None) => format!("accounts_{name}.csv"),
+        (None, Some(partition)) => format!("accounts_{partition}.csv"),
+        (None, None) => "-".to_owned(),
+    }
+}
+
+/// Whether the workers can write their accounts as they finish, as nothing needs every
+/// account at once to sort or report on them
+fn streams_accounts(args: &Args) -> bool {
+    args.emit == Emit::Snapshot
+        && !args.sorted
+        && !args.audit_digest
+        && args.chargeback_limit.is_none()
+        && args.top_balances.is_none()
+        && args.top_dispute_clients.is_none()
+        && !args.negative_balances
+        && args.locked_out.is_none()
+        && args.camt_out.is_none()
+        && args.export.is_none()
+        && args.snapshot_out.is_none()
+        && args.avro_out.is_none()
+        && !args.verify_invariants
+}
+
+/// Spawns the writers of the accounts sent to the returned sink, one per file with
+/// `--output-partitions` and otherwise one for `output`
+async fn spawn_account_writers<W>(
+    tenant: Option<&str>,
+    args: &Args,
+    output: Option<W>,
+) -> anyhow::Result<(AccountSink, Vec<JoinHandle<anyhow::Result<()>>>)>
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (columns, pseudonyms) = (args.columns, &args.pseudonyms);
+    let Some(partitions) = args.output_partitions else {
+        let (sink, mut receivers) = AccountSink::new(1);
+        let receiver = receivers.remove(0);
+        let pseudonyms = pseudonyms.clone();
+        let writer = match output {
+            Some(output) => tokio::spawn(stream_results(receiver, columns, pseudonyms, output)),
+            None => tokio::spawn(stream_results(
+                receiver,
+                columns,
+                pseudonyms,
+                tokio::io::sink(),
+            )),
+        };
+        return Ok((sink, vec![writer]));
+    };
+    let pattern = output_path(tenant, args);
+    let (sink, receivers) = AccountSink::new(partitions);
+    let mut writers = Vec::with_capacity(partitions);
+    for (partition, receiver) in receivers.into_iter().enumerate() {
+        let path = pattern.replace('*', &partition.to_string());
+        let output = create_retrying(&path, args.io_retry).await?;
+        let pseudonyms = pseudonyms.clone();
+        writers.push(tokio::spawn(stream_results(
+            receiver, columns, pseudonyms, output,
+        )));
+    }
+    Ok((sink, writers))
+}
+
+/// Writes each range of client ids to its own file, in parallel
+async fn write_partitions(
+    tenant: Option<&str>,
+    results: HashMap<u16, ClientState>,
+    partitions: usize,
+    args: &Args,
+) -> anyhow::Result<()> {
+    let pattern = output_path(tenant, args);
+    let (columns, sorted, io_retry) = (args.columns, args.sorted, args.io_retry);
+    let writers = partition_by_client(results, partitions)
+        .into_iter()
+        .enumerate()
+        .map(|(partition, results)| {
+            let path = pattern.replace('*', &partition.to_string());
+            let pseudonyms = args.pseudonyms.clone();
+            tokio::spawn(async move {
+                let output = create_retrying(&path, io_retry).await?;
+                write_results(results, columns, sorted, pseudonyms.as_ref(), output).await
+            })
+        });
+    for writer in try_join_all(writers).await? {
+        writer?;
+    }
+    Ok(())
+}
+
+/// Processes one `(tenant, file_path)` input, streaming every account update to `output`
+/// with `--emit updates`. Otherwise the workers write their accounts to `output`, or to the
+/// partition files, as they finish, unless they are needed as a whole by `finish_input`; then
+/// `output` is handed back to receive them.
+async fn process_input<W>(
+    (tenant, file_path): (Option<&str>, &str),
+    args: &Args,
+    mut sinks: WorkerSinks,
+    shutdown: &Shutdown,
+    output: Option<W>,
+) -> anyhow::Result<(Ledger, Option<W>)>
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let output = match output {
+        Some(output) if args.emit == Emit::Updates => output,
+        output if streams_accounts(args) => {
+            let (sink, writers) = spawn_account_writers(tenant, args, output).await?;
+            sinks.finished_accounts = Some(sink);
+            let ledger = process_file(file_path, args, sinks, shutdown).await;
+            // The writers stop once every worker dropped the sink, even if one failed
+            for writer in try_join_all(writers).await? {
+                writer?;
+            }
+            return Ok((ledger?, None));
+        }
+        output => {
+            let ledger = process_file(file_path, args, sinks, shutdown).await?;
+            return Ok((ledger, output));
+        }
+    };
 
-    display_results(results).await
+    let (sender, receiver) = mpsc::unbounded_channel();
+    sinks.account_updates = Some(sender);
+    let pseudonyms = args.pseudonyms.clone();
+    let update_writer = tokio::spawn(write_account_updates(receiver, pseudonyms, output));
+    let ledger = process_file(file_path, args, sinks, shutdown).await?;
+    update_writer.await??;
+    Ok((ledger, None))
 }
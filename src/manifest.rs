@@ -0,0 +1,291 @@
+//! Machine-readable summary of a run, written with `--manifest`
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    digest::{sha256_file, to_hex},
+    ledger::Ledger,
+    router::RecordFilter,
+    settlement::Settlement,
+    warning::WarningKind,
+};
+
+/// What became of one input file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputManifest {
+    /// Name of the tenant, `None` for the positional input
+    pub tenant: Option<String>,
+    pub path: String,
+    pub sha256: [u8; 32],
+    /// Records dispatched to the workers
+    pub records: u64,
+    pub applied: u64,
+    /// Transactions rejected, by `retry::rejection_reason`
+    pub rejected: BTreeMap<String, u64>,
+    /// Records skipped with a warning, by kind
+    pub warnings: BTreeMap<WarningKind, u64>,
+    pub accounts: usize,
+    pub settlement: Settlement,
+    /// Where the accounts were written, `-` for stdout
+    pub output: String,
+    pub elapsed: Duration,
+}
+
+impl InputManifest {
+    /// Describes the input at `path` after `ledger` processed it, hashing the file
+    ///
+    /// # Errors
+    /// If the file cannot be read
+    pub async fn new(
+        tenant: Option<&str>,
+        path: &str,
+        output: &str,
+        ledger: &Ledger,
+        elapsed: Duration,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            tenant: tenant.map(str::to_owned),
+            path: path.to_owned(),
+            sha256: sha256_file(path).await?,
+            records: ledger.received(),
+            applied: ledger.applied(),
+            rejected: ledger.rejected().clone(),
+            warnings: ledger.warnings().clone(),
+            accounts: ledger.account_count(),
+            settlement: ledger.settlement(),
+            output: output.to_owned(),
+            elapsed,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunManifest {
+    pub started: SystemTime,
+    pub elapsed: Duration,
+    /// Records the run skipped, the clients filtered on are only counted
+    pub filter: RecordFilter,
+    pub inputs: Vec<InputManifest>,
+}
+
+impl RunManifest {
+    pub fn to_json(&self) -> String {
+        let started = self
+            .started
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let mut json = String::new();
+        json.push_str("{\n");
+        let _ = writeln!(
+            json,
+            r#"  "engine": {{"name": "{}", "version": "{}"}},"#,
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        );
+        let _ = writeln!(json, r#"  "started_at_ms": {started},"#);
+        let _ = writeln!(json, r#"  "elapsed_ms": {},"#, self.elapsed.as_millis());
+        write_filter(&mut json, &self.filter);
+        json.push_str(r#"  "inputs": ["#);
+        for (i, input) in self.inputs.iter().enumerate() {
+            json.push_str(if i == 0 { "\n" } else { ",\n" });
+            write_input(&mut json, input);
+        }
+        json.push_str(if self.inputs.is_empty() {
+            "]\n}\n"
+        } else {
+            "\n  ]\n}\n"
+        });
+        json
+    }
+}
+
+fn write_filter(json: &mut String, filter: &RecordFilter) {
+    let only_clients = filter
+        .only_clients
+        .as_ref()
+        .map_or_else(|| "null".to_owned(), |only| only.len().to_string());
+    let only_types = filter.only_types.as_ref().map_or_else(
+        || "null".to_owned(),
+        |types| {
+            let types: Vec<_> = types.iter().map(|t| quote(&t.to_string())).collect();
+            format!("[{}]", types.join(", "))
+        },
+    );
+    let sample = filter.sample.map_or_else(
+        || "null".to_owned(),
+        |sample| {
+            let fraction = Decimal::new(i64::from(sample.per_million), 6).normalize();
+            format!(r#"{{"fraction": {fraction}, "seed": {}}}"#, sample.seed)
+        },
+    );
+    let _ = writeln!(
+        json,
+        r#"  "filter": {{"only_clients": {only_clients}, "exclude_clients": {}, "only_types": {only_types}, "sample": {sample}}},"#,
+        filter.exclude_clients.len()
+    );
+}
+
+fn write_input(json: &mut String, input: &InputManifest) {
+    let tenant = input
+        .tenant
+        .as_deref()
+        .map_or_else(|| "null".to_owned(), quote);
+    let rejected: u64 = input.rejected.values().sum();
+    let reasons: Vec<_> = input
+        .rejected
+        .iter()
+        .map(|(reason, count)| format!("{}: {count}", quote(reason)))
+        .collect();
+    let warnings: u64 = input.warnings.values().sum();
+    let kinds: Vec<_> = input
+        .warnings
+        .iter()
+        .map(|(kind, count)| format!("{}: {count}", quote(&kind.to_string())))
+        .collect();
+    let settlement = &input.settlement;
+    let _ = write!(
+        json,
+        r#"    {{
+      "tenant": {tenant},
+      "path": {},
+      "sha256": "{}",
+      "records": {},
+      "applied": {},
+      "rejected": {rejected},
+      "rejected_by_reason": {{{}}},
+      "warnings": {warnings},
+      "warnings_by_kind": {{{}}},
+      "accounts": {},
+      "settlement": {{"deposits": "{}", "withdrawals": "{}", "chargebacks": "{}", "adjustments": "{}", "net_in": "{}"}},
+      "output": {},
+      "elapsed_ms": {}
+    }}"#,
+        quote(&input.path),
+        to_hex(&input.sha256),
+        input.records,
+        input.applied,
+        reasons.join(", "),
+        kinds.join(", "),
+        input.accounts,
+        settlement.deposits,
+        settlement.withdrawals,
+        settlement.chargebacks,
+        settlement.adjustments,
+        settlement.balance(),
+        quote(&input.output),
+        input.elapsed.as_millis()
+    );
+}
+
+/// `value` as a JSON string
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", u32::from(c));
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeMap,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::TransactionType,
+        manifest::{quote, InputManifest, RunManifest},
+        router::{ClientSample, RecordFilter},
+        settlement::Settlement,
+        warning::WarningKind,
+    };
+
+    #[test]
+    fn manifest_is_json() {
+        let manifest = RunManifest {
+            started: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            elapsed: Duration::from_millis(42),
+            filter: RecordFilter {
+                only_types: Some(vec![TransactionType::Deposit, TransactionType::Withdrawal]),
+                sample: Some(ClientSample {
+                    per_million: 10_000,
+                    seed: 42,
+                }),
+                ..RecordFilter::default()
+            },
+            inputs: vec![InputManifest {
+                tenant: Some("eu".to_owned()),
+                path: "in/eu.csv".to_owned(),
+                sha256: [0xab; 32],
+                records: 5,
+                applied: 3,
+                rejected: BTreeMap::from([("Account '*' is locked".to_owned(), 1)]),
+                warnings: BTreeMap::from([(WarningKind::UnknownTransaction, 1)]),
+                accounts: 2,
+                settlement: Settlement {
+                    deposits: Decimal::TEN,
+                    withdrawals: Decimal::ONE,
+                    ..Settlement::default()
+                },
+                output: "accounts_eu.csv".to_owned(),
+                elapsed: Duration::from_millis(40),
+            }],
+        };
+        let expected = format!(
+            r#"{{
+  "engine": {{"name": "effective-train", "version": "{}"}},
+  "started_at_ms": 1700000000123,
+  "elapsed_ms": 42,
+  "filter": {{"only_clients": null, "exclude_clients": 0, "only_types": ["deposit", "withdrawal"], "sample": {{"fraction": 0.01, "seed": 42}}}},
+  "inputs": [
+    {{
+      "tenant": "eu",
+      "path": "in/eu.csv",
+      "sha256": "{}",
+      "records": 5,
+      "applied": 3,
+      "rejected": 1,
+      "rejected_by_reason": {{"Account '*' is locked": 1}},
+      "warnings": 1,
+      "warnings_by_kind": {{"unknown_transaction": 1}},
+      "accounts": 2,
+      "settlement": {{"deposits": "10", "withdrawals": "1", "chargebacks": "0", "adjustments": "0", "net_in": "9"}},
+      "output": "accounts_eu.csv",
+      "elapsed_ms": 40
+    }}
+  ]
+}}
+"#,
+            env!("CARGO_PKG_VERSION"),
+            "ab".repeat(32)
+        );
+        assert_eq!(manifest.to_json(), expected);
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(quote("C:\\in \"a\"\n\u{1}"), r#""C:\\in \"a\"\n\u0001""#);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    fmt,
+    mem::size_of,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    account::ClientState,
+    data::{Activity, Sequenced, TransactionType},
+    risk::ClientHistory,
+    store::PackedRecord,
+};
+
+/// Approximate bytes held for each account, ignoring map overhead
+pub const ACCOUNT_BYTES: usize = size_of::<(u16, ClientState)>();
+/// Approximate bytes held for each approved deposit or withdrawal, ignoring map overhead and
+/// the few whose amount is too long to be packed
+pub const TRANSACTION_BYTES: usize = size_of::<(u32, PackedRecord)>();
+
+/// Approximate bytes held for each transaction recorded as account activity
+pub const ACTIVITY_BYTES: usize = size_of::<Activity>();
+
+/// Approximate bytes held for each open dispute, its id in the client's list and the
+/// sequence number of the event which opened it
+pub const OPEN_DISPUTE_BYTES: usize = size_of::<u32>() + size_of::<(u32, u64)>();
+/// Approximate bytes held for each dispute holding less than its transaction's amount
+pub const PARTIAL_HOLD_BYTES: usize = size_of::<(u32, Decimal)>();
+
+/// Approximate bytes held for each event held back, quarantined, buffered or awaiting a retry
+pub const EVENT_BYTES: usize = size_of::<(Sequenced, anyhow::Error)>();
+
+/// Approximate bytes held for each client's history kept for the risk policy, besides its
+/// entries
+pub const HISTORY_BYTES: usize = size_of::<(u16, ClientHistory)>();
+/// Approximate bytes held for each entry of a client's history
+pub const HISTORY_ENTRY_BYTES: usize = size_of::<(TransactionType, Option<Decimal>)>();
+
+/// Upper bound on the approximate memory held by the ledgers sharing it
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// # Errors
+    /// If `additional` bytes would take the usage over the limit
+    pub fn ensure(&self, additional: usize) -> Result<(), MemoryBudgetExceeded> {
+        let used = self.used();
+        if used.saturating_add(additional) > self.limit {
+            return Err(MemoryBudgetExceeded {
+                limit: self.limit,
+                used,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn grow(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Gives back `bytes` a ledger no longer holds, e.g. once its accounts were drained
+    pub fn shrink(&self, bytes: usize) {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(bytes))
+            })
+            .ok();
+    }
+}
+
+/// Processing stopped as the ledgers would outgrow their memory budget
+#[derive(Debug, PartialEq, Eq)]
+pub struct MemoryBudgetExceeded {
+    pub limit: usize,
+    pub used: usize,
+}
+
+impl fmt::Display for MemoryBudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Memory budget of {} bytes exceeded with ~{} bytes of accounts and transactions held, raise `--max-memory` or split the input",
+            self.limit, self.used
+        )
+    }
+}
+
+impl std::error::Error for MemoryBudgetExceeded {}
+
+#[cfg(test)]
+mod test {
+    use crate::memory::{MemoryBudget, MemoryBudgetExceeded};
+
+    #[test]
+    fn budget_rejects_growth_past_the_limit() {
+        let budget = MemoryBudget::new(100);
+        budget.ensure(100).unwrap();
+        budget.grow(60);
+        assert_eq!(
+            budget.ensure(41),
+            Err(MemoryBudgetExceeded {
+                limit: 100,
+                used: 60
+            })
+        );
+        assert_eq!(budget.used(), 60);
+
+        // Bytes given back make room again
+        budget.shrink(20);
+        budget.ensure(60).unwrap();
+        budget.shrink(100);
+        assert_eq!(budget.used(), 0);
+    }
+}
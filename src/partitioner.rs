@@ -0,0 +1,155 @@
+//! How the router assigns clients to workers
+//!
+//! A client's ledger state lives in one worker and its events must be applied in order, so
+//! the router asks its `Partitioner` once per client, when the client's first event is
+//! routed, and sends every later event of the client to the same worker.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, Result};
+
+use crate::router::RoutingStats;
+
+/// Chooses the worker of each new client
+pub trait Partitioner: Send {
+    /// The worker of `client_id`, below the number of workers in `stats`. `running` tells
+    /// which workers are still running, the events sent to a stopped one are lost.
+    fn assign(&mut self, client_id: u16, stats: &RoutingStats, running: &[bool]) -> usize;
+}
+
+/// The running worker with the fewest events, the first one if none is running. A skewed
+/// input does not pile further clients onto the worker handling a hot client, and the
+/// clients of a stopped worker are not joined by others.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sticky;
+
+impl Partitioner for Sticky {
+    fn assign(&mut self, _client_id: u16, stats: &RoutingStats, running: &[bool]) -> usize {
+        (0..stats.events.len())
+            .filter(|worker| running[*worker])
+            .min_by_key(|worker| stats.events[*worker])
+            .unwrap_or_default()
+    }
+}
+
+/// The client id modulo the number of workers, so a client always lands on the same worker
+/// whatever the input
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Modulo;
+
+impl Partitioner for Modulo {
+    fn assign(&mut self, client_id: u16, stats: &RoutingStats, _running: &[bool]) -> usize {
+        usize::from(client_id) % stats.events.len().max(1)
+    }
+}
+
+/// The running worker with the highest hash of the client and worker ids. A client keeps
+/// its worker when others stop, and only the clients of a stopped worker move, spread
+/// evenly over the rest.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rendezvous;
+
+impl Partitioner for Rendezvous {
+    fn assign(&mut self, client_id: u16, stats: &RoutingStats, running: &[bool]) -> usize {
+        (0..stats.events.len())
+            .filter(|worker| running[*worker])
+            .max_by_key(|worker| splitmix((u64::from(client_id) << 32) | *worker as u64))
+            .unwrap_or_default()
+    }
+}
+
+/// SplitMix64 finaliser, so neighbouring inputs give uncorrelated hashes
+pub(crate) fn splitmix(value: u64) -> u64 {
+    let mut hash = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// The partitioners which can be chosen with `--partitioner`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PartitionerKind {
+    #[default]
+    Sticky,
+    Modulo,
+    Rendezvous,
+}
+
+impl PartitionerKind {
+    pub fn partitioner(self) -> Box<dyn Partitioner> {
+        match self {
+            Self::Sticky => Box::new(Sticky),
+            Self::Modulo => Box::new(Modulo),
+            Self::Rendezvous => Box::new(Rendezvous),
+        }
+    }
+}
+
+impl fmt::Display for PartitionerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sticky => "sticky",
+            Self::Modulo => "modulo",
+            Self::Rendezvous => "rendezvous",
+        })
+    }
+}
+
+impl FromStr for PartitionerKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sticky" => Ok(Self::Sticky),
+            "modulo" => Ok(Self::Modulo),
+            "rendezvous" => Ok(Self::Rendezvous),
+            _ => bail!("unknown partitioner `{s}`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        partitioner::{Modulo, Partitioner, Rendezvous, Sticky},
+        router::RoutingStats,
+    };
+
+    fn stats(events: Vec<u64>) -> RoutingStats {
+        RoutingStats {
+            clients: vec![0; events.len()],
+            events,
+            skipped: 0,
+            rejected: 0,
+            lost: 0,
+            malformed: 0,
+        }
+    }
+
+    #[test]
+    fn assigns_clients_by_each_policy() {
+        let stats = stats(vec![5, 1, 3]);
+        let running = [true; 3];
+        assert_eq!(Sticky.assign(7, &stats, &running), 1);
+        assert_eq!(Sticky.assign(7, &stats, &[true, false, true]), 2);
+        assert_eq!(Modulo.assign(7, &stats, &running), 1);
+        assert_eq!(Modulo.assign(9, &stats, &[false; 3]), 0);
+
+        // Only the clients of a stopped worker move
+        let before: Vec<_> = (0..300)
+            .map(|client| Rendezvous.assign(client, &stats, &running))
+            .collect();
+        for worker in 0..3 {
+            assert!(before.iter().filter(|w| **w == worker).count() > 50);
+        }
+        let stopped = [true, false, true];
+        for (client, worker) in (0..300).zip(&before) {
+            let after = Rendezvous.assign(client, &stats, &stopped);
+            if *worker == 1 {
+                assert_ne!(after, 1);
+            } else {
+                assert_eq!(after, *worker);
+            }
+        }
+    }
+}
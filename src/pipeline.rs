@@ -0,0 +1,710 @@
+use std::{fmt, sync::Arc};
+
+use anyhow::{Context, Result};
+use futures::{future::ready, Stream, StreamExt};
+use rust_decimal::Decimal;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{error, info, warn};
+
+use crate::{
+    account::{AccountUpdate, ClientState},
+    cli::Args,
+    data::{DisputeRecord, Transaction},
+    health::Health,
+    io_ops::{ChunkedSource, CsvSource, FastSource},
+    ledger::{event_handler, Ledger, WorkerOptions, WorkerPanic, WorkerSinks},
+    limits::LimitConfig,
+    memory::MemoryBudget,
+    retry::RetryPolicy,
+    router::{Router, SliceEnd},
+    shutdown::{Cancellation, Cancelled, Shutdown},
+    snapshot::{self, Restored, SnapshotKey},
+    source::route_events,
+};
+
+/// Workers panicked, the accounts of the others were written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkersPanicked {
+    pub panics: Vec<WorkerPanic>,
+}
+
+impl fmt::Display for WorkersPanicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} workers panicked, the output only holds the accounts of the others",
+            self.panics.len()
+        )?;
+        for panic in &self.panics {
+            write!(f, "\n    {panic}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for WorkersPanicked {}
+
+/// The message a task panicked with
+fn panic_message(e: tokio::task::JoinError) -> String {
+    let panic = e.into_panic();
+    match panic.downcast_ref::<&str>() {
+        Some(message) => (*message).to_owned(),
+        None => panic
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "a non-string payload".to_owned()),
+    }
+}
+
+/// Applies `transactions` in order with a single default ledger, yielding the client's
+/// account after every transaction which applied. Rejected transactions are logged and
+/// yield nothing.
+pub fn process_stream(
+    transactions: impl Stream<Item = Transaction>,
+) -> impl Stream<Item = AccountUpdate> {
+    process_stream_with(Ledger::new(), transactions)
+}
+
+/// As `process_stream`, with a configured `ledger`
+pub fn process_stream_with(
+    ledger: Ledger,
+    transactions: impl Stream<Item = Transaction>,
+) -> impl Stream<Item = AccountUpdate> {
+    transactions
+        .scan(ledger, |ledger, tx| {
+            let update = ledger.apply(tx).map_err(|e| error!("{}", e)).ok();
+            ready(Some(update))
+        })
+        .filter_map(ready)
+}
+
+/// Applies `transactions` in order to `ledger` on the calling thread, without a runtime,
+/// channels or workers, and returns it. Rejected transactions are logged. For a small input
+/// this avoids the overhead of `process_file`, see `examples/sync_crossover.rs`.
+pub fn process_iter(
+    mut ledger: Ledger,
+    transactions: impl IntoIterator<Item = Transaction>,
+) -> Ledger {
+    for tx in transactions {
+        if let Err((_, e)) = ledger.process_transaction(tx) {
+            error!("{}", e);
+        }
+    }
+    ledger
+}
+
+/// Processes one input file with a fresh set of workers, so its ledgers share no state
+/// with any other file processed by the same process. Reading stops early once `shutdown`
+/// is triggered, the records read until then are still applied. A failure of the reader or
+/// of any worker cancels the others, and is what the input fails with. A worker which
+/// panicked does not: the events of its clients are dropped, the others carry on and the
+/// panic is recorded in `Ledger::worker_panics`. Returns the workers' ledgers merged into
+/// one, transactions included. While the file is read its accounts can be queried through
+/// `sinks.queries`.
+///
+/// # Errors
+/// If the file cannot be read or a worker fails
+pub async fn process_file(
+    file_path: &str,
+    args: &Args,
+    sinks: WorkerSinks,
+    shutdown: &Shutdown,
+) -> Result<Ledger> {
+    let opening = match &args.opening_balances {
+        Some(path) => opening_balances(path, args.snapshot_key.as_ref()).await?,
+        None => OpeningBalances::default(),
+    };
+    let withdrawal_limits = LimitConfig::load(args.withdrawal_limits.as_deref())?;
+    // one worker per logical core this process could try to use, unless told otherwise
+    let num = args.workers.unwrap_or_else(num_cpus::get);
+    let options = WorkerOptions {
+        retry: RetryPolicy {
+            retries: args.retries,
+            ..RetryPolicy::default()
+        },
+        reorder_window: args.reorder_window,
+        audit: args.audit_digest,
+        activity: args.export.is_some(),
+        velocity: args.velocity,
+        anomaly: args.anomaly,
+        overdrafts: Arc::new(args.overdrafts.clone()),
+        locked_policy: args.locked_policy,
+        dispute_policy: args.dispute_policy,
+        chargeback_limit: args.chargeback_limit,
+        max_amount: args.max_amount,
+        withdrawal_limits,
+        disputable: args.disputable,
+        // Usage is tracked for reporting even without a limit
+        memory: Some(Arc::new(MemoryBudget::new(
+            args.max_memory.unwrap_or(usize::MAX),
+        ))),
+        cancel: Cancellation::new(),
+        pseudonyms: args.pseudonyms.clone(),
+        latency_budget: args.latency_budget,
+    };
+
+    // Instantiate workers and senders
+    let (mut event_senders, mut workers) = (Vec::with_capacity(num), Vec::with_capacity(num));
+    let mut probes = Vec::new();
+    for _ in 0..num {
+        let (client_sender, client_receiver) = mpsc::unbounded_channel();
+        event_senders.push(client_sender);
+        let probe = sinks.health.as_ref().map(Health::probe);
+        probes.extend(probe.clone());
+        let sinks = WorkerSinks {
+            probe,
+            ..sinks.clone()
+        };
+        workers.push(tokio::spawn(event_handler(
+            client_receiver,
+            options.clone(),
+            sinks,
+        )));
+    }
+    // Queries reach the workers until the input was read
+    let queries = sinks.queries.clone();
+    if let Some(queries) = &queries {
+        queries.attach(&event_senders);
+    }
+    drop(sinks);
+
+    // Read the events of the input's source and push them to the Event Router
+    let mut router = Router::new(event_senders)
+        .with_partitioner(args.partitioner.partitioner())
+        .with_probes(probes)
+        .with_timestamps(args.latency_budget.is_some())
+        .with_filter(args.filter.clone())
+        .with_slice(args.skip, args.limit)
+        .with_minor_units(args.columns.minor_units);
+    // Accounts carried over reach their workers before any event of their clients
+    for state in opening.accounts {
+        router.open(state);
+    }
+    for (tx_id, record, held) in opening.transactions {
+        router.restore(tx_id, record, held);
+    }
+    let reading = async {
+        let (format, io_retry) = (args.csv, args.io_retry);
+        #[cfg(feature = "avro")]
+        if crate::avro::is_container(file_path) {
+            let mut source = crate::avro::AvroSource::open(file_path, format).await?;
+            return route_events(&mut source, &mut router).await;
+        }
+        #[cfg(feature = "xlsx")]
+        if crate::xlsx::is_workbook(file_path) {
+            let mut source = crate::xlsx::XlsxSource::open(file_path, format).await?;
+            return route_events(&mut source, &mut router).await;
+        }
+        if args.readers > 1 {
+            let mut source = ChunkedSource::open(file_path, args.readers, format, io_retry).await?;
+            route_events(&mut source, &mut router).await
+        } else if args.fast_parse {
+            let mut source = FastSource::open(file_path, format, io_retry).await?;
+            route_events(&mut source, &mut router).await
+        } else {
+            let mut source = CsvSource::open(file_path, format, io_retry).await?;
+            route_events(&mut source, &mut router).await
+        }
+    };
+    let read = tokio::select! {
+        biased;
+        () = shutdown.triggered() => {
+            warn!("Stopped reading {} on shutdown", file_path);
+            Ok(())
+        }
+        read = reading => read,
+        () = options.cancel.cancelled() => Err(Cancelled.into()),
+    };
+    // Reading stops early once the slice of `--limit` records was routed
+    let read = read.or_else(|e| if e.is::<SliceEnd>() { Ok(()) } else { Err(e) });
+    if matches!(&read, Err(e) if !e.is::<Cancelled>()) {
+        options.cancel.cancel();
+    }
+    let stats = router.stats().clone();
+    drop(router);
+    if let Some(queries) = queries {
+        queries.detach();
+    }
+    info!("Routed {}:\n{}", file_path, stats);
+    if stats.malformed > 0 {
+        warn!(
+            "Skipped {} malformed records of {}",
+            stats.malformed, file_path
+        );
+    }
+    if args.worker_stats {
+        eprint!("worker stats {file_path}:\n{stats}");
+    }
+
+    let (merged, cancelled) = merge_workers(file_path, workers, &stats.clients).await?;
+    read?;
+    // A worker which failed explains why the others were cancelled
+    if let Some(e) = cancelled {
+        return Err(e);
+    }
+    if let Some(memory) = options.memory {
+        info!("Ledgers of {} hold ~{} bytes", file_path, memory.used());
+        if args.worker_stats {
+            eprintln!("memory {file_path}: ~{} bytes", memory.used());
+        }
+    }
+    if args.worker_stats {
+        eprintln!("settlement {file_path}: {}", merged.settlement());
+        let warnings: Vec<_> = merged
+            .warnings()
+            .iter()
+            .map(|(kind, count)| format!("{kind} {count}"))
+            .collect();
+        eprintln!("warnings {file_path}: [{}]", warnings.join(", "));
+    }
+
+    Ok(merged)
+}
+
+/// What `--opening-balances` carries over from an earlier run
+#[derive(Debug, Default)]
+pub struct OpeningBalances {
+    /// In client id order
+    pub accounts: Vec<ClientState>,
+    /// The deposits and withdrawals applied to the accounts in transaction id order, with
+    /// what a dispute holding less than the amount holds. Empty for a CSV of the accounts.
+    pub transactions: Vec<(u32, DisputeRecord, Option<Decimal>)>,
+}
+
+impl From<Restored> for OpeningBalances {
+    fn from(restored: Restored) -> Self {
+        let Restored {
+            accounts,
+            transactions,
+            mut partial_holds,
+        } = restored;
+        let mut accounts: Vec<_> = accounts.into_values().collect();
+        accounts.sort_unstable_by_key(ClientState::id);
+        let mut transactions: Vec<_> = transactions
+            .into_iter()
+            .map(|(tx_id, record)| (tx_id, record, partial_holds.remove(&tx_id)))
+            .collect();
+        transactions.sort_unstable_by_key(|(tx_id, ..)| *tx_id);
+        Self {
+            accounts,
+            transactions,
+        }
+    }
+}
+
+/// The accounts of `--opening-balances` and, for a snapshot, the transactions applied to
+/// them. A sealed snapshot is opened with `key`.
+///
+/// # Errors
+/// If the file cannot be read, cannot be opened with `key` or is malformed
+pub async fn opening_balances(
+    file_path: &str,
+    key: Option<&SnapshotKey>,
+) -> Result<OpeningBalances> {
+    let restored = snapshot::load(file_path, key)
+        .await
+        .with_context(|| format!("Cannot read the opening balances {file_path}"))?;
+    Ok(restored.into())
+}
+
+/// Merges the ledgers of the `workers` of `file_path` as they finish, recording those which
+/// panicked with the number of `clients` assigned to each. Returns the error of the last
+/// worker cancelled, if any.
+///
+/// # Errors
+/// With the error of a worker which failed, other than being cancelled
+async fn merge_workers(
+    file_path: &str,
+    workers: Vec<JoinHandle<Result<Ledger>>>,
+    clients: &[usize],
+) -> Result<(Ledger, Option<anyhow::Error>)> {
+    let (mut merged, mut cancelled) = (Ledger::new(), None);
+    for (worker, event_handler) in workers.into_iter().enumerate() {
+        match event_handler.await {
+            Ok(Ok(ledger)) => merged.merge(ledger)?,
+            Ok(Err(e)) if e.is::<Cancelled>() => cancelled = Some(e),
+            Ok(Err(e)) => return Err(e),
+            Err(e) if e.is_panic() => {
+                let panic = WorkerPanic {
+                    file_path: file_path.to_owned(),
+                    worker,
+                    clients: clients.get(worker).copied().unwrap_or_default(),
+                    message: panic_message(e),
+                };
+                error!("{}", panic);
+                merged.record_worker_panic(panic);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok((merged, cancelled))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, time::Duration};
+
+    use futures::{future::try_join, stream, StreamExt};
+    use rust_decimal::Decimal;
+    use tokio::{sync::mpsc, task::JoinHandle};
+
+    use crate::{
+        account::{AccountSummary, AccountUpdate, ClientState},
+        cli::Args,
+        data::{Sequenced, Transaction},
+        digest::run_digest,
+        io_ops::{read_transactions_blocking, AccountSink},
+        ledger::{event_handler, Ledger, WorkerMsg, WorkerOptions, WorkerPanic, WorkerSinks},
+        pipeline::{merge_workers, process_file, process_iter, process_stream},
+        pseudonym::ClientLabel,
+        router::Router,
+        shutdown::Shutdown,
+    };
+
+    fn write_fixture(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("effective-train-{name}.csv"));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn tenants_have_isolated_ledgers() {
+        let acme = write_fixture(
+            "tenant-acme",
+            "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\n",
+        );
+        let globex = write_fixture(
+            "tenant-globex",
+            "type,client,tx,amount\ndeposit,1,3,1.0\ndispute,1,1,\n",
+        );
+        let args = Args::parse(
+            ["bin", "--tenant", "acme=a.csv"]
+                .map(String::from)
+                .into_iter(),
+        )
+        .unwrap();
+
+        let (acme, globex) = try_join(
+            process_file(&acme, &args, WorkerSinks::default(), &Shutdown::new()),
+            process_file(&globex, &args, WorkerSinks::default(), &Shutdown::new()),
+        )
+        .await
+        .unwrap();
+        // tx 1 belongs to the other tenant so the dispute has nothing to hold
+        assert!(globex.transaction(1).is_none());
+        assert!(globex.open_disputes(1).is_empty());
+        assert_eq!(acme.transaction(1).unwrap().amount(), Decimal::TEN);
+
+        let (acme, globex) = (acme.into_accounts(), globex.into_accounts());
+        assert_eq!(acme.len(), 2);
+        assert_eq!(acme.get(&1).unwrap().available().to_string(), "10");
+        assert_eq!(globex.len(), 1);
+        assert_eq!(globex.get(&1).unwrap().held().to_string(), "0");
+        assert_eq!(globex.get(&1).unwrap().available().to_string(), "1");
+    }
+
+    #[tokio::test]
+    async fn sync_processing_matches_the_workers() {
+        let contents = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\n\
+            withdrawal,1,3,4.0\ndispute,2,2,\nwithdrawal,2,4,1.0\nchargeback,2,2,\n";
+        let path = write_fixture("sync", contents);
+        let workers = process_file(
+            &path,
+            &Args::default(),
+            WorkerSinks::default(),
+            &Shutdown::new(),
+        )
+        .await
+        .unwrap()
+        .into_accounts();
+
+        let transactions =
+            read_transactions_blocking(contents.as_bytes(), Args::default().csv).unwrap();
+        let sync = process_iter(Ledger::new(), transactions).into_accounts();
+        let summaries = |accounts: &HashMap<u16, ClientState>| {
+            let mut summaries: Vec<_> = accounts.values().map(AccountSummary::from).collect();
+            summaries.sort_unstable_by_key(|summary| summary.client);
+            summaries
+        };
+        assert_eq!(summaries(&sync), summaries(&workers));
+        assert!(sync.get(&2).unwrap().is_locked());
+        assert_eq!(sync.get(&1).unwrap().available().to_string(), "6.0");
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_reading_the_input() {
+        let path = write_fixture("shutdown", "type,client,tx,amount\ndeposit,1,1,10.0\n");
+        let args = Args::default();
+        let shutdown = Shutdown::new();
+
+        let results = process_file(&path, &args, WorkerSinks::default(), &shutdown)
+            .await
+            .unwrap();
+        assert_eq!(results.into_accounts().len(), 1);
+        shutdown.trigger();
+        let results = process_file(&path, &args, WorkerSinks::default(), &shutdown)
+            .await
+            .unwrap();
+        assert!(results.into_accounts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failing_worker_cancels_the_input() {
+        let records: String = (1..=50)
+            .map(|client| format!("deposit,{client},{client},1.0\n"))
+            .collect();
+        let path = write_fixture("cancel", &format!("type,client,tx,amount\n{records}"));
+        let args = Args {
+            max_memory: Some(1),
+            ..Args::default()
+        };
+
+        let Err(e) = process_file(&path, &args, WorkerSinks::default(), &Shutdown::new()).await
+        else {
+            panic!("the input should exceed its memory budget");
+        };
+        // The failure which cancelled the other workers is reported rather than theirs
+        assert!(e.to_string().starts_with("Memory budget of"), "{e}");
+    }
+
+    #[tokio::test]
+    async fn workers_stream_finished_accounts_to_the_sink() {
+        let path = write_fixture(
+            "finished-accounts",
+            "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,40000,2,5.0\ndeposit,2,3,1.0\n",
+        );
+        let (sink, receivers) = AccountSink::new(2);
+        let sinks = WorkerSinks {
+            finished_accounts: Some(sink),
+            ..WorkerSinks::default()
+        };
+        let collect = receivers.into_iter().map(|mut receiver| async move {
+            let mut clients = Vec::new();
+            while let Some(state) = receiver.recv().await {
+                clients.push(state.id());
+            }
+            clients.sort_unstable();
+            clients
+        });
+
+        let (ledger, partitions) = futures::join!(
+            process_file(&path, &Args::default(), sinks, &Shutdown::new()),
+            futures::future::join_all(collect)
+        );
+        let ledger = ledger.unwrap();
+        assert_eq!(partitions, vec![vec![1, 2], vec![40000]]);
+        assert_eq!(ledger.account_count(), 3);
+        assert_eq!(ledger.applied(), 3);
+        assert!(ledger.into_accounts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stream_yields_an_update_per_applied_transaction() {
+        let transactions = stream::iter([
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::withdrawal(1, 2, Decimal::ONE_HUNDRED),
+            Transaction::dispute(1, 1),
+            Transaction::chargeback(1, 1),
+        ]);
+
+        let updates: Vec<_> = process_stream(transactions).collect().await;
+        assert_eq!(
+            updates,
+            vec![
+                AccountUpdate {
+                    client: 1,
+                    label: ClientLabel::Id(1),
+                    tx: 1,
+                    available: Decimal::TEN,
+                    held: Decimal::ZERO,
+                    total: Decimal::TEN,
+                    locked: false,
+                },
+                AccountUpdate {
+                    client: 1,
+                    label: ClientLabel::Id(1),
+                    tx: 1,
+                    available: Decimal::ZERO,
+                    held: Decimal::TEN,
+                    total: Decimal::TEN,
+                    locked: false,
+                },
+                AccountUpdate {
+                    client: 1,
+                    label: ClientLabel::Id(1),
+                    tx: 1,
+                    available: Decimal::ZERO,
+                    held: Decimal::ZERO,
+                    total: Decimal::ZERO,
+                    locked: true,
+                },
+            ]
+        );
+    }
+
+    /// xorshift64*, so a failing seed replays the same workload and delays
+    struct Chaos(u64);
+
+    impl Chaos {
+        fn new(seed: u64) -> Self {
+            Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+        }
+
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % bound
+        }
+
+        /// Lets other tasks run for a random number of scheduler turns, occasionally
+        /// sleeping so timers fire in between too
+        async fn stall(&mut self) {
+            for _ in 0..self.next(4) {
+                tokio::task::yield_now().await;
+            }
+            if self.next(64) == 0 {
+                tokio::time::sleep(Duration::from_micros(self.next(200))).await;
+            }
+        }
+    }
+
+    /// Deposits and withdrawals over `clients`, mixed with disputes, resolves and
+    /// chargebacks of earlier deposits, some of which are rejected
+    fn workload(seed: u64, clients: u16, len: u32) -> Vec<Transaction> {
+        let mut chaos = Chaos::new(seed);
+        let mut deposits: Vec<(u16, u32)> = Vec::new();
+        (1..=len)
+            .map(|tx| {
+                let client = u16::try_from(chaos.next(u64::from(clients))).unwrap() + 1;
+                let amount = Decimal::new(i64::try_from(chaos.next(10_000)).unwrap(), 2);
+                let earlier = usize::try_from(chaos.next(deposits.len() as u64 + 1)).unwrap();
+                match (chaos.next(20), deposits.get(earlier).copied()) {
+                    (0..=7, _) | (14.., None) => {
+                        deposits.push((client, tx));
+                        Transaction::deposit(client, tx, amount)
+                    }
+                    (8..=13, _) => Transaction::withdrawal(client, tx, amount),
+                    (14..=16, Some((client, disputed))) => Transaction::dispute(client, disputed),
+                    (17..=18, Some((client, disputed))) => Transaction::resolve(client, disputed),
+                    (_, Some((client, disputed))) => Transaction::chargeback(client, disputed),
+                }
+            })
+            .collect()
+    }
+
+    /// Routes `transactions` to `workers` workers as `process_file` does, through a relay in
+    /// front of each worker which stalls every event by a random amount
+    async fn run_with_chaos(
+        seed: u64,
+        transactions: &[Transaction],
+        workers: u64,
+    ) -> (HashMap<u16, ClientState>, Vec<AccountUpdate>) {
+        let (update_sender, mut update_receiver) = mpsc::unbounded_channel();
+        let options = WorkerOptions {
+            audit: true,
+            ..WorkerOptions::default()
+        };
+        let sinks = WorkerSinks {
+            account_updates: Some(update_sender),
+            ..WorkerSinks::default()
+        };
+
+        let (mut senders, mut handles) = (Vec::new(), Vec::new());
+        for worker in 0..workers {
+            let (relay_sender, mut relay_receiver) = mpsc::unbounded_channel::<WorkerMsg>();
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let mut chaos = Chaos::new(seed.wrapping_add(worker + 1));
+            tokio::spawn(async move {
+                while let Some(event) = relay_receiver.recv().await {
+                    chaos.stall().await;
+                    sender.send(event).unwrap();
+                }
+            });
+            senders.push(relay_sender);
+            handles.push(tokio::spawn(event_handler(
+                receiver,
+                options.clone(),
+                sinks.clone(),
+            )));
+        }
+        drop(sinks);
+
+        let (mut router, mut chaos) = (Router::new(senders), Chaos::new(seed));
+        for (seq, tx) in (0..).zip(transactions) {
+            chaos.stall().await;
+            let tx = tx.clone();
+            router.route(Sequenced { seq, tx }).unwrap();
+        }
+        drop(router);
+
+        let mut merged = Ledger::new();
+        for handle in handles {
+            merged.merge(handle.await.unwrap().unwrap()).unwrap();
+        }
+        let results = merged.into_accounts();
+        let mut updates = Vec::new();
+        while let Some(update) = update_receiver.recv().await {
+            updates.push(update);
+        }
+        (results, updates)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn chaos_keeps_client_order_and_balances() {
+        let transactions = workload(7, 40, 3_000);
+        let mut ledger = Ledger::new().with_audit(true);
+        let expected_updates: Vec<_> = transactions
+            .iter()
+            .filter_map(|tx| ledger.apply(tx.clone()).ok())
+            .collect();
+        let expected = ledger.into_accounts();
+        let per_client = |updates: &[AccountUpdate], client: u16| -> Vec<AccountUpdate> {
+            let updates = updates.iter().filter(|update| update.client == client);
+            updates.cloned().collect()
+        };
+
+        let mut interleaved = false;
+        for seed in 0..8 {
+            let (results, updates) = run_with_chaos(seed, &transactions, 4).await;
+            interleaved |= updates != expected_updates;
+            // The digest chains every state transition of every account
+            assert_eq!(run_digest(&results), run_digest(&expected), "seed {seed}");
+            for client in expected.keys() {
+                assert_eq!(
+                    per_client(&updates, *client),
+                    per_client(&expected_updates, *client),
+                    "seed {seed}"
+                );
+            }
+        }
+        // Otherwise the relays did not stall anything
+        assert!(interleaved);
+    }
+
+    #[tokio::test]
+    async fn a_panicked_worker_leaves_the_others_merged() {
+        let healthy = tokio::spawn(async {
+            let mut ledger = Ledger::new();
+            ledger.apply(Transaction::deposit(1, 1, Decimal::ONE))?;
+            anyhow::Ok(ledger)
+        });
+        let panicked: JoinHandle<anyhow::Result<Ledger>> =
+            tokio::spawn(async { panic!("worker bug") });
+
+        let (merged, cancelled) = merge_workers("tx.csv", vec![healthy, panicked], &[1, 4])
+            .await
+            .unwrap();
+        assert!(cancelled.is_none());
+        assert_eq!(merged.summary(1).unwrap().total, Decimal::ONE);
+        assert_eq!(
+            merged.worker_panics().to_vec(),
+            vec![WorkerPanic {
+                file_path: "tx.csv".to_owned(),
+                worker: 1,
+                clients: 4,
+                message: "worker bug".to_owned(),
+            }]
+        );
+    }
+}
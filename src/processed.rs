@@ -0,0 +1,83 @@
+//! Checksums of the inputs a run applied, so a batch is not processed twice
+
+use std::{collections::HashMap, io};
+
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::digest::to_hex;
+
+/// Inputs recorded in a `sha256,path` file by earlier runs, keyed by their hash
+#[derive(Debug, Default)]
+pub struct ProcessedLog {
+    path: String,
+    /// Path each input was processed under, by the hex SHA-256 of its contents
+    inputs: HashMap<String, String>,
+}
+
+impl ProcessedLog {
+    /// Reads the log at `path`, which is empty if it does not exist yet
+    ///
+    /// # Errors
+    /// If the log exists but cannot be read
+    pub async fn load(path: &str) -> io::Result<Self> {
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let inputs = contents
+            .lines()
+            .filter_map(|line| line.split_once(','))
+            .map(|(digest, input)| (digest.to_owned(), input.to_owned()))
+            .collect();
+        Ok(Self {
+            path: path.to_owned(),
+            inputs,
+        })
+    }
+
+    /// Path an input with these contents was processed under before
+    pub fn find(&self, digest: &[u8; 32]) -> Option<&str> {
+        self.inputs.get(&to_hex(digest)).map(String::as_str)
+    }
+
+    /// Appends the input to the log
+    ///
+    /// # Errors
+    /// If the log cannot be written
+    pub async fn record(&mut self, digest: &[u8; 32], file_path: &str) -> io::Result<()> {
+        let digest = to_hex(digest);
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        log.write_all(format!("{digest},{file_path}\n").as_bytes())
+            .await?;
+        log.flush().await?;
+        self.inputs.insert(digest, file_path.to_owned());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{digest::sha256, processed::ProcessedLog};
+
+    #[tokio::test]
+    async fn recorded_inputs_are_found_by_later_runs() {
+        let path = std::env::temp_dir().join("effective-train-processed.log");
+        let path = path.to_string_lossy().into_owned();
+        std::fs::remove_file(&path).ok();
+        let (monday, tuesday) = (sha256(b"monday"), sha256(b"tuesday"));
+
+        let mut log = ProcessedLog::load(&path).await.unwrap();
+        assert_eq!(log.find(&monday), None);
+        log.record(&monday, "in/batch,monday.csv").await.unwrap();
+        assert_eq!(log.find(&monday), Some("in/batch,monday.csv"));
+
+        let log = ProcessedLog::load(&path).await.unwrap();
+        assert_eq!(log.find(&monday), Some("in/batch,monday.csv"));
+        assert_eq!(log.find(&tuesday), None);
+    }
+}
@@ -0,0 +1,345 @@
+//! Protobuf messages of `proto/effective_train.proto`, so the records and account summaries
+//! exchanged with other services share one schema.
+//!
+//! The types are derived with prost rather than generated by `prost-build`, which needs
+//! `protoc` at build time; the tests check their numbers against the schema. Amounts are
+//! decimal strings, as in the CSV files, and converted with the `TryFrom` impls here.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rust_decimal::Decimal;
+
+use crate::{account, account::ClientState, data, pseudonym::ClientLabel};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum TransactionType {
+    Deposit = 0,
+    Withdrawal = 1,
+    Dispute = 2,
+    Resolve = 3,
+    Chargeback = 4,
+    Reversal = 5,
+    Adjustment = 6,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum ReasonCode {
+    Correction = 0,
+    Refund = 1,
+    Fee = 2,
+    Goodwill = 3,
+}
+
+/// A single input record, mirroring the `type,client,tx,amount` CSV columns
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Transaction {
+    #[prost(enumeration = "TransactionType", tag = "1")]
+    pub r#type: i32,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(uint32, tag = "3")]
+    pub tx: u32,
+    #[prost(string, optional, tag = "4")]
+    pub amount: Option<String>,
+    /// Set for adjustments only
+    #[prost(enumeration = "ReasonCode", optional, tag = "5")]
+    pub reason: Option<i32>,
+}
+
+/// The reported state of a client account, amounts rounded to four decimal places
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AccountSummary {
+    #[prost(uint32, tag = "1")]
+    pub client: u32,
+    #[prost(string, tag = "2")]
+    pub available: String,
+    #[prost(string, tag = "3")]
+    pub held: String,
+    #[prost(string, tag = "4")]
+    pub total: String,
+    #[prost(bool, tag = "5")]
+    pub locked: bool,
+}
+
+impl From<data::ReasonCode> for ReasonCode {
+    fn from(reason: data::ReasonCode) -> Self {
+        match reason {
+            data::ReasonCode::Correction => Self::Correction,
+            data::ReasonCode::Refund => Self::Refund,
+            data::ReasonCode::Fee => Self::Fee,
+            data::ReasonCode::Goodwill => Self::Goodwill,
+        }
+    }
+}
+
+impl From<ReasonCode> for data::ReasonCode {
+    fn from(reason: ReasonCode) -> Self {
+        match reason {
+            ReasonCode::Correction => Self::Correction,
+            ReasonCode::Refund => Self::Refund,
+            ReasonCode::Fee => Self::Fee,
+            ReasonCode::Goodwill => Self::Goodwill,
+        }
+    }
+}
+
+/// Fails for a transaction of a custom kind, which only the registry of the ledger it was
+/// built for knows
+impl TryFrom<&data::Transaction> for Transaction {
+    type Error = anyhow::Error;
+
+    fn try_from(tx: &data::Transaction) -> Result<Self> {
+        let (tx_type, reason) = match tx.tx_type() {
+            data::TransactionType::Deposit => (TransactionType::Deposit, None),
+            data::TransactionType::Withdrawal => (TransactionType::Withdrawal, None),
+            data::TransactionType::Dispute => (TransactionType::Dispute, None),
+            data::TransactionType::Resolve => (TransactionType::Resolve, None),
+            data::TransactionType::Chargeback => (TransactionType::Chargeback, None),
+            data::TransactionType::Reversal => (TransactionType::Reversal, None),
+            data::TransactionType::Adjustment(reason) => {
+                (TransactionType::Adjustment, Some(ReasonCode::from(reason)))
+            }
+            data::TransactionType::Custom(_) => bail!(
+                "Transaction `{}` is a {} which has no protobuf type",
+                tx.tx_id(),
+                tx.tx_type()
+            ),
+        };
+        Ok(Self {
+            r#type: tx_type.into(),
+            client: tx.client_id().into(),
+            tx: tx.tx_id(),
+            amount: tx.amount().map(|amount| amount.to_string()),
+            reason: reason.map(Into::into),
+        })
+    }
+}
+
+/// Fails for a client id beyond `u16`, an unknown type or reason, an amount which is not a
+/// decimal, or a reason given to anything but an adjustment. Adjustments are never read
+/// from CSV, so a host application decoding records from elsewhere decides whether to
+/// accept them.
+impl TryFrom<Transaction> for data::Transaction {
+    type Error = anyhow::Error;
+
+    fn try_from(tx: Transaction) -> Result<Self> {
+        let client_id = u16::try_from(tx.client)
+            .with_context(|| format!("Client `{}` of transaction `{}`", tx.client, tx.tx))?;
+        let tx_type = TransactionType::try_from(tx.r#type)
+            .map_err(|_| anyhow!("Transaction `{}` has unknown type {}", tx.tx, tx.r#type))?;
+        let reason = tx
+            .reason
+            .map(|reason| {
+                ReasonCode::try_from(reason)
+                    .map_err(|_| anyhow!("Transaction `{}` has unknown reason {reason}", tx.tx))
+            })
+            .transpose()?;
+        let amount = tx
+            .amount
+            .as_deref()
+            .map(Decimal::from_str)
+            .transpose()
+            .with_context(|| format!("Amount of transaction `{}`", tx.tx))?;
+        let tx_type = match (tx_type, reason) {
+            (TransactionType::Adjustment, Some(reason)) => {
+                data::TransactionType::Adjustment(reason.into())
+            }
+            (TransactionType::Adjustment, None) => bail!("Adjustment `{}` has no reason", tx.tx),
+            (_, Some(_)) => bail!("Transaction `{}` has a reason but is no adjustment", tx.tx),
+            (TransactionType::Deposit, None) => data::TransactionType::Deposit,
+            (TransactionType::Withdrawal, None) => data::TransactionType::Withdrawal,
+            (TransactionType::Dispute, None) => data::TransactionType::Dispute,
+            (TransactionType::Resolve, None) => data::TransactionType::Resolve,
+            (TransactionType::Chargeback, None) => data::TransactionType::Chargeback,
+            (TransactionType::Reversal, None) => data::TransactionType::Reversal,
+        };
+        Ok(Self::new(tx_type, client_id, tx.tx, amount))
+    }
+}
+
+impl From<&account::AccountSummary> for AccountSummary {
+    fn from(summary: &account::AccountSummary) -> Self {
+        Self {
+            client: summary.client.into(),
+            available: summary.available.to_string(),
+            held: summary.held.to_string(),
+            total: summary.total.to_string(),
+            locked: summary.locked,
+        }
+    }
+}
+
+impl From<&ClientState> for AccountSummary {
+    fn from(client: &ClientState) -> Self {
+        Self::from(&account::AccountSummary::from(client))
+    }
+}
+
+/// Fails for a client id beyond `u16` or an amount which is not a decimal
+impl TryFrom<AccountSummary> for account::AccountSummary {
+    type Error = anyhow::Error;
+
+    fn try_from(summary: AccountSummary) -> Result<Self> {
+        let client = u16::try_from(summary.client)
+            .with_context(|| format!("Client `{}` of an account summary", summary.client))?;
+        let amount = |column: &str, amount: &str| {
+            Decimal::from_str(amount)
+                .with_context(|| format!("`{column}` of the account of client `{client}`"))
+        };
+        Ok(Self {
+            client,
+            label: ClientLabel::Id(client),
+            available: amount("available", &summary.available)?,
+            held: amount("held", &summary.held)?,
+            total: amount("total", &summary.total)?,
+            locked: summary.locked,
+            flags: None,
+            overdraft_used: None,
+            deposited: None,
+            withdrawn: None,
+            disputes: None,
+        })
+    }
+}
+
+/// The account as an opening balance, without its transaction history, as a snapshot
+/// restores it. `total` is not read, it follows from the funds available and held.
+impl TryFrom<AccountSummary> for ClientState {
+    type Error = anyhow::Error;
+
+    fn try_from(summary: AccountSummary) -> Result<Self> {
+        let summary = account::AccountSummary::try_from(summary)?;
+        Ok(ClientState::restore(
+            summary.client,
+            summary.available,
+            summary.held,
+            summary.locked,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prost::Message;
+    use rust_decimal::Decimal;
+
+    use crate::{
+        account::{self, ClientState},
+        data::{self, CustomKind},
+        ledger::Ledger,
+        proto::{AccountSummary, ReasonCode, Transaction, TransactionType},
+    };
+
+    #[test]
+    fn numbers_match_the_schema() {
+        let schema = include_str!("../proto/effective_train.proto");
+        let numbers = [
+            ("DEPOSIT", i32::from(TransactionType::Deposit)),
+            ("WITHDRAWAL", TransactionType::Withdrawal.into()),
+            ("DISPUTE", TransactionType::Dispute.into()),
+            ("RESOLVE", TransactionType::Resolve.into()),
+            ("CHARGEBACK", TransactionType::Chargeback.into()),
+            ("REVERSAL", TransactionType::Reversal.into()),
+            ("ADJUSTMENT", TransactionType::Adjustment.into()),
+            ("CORRECTION", ReasonCode::Correction.into()),
+            ("REFUND", ReasonCode::Refund.into()),
+            ("FEE", ReasonCode::Fee.into()),
+            ("GOODWILL", ReasonCode::Goodwill.into()),
+        ];
+        for (name, number) in numbers {
+            assert!(schema.contains(&format!("  {name} = {number};")), "{name}");
+        }
+    }
+
+    #[test]
+    fn transactions_round_trip_through_their_encoding() {
+        let amount = Decimal::new(-12_345, 4);
+        let transactions = [
+            data::Transaction::deposit(1, 1, Decimal::new(1050, 2)),
+            data::Transaction::withdrawal(2, 2, Decimal::ONE),
+            data::Transaction::dispute(1, 1),
+            data::Transaction::resolve(1, 1),
+            data::Transaction::chargeback(u16::MAX, u32::MAX),
+            data::Transaction::reversal(2, 2),
+            data::Transaction::adjustment(3, 3, amount, data::ReasonCode::Goodwill),
+        ];
+        for tx in transactions {
+            let encoded = Transaction::try_from(&tx).unwrap().encode_to_vec();
+            let decoded = Transaction::decode(encoded.as_slice()).unwrap();
+            let decoded = data::Transaction::try_from(decoded).unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{tx:?}"));
+        }
+
+        let custom = data::Transaction::custom(CustomKind(0), 1, 4, None);
+        assert!(Transaction::try_from(&custom).is_err());
+    }
+
+    #[test]
+    fn malformed_transactions_are_rejected() {
+        let deposit = Transaction {
+            r#type: TransactionType::Deposit.into(),
+            client: 1,
+            tx: 1,
+            amount: Some("1.5".to_string()),
+            reason: None,
+        };
+        assert!(data::Transaction::try_from(deposit.clone()).is_ok());
+
+        let malformed = [
+            Transaction {
+                client: u32::from(u16::MAX) + 1,
+                ..deposit.clone()
+            },
+            Transaction {
+                r#type: 7,
+                ..deposit.clone()
+            },
+            Transaction {
+                amount: Some("1,5".to_string()),
+                ..deposit.clone()
+            },
+            Transaction {
+                reason: Some(ReasonCode::Fee.into()),
+                ..deposit.clone()
+            },
+            Transaction {
+                r#type: TransactionType::Adjustment.into(),
+                ..deposit
+            },
+        ];
+        for tx in malformed {
+            assert!(data::Transaction::try_from(tx).is_err());
+        }
+    }
+
+    #[test]
+    fn accounts_round_trip_through_their_encoding() {
+        let mut ledger = Ledger::default();
+        let transactions = [
+            data::Transaction::deposit(1, 1, Decimal::new(100_005, 5)),
+            data::Transaction::deposit(1, 2, Decimal::TWO),
+            data::Transaction::dispute(1, 2),
+            data::Transaction::chargeback(1, 2),
+        ];
+        for tx in transactions {
+            ledger.apply(tx).unwrap();
+        }
+        let client = ledger.accounts().next().unwrap();
+
+        let encoded = AccountSummary::from(client).encode_to_vec();
+        let decoded = AccountSummary::decode(encoded.as_slice()).unwrap();
+        assert_eq!(
+            account::AccountSummary::try_from(decoded.clone()).unwrap(),
+            account::AccountSummary::from(client)
+        );
+        let restored = ClientState::try_from(decoded).unwrap();
+        assert_eq!(
+            account::AccountSummary::from(&restored),
+            account::AccountSummary::from(client)
+        );
+        assert!(restored.is_locked());
+    }
+}
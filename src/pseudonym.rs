@@ -0,0 +1,211 @@
+//! Keyed pseudonyms of client ids, written in place of the ids with `--pseudonym-key` so
+//! reports can be shared with third parties. Only holders of the key can tell which client
+//! a pseudonym stands for, see `reveal`.
+
+use std::{fmt, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+#[cfg(feature = "runtime")]
+use csv_async::AsyncWriter;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+#[cfg(feature = "runtime")]
+use tokio::io::AsyncWrite;
+
+use crate::digest::to_hex;
+#[cfg(feature = "runtime")]
+use crate::io_ops::finish_csv;
+
+/// Shorter keys could be found by trying every key against a known client's pseudonym
+const MIN_KEY_LEN: usize = 16;
+/// Hex digits of the HMAC kept in a pseudonym, ample to tell 65536 clients apart
+const PSEUDONYM_LEN: usize = 16;
+
+/// Derives the pseudonym of each client id as the HMAC-SHA256 of the id under a key
+#[derive(Clone, PartialEq, Eq)]
+pub struct Pseudonymizer {
+    key: Arc<[u8]>,
+}
+
+impl Pseudonymizer {
+    /// # Errors
+    /// If `key` is shorter than 16 bytes
+    pub fn new(key: &[u8]) -> Result<Self> {
+        if key.len() < MIN_KEY_LEN {
+            bail!("A pseudonym key needs at least {MIN_KEY_LEN} bytes");
+        }
+        Ok(Self { key: key.into() })
+    }
+
+    /// Reads the key from `file_path`, ignoring a trailing newline
+    ///
+    /// # Errors
+    /// If the file cannot be read or holds too short a key
+    pub fn load(file_path: &str) -> Result<Self> {
+        let key = std::fs::read(file_path)
+            .with_context(|| format!("Cannot read the pseudonym key {file_path}"))?;
+        Self::new(key.trim_ascii_end())
+    }
+
+    /// The client's pseudonym, `p` followed by 16 lowercase hex digits
+    pub fn pseudonym(&self, client_id: u16) -> String {
+        let digest = self.hmac(&client_id.to_be_bytes());
+        format!("p{}", &to_hex(&digest)[..PSEUDONYM_LEN])
+    }
+
+    /// The client id whose pseudonym is `pseudonym` under this key, if any
+    pub fn reveal(&self, pseudonym: &str) -> Option<u16> {
+        (0..=u16::MAX).find(|&client_id| self.pseudonym(client_id) == pseudonym)
+    }
+
+    /// HMAC-SHA256 of `data`, per RFC 2104
+    fn hmac(&self, data: &[u8]) -> [u8; 32] {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(data);
+        mac.finalize().into_bytes().into()
+    }
+}
+
+/// Never prints the key
+impl fmt::Debug for Pseudonymizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pseudonymizer").finish_non_exhaustive()
+    }
+}
+
+/// A client as written to an output, by id or by pseudonym
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ClientLabel {
+    Id(u16),
+    Pseudonym(String),
+}
+
+impl ClientLabel {
+    /// The client's pseudonym if `pseudonyms` are enabled, and its id otherwise
+    pub fn new(client_id: u16, pseudonyms: Option<&Pseudonymizer>) -> Self {
+        match pseudonyms {
+            Some(pseudonyms) => Self::Pseudonym(pseudonyms.pseudonym(client_id)),
+            None => Self::Id(client_id),
+        }
+    }
+}
+
+impl fmt::Display for ClientLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Id(client_id) => write!(f, "{client_id}"),
+            Self::Pseudonym(pseudonym) => f.write_str(pseudonym),
+        }
+    }
+}
+
+/// Writes the client id of each of `pseudonyms` under the key of `pseudonymizer`
+///
+/// # Errors
+/// If a pseudonym matches no client, or `writer` cannot be written
+#[cfg(feature = "runtime")]
+pub async fn write_revealed<W: AsyncWrite + Unpin>(
+    pseudonyms: &[String],
+    pseudonymizer: &Pseudonymizer,
+    writer: W,
+) -> Result<()> {
+    let mut revealed = Vec::with_capacity(pseudonyms.len());
+    for pseudonym in pseudonyms {
+        let client_id = pseudonymizer
+            .reveal(pseudonym)
+            .with_context(|| format!("Pseudonym `{pseudonym}` matches no client under this key"))?;
+        revealed.push((pseudonym, client_id));
+    }
+    let mut writer = AsyncWriter::from_writer(writer);
+    writer.write_record(&["pseudonym", "client"]).await?;
+    for (pseudonym, client_id) in revealed {
+        writer
+            .write_record(&[pseudonym.clone(), client_id.to_string()])
+            .await?;
+    }
+    finish_csv(writer).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        digest::to_hex,
+        pseudonym::{write_revealed, ClientLabel, Pseudonymizer},
+    };
+
+    #[test]
+    fn hmac_matches_known_vectors() {
+        // RFC 4231 test case 2, a key shorter than the block
+        let pseudonyms = Pseudonymizer {
+            key: b"Jefe".as_slice().into(),
+        };
+        assert_eq!(
+            to_hex(&pseudonyms.hmac(b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // RFC 4231 test case 6, a key longer than the block
+        let pseudonyms = Pseudonymizer {
+            key: vec![0xaa; 131].into(),
+        };
+        assert_eq!(
+            to_hex(&pseudonyms.hmac(b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn pseudonyms_are_keyed_and_reversible_with_the_key() {
+        let pseudonyms = Pseudonymizer::new(b"0123456789abcdef").unwrap();
+        let other = Pseudonymizer::new(b"fedcba9876543210").unwrap();
+
+        let pseudonym = pseudonyms.pseudonym(42);
+        assert_eq!(pseudonym.len(), 17);
+        assert!(pseudonym.starts_with('p'));
+        assert_eq!(pseudonym, pseudonyms.pseudonym(42));
+        assert_ne!(pseudonym, pseudonyms.pseudonym(43));
+        assert_ne!(pseudonym, other.pseudonym(42));
+
+        assert_eq!(pseudonyms.reveal(&pseudonym), Some(42));
+        assert_eq!(other.reveal(&pseudonym), None);
+        assert!(Pseudonymizer::new(b"short").is_err());
+        assert_eq!(format!("{pseudonyms:?}"), "Pseudonymizer { .. }");
+    }
+
+    #[tokio::test]
+    async fn reveals_pseudonyms_to_key_holders() {
+        let pseudonyms = Pseudonymizer::new(b"0123456789abcdef").unwrap();
+        let revealed = [pseudonyms.pseudonym(9), pseudonyms.pseudonym(65_535)];
+        let mut output = Vec::new();
+        write_revealed(&revealed, &pseudonyms, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "pseudonym,client\n{},9\n{},65535\n",
+                revealed[0], revealed[1]
+            )
+        );
+
+        let unknown = ["p0000000000000000".to_owned()];
+        let e = write_revealed(&unknown, &pseudonyms, Vec::new())
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("matches no client"));
+    }
+
+    #[test]
+    fn labels_are_ids_unless_pseudonymized() {
+        let pseudonyms = Pseudonymizer::new(b"0123456789abcdef").unwrap();
+        assert_eq!(ClientLabel::new(7, None).to_string(), "7");
+        assert_eq!(
+            ClientLabel::new(7, Some(&pseudonyms)).to_string(),
+            pseudonyms.pseudonym(7)
+        );
+    }
+}
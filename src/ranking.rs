@@ -0,0 +1,175 @@
+//! Ranked summaries of an input's accounts, printed with the stats for triage after a batch
+
+use std::{cmp::Reverse, collections::HashMap, fmt};
+
+use rust_decimal::Decimal;
+
+use crate::account::{AccountSummary, ClientState};
+
+/// The accounts with the highest totals, highest first and ties in client id order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopBalances(pub Vec<(u16, Decimal)>);
+
+impl TopBalances {
+    #[allow(clippy::implicit_hasher)]
+    pub fn new(results: &HashMap<u16, ClientState>, n: usize) -> Self {
+        let mut totals: Vec<_> = results
+            .values()
+            .map(|client| (client.id(), AccountSummary::from(client).total))
+            .collect();
+        totals.sort_unstable_by_key(|(client, total)| (Reverse(*total), *client));
+        totals.truncate(n);
+        Self(totals)
+    }
+}
+
+impl fmt::Display for TopBalances {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (rank, (client, total)) in (1..).zip(&self.0) {
+            writeln!(f, "{rank}. client {client}: total {total}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The clients with the most disputes ever opened against their transactions, most first
+/// and ties in client id order. Clients without any dispute are left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopDisputeClients(pub Vec<(u16, u32)>);
+
+impl TopDisputeClients {
+    #[allow(clippy::implicit_hasher)]
+    pub fn new(results: &HashMap<u16, ClientState>, n: usize) -> Self {
+        let mut disputes: Vec<_> = results
+            .values()
+            .filter(|client| client.disputes() > 0)
+            .map(|client| (client.id(), client.disputes()))
+            .collect();
+        disputes.sort_unstable_by_key(|(client, disputes)| (Reverse(*disputes), *client));
+        disputes.truncate(n);
+        Self(disputes)
+    }
+}
+
+impl fmt::Display for TopDisputeClients {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (rank, (client, disputes)) in (1..).zip(&self.0) {
+            writeln!(f, "{rank}. client {client}: {disputes} disputes")?;
+        }
+        Ok(())
+    }
+}
+
+/// The accounts with negative available funds, e.g. as a deposit already withdrawn was
+/// disputed, most negative first and ties in client id order, so operations can chase their
+/// recovery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegativeBalances(pub Vec<(u16, Decimal, Decimal)>);
+
+impl NegativeBalances {
+    #[allow(clippy::implicit_hasher)]
+    pub fn new(results: &HashMap<u16, ClientState>) -> Self {
+        let mut negative: Vec<_> = results
+            .values()
+            .map(AccountSummary::from)
+            .filter(|summary| summary.available < Decimal::ZERO)
+            .map(|summary| (summary.client, summary.available, summary.held))
+            .collect();
+        negative.sort_unstable_by_key(|(client, available, _)| (*available, *client));
+        Self(negative)
+    }
+}
+
+impl fmt::Display for NegativeBalances {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (client, available, held) in &self.0 {
+            writeln!(f, "client {client}: available {available}, held {held}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        account::ClientState,
+        data::Transaction,
+        ledger::Ledger,
+        ranking::{NegativeBalances, TopBalances, TopDisputeClients},
+    };
+
+    #[test]
+    fn ranks_clients_highest_first() {
+        let mut ledger = Ledger::new();
+        let transactions = [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::deposit(2, 2, Decimal::ONE_HUNDRED),
+            Transaction::deposit(3, 3, Decimal::TEN),
+            Transaction::deposit(3, 4, Decimal::ONE),
+            Transaction::dispute(3, 3),
+            Transaction::resolve(3, 3),
+            Transaction::dispute(3, 4),
+            Transaction::dispute(1, 1),
+        ];
+        for tx in transactions {
+            ledger.apply(tx).unwrap();
+        }
+        let mut results = ledger.into_accounts();
+        results.insert(4, ClientState::new(4));
+
+        let balances = TopBalances::new(&results, 3);
+        assert_eq!(
+            balances.0,
+            vec![
+                (2, Decimal::ONE_HUNDRED),
+                (3, Decimal::from(11)),
+                (1, Decimal::TEN)
+            ]
+        );
+        assert_eq!(
+            balances.to_string(),
+            "1. client 2: total 100\n2. client 3: total 11\n3. client 1: total 10\n"
+        );
+
+        let disputes = TopDisputeClients::new(&results, 5);
+        assert_eq!(disputes.0, vec![(3, 2), (1, 1)]);
+        assert_eq!(
+            disputes.to_string(),
+            "1. client 3: 2 disputes\n2. client 1: 1 disputes\n"
+        );
+        assert!(TopDisputeClients::new(&HashMap::new(), 5).0.is_empty());
+    }
+
+    #[test]
+    fn lists_negative_balances_most_negative_first() {
+        let mut ledger = Ledger::new();
+        let transactions = [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::withdrawal(1, 2, Decimal::from(8)),
+            Transaction::dispute(1, 1),
+            Transaction::deposit(2, 3, Decimal::TEN),
+            Transaction::deposit(3, 4, Decimal::from(5)),
+            Transaction::withdrawal(3, 5, Decimal::from(5)),
+            Transaction::dispute(3, 4),
+        ];
+        for tx in transactions {
+            ledger.apply(tx).unwrap();
+        }
+        let negative = NegativeBalances::new(&ledger.into_accounts());
+        assert_eq!(
+            negative.0,
+            vec![
+                (1, Decimal::from(-8), Decimal::TEN),
+                (3, Decimal::from(-5), Decimal::from(5))
+            ]
+        );
+        assert_eq!(
+            negative.to_string(),
+            "client 1: available -8, held 10\nclient 3: available -5, held 5\n"
+        );
+    }
+}
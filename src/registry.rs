@@ -0,0 +1,117 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    account::ClientState,
+    data::{CustomKind, Transaction},
+};
+
+/// Applies a custom transaction to its client's account. A handler which returns an error
+/// must leave the account unchanged, as the transaction is then reported as rejected.
+pub type CustomHandler = Box<dyn Fn(&mut ClientState, &Transaction) -> Result<()> + Send + Sync>;
+
+const BUILT_IN: [&str; 6] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "reversal",
+];
+
+/// Transaction kinds a library user added on top of the built-in types, e.g. bonuses, each
+/// applied by its handler when a `Ledger` configured `with_registry` processes one
+#[derive(Default)]
+pub struct TransactionRegistry {
+    kinds: Vec<(String, CustomHandler)>,
+}
+
+impl TransactionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `name`, returning the kind to create its transactions with
+    /// `Transaction::custom`
+    ///
+    /// # Errors
+    /// If `name` is a built-in type or already registered, or 256 kinds are registered
+    pub fn register(
+        &mut self,
+        name: &str,
+        handler: impl Fn(&mut ClientState, &Transaction) -> Result<()> + Send + Sync + 'static,
+    ) -> Result<CustomKind> {
+        if BUILT_IN.contains(&name) || self.kind(name).is_some() {
+            bail!("Transaction kind `{name}` is already registered");
+        }
+        let Ok(kind) = u8::try_from(self.kinds.len()) else {
+            bail!("No more than 256 custom transaction kinds can be registered");
+        };
+        self.kinds.push((name.to_owned(), Box::new(handler)));
+        Ok(CustomKind(kind))
+    }
+
+    pub fn kind(&self, name: &str) -> Option<CustomKind> {
+        let kind = self.kinds.iter().position(|(kind, _)| kind == name)?;
+        u8::try_from(kind).ok().map(CustomKind)
+    }
+
+    pub fn name(&self, kind: CustomKind) -> Option<&str> {
+        self.kinds
+            .get(usize::from(kind.0))
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub(crate) fn apply(
+        &self,
+        kind: CustomKind,
+        state: &mut ClientState,
+        tx: &Transaction,
+    ) -> Result<()> {
+        match self.kinds.get(usize::from(kind.0)) {
+            Some((_, handler)) => handler(state, tx),
+            None => bail!("Transaction `{}` has an unregistered kind", tx.tx_id()),
+        }
+    }
+}
+
+impl fmt::Debug for TransactionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.kinds.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use crate::{data::Transaction, registry::TransactionRegistry};
+
+    #[test]
+    fn kinds_are_registered_once_by_name() {
+        let mut registry = TransactionRegistry::new();
+        let bonus = registry.register("bonus", |_, _| Ok(())).unwrap();
+        let fee = registry.register("fee", |_, _| Ok(())).unwrap();
+        assert_ne!(bonus, fee);
+        assert_eq!(registry.kind("fee"), Some(fee));
+        assert_eq!(registry.name(bonus), Some("bonus"));
+        assert_eq!(
+            Transaction::custom(fee, 1, 2, Some(Decimal::ONE))
+                .tx_type()
+                .to_string(),
+            "custom:1"
+        );
+
+        assert!(registry.register("bonus", |_, _| Ok(())).is_err());
+        assert_eq!(
+            registry
+                .register("deposit", |_, _| Ok(()))
+                .unwrap_err()
+                .to_string(),
+            "Transaction kind `deposit` is already registered"
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use tokio::{sync::mpsc::UnboundedSender, time::Instant};
+use tracing::{error, warn};
+
+use crate::{
+    data::{Sequenced, TxOutcome, TxStatus},
+    ledger::AmountOutOfBounds,
+    pseudonym::Pseudonymizer,
+    warning::{SkippedRecord, Warning, WarningKind},
+};
+
+/// How often a failed transaction is retried before it is dead-lettered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    /// Delay before the first retry, doubled on each subsequent attempt
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+/// A transaction which failed every attempt, with the error from its final attempt
+#[derive(Debug)]
+pub struct DeadLetter {
+    pub event: Sequenced,
+    pub reason: String,
+}
+
+struct PendingRetry {
+    due: Instant,
+    attempt: u32,
+    event: Sequenced,
+}
+
+/// Failed transactions waiting for their next attempt
+pub struct RetryQueue {
+    policy: RetryPolicy,
+    pending: Vec<PendingRetry>,
+    dead_letters: Option<UnboundedSender<DeadLetter>>,
+    tx_outcomes: Option<UnboundedSender<TxOutcome>>,
+    /// Records skipped with a warning, sent here rather than to `dead_letters`
+    skipped: Option<UnboundedSender<SkippedRecord>>,
+    /// Transactions given up on, by `rejection_reason`
+    rejected: BTreeMap<String, u64>,
+    /// Records skipped with a warning, by kind
+    warnings: BTreeMap<WarningKind, u64>,
+    /// Logs clients by pseudonym and errors by their `rejection_reason` when set
+    pseudonyms: Option<Pseudonymizer>,
+}
+
+impl RetryQueue {
+    pub fn new(policy: RetryPolicy, dead_letters: Option<UnboundedSender<DeadLetter>>) -> Self {
+        Self {
+            policy,
+            pending: Vec::new(),
+            dead_letters,
+            tx_outcomes: None,
+            skipped: None,
+            rejected: BTreeMap::new(),
+            warnings: BTreeMap::new(),
+            pseudonyms: None,
+        }
+    }
+
+    /// Publishes every transaction given up on to `sender` as rejected
+    #[must_use]
+    pub fn with_tx_outcomes(mut self, sender: Option<UnboundedSender<TxOutcome>>) -> Self {
+        self.tx_outcomes = sender;
+        self
+    }
+
+    /// Publishes every record skipped with a warning to `sender` rather than dead-lettering it
+    #[must_use]
+    pub fn with_warnings(mut self, sender: Option<UnboundedSender<SkippedRecord>>) -> Self {
+        self.skipped = sender;
+        self
+    }
+
+    /// Keeps client ids out of the log, see `Pseudonymizer`
+    #[must_use]
+    pub fn with_pseudonyms(mut self, pseudonyms: Option<Pseudonymizer>) -> Self {
+        self.pseudonyms = pseudonyms;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// When the earliest pending retry becomes due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending.iter().map(|retry| retry.due).min()
+    }
+
+    /// Removes the retries which are due, with the attempt number each is on
+    pub fn take_due(&mut self) -> Vec<(u32, Sequenced)> {
+        let now = Instant::now();
+        let (due, waiting) = self.pending.drain(..).partition(|retry| retry.due <= now);
+        self.pending = waiting;
+        due.into_iter()
+            .map(|retry| (retry.attempt, retry.event))
+            .collect()
+    }
+
+    /// Schedules another attempt with exponential backoff, or dead-letters the
+    /// transaction once `attempt` has exhausted the retry budget. Warnings and amounts out of
+    /// bounds, which no later attempt can clear, are not retried.
+    pub fn failed(&mut self, attempt: u32, event: Sequenced, err: &anyhow::Error) {
+        let retryable = err
+            .downcast_ref::<Warning>()
+            .is_none_or(|warning| warning.kind.retryable())
+            && !err.is::<AmountOutOfBounds>();
+        if retryable && attempt < self.policy.retries {
+            let delay = self.policy.backoff.saturating_mul(1 << attempt.min(16));
+            self.pending.push(PendingRetry {
+                due: Instant::now() + delay,
+                attempt: attempt + 1,
+                event,
+            });
+            return;
+        }
+        self.dead_letter(event, err);
+    }
+
+    /// Gives up on the transaction without retrying it, skipping it if the error is a
+    /// `Warning`
+    pub fn dead_letter(&mut self, event: Sequenced, err: &anyhow::Error) {
+        if let Some(warning) = err.downcast_ref::<Warning>() {
+            self.skip(event, warning.clone());
+            return;
+        }
+        let reason = rejection_reason(&err.to_string());
+        match &self.pseudonyms {
+            Some(pseudonyms) => error!(
+                "Processing transaction error for client {} `{}`",
+                pseudonyms.pseudonym(event.tx.client_id()),
+                reason
+            ),
+            None => error!("Processing transaction error `{}`", err),
+        }
+        *self.rejected.entry(reason).or_default() += 1;
+        if let Some(sender) = &self.tx_outcomes {
+            sender
+                .send(TxOutcome {
+                    tx_id: event.tx.tx_id(),
+                    client_id: event.tx.client_id(),
+                    status: TxStatus::Rejected(err.to_string()),
+                })
+                .ok();
+        }
+        if let Some(dead_letters) = &self.dead_letters {
+            dead_letters
+                .send(DeadLetter {
+                    event,
+                    reason: err.to_string(),
+                })
+                .ok();
+        }
+    }
+
+    fn skip(&mut self, event: Sequenced, warning: Warning) {
+        match &self.pseudonyms {
+            Some(pseudonyms) => warn!(
+                "Skipped transaction for client {} `{}`",
+                pseudonyms.pseudonym(event.tx.client_id()),
+                rejection_reason(&warning.message)
+            ),
+            None => warn!("Skipped transaction `{}`", warning),
+        }
+        *self.warnings.entry(warning.kind).or_default() += 1;
+        if let Some(sender) = &self.tx_outcomes {
+            sender
+                .send(TxOutcome {
+                    tx_id: event.tx.tx_id(),
+                    client_id: event.tx.client_id(),
+                    status: TxStatus::Skipped(warning.message.clone()),
+                })
+                .ok();
+        }
+        if let Some(skipped) = &self.skipped {
+            skipped.send(SkippedRecord { event, warning }).ok();
+        }
+    }
+
+    /// Number of transactions given up on, by `rejection_reason`
+    pub fn rejected(&self) -> &BTreeMap<String, u64> {
+        &self.rejected
+    }
+
+    /// Number of records skipped with a warning, by kind
+    pub fn warnings(&self) -> &BTreeMap<WarningKind, u64> {
+        &self.warnings
+    }
+}
+
+/// The error message with quoted values and numbers replaced by `*`, so rejections of
+/// different clients and transactions for the same reason are counted together
+pub fn rejection_reason(message: &str) -> String {
+    let mut reason = String::with_capacity(message.len());
+    let mut chars = message.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '`' | '\'' => {
+                reason.push(c);
+                reason.push('*');
+                if chars.by_ref().any(|end| end == c) {
+                    reason.push(c);
+                }
+            }
+            '0'..='9' => {
+                reason.push('*');
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+            }
+            c => reason.push(c),
+        }
+    }
+    reason
+}
+
+#[cfg(test)]
+mod test {
+    use crate::retry::rejection_reason;
+
+    #[test]
+    fn rejection_reasons_hide_ids() {
+        assert_eq!(
+            rejection_reason("Withdrawal failed due to insufficient funds in Client Account `12`"),
+            "Withdrawal failed due to insufficient funds in Client Account `*`"
+        );
+        assert_eq!(
+            rejection_reason("Transaction `4` arrived out of order (sequence 10 after 32)"),
+            "Transaction `*` arrived out of order (sequence * after *)"
+        );
+        assert_eq!(
+            rejection_reason("Unmatched transaction `Transaction { client_id: 1 }`"),
+            "Unmatched transaction `*`"
+        );
+    }
+}
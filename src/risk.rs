@@ -0,0 +1,334 @@
+//! Hooks deciding whether a transaction may be applied to a client's account
+
+use std::collections::VecDeque;
+
+use rust_decimal::Decimal;
+
+use crate::data::{Transaction, TransactionType};
+
+/// Amounts of a type in the history below which `AnomalyPolicy` does not assess it
+pub const MIN_SAMPLES: usize = 5;
+
+/// Outcome of assessing a transaction before it is applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RiskDecision {
+    Allow,
+    /// Apply the transaction but count a flag against the account
+    Flag(String),
+    /// Do not apply the transaction
+    Reject(String),
+}
+
+/// Transactions recently applied to a client's account, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct ClientHistory {
+    recent: VecDeque<(TransactionType, Option<Decimal>)>,
+    capacity: usize,
+}
+
+impl ClientHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            recent: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn recent(&self) -> impl DoubleEndedIterator<Item = &TransactionType> {
+        self.recent.iter().map(|(tx_type, _)| tx_type)
+    }
+
+    /// Amounts of the transactions of `tx_type` among the `last` ones, newest first
+    pub fn recent_amounts(
+        &self,
+        tx_type: TransactionType,
+        last: usize,
+    ) -> impl Iterator<Item = Decimal> + '_ {
+        self.recent
+            .iter()
+            .rev()
+            .take(last)
+            .filter(move |(recent_type, _)| *recent_type == tx_type)
+            .filter_map(|(_, amount)| *amount)
+    }
+
+    pub(crate) fn push(&mut self, tx: &Transaction) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.recent.len() == self.capacity {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((tx.tx_type(), tx.amount()));
+    }
+}
+
+/// Invoked by the ledger before each transaction is applied
+pub trait RiskPolicy: Send {
+    /// How many of a client's most recent transactions `assess` is given
+    fn history_len(&self) -> usize;
+
+    fn assess(&self, tx: &Transaction, history: &ClientHistory) -> RiskDecision;
+}
+
+/// Policies assessed in turn, given the longest history any of them needs. The first
+/// rejection wins, otherwise the reasons of every flag are joined.
+impl RiskPolicy for Vec<Box<dyn RiskPolicy>> {
+    fn history_len(&self) -> usize {
+        self.iter()
+            .map(|policy| policy.history_len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn assess(&self, tx: &Transaction, history: &ClientHistory) -> RiskDecision {
+        let mut flags = Vec::new();
+        for policy in self {
+            match policy.assess(tx, history) {
+                RiskDecision::Allow => {}
+                RiskDecision::Flag(reason) => flags.push(reason),
+                RiskDecision::Reject(reason) => return RiskDecision::Reject(reason),
+            }
+        }
+        if flags.is_empty() {
+            RiskDecision::Allow
+        } else {
+            RiskDecision::Flag(flags.join("; "))
+        }
+    }
+}
+
+/// The policies enabled by the options, combined if there are several
+pub fn risk_policy(
+    velocity: Option<VelocityPolicy>,
+    anomaly: Option<AnomalyPolicy>,
+) -> Option<Box<dyn RiskPolicy>> {
+    let mut policies: Vec<Box<dyn RiskPolicy>> = Vec::new();
+    if let Some(velocity) = velocity {
+        policies.push(Box::new(velocity));
+    }
+    if let Some(anomaly) = anomaly {
+        policies.push(Box::new(anomaly));
+    }
+    match policies.len() {
+        0 | 1 => policies.pop(),
+        _ => Some(Box::new(policies)),
+    }
+}
+
+/// Flags, or rejects, a withdrawal which would make more than `max_withdrawals` of the
+/// client's last `window` transactions withdrawals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VelocityPolicy {
+    pub max_withdrawals: usize,
+    pub window: usize,
+    pub reject: bool,
+}
+
+impl RiskPolicy for VelocityPolicy {
+    fn history_len(&self) -> usize {
+        self.window.saturating_sub(1)
+    }
+
+    fn assess(&self, tx: &Transaction, history: &ClientHistory) -> RiskDecision {
+        if tx.tx_type() != TransactionType::Withdrawal {
+            return RiskDecision::Allow;
+        }
+
+        let withdrawals = 1 + history
+            .recent()
+            .rev()
+            .take(self.history_len())
+            .filter(|tx_type| **tx_type == TransactionType::Withdrawal)
+            .count();
+        if withdrawals <= self.max_withdrawals {
+            return RiskDecision::Allow;
+        }
+
+        let reason = format!(
+            "{withdrawals} withdrawals in the last {} transactions",
+            self.window
+        );
+        if self.reject {
+            RiskDecision::Reject(reason)
+        } else {
+            RiskDecision::Flag(reason)
+        }
+    }
+}
+
+/// Flags a deposit or withdrawal whose amount is more than `max_zscore` standard deviations
+/// from the mean amount of the client's deposits, or withdrawals, among its last `window`
+/// transactions. Clients with fewer than `MIN_SAMPLES` such amounts are not assessed, and a
+/// client whose amounts never varied is flagged on any other amount. Unusual amounts are
+/// never rejected, only flagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnomalyPolicy {
+    pub max_zscore: Decimal,
+    pub window: usize,
+}
+
+impl RiskPolicy for AnomalyPolicy {
+    fn history_len(&self) -> usize {
+        self.window
+    }
+
+    fn assess(&self, tx: &Transaction, history: &ClientHistory) -> RiskDecision {
+        let (tx_type, Some(amount)) = (tx.tx_type(), tx.amount()) else {
+            return RiskDecision::Allow;
+        };
+        if !matches!(
+            tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            return RiskDecision::Allow;
+        }
+
+        let amounts: Vec<_> = history.recent_amounts(tx_type, self.window).collect();
+        let samples = amounts.len();
+        if samples < MIN_SAMPLES {
+            return RiskDecision::Allow;
+        }
+        // Compares squares, (amount - mean)² > z² × variance, as a Decimal has no square root
+        let n = Decimal::from(samples);
+        let mean = amounts.iter().sum::<Decimal>() / n;
+        let squares = amounts.iter().try_fold(Decimal::ZERO, |sum, amount| {
+            let deviation = amount - mean;
+            sum.checked_add(deviation.checked_mul(deviation)?)
+        });
+        let deviation = amount - mean;
+        let limit = squares.and_then(|squares| {
+            (squares / n).checked_mul(self.max_zscore.checked_mul(self.max_zscore)?)
+        });
+        let outlier = limit
+            .zip(deviation.checked_mul(deviation))
+            .is_some_and(|(limit, square)| square > limit);
+        if !outlier {
+            return RiskDecision::Allow;
+        }
+
+        RiskDecision::Flag(format!(
+            "{tx_type} of {amount} is more than {} standard deviations from the mean {} of \
+             the last {samples}",
+            self.max_zscore,
+            mean.round_dp(4).normalize()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::Transaction,
+        risk::{
+            risk_policy, AnomalyPolicy, ClientHistory, RiskDecision, RiskPolicy, VelocityPolicy,
+        },
+    };
+
+    #[test]
+    fn velocity_policy_counts_withdrawals_in_window() {
+        let policy = VelocityPolicy {
+            max_withdrawals: 2,
+            window: 3,
+            reject: false,
+        };
+        let mut history = ClientHistory::new(policy.history_len());
+        let withdrawal = Transaction::withdrawal(1, 9, Decimal::ONE);
+
+        history.push(&withdrawal);
+        assert_eq!(policy.assess(&withdrawal, &history), RiskDecision::Allow);
+        history.push(&withdrawal);
+        assert_eq!(
+            policy.assess(&withdrawal, &history),
+            RiskDecision::Flag("3 withdrawals in the last 3 transactions".to_owned())
+        );
+        assert_eq!(
+            policy.assess(&Transaction::deposit(1, 9, Decimal::ONE), &history),
+            RiskDecision::Allow
+        );
+
+        // The oldest withdrawal falls out of the window
+        history.push(&Transaction::deposit(1, 10, Decimal::ONE));
+        assert_eq!(policy.assess(&withdrawal, &history), RiskDecision::Allow);
+    }
+
+    #[test]
+    fn velocity_policy_can_reject() {
+        let policy = VelocityPolicy {
+            max_withdrawals: 0,
+            window: 1,
+            reject: true,
+        };
+        let history = ClientHistory::new(policy.history_len());
+        let withdrawal = Transaction::withdrawal(1, 9, Decimal::ONE);
+        assert!(matches!(
+            policy.assess(&withdrawal, &history),
+            RiskDecision::Reject(_)
+        ));
+    }
+
+    #[test]
+    fn anomaly_policy_flags_outlying_amounts() {
+        let policy = AnomalyPolicy {
+            max_zscore: Decimal::from(3),
+            window: 10,
+        };
+        let mut history = ClientHistory::new(policy.history_len());
+        let deposit = |tx, amount| Transaction::deposit(1, tx, Decimal::from(amount));
+        let outlier = deposit(99, 1000);
+
+        for (tx, amount) in [(1, 90), (2, 110), (3, 100), (4, 95)] {
+            history.push(&deposit(tx, amount));
+        }
+        // Too few deposits to tell
+        assert_eq!(policy.assess(&outlier, &history), RiskDecision::Allow);
+        history.push(&deposit(5, 105));
+        assert_eq!(
+            policy.assess(&outlier, &history),
+            RiskDecision::Flag(
+                "deposit of 1000 is more than 3 standard deviations from the mean 100 of the \
+                 last 5"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            policy.assess(&deposit(6, 120), &history),
+            RiskDecision::Allow
+        );
+        // Withdrawals are assessed against the client's withdrawals alone
+        let withdrawal = Transaction::withdrawal(1, 7, Decimal::from(1000));
+        assert_eq!(policy.assess(&withdrawal, &history), RiskDecision::Allow);
+    }
+
+    #[test]
+    fn combined_policies_join_their_flags() {
+        let policy = risk_policy(
+            Some(VelocityPolicy {
+                max_withdrawals: 0,
+                window: 1,
+                reject: false,
+            }),
+            Some(AnomalyPolicy {
+                max_zscore: Decimal::ONE,
+                window: 5,
+            }),
+        )
+        .unwrap();
+        assert_eq!(policy.history_len(), 5);
+        let mut history = ClientHistory::new(policy.history_len());
+        for tx in 1..=5 {
+            history.push(&Transaction::withdrawal(1, tx, Decimal::ONE));
+        }
+        assert_eq!(
+            policy.assess(&Transaction::withdrawal(1, 6, Decimal::TEN), &history),
+            RiskDecision::Flag(
+                "1 withdrawals in the last 1 transactions; withdrawal of 10 is more than 1 \
+                 standard deviations from the mean 1 of the last 5"
+                    .to_owned()
+            )
+        );
+        assert!(risk_policy(None, None).is_none());
+    }
+}
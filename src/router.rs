@@ -0,0 +1,722 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use anyhow::Result;
+use futures::future::join_all;
+use rust_decimal::Decimal;
+use tokio::{
+    sync::{mpsc::UnboundedSender, oneshot},
+    time::Instant,
+};
+
+use crate::{
+    account::{AccountSummary, ClientState},
+    data::{DisputeRecord, Sequenced, Transaction, TransactionType},
+    hasher::IdMap,
+    health::WorkerProbe,
+    ledger::WorkerMsg,
+    partitioner::{splitmix, Partitioner, Sticky},
+    units::MinorUnits,
+    warning::Warning,
+};
+
+/// Routes events to workers, pinning each client to the worker its `Partitioner` chooses
+/// when its first event arrives, by default the least loaded one.
+///
+/// A client never moves once pinned, its ledger state lives in that worker and its events
+/// must be applied in order, so a single hot client still occupies one worker. By default
+/// other clients are steered away from it instead of colliding with it by client id.
+pub struct Router {
+    senders: Vec<UnboundedSender<WorkerMsg>>,
+    partitioner: Box<dyn Partitioner>,
+    assignments: IdMap<u16, usize>,
+    stats: RoutingStats,
+    /// Counts the events routed to each worker for the health endpoints
+    probes: Vec<Arc<WorkerProbe>>,
+    /// Sends events as `WorkerMsg::Timed`
+    timed: bool,
+    filter: RecordFilter,
+    /// Records before the slice of the input which is routed
+    skip: u64,
+    /// Records in the slice, the rest of the input is not read
+    limit: Option<u64>,
+    /// Records seen so far, whether routed or skipped
+    position: u64,
+    /// Converts the amounts of the input from minor units
+    minor_units: Option<MinorUnits>,
+    /// The client whose deposit or withdrawal first used each transaction id, as ids are
+    /// unique across clients whichever workers the clients are pinned to
+    owners: IdMap<u32, u16>,
+}
+
+/// Returned by `Router::route` once the slice of the input was routed, so reading stops
+#[derive(Debug)]
+pub struct SliceEnd;
+
+impl fmt::Display for SliceEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Routed every record of the slice of the input")
+    }
+}
+
+impl std::error::Error for SliceEnd {}
+
+/// Events which are routed, any other event is skipped
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordFilter {
+    /// Only the events of these clients are routed if set
+    pub only_clients: Option<HashSet<u16>>,
+    /// Never routed, even if in `only_clients`
+    pub exclude_clients: HashSet<u16>,
+    /// Only events of these types are routed if set
+    pub only_types: Option<Vec<TransactionType>>,
+    /// Only the events of a sample of the clients are routed if set
+    pub sample: Option<ClientSample>,
+}
+
+/// A deterministic sample of the clients, every client being kept with the same probability
+/// by a hash of its id and the seed, so runs with the same seed keep the same clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientSample {
+    /// Clients kept per million
+    pub per_million: u32,
+    pub seed: u64,
+}
+
+impl ClientSample {
+    pub fn keeps(self, client_id: u16) -> bool {
+        splitmix(self.seed ^ u64::from(client_id)) % 1_000_000 < u64::from(self.per_million)
+    }
+}
+
+impl RecordFilter {
+    pub fn allows(&self, tx: &Transaction) -> bool {
+        self.keeps_client(tx.client_id())
+            && self
+                .only_types
+                .as_ref()
+                .is_none_or(|types| types.contains(&tx.tx_type()))
+    }
+
+    /// Whether any event of the client may be routed
+    pub fn keeps_client(&self, client_id: u16) -> bool {
+        self.only_clients
+            .as_ref()
+            .is_none_or(|only| only.contains(&client_id))
+            && !self.exclude_clients.contains(&client_id)
+            && self.sample.is_none_or(|sample| sample.keeps(client_id))
+    }
+}
+
+impl Router {
+    pub fn new(senders: Vec<UnboundedSender<WorkerMsg>>) -> Self {
+        let workers = senders.len();
+        Self {
+            senders,
+            partitioner: Box::new(Sticky),
+            assignments: IdMap::default(),
+            stats: RoutingStats {
+                events: vec![0; workers],
+                clients: vec![0; workers],
+                skipped: 0,
+                rejected: 0,
+                lost: 0,
+                malformed: 0,
+            },
+            probes: Vec::new(),
+            timed: false,
+            filter: RecordFilter::default(),
+            skip: 0,
+            limit: None,
+            position: 0,
+            minor_units: None,
+            owners: IdMap::default(),
+        }
+    }
+
+    /// Assigns new clients to workers with `partitioner`
+    #[must_use]
+    pub fn with_partitioner(mut self, partitioner: Box<dyn Partitioner>) -> Self {
+        self.partitioner = partitioner;
+        self
+    }
+
+    /// Only routes the `limit` records following the first `skip`, counting the records
+    /// filtered out as well
+    #[must_use]
+    pub fn with_slice(mut self, skip: u64, limit: Option<u64>) -> Self {
+        self.skip = skip;
+        self.limit = limit;
+        self
+    }
+
+    /// Skips the events `filter` does not allow
+    #[must_use]
+    pub fn with_filter(mut self, filter: RecordFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Reads the amounts of the events as whole minor units, converting them to major units
+    #[must_use]
+    pub fn with_minor_units(mut self, minor_units: Option<MinorUnits>) -> Self {
+        self.minor_units = minor_units;
+        self
+    }
+
+    /// Stamps each event with when it was routed, right after its record was read, for the
+    /// workers to track its latency
+    #[must_use]
+    pub fn with_timestamps(mut self, timed: bool) -> Self {
+        self.timed = timed;
+        self
+    }
+
+    /// Counts the events routed to each worker on its probe, in the order of the senders
+    #[must_use]
+    pub fn with_probes(mut self, probes: Vec<Arc<WorkerProbe>>) -> Self {
+        self.probes = probes;
+        self
+    }
+
+    /// Events of a client whose worker stopped, as one which panicked does, are counted as
+    /// lost. The partitioner is told which workers are still running. An event whose amount
+    /// is not a whole number of minor units, or a deposit or withdrawal with the id of
+    /// another client's, is reported by a worker without reaching its ledger.
+    ///
+    /// # Errors
+    /// With `SliceEnd` once past the slice
+    ///
+    /// # Panics
+    /// If there are no workers, or the partitioner assigns a worker which does not exist
+    pub fn route(&mut self, mut event: Sequenced) -> Result<()> {
+        let stats = &mut self.stats;
+        if self
+            .limit
+            .is_some_and(|limit| self.position >= self.skip.saturating_add(limit))
+        {
+            return Err(SliceEnd.into());
+        }
+        self.position += 1;
+        if self.position <= self.skip {
+            stats.skipped += 1;
+            return Ok(());
+        }
+        if !self.filter.allows(&event.tx) {
+            stats.skipped += 1;
+            return Ok(());
+        }
+        if let Some(units) = self.minor_units {
+            match units.to_major(event.tx) {
+                Ok(tx) => event.tx = tx,
+                Err(e) => {
+                    self.reject(event, e.into());
+                    return Ok(());
+                }
+            }
+        }
+        let tx = &event.tx;
+        if tx.is_disputable() {
+            let owner = *self.owners.entry(tx.tx_id()).or_insert(tx.client_id());
+            if owner != tx.client_id() {
+                let warning = Warning::duplicate_transaction(tx);
+                self.reject(event, warning.into());
+                return Ok(());
+            }
+        }
+        let worker = self.assign(event.tx.client_id());
+        let stats = &mut self.stats;
+        stats.events[worker] += 1;
+        if let Some(probe) = self.probes.get(worker) {
+            probe.routed();
+        }
+        let msg = if self.timed {
+            WorkerMsg::Timed(event, Instant::now())
+        } else {
+            WorkerMsg::Tx(event)
+        };
+        if self.senders[worker].send(msg).is_err() {
+            stats.lost += 1;
+        }
+        Ok(())
+    }
+
+    /// Sends an account carried over from an earlier run to the worker its client is
+    /// pinned to, unless the filter skips the client. To be called before any event of the
+    /// client is routed.
+    ///
+    /// # Panics
+    /// As `route`
+    pub fn open(&mut self, state: ClientState) {
+        if !self.filter.keeps_client(state.id()) {
+            return;
+        }
+        let worker = self.assign(state.id());
+        self.senders[worker].send(WorkerMsg::Open(state)).ok();
+    }
+
+    /// Sends a deposit or withdrawal carried over from an earlier run to the worker its
+    /// client is pinned to, with what its dispute holds if less than its amount, unless the
+    /// filter skips the client. To be called before any event of the client is routed.
+    ///
+    /// # Panics
+    /// As `route`
+    pub fn restore(&mut self, tx_id: u32, record: DisputeRecord, held: Option<Decimal>) {
+        if !self.filter.keeps_client(record.client_id()) {
+            return;
+        }
+        self.owners.insert(tx_id, record.client_id());
+        let worker = self.assign(record.client_id());
+        self.senders[worker]
+            .send(WorkerMsg::Restore(tx_id, record, held))
+            .ok();
+    }
+
+    /// Sends an event rejected with `e` to be reported by the worker its client is pinned to,
+    /// or by the first worker for a client without one, without applying it or pinning the
+    /// client
+    fn reject(&mut self, event: Sequenced, e: anyhow::Error) {
+        let client_id = event.tx.client_id();
+        let worker = self
+            .assignments
+            .get(&client_id)
+            .copied()
+            .unwrap_or_default();
+        self.stats.rejected += 1;
+        if self.senders[worker]
+            .send(WorkerMsg::Reject(event, e))
+            .is_err()
+        {
+            self.stats.lost += 1;
+        }
+    }
+
+    /// The worker the client is pinned to, pinning it on first sight
+    fn assign(&mut self, client_id: u16) -> usize {
+        let (senders, partitioner, stats) = (&self.senders, &mut self.partitioner, &mut self.stats);
+        *self.assignments.entry(client_id).or_insert_with(|| {
+            let running: Vec<_> = senders.iter().map(|sender| !sender.is_closed()).collect();
+            let worker = partitioner.assign(client_id, stats, &running);
+            stats.clients[worker] += 1;
+            worker
+        })
+    }
+
+    pub fn stats(&self) -> &RoutingStats {
+        &self.stats
+    }
+
+    /// Records the `records` of the input skipped so far by its source as malformed
+    pub fn count_malformed(&mut self, records: u64) {
+        self.stats.malformed = records;
+    }
+}
+
+/// Queries the accounts held by running workers without stopping them, e.g. to report
+/// balances mid-run. Cloned handles share the workers they are attached to.
+#[derive(Debug, Clone, Default)]
+pub struct AccountQueries {
+    /// Cleared once the input was read, as the workers only finish when every sender for
+    /// them was dropped
+    workers: Arc<Mutex<Option<Vec<UnboundedSender<WorkerMsg>>>>>,
+}
+
+impl AccountQueries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn attach(&self, senders: &[UnboundedSender<WorkerMsg>]) {
+        *self.workers.lock().unwrap_or_else(PoisonError::into_inner) = Some(senders.to_vec());
+    }
+
+    pub(crate) fn detach(&self) {
+        self.workers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+    }
+
+    /// The client's account with every column, reflecting at least the events routed
+    /// before the query. `None` if no worker holds the client or none is attached.
+    pub async fn account(&self, client_id: u16) -> Option<AccountSummary> {
+        let replies: Vec<_> = {
+            let workers = self.workers.lock().unwrap_or_else(PoisonError::into_inner);
+            workers
+                .iter()
+                .flatten()
+                .filter_map(|sender| {
+                    let (reply, receiver) = oneshot::channel();
+                    sender.send(WorkerMsg::Query(client_id, reply)).ok()?;
+                    Some(receiver)
+                })
+                .collect()
+        };
+        join_all(replies)
+            .await
+            .into_iter()
+            .find_map(|reply| reply.ok().flatten())
+    }
+}
+
+/// Events and clients routed to each worker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingStats {
+    pub events: Vec<u64>,
+    pub clients: Vec<usize>,
+    /// Events filtered out, which reached no worker
+    pub skipped: u64,
+    /// Events rejected or skipped by the router, which reached no ledger
+    pub rejected: u64,
+    /// Events of clients whose worker had stopped
+    pub lost: u64,
+    /// Records of a lenient input skipped as they are not transactions
+    pub malformed: u64,
+}
+
+impl fmt::Display for RoutingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (worker, (events, clients)) in self.events.iter().zip(&self.clients).enumerate() {
+            writeln!(f, "worker {worker}: {events} events, {clients} clients")?;
+        }
+        if self.skipped > 0 {
+            writeln!(f, "skipped: {} filtered events", self.skipped)?;
+        }
+        if self.rejected > 0 {
+            writeln!(
+                f,
+                "rejected: {} events before reaching a ledger",
+                self.rejected
+            )?;
+        }
+        if self.lost > 0 {
+            writeln!(f, "lost: {} events of stopped workers", self.lost)?;
+        }
+        if self.malformed > 0 {
+            writeln!(f, "malformed: {} records skipped", self.malformed)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+    use tokio::sync::mpsc;
+
+    use crate::{
+        account::ClientState,
+        data::{Sequenced, Transaction, TransactionType},
+        ledger::{event_handler, WorkerMsg, WorkerOptions, WorkerSinks},
+        partitioner::Modulo,
+        router::{AccountQueries, ClientSample, RecordFilter, Router, SliceEnd},
+        units::MinorUnits,
+        warning::WarningKind,
+    };
+
+    fn deposit(seq: u64, client: u16) -> Sequenced {
+        Sequenced {
+            seq,
+            tx: Transaction::deposit(client, u32::try_from(seq).unwrap(), 1.into()),
+        }
+    }
+
+    #[test]
+    fn clients_are_pinned_by_the_partitioner() {
+        let (senders, _receivers): (Vec<_>, Vec<_>) =
+            (0..3).map(|_| mpsc::unbounded_channel()).unzip();
+        let mut router = Router::new(senders).with_partitioner(Box::new(Modulo));
+        for (seq, client) in [1, 4, 2, 1, 6].into_iter().enumerate() {
+            router.route(deposit(seq as u64, client)).unwrap();
+        }
+        assert_eq!(router.stats().events, vec![1, 3, 1]);
+        assert_eq!(router.stats().clients, vec![1, 2, 1]);
+    }
+
+    #[test]
+    fn new_clients_avoid_the_hot_worker() {
+        let (senders, mut receivers): (Vec<_>, Vec<_>) =
+            (0..2).map(|_| mpsc::unbounded_channel()).unzip();
+        let mut router = Router::new(senders);
+
+        // Clients 1 and 3 would share a worker by client id
+        for seq in 0..5 {
+            router.route(deposit(seq, 1)).unwrap();
+        }
+        router.route(deposit(5, 3)).unwrap();
+        router.route(deposit(6, 5)).unwrap();
+        router.route(deposit(7, 1)).unwrap();
+
+        assert_eq!(router.stats().events, vec![6, 2]);
+        assert_eq!(router.stats().clients, vec![1, 2]);
+        let pinned: Vec<_> = std::iter::from_fn(|| receivers[1].try_recv().ok())
+            .filter_map(WorkerMsg::into_event)
+            .map(|event| event.tx.client_id())
+            .collect();
+        assert_eq!(pinned, vec![3, 5]);
+        assert_eq!(
+            router.stats().to_string(),
+            "worker 0: 6 events, 1 clients\nworker 1: 2 events, 2 clients\n"
+        );
+    }
+
+    #[test]
+    fn events_of_a_stopped_worker_are_lost() {
+        let (senders, mut receivers): (Vec<_>, Vec<_>) =
+            (0..2).map(|_| mpsc::unbounded_channel()).unzip();
+        let mut router = Router::new(senders);
+        router.route(deposit(0, 1)).unwrap();
+        router.route(deposit(1, 2)).unwrap();
+
+        // Worker 0, holding client 1, stops as one which panicked does
+        drop(receivers.remove(0));
+        router.route(deposit(2, 1)).unwrap();
+        router.route(deposit(3, 3)).unwrap();
+
+        assert_eq!(router.stats().lost, 1);
+        assert_eq!(router.stats().clients, vec![1, 2]);
+        let routed: Vec<_> = std::iter::from_fn(|| receivers[0].try_recv().ok())
+            .filter_map(WorkerMsg::into_event)
+            .map(|event| event.tx.client_id())
+            .collect();
+        assert_eq!(routed, vec![2, 3]);
+        assert!(router
+            .stats()
+            .to_string()
+            .ends_with("lost: 1 events of stopped workers\n"));
+    }
+
+    #[tokio::test]
+    async fn opening_balances_reach_the_worker_of_their_client() {
+        let (senders, mut receivers): (Vec<_>, Vec<_>) =
+            (0..2).map(|_| mpsc::unbounded_channel()).unzip();
+        let filter = RecordFilter {
+            exclude_clients: [5].into(),
+            ..RecordFilter::default()
+        };
+        let mut router = Router::new(senders)
+            .with_partitioner(Box::new(Modulo))
+            .with_filter(filter);
+        for client in [1, 3, 5] {
+            router.open(ClientState::restore(
+                client,
+                Decimal::TEN,
+                Decimal::ZERO,
+                false,
+            ));
+        }
+        for (tx_id, client) in [(7, 3), (8, 5)] {
+            let mut record = Transaction::deposit(client, tx_id, Decimal::TWO)
+                .dispute_record()
+                .unwrap();
+            record.mark_disputed(tx_id).unwrap();
+            router.restore(tx_id, record, Some(Decimal::ONE));
+        }
+        router
+            .route(Sequenced {
+                seq: 0,
+                tx: Transaction::withdrawal(1, 1, Decimal::from(4)),
+            })
+            .unwrap();
+        assert_eq!(router.stats().clients, vec![0, 2]);
+        drop(router);
+        drop(receivers.remove(0));
+
+        let ledger = event_handler(
+            receivers.remove(0),
+            WorkerOptions::default(),
+            WorkerSinks::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(ledger.open_disputes(3), &[7]);
+        assert_eq!(
+            ledger.partial_holds().collect::<Vec<_>>(),
+            vec![(7, Decimal::ONE)]
+        );
+        assert!(ledger.transaction(8).is_none());
+        let accounts = ledger.into_accounts();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[&1].available(), Decimal::from(6));
+        assert_eq!(accounts[&3].available(), Decimal::TEN);
+    }
+
+    #[tokio::test]
+    async fn ids_of_another_client_are_skipped_whatever_its_worker() {
+        let (senders, mut receivers): (Vec<_>, Vec<_>) =
+            (0..2).map(|_| mpsc::unbounded_channel()).unzip();
+        let mut router = Router::new(senders).with_partitioner(Box::new(Modulo));
+        router.route(deposit(1, 1)).unwrap();
+        router
+            .route(Sequenced {
+                seq: 2,
+                tx: Transaction::withdrawal(2, 1, Decimal::ONE),
+            })
+            .unwrap();
+        assert_eq!(router.stats().events, vec![0, 1]);
+        assert_eq!(router.stats().rejected, 1);
+        assert!(router
+            .stats()
+            .to_string()
+            .ends_with("rejected: 1 events before reaching a ledger\n"));
+        drop(router);
+
+        let (warning_sender, mut warning_receiver) = mpsc::unbounded_channel();
+        let sinks = WorkerSinks {
+            warnings: Some(warning_sender),
+            ..WorkerSinks::default()
+        };
+        let ledger = event_handler(receivers.remove(0), WorkerOptions::default(), sinks)
+            .await
+            .unwrap();
+        assert!(ledger.transaction(1).is_none());
+        let skipped = warning_receiver.recv().await.unwrap();
+        assert_eq!(skipped.event.seq, 2);
+        assert_eq!(skipped.warning.kind, WarningKind::DuplicateTransaction);
+    }
+
+    #[tokio::test]
+    async fn fractional_minor_units_are_dead_lettered_alone() {
+        let (senders, mut receivers): (Vec<_>, Vec<_>) =
+            (0..1).map(|_| mpsc::unbounded_channel()).unzip();
+        let mut router = Router::new(senders).with_minor_units(Some(MinorUnits::new(2)));
+        let events = [
+            Transaction::deposit(1, 1, Decimal::from(1050)),
+            Transaction::deposit(1, 2, Decimal::new(15, 1)),
+            Transaction::deposit(1, 3, Decimal::from(50)),
+        ];
+        for (seq, tx) in (1..).zip(events) {
+            router.route(Sequenced { seq, tx }).unwrap();
+        }
+        assert_eq!(router.stats().rejected, 1);
+        drop(router);
+
+        let (dl_sender, mut dl_receiver) = mpsc::unbounded_channel();
+        let sinks = WorkerSinks {
+            dead_letters: Some(dl_sender),
+            ..WorkerSinks::default()
+        };
+        let ledger = event_handler(receivers.remove(0), WorkerOptions::default(), sinks)
+            .await
+            .unwrap();
+        assert_eq!(ledger.into_accounts()[&1].available(), Decimal::from(11));
+        let dead_letter = dl_receiver.recv().await.unwrap();
+        assert_eq!(dead_letter.event.seq, 2);
+        assert_eq!(
+            dead_letter.reason,
+            "Amount `1.5` of transaction `2` is not a whole number of minor units"
+        );
+    }
+
+    #[test]
+    fn filtered_clients_are_skipped() {
+        let (senders, mut receivers): (Vec<_>, Vec<_>) =
+            (0..1).map(|_| mpsc::unbounded_channel()).unzip();
+        let filter = RecordFilter {
+            only_clients: Some([1, 2].into()),
+            exclude_clients: [2].into(),
+            ..RecordFilter::default()
+        };
+        let mut router = Router::new(senders).with_filter(filter);
+        for (seq, client) in (0..).zip([1, 2, 3, 1]) {
+            router.route(deposit(seq, client)).unwrap();
+        }
+
+        let routed: Vec<_> = std::iter::from_fn(|| receivers[0].try_recv().ok())
+            .filter_map(WorkerMsg::into_event)
+            .map(|event| event.seq)
+            .collect();
+        assert_eq!(routed, vec![0, 3]);
+        assert_eq!(
+            router.stats().to_string(),
+            "worker 0: 2 events, 1 clients\nskipped: 2 filtered events\n"
+        );
+
+        let deposits = RecordFilter {
+            only_types: Some(vec![TransactionType::Deposit]),
+            ..RecordFilter::default()
+        };
+        assert!(deposits.allows(&deposit(0, 1).tx));
+        assert!(!deposits.allows(&Transaction::dispute(1, 0)));
+    }
+
+    #[test]
+    fn routes_a_slice_of_the_input() {
+        let (senders, mut receivers): (Vec<_>, Vec<_>) =
+            (0..1).map(|_| mpsc::unbounded_channel()).unzip();
+        let mut router = Router::new(senders).with_slice(2, Some(3));
+        for seq in 0..5 {
+            router.route(deposit(seq, 1)).unwrap();
+        }
+        assert!(router.route(deposit(5, 1)).unwrap_err().is::<SliceEnd>());
+
+        let routed: Vec<_> = std::iter::from_fn(|| receivers[0].try_recv().ok())
+            .filter_map(WorkerMsg::into_event)
+            .map(|event| event.seq)
+            .collect();
+        assert_eq!(routed, vec![2, 3, 4]);
+        assert_eq!(router.stats().skipped, 2);
+    }
+
+    #[test]
+    fn samples_are_deterministic_per_seed() {
+        let sample = |seed| ClientSample {
+            per_million: 10_000,
+            seed,
+        };
+        let kept = |seed| -> Vec<u16> {
+            (0..=u16::MAX)
+                .filter(|&client| sample(seed).keeps(client))
+                .collect()
+        };
+        let kept_42 = kept(42);
+        // About 1% of the clients
+        assert!((500..800).contains(&kept_42.len()));
+        assert_eq!(kept_42, kept(42));
+        assert_ne!(kept_42, kept(43));
+
+        let everyone = ClientSample {
+            per_million: 1_000_000,
+            seed: 0,
+        };
+        assert!((0..=u16::MAX).all(|client| everyone.keeps(client)));
+    }
+
+    #[tokio::test]
+    async fn running_workers_answer_account_queries() {
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            (0..2).map(|_| mpsc::unbounded_channel()).unzip();
+        let queries = AccountQueries::new();
+        queries.attach(&senders);
+        let workers: Vec<_> = receivers
+            .into_iter()
+            .map(|receiver| {
+                tokio::spawn(event_handler(
+                    receiver,
+                    WorkerOptions::default(),
+                    WorkerSinks::default(),
+                ))
+            })
+            .collect();
+
+        let mut router = Router::new(senders);
+        router.route(deposit(0, 1)).unwrap();
+        router.route(deposit(1, 2)).unwrap();
+        router.route(deposit(2, 1)).unwrap();
+        let account = queries.account(1).await.unwrap();
+        assert_eq!((account.client, account.available), (1, 2.into()));
+        assert_eq!(account.deposited, Some(2.into()));
+        assert!(queries.account(3).await.is_none());
+
+        // Workers only finish once the queries no longer reach them
+        drop(router);
+        queries.detach();
+        for worker in workers {
+            worker.await.unwrap().unwrap();
+        }
+        assert!(queries.account(1).await.is_none());
+    }
+}
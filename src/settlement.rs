@@ -0,0 +1,109 @@
+//! The settlement account, the counterparty of every movement of money into or out of the
+//! client accounts, reported with `--worker-stats` for treasury to reconcile a run against
+//! the bank statements
+//!
+//! Disputes and resolves only move funds between available and held, so they leave it
+//! untouched. A reversal counts against the deposits or withdrawals it undoes.
+
+use std::{fmt, ops::AddAssign};
+
+use rust_decimal::Decimal;
+
+use crate::data::TransactionType;
+
+/// Money which came in and went out through the settlement account
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Settlement {
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    pub chargebacks: Decimal,
+    /// Adjustments and custom transactions, negative when they took money out
+    pub adjustments: Decimal,
+}
+
+impl Settlement {
+    /// Records a transaction of `tx_type` which changed its client's total by `change`
+    pub fn record(&mut self, tx_type: TransactionType, change: Decimal) {
+        match tx_type {
+            TransactionType::Deposit => self.deposits += change,
+            TransactionType::Withdrawal => self.withdrawals -= change,
+            TransactionType::Chargeback => self.chargebacks -= change,
+            TransactionType::Adjustment(_) | TransactionType::Custom(_) => {
+                self.adjustments += change;
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Reversal => {}
+        }
+    }
+
+    /// Net money in, which the client accounts hold between them
+    pub fn balance(&self) -> Decimal {
+        self.deposits - self.withdrawals - self.chargebacks + self.adjustments
+    }
+}
+
+impl AddAssign for Settlement {
+    fn add_assign(&mut self, other: Self) {
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.chargebacks += other.chargebacks;
+        self.adjustments += other.adjustments;
+    }
+}
+
+impl fmt::Display for Settlement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "deposits {}, withdrawals {}, chargebacks {}, adjustments {}, net in {}",
+            self.deposits,
+            self.withdrawals,
+            self.chargebacks,
+            self.adjustments,
+            self.balance()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::{ReasonCode, Transaction},
+        ledger::Ledger,
+        settlement::Settlement,
+    };
+
+    #[test]
+    fn nets_the_money_in_and_out() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::deposit(1, 1, Decimal::TEN),
+            Transaction::withdrawal(1, 2, Decimal::ONE),
+            Transaction::adjustment(1, 3, -Decimal::TWO, ReasonCode::Goodwill),
+            Transaction::deposit(2, 4, Decimal::TEN),
+            Transaction::dispute(2, 4),
+            Transaction::chargeback(2, 4),
+            Transaction::deposit(3, 5, Decimal::ONE),
+            Transaction::dispute(3, 5),
+        ] {
+            ledger.apply(tx).unwrap();
+        }
+        let settlement = ledger.settlement();
+        assert_eq!(
+            settlement,
+            Settlement {
+                deposits: Decimal::from(21),
+                withdrawals: Decimal::ONE,
+                chargebacks: Decimal::TEN,
+                adjustments: -Decimal::TWO,
+            }
+        );
+        let held: Decimal = ledger.accounts().map(|account| account.total()).sum();
+        assert_eq!(settlement.balance(), held);
+        assert_eq!(
+            settlement.to_string(),
+            "deposits 21, withdrawals 1, chargebacks 10, adjustments -2, net in 8"
+        );
+    }
+}
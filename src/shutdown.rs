@@ -0,0 +1,116 @@
+use std::{fmt, sync::Arc};
+
+use tokio::sync::watch;
+
+/// Asks every input still being read to stop early. Events already read are applied and the
+/// accounts written as usual, so the output only covers part of each interrupted input.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            sender: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Completes once `trigger` was called, immediately if it already was
+    pub async fn triggered(&self) {
+        let mut receiver = self.sender.subscribe();
+        while !*receiver.borrow_and_update() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stops the reader and every worker of an input once one of them failed, rather than the
+/// others carrying on with an input which fails as a whole or waiting on channels nobody
+/// serves any more
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    token: Shutdown,
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.token.trigger();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_triggered()
+    }
+
+    /// Completes once `cancel` was called, immediately if it already was
+    pub async fn cancelled(&self) {
+        self.token.triggered().await;
+    }
+}
+
+/// A reader or worker stopped as another component of its input failed
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Stopped as another reader or worker of the input failed")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::shutdown::{Cancellation, Shutdown};
+
+    #[tokio::test]
+    async fn trigger_wakes_every_waiter() {
+        let shutdown = Shutdown::new();
+        let waiter = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.triggered().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!shutdown.is_triggered());
+
+        shutdown.trigger();
+        waiter.await.unwrap();
+        assert!(shutdown.is_triggered());
+        shutdown.triggered().await;
+    }
+
+    #[tokio::test]
+    async fn clones_share_the_cancellation() {
+        let cancellation = Cancellation::new();
+        let clone = cancellation.clone();
+        assert!(!clone.is_cancelled());
+
+        cancellation.cancel();
+        assert!(clone.is_cancelled());
+        clone.cancelled().await;
+        assert!(!Cancellation::new().is_cancelled());
+    }
+}
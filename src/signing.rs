@@ -0,0 +1,237 @@
+//! Ed25519 signatures of the accounts written, so whoever receives them can check they were
+//! not altered on the way
+//!
+//! `--sign-key` names a PKCS#8 PEM private key, as `openssl genpkey -algorithm ed25519`
+//! writes it. Once a run completed, each file of accounts is signed whole and its 64-byte
+//! signature written next to it with a `.sig` extension, or to `--signature-out` for the
+//! accounts written to stdout, a copy of which `Recorder` keeps. The signature is of the
+//! bytes as written, so `verify` and `openssl pkeyutl -verify -rawin` both check it against
+//! the public key.
+
+use std::{
+    fmt, io,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+};
+
+use anyhow::{bail, Context as _, Result};
+use ed25519_dalek::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    Signature, Signer, SigningKey, Verifier, VerifyingKey, SIGNATURE_LENGTH,
+};
+use tokio::io::AsyncWrite;
+
+use crate::{cli::VerifyOptions, io_ops::write_file_retrying, retry::RetryPolicy};
+
+/// Signs the accounts written with the private key of `--sign-key`
+pub struct OutputSigner {
+    key: SigningKey,
+}
+
+impl OutputSigner {
+    /// Reads the private key from the PEM file at `file_path`
+    ///
+    /// # Errors
+    /// If the file cannot be read or does not hold an Ed25519 private key
+    pub fn load(file_path: &str) -> Result<Self> {
+        let pem = std::fs::read_to_string(file_path)
+            .with_context(|| format!("Cannot read the signing key {file_path}"))?;
+        let key = SigningKey::from_pkcs8_pem(&pem)
+            .map_err(|e| anyhow::anyhow!("{e}"))
+            .with_context(|| format!("{file_path} is not an Ed25519 private key"))?;
+        Ok(Self { key })
+    }
+
+    pub fn sign(&self, bytes: &[u8]) -> [u8; SIGNATURE_LENGTH] {
+        self.key.sign(bytes).to_bytes()
+    }
+
+    /// Signs the file at `file_path` to `signature_path(file_path)`
+    ///
+    /// # Errors
+    /// If the file cannot be read or its signature written
+    pub async fn sign_file(&self, file_path: &str, policy: RetryPolicy) -> Result<()> {
+        let bytes = tokio::fs::read(file_path)
+            .await
+            .with_context(|| format!("Cannot read {file_path} to sign it"))?;
+        let signature = self.sign(&bytes);
+        write_file_retrying(&signature_path(file_path), &signature, policy).await?;
+        Ok(())
+    }
+
+    /// Signs the bytes of `recording` to `signature_path`
+    ///
+    /// # Errors
+    /// If the signature cannot be written
+    pub async fn sign_recording(
+        &self,
+        recording: &Recording,
+        signature_path: &str,
+        policy: RetryPolicy,
+    ) -> Result<()> {
+        let signature = self.sign(&recording.lock().unwrap_or_else(PoisonError::into_inner));
+        write_file_retrying(signature_path, &signature, policy).await?;
+        Ok(())
+    }
+}
+
+/// Never prints the key
+impl fmt::Debug for OutputSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutputSigner").finish_non_exhaustive()
+    }
+}
+
+/// Where the signature of the file at `file_path` is written
+pub fn signature_path(file_path: &str) -> String {
+    format!("{file_path}.sig")
+}
+
+/// Checks `signature` is of `bytes` under the PEM public key `public_key`
+///
+/// # Errors
+/// If the key or the signature is malformed, or the signature is not of `bytes`
+pub fn verify(bytes: &[u8], signature: &[u8], public_key: &str) -> Result<()> {
+    let key = VerifyingKey::from_public_key_pem(public_key)
+        .map_err(|e| anyhow::anyhow!("{e}"))
+        .context("The public key is not an Ed25519 public key")?;
+    let Ok(signature) = Signature::from_slice(signature) else {
+        bail!(
+            "A signature is {SIGNATURE_LENGTH} bytes, found {}",
+            signature.len()
+        );
+    };
+    key.verify(bytes, &signature)
+        .map_err(|_| anyhow::anyhow!("The signature does not match the file"))
+}
+
+/// Checks the accounts at `file_path` against their signature and writes whether they
+/// match to stdout
+///
+/// # Errors
+/// If a file cannot be read or the signature does not match
+pub async fn run_verify(file_path: &str, options: &VerifyOptions) -> Result<()> {
+    let read = |path: &str| {
+        let path = path.to_owned();
+        async move {
+            tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("Cannot read {path}"))
+        }
+    };
+    let bytes = read(file_path).await?;
+    let signature = read(&options.signature).await?;
+    let public_key = read(&options.public_key).await?;
+    let public_key = String::from_utf8(public_key)
+        .with_context(|| format!("{} is not a PEM file", options.public_key))?;
+    verify(&bytes, &signature, &public_key).with_context(|| format!("Verifying {file_path}"))?;
+    println!("{file_path}: signature verified");
+    Ok(())
+}
+
+/// The bytes written through a `Recorder`
+pub type Recording = Arc<Mutex<Vec<u8>>>;
+
+/// Passes writes through to `W`, keeping a copy of what it accepted when recording
+#[derive(Debug)]
+pub struct Recorder<W> {
+    inner: W,
+    recording: Option<Recording>,
+}
+
+impl<W> Recorder<W> {
+    /// A writer to `inner`, and the copy of what is written to it if `record`
+    pub fn new(inner: W, record: bool) -> (Self, Option<Recording>) {
+        let recording = record.then(Recording::default);
+        let recorder = Self {
+            inner,
+            recording: recording.clone(),
+        };
+        (recorder, recording)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Recorder<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let written = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let (Poll::Ready(Ok(n)), Some(recording)) = (&written, &this.recording) {
+            let mut recording = recording.lock().unwrap_or_else(PoisonError::into_inner);
+            recording.extend_from_slice(&buf[..*n]);
+        }
+        written
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use tokio::io::AsyncWriteExt;
+
+    use crate::signing::{verify, OutputSigner, Recorder};
+
+    /// The key pair of `openssl genpkey -algorithm ed25519`, private and public
+    fn fixtures() -> (String, String) {
+        let fixture = |name: &str| {
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name)
+                .to_string_lossy()
+                .into_owned()
+        };
+        (fixture("signing_key.pem"), fixture("signing_key.pub.pem"))
+    }
+
+    #[test]
+    fn signatures_only_verify_the_bytes_signed() {
+        let (private_key, public_key) = fixtures();
+        let signer = OutputSigner::load(&private_key).unwrap();
+        let public_key = std::fs::read_to_string(public_key).unwrap();
+        let accounts = b"client,available,held,total,locked\n1,1.5,0,1.5,false\n";
+        let signature = signer.sign(accounts);
+        verify(accounts, &signature, &public_key).unwrap();
+
+        let tampered = b"client,available,held,total,locked\n1,9.5,0,9.5,false\n";
+        assert!(verify(tampered, &signature, &public_key).is_err());
+        assert!(verify(accounts, &signature[1..], &public_key).is_err());
+        // The private key is not a public key
+        let pem = std::fs::read_to_string(private_key).unwrap();
+        assert!(verify(accounts, &signature, &pem).is_err());
+    }
+
+    #[test]
+    fn public_keys_are_not_signing_keys() {
+        let (_, public_key) = fixtures();
+        let e = OutputSigner::load(&public_key).unwrap_err();
+        assert!(e.to_string().ends_with("is not an Ed25519 private key"));
+        assert!(OutputSigner::load("missing.pem").is_err());
+    }
+
+    #[tokio::test]
+    async fn recorders_keep_what_was_written() {
+        let (mut recorder, recording) = Recorder::new(Vec::new(), true);
+        recorder.write_all(b"client,available").await.unwrap();
+        recorder.write_all(b",held\n").await.unwrap();
+        assert_eq!(
+            recording.unwrap().lock().unwrap().as_slice(),
+            recorder.inner
+        );
+
+        let (_, recording) = Recorder::new(Vec::<u8>::new(), false);
+        assert!(recording.is_none());
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use csv_async::AsyncWriter;
+use futures::stream::StreamExt;
+use tokio::io::AsyncWrite;
+
+use crate::{
+    account::ClientState,
+    cli::Args,
+    data::Transaction,
+    io_ops::{async_read_csv, display_results, finish_csv, read_transaction},
+    ledger::Ledger,
+    pseudonym::{ClientLabel, Pseudonymizer},
+    retry::rejection_reason,
+    snapshot::{self, Restored, SnapshotKey},
+};
+
+/// A hypothetical transaction the ledger refused to apply
+#[derive(Debug)]
+pub struct Rejection {
+    pub tx: Transaction,
+    pub reason: String,
+}
+
+/// Restores the accounts written by a previous run, either as CSV or as a snapshot of any
+/// version, see `snapshot`. Only a snapshot since version 2 holds the deposits and
+/// withdrawals of the previous run, otherwise hypothetical disputes can only reference
+/// transactions of the simulated input. A sealed snapshot is opened with `key`.
+///
+/// # Errors
+/// If the snapshot cannot be read or opened with `key`, or a row is not an account
+pub async fn load_snapshot(file_path: &str, key: Option<&SnapshotKey>) -> Result<Restored> {
+    snapshot::load(file_path, key).await
+}
+
+/// Applies `transactions` in order to the accounts of `ledger`, collecting every rejection
+pub fn simulate(
+    mut ledger: Ledger,
+    transactions: Vec<Transaction>,
+) -> (HashMap<u16, ClientState>, Vec<Rejection>) {
+    let mut rejections = Vec::new();
+    for tx in transactions {
+        if let Err((tx, e)) = ledger.process_transaction(tx) {
+            rejections.push(Rejection {
+                tx,
+                reason: e.to_string(),
+            });
+        }
+    }
+    (ledger.into_accounts(), rejections)
+}
+
+/// Writes the rejected transactions, with the client's pseudonym and the error's
+/// `rejection_reason` if `pseudonyms` are enabled
+///
+/// # Errors
+/// Can fail to write to `writer`
+pub async fn write_rejections<W: AsyncWrite + Unpin>(
+    rejections: &[Rejection],
+    pseudonyms: Option<&Pseudonymizer>,
+    writer: W,
+) -> Result<()> {
+    let mut writer = AsyncWriter::from_writer(writer);
+    writer
+        .write_record(&["type", "client", "tx", "amount", "error"])
+        .await?;
+    for Rejection { tx, reason } in rejections {
+        writer
+            .write_record(&[
+                tx.tx_type().to_string(),
+                ClientLabel::new(tx.client_id(), pseudonyms).to_string(),
+                tx.tx_id().to_string(),
+                tx.amount().map(|a| a.to_string()).unwrap_or_default(),
+                match pseudonyms {
+                    Some(_) => rejection_reason(reason),
+                    None => reason.clone(),
+                },
+            ])
+            .await?;
+    }
+    finish_csv(writer).await?;
+
+    Ok(())
+}
+
+/// Simulates the input of `args` against `snapshot_path`, writing the resulting accounts to
+/// `stdout` and the rejected transactions to `stderr`. The snapshot file is never written.
+///
+/// # Errors
+/// If either file cannot be read or the output cannot be written
+pub async fn run_simulation(snapshot_path: &str, file_path: &str, args: &Args) -> Result<()> {
+    let Restored {
+        mut accounts,
+        transactions: history,
+        partial_holds,
+    } = load_snapshot(snapshot_path, args.snapshot_key.as_ref()).await?;
+    for (client, limit) in &args.overdrafts {
+        if let Some(state) = accounts.remove(client) {
+            accounts.insert(*client, state.with_overdraft_limit(*limit));
+        }
+    }
+
+    let mut reader = async_read_csv(file_path, args.csv, args.io_retry).await?;
+    let mut records = reader.records();
+    let mut transactions = Vec::new();
+    while let Some(record) = records.next().await {
+        transactions.push(read_transaction(&record?)?);
+    }
+
+    let ledger = Ledger::new()
+        .with_locked_policy(args.locked_policy)
+        .with_dispute_policy(args.dispute_policy)
+        .with_chargeback_limit(args.chargeback_limit)
+        .with_max_amount(args.max_amount)
+        .with_accounts(accounts)
+        .with_transactions(history)
+        .with_partial_holds(partial_holds);
+    let (accounts, rejections) = simulate(ledger, transactions);
+    let pseudonyms = args.pseudonyms.as_ref();
+    write_rejections(&rejections, pseudonyms, tokio::io::stderr()).await?;
+    display_results(accounts, args.columns, args.sorted, pseudonyms).await
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use crate::{
+        account::LockedAccountPolicy,
+        data::Transaction,
+        ledger::Ledger,
+        simulate::{load_snapshot, simulate},
+        snapshot,
+    };
+
+    #[tokio::test]
+    async fn hypothetical_transactions_apply_to_the_snapshot() {
+        let path = std::env::temp_dir().join("effective-train-snapshot.csv");
+        let snapshot = "client,available,held,total,locked\n1,10,5,15,false\n2,3,0,3,true\n";
+        std::fs::write(&path, snapshot).unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let ledger =
+            Ledger::new().with_accounts(load_snapshot(&path, None).await.unwrap().accounts);
+        let transactions = vec![
+            Transaction::withdrawal(1, 10, Decimal::from(4)),
+            Transaction::withdrawal(1, 11, Decimal::TEN),
+            Transaction::deposit(2, 12, Decimal::ONE),
+            Transaction::deposit(3, 13, Decimal::TWO),
+        ];
+        let (accounts, rejections) = simulate(ledger, transactions.clone());
+
+        assert_eq!(accounts.get(&1).unwrap().available().to_string(), "6");
+        assert_eq!(accounts.get(&1).unwrap().held().to_string(), "5");
+        assert_eq!(accounts.get(&3).unwrap().available().to_string(), "2");
+        let rejected: Vec<_> = rejections.iter().map(|r| r.tx.tx_id()).collect();
+        assert_eq!(rejected, vec![11, 12]);
+        assert_eq!(rejections[1].reason, "Account '2' is locked");
+
+        let ledger = Ledger::new()
+            .with_locked_policy(LockedAccountPolicy::AllowDeposits)
+            .with_accounts(load_snapshot(&path, None).await.unwrap().accounts);
+        let (accounts, rejections) = simulate(ledger, transactions);
+        assert_eq!(accounts.get(&2).unwrap().available().to_string(), "4");
+        assert!(accounts.get(&2).unwrap().is_locked());
+        assert_eq!(rejections.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn message_pack_snapshots_are_restored() {
+        let path = std::env::temp_dir().join("effective-train-snapshot.msgpack");
+        let csv = std::env::temp_dir().join("effective-train-snapshot-source.csv");
+        std::fs::write(
+            &csv,
+            "client,available,held,total,locked\n1,10,5,15,false\n",
+        )
+        .unwrap();
+        let accounts = load_snapshot(&csv.to_string_lossy(), None)
+            .await
+            .unwrap()
+            .accounts;
+        std::fs::write(&path, snapshot::encode(&accounts, [], []).unwrap()).unwrap();
+
+        let restored = load_snapshot(&path.to_string_lossy(), None)
+            .await
+            .unwrap()
+            .accounts;
+        assert_eq!(restored.get(&1).unwrap().available(), Decimal::TEN);
+        assert_eq!(restored.get(&1).unwrap().held().to_string(), "5");
+    }
+
+    #[tokio::test]
+    async fn disputes_reference_transactions_of_the_snapshot() {
+        let path = std::env::temp_dir().join("effective-train-snapshot-history.msgpack");
+        let mut previous = Ledger::new();
+        previous
+            .apply(Transaction::deposit(1, 1, Decimal::TEN))
+            .unwrap();
+        previous
+            .apply(Transaction::deposit(1, 2, Decimal::ONE))
+            .unwrap();
+        previous.apply(Transaction::dispute(1, 2)).unwrap();
+        let transactions: Vec<_> = previous.transactions().collect();
+        let bytes = snapshot::encode(&previous.into_accounts(), transactions, []).unwrap();
+        std::fs::write(&path, bytes).unwrap();
+
+        let restored = load_snapshot(&path.to_string_lossy(), None).await.unwrap();
+        let ledger = Ledger::new()
+            .with_accounts(restored.accounts)
+            .with_transactions(restored.transactions);
+        assert_eq!(ledger.open_disputes(1), &[2]);
+        let (accounts, rejections) = simulate(
+            ledger,
+            vec![
+                Transaction::dispute(1, 1),
+                Transaction::resolve(1, 2),
+                Transaction::deposit(1, 1, Decimal::ONE),
+            ],
+        );
+        assert_eq!(accounts[&1].held(), Decimal::TEN);
+        assert_eq!(accounts[&1].available(), Decimal::ONE);
+        // A deposit of an earlier batch is not applied again
+        let rejected: Vec<_> = rejections.iter().map(|r| r.tx.tx_id()).collect();
+        assert_eq!(rejected, vec![1]);
+    }
+}
@@ -0,0 +1,639 @@
+//! Snapshots of the accounts and the deposits and withdrawals applied to them, restored by
+//! `simulate` and `--opening-balances`.
+//!
+//! Every version of the format this engine ever wrote stays readable: a snapshot is decoded
+//! with the types of the version it was written with, then migrated one version at a time
+//! up to `SNAPSHOT_VERSION`. Version 0 is the CSV of the accounts written by a run, which
+//! carries no version of its own. Later versions are compact MessagePack snapshots written
+//! with `--snapshot-out`, starting with `MAGIC` and the big-endian `u16` version of the
+//! body which follows.
+//!
+//! A new version adds its body type, a `Versioned` variant decoded by `decode_body` and a
+//! migration from the previous version. Existing types and migrations are never changed.
+//!
+//! Snapshots hold every balance, so they can be encrypted at rest with a `SnapshotKey`:
+//! a sealed snapshot is `SEALED_MAGIC`, a random 96-bit nonce and the AES-256-GCM
+//! ciphertext of the snapshot, whose tag also authenticates the magic.
+
+use std::{collections::HashMap, fmt};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use csv_async::AsyncReaderBuilder;
+use futures::stream::StreamExt;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::ClientState,
+    data::{DisputeRecord, RecordState, TransactionType},
+};
+
+const MAGIC: &[u8; 4] = b"ETSN";
+const SEALED_MAGIC: &[u8; 4] = b"ETSE";
+const NONCE_LEN: usize = 12;
+/// Environment variable holding the `SnapshotKey` of the binary, as 64 hex digits
+pub const SNAPSHOT_KEY_VAR: &str = "EFFECTIVE_TRAIN_SNAPSHOT_KEY";
+/// Version of the snapshots written by `encode`
+pub const SNAPSHOT_VERSION: u16 = 4;
+
+/// A snapshot of a version this engine cannot read, e.g. one written by a newer engine
+#[derive(Debug)]
+pub struct UnsupportedSnapshot(pub u16);
+
+impl fmt::Display for UnsupportedSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Snapshot version {} is not supported, expected at most {SNAPSHOT_VERSION}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedSnapshot {}
+
+/// AES-256-GCM key snapshots are sealed with and opened by
+#[derive(Clone, PartialEq, Eq)]
+pub struct SnapshotKey {
+    key: Key<Aes256Gcm>,
+}
+
+impl SnapshotKey {
+    /// # Errors
+    /// If `key` is not 32 bytes
+    pub fn new(key: &[u8]) -> Result<Self> {
+        if key.len() != 32 {
+            bail!("A snapshot key needs 32 bytes, got {}", key.len());
+        }
+        Ok(Self {
+            key: *Key::<Aes256Gcm>::from_slice(key),
+        })
+    }
+
+    /// Reads a key of 64 hex digits
+    ///
+    /// # Errors
+    /// If `hex` holds anything else
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        if !hex.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+            bail!("A snapshot key is written as hex digits");
+        }
+        let key = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()
+            .context("A snapshot key is written as pairs of hex digits")?;
+        Self::new(&key)
+    }
+
+    /// The key of `SNAPSHOT_KEY_VAR`, if it is set
+    ///
+    /// # Errors
+    /// If the variable is set to anything but 64 hex digits
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var(SNAPSHOT_KEY_VAR) {
+            Ok(hex) => Self::from_hex(hex.trim())
+                .with_context(|| format!("Invalid {SNAPSHOT_KEY_VAR}"))
+                .map(Some),
+            Err(std::env::VarError::NotPresent) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Invalid {SNAPSHOT_KEY_VAR}")),
+        }
+    }
+
+    /// Encrypts the `snapshot` written by `encode`
+    ///
+    /// # Errors
+    /// If the snapshot is too long to be encrypted under a single nonce
+    pub fn seal(&self, snapshot: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: snapshot,
+            aad: SEALED_MAGIC,
+        };
+        let ciphertext = Aes256Gcm::new(&self.key)
+            .encrypt(&nonce, payload)
+            .map_err(|_| anyhow!("The snapshot is too long to be encrypted"))?;
+        let mut bytes = SEALED_MAGIC.to_vec();
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&ciphertext);
+        Ok(bytes)
+    }
+
+    /// Decrypts a snapshot sealed with this key
+    ///
+    /// # Errors
+    /// If `bytes` are not sealed, or were sealed with another key or changed since
+    pub fn open(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let body = bytes
+            .strip_prefix(SEALED_MAGIC)
+            .context("Missing encrypted snapshot header")?;
+        if body.len() < NONCE_LEN {
+            bail!("Missing encrypted snapshot nonce");
+        }
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: SEALED_MAGIC,
+        };
+        Aes256Gcm::new(&self.key)
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| {
+                anyhow!("Cannot decrypt the snapshot, it was encrypted with another key or changed")
+            })
+    }
+}
+
+/// Never prints the key
+impl fmt::Debug for SnapshotKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SnapshotKey").finish_non_exhaustive()
+    }
+}
+
+/// Row of version 0, an account as written by a run, optional columns are ignored
+#[derive(Deserialize)]
+struct AccountV0 {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+}
+
+/// Body of version 1, the balances of each account in client id order
+#[derive(Serialize, Deserialize)]
+struct SnapshotV1 {
+    accounts: Vec<AccountV1>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AccountV1 {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+}
+
+impl From<Vec<AccountV0>> for SnapshotV1 {
+    fn from(rows: Vec<AccountV0>) -> Self {
+        let accounts = rows
+            .into_iter()
+            .map(|row| AccountV1 {
+                client: row.client,
+                available: row.available,
+                held: row.held,
+                locked: row.locked,
+            })
+            .collect();
+        Self { accounts }
+    }
+}
+
+/// Body of version 2, version 1 with the deposits and withdrawals applied in transaction id
+/// order, so disputes of a later batch can reference them
+#[derive(Serialize, Deserialize)]
+struct SnapshotV2 {
+    accounts: Vec<AccountV1>,
+    transactions: Vec<TransactionV2>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TransactionV2 {
+    tx: u32,
+    client: u16,
+    /// A withdrawal rather than a deposit
+    withdrawal: bool,
+    amount: Decimal,
+    disputed: bool,
+    reversed: bool,
+}
+
+impl From<SnapshotV1> for SnapshotV2 {
+    fn from(snapshot: SnapshotV1) -> Self {
+        Self {
+            accounts: snapshot.accounts,
+            transactions: Vec::new(),
+        }
+    }
+}
+
+/// Body of version 3, version 2 with the amount held by each open dispute holding less than
+/// its transaction's, in transaction id order
+#[derive(Serialize, Deserialize)]
+struct SnapshotV3 {
+    accounts: Vec<AccountV1>,
+    transactions: Vec<TransactionV2>,
+    partial_holds: Vec<HoldV3>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HoldV3 {
+    tx: u32,
+    held: Decimal,
+}
+
+impl From<SnapshotV2> for SnapshotV3 {
+    fn from(snapshot: SnapshotV2) -> Self {
+        Self {
+            accounts: snapshot.accounts,
+            transactions: snapshot.transactions,
+            partial_holds: Vec::new(),
+        }
+    }
+}
+
+/// Body of version 4, version 3 with the transactions charged back told apart from those
+/// still under dispute. Earlier versions kept a charged-back transaction as disputed, so
+/// their transactions migrate with none charged back.
+#[derive(Serialize, Deserialize)]
+struct SnapshotV4 {
+    accounts: Vec<AccountV1>,
+    transactions: Vec<TransactionV4>,
+    partial_holds: Vec<HoldV3>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TransactionV4 {
+    tx: u32,
+    client: u16,
+    /// A withdrawal rather than a deposit
+    withdrawal: bool,
+    amount: Decimal,
+    disputed: bool,
+    reversed: bool,
+    charged_back: bool,
+}
+
+impl From<SnapshotV3> for SnapshotV4 {
+    fn from(snapshot: SnapshotV3) -> Self {
+        let transactions = snapshot
+            .transactions
+            .into_iter()
+            .map(|transaction| TransactionV4 {
+                tx: transaction.tx,
+                client: transaction.client,
+                withdrawal: transaction.withdrawal,
+                amount: transaction.amount,
+                disputed: transaction.disputed,
+                reversed: transaction.reversed,
+                charged_back: false,
+            })
+            .collect();
+        Self {
+            accounts: snapshot.accounts,
+            transactions,
+            partial_holds: snapshot.partial_holds,
+        }
+    }
+}
+
+/// The current version
+type Snapshot = SnapshotV4;
+
+/// A snapshot decoded with the types of the version it was written with
+enum Versioned {
+    V0(Vec<AccountV0>),
+    V1(SnapshotV1),
+    V2(SnapshotV2),
+    V3(SnapshotV3),
+    V4(SnapshotV4),
+}
+
+impl Versioned {
+    /// Migrates the snapshot forward one version at a time up to the current one
+    fn migrate(self) -> Snapshot {
+        match self {
+            Self::V0(rows) => Self::V1(rows.into()).migrate(),
+            Self::V1(snapshot) => Self::V2(snapshot.into()).migrate(),
+            Self::V2(snapshot) => Self::V3(snapshot.into()).migrate(),
+            Self::V3(snapshot) => Self::V4(snapshot.into()).migrate(),
+            Self::V4(snapshot) => snapshot,
+        }
+    }
+}
+
+/// What a snapshot restores, the transactions are empty for versions before 2
+#[derive(Debug, Default)]
+pub struct Restored {
+    pub accounts: HashMap<u16, ClientState>,
+    /// The deposits and withdrawals applied to the accounts, by transaction id
+    pub transactions: HashMap<u32, DisputeRecord>,
+    /// The amount held by each dispute holding less than its transaction's, empty for
+    /// versions before 3
+    pub partial_holds: HashMap<u32, Decimal>,
+}
+
+/// Whether `bytes` start like a MessagePack snapshot rather than a CSV of the accounts
+pub fn is_snapshot(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Whether `bytes` start like a snapshot sealed with a `SnapshotKey`
+pub fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.starts_with(SEALED_MAGIC)
+}
+
+/// Encodes the balances of `accounts`, the deposits and withdrawals applied to them and the
+/// amounts held by partially holding disputes as a snapshot of the current version
+///
+/// # Errors
+/// If an account cannot be serialised
+#[allow(clippy::implicit_hasher)]
+pub fn encode(
+    accounts: &HashMap<u16, ClientState>,
+    transactions: impl IntoIterator<Item = (u32, DisputeRecord)>,
+    partial_holds: impl IntoIterator<Item = (u32, Decimal)>,
+) -> Result<Vec<u8>> {
+    let mut body = Snapshot {
+        accounts: accounts
+            .values()
+            .map(|state| AccountV1 {
+                client: state.id(),
+                available: state.available(),
+                held: state.held(),
+                locked: state.is_locked(),
+            })
+            .collect(),
+        transactions: transactions
+            .into_iter()
+            .map(|(tx, record)| TransactionV4 {
+                tx,
+                client: record.client_id(),
+                withdrawal: record.tx_type() == TransactionType::Withdrawal,
+                amount: record.amount(),
+                disputed: record.in_dispute(),
+                reversed: record.is_reversed(),
+                charged_back: record.is_charged_back(),
+            })
+            .collect(),
+        partial_holds: partial_holds
+            .into_iter()
+            .map(|(tx, held)| HoldV3 { tx, held })
+            .collect(),
+    };
+    body.accounts.sort_unstable_by_key(|account| account.client);
+    body.transactions
+        .sort_unstable_by_key(|transaction| transaction.tx);
+    body.partial_holds.sort_unstable_by_key(|hold| hold.tx);
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&SNAPSHOT_VERSION.to_be_bytes());
+    rmp_serde::encode::write_named(&mut bytes, &body)?;
+    Ok(bytes)
+}
+
+/// Restores the accounts and transactions of a MessagePack snapshot of any version up to the
+/// current one
+///
+/// # Errors
+/// If `bytes` are not a snapshot, of a newer version, or their body is malformed
+pub fn decode(bytes: &[u8]) -> Result<Restored> {
+    let body = bytes
+        .strip_prefix(MAGIC)
+        .context("Missing snapshot header")?;
+    let (version, body) = match body {
+        [high, low, body @ ..] => (u16::from_be_bytes([*high, *low]), body),
+        _ => bail!("Missing snapshot version"),
+    };
+    Ok(restore(decode_body(version, body)?.migrate()))
+}
+
+/// Restores the snapshot or CSV of the accounts at `file_path`, opening a sealed snapshot
+/// with `key`
+///
+/// # Errors
+/// If the file cannot be read, is sealed with another key or none is given, is of a newer
+/// version or is malformed
+pub async fn load(file_path: &str, key: Option<&SnapshotKey>) -> Result<Restored> {
+    parse(&tokio::fs::read(file_path).await?, key).await
+}
+
+/// Restores a snapshot or CSV of the accounts read already, opening a sealed snapshot with
+/// `key`. Needs no runtime, so it can be polled with `futures::executor::block_on`.
+///
+/// # Errors
+/// If `bytes` are sealed with another key or none is given, of a newer version or malformed
+pub async fn parse(bytes: &[u8], key: Option<&SnapshotKey>) -> Result<Restored> {
+    if is_sealed(bytes) {
+        let key = key.with_context(|| {
+            format!("The snapshot is encrypted, set {SNAPSHOT_KEY_VAR} to its key")
+        })?;
+        return decode(&key.open(bytes)?);
+    }
+    if is_snapshot(bytes) {
+        return decode(bytes);
+    }
+    let mut reader = AsyncReaderBuilder::new().create_deserializer(bytes);
+    let mut rows = reader.deserialize::<AccountV0>();
+    let mut accounts = Vec::new();
+    while let Some(row) = rows.next().await {
+        accounts.push(row?);
+    }
+    Ok(restore(Versioned::V0(accounts).migrate()))
+}
+
+fn decode_body(version: u16, body: &[u8]) -> Result<Versioned> {
+    let malformed = || format!("Malformed snapshot of version {version}");
+    match version {
+        1 => Ok(Versioned::V1(
+            rmp_serde::from_slice(body).with_context(malformed)?,
+        )),
+        2 => Ok(Versioned::V2(
+            rmp_serde::from_slice(body).with_context(malformed)?,
+        )),
+        3 => Ok(Versioned::V3(
+            rmp_serde::from_slice(body).with_context(malformed)?,
+        )),
+        4 => Ok(Versioned::V4(
+            rmp_serde::from_slice(body).with_context(malformed)?,
+        )),
+        _ => Err(UnsupportedSnapshot(version).into()),
+    }
+}
+
+fn restore(snapshot: Snapshot) -> Restored {
+    let accounts = snapshot
+        .accounts
+        .into_iter()
+        .map(|account| {
+            let state = ClientState::restore(
+                account.client,
+                account.available,
+                account.held,
+                account.locked,
+            );
+            (account.client, state)
+        })
+        .collect();
+    let transactions = snapshot
+        .transactions
+        .into_iter()
+        .map(|transaction| {
+            let tx_type = if transaction.withdrawal {
+                TransactionType::Withdrawal
+            } else {
+                TransactionType::Deposit
+            };
+            let state = if transaction.charged_back {
+                RecordState::ChargedBack
+            } else if transaction.disputed {
+                RecordState::Disputed
+            } else if transaction.reversed {
+                RecordState::Reversed
+            } else {
+                RecordState::Settled
+            };
+            let record =
+                DisputeRecord::restore(transaction.client, tx_type, transaction.amount, state);
+            (transaction.tx, record)
+        })
+        .collect();
+    let partial_holds = snapshot
+        .partial_holds
+        .into_iter()
+        .map(|hold| (hold.tx, hold.held))
+        .collect();
+    Restored {
+        accounts,
+        transactions,
+        partial_holds,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        account::ClientState,
+        data::{DisputeRecord, RecordState, TransactionType},
+        snapshot::{
+            decode, encode, is_sealed, is_snapshot, load, parse, SnapshotKey, UnsupportedSnapshot,
+        },
+    };
+
+    /// A version 1 snapshot of client 1 with 10.5 available and 2 held, as written by the
+    /// first engine to write them. It must stay readable by every later engine.
+    const SNAPSHOT_V1: &[u8] = b"ETSN\x00\x01\x81\xa8accounts\x91\x84\xa6client\x01\
+        \xa9available\xa410.5\xa4held\xa12\xa6locked\xc2";
+
+    #[test]
+    fn snapshots_round_trip() {
+        let accounts = HashMap::from([
+            (
+                1,
+                ClientState::restore(1, Decimal::new(105, 1), Decimal::TWO, false),
+            ),
+            (
+                2,
+                ClientState::restore(2, Decimal::ZERO, Decimal::ZERO, true),
+            ),
+        ]);
+        let record = |tx_type, amount, state| DisputeRecord::restore(1, tx_type, amount, state);
+        let deposit = record(
+            TransactionType::Deposit,
+            Decimal::TWO,
+            RecordState::Disputed,
+        );
+        let withdrawal = record(
+            TransactionType::Withdrawal,
+            Decimal::ONE,
+            RecordState::Reversed,
+        );
+        let charged_back = record(
+            TransactionType::Deposit,
+            Decimal::TEN,
+            RecordState::ChargedBack,
+        );
+        let transactions = [(7, deposit), (3, withdrawal), (9, charged_back)];
+        let bytes = encode(&accounts, transactions, [(7, Decimal::ONE)]).unwrap();
+        assert!(is_snapshot(&bytes));
+        assert_eq!(&bytes[4..6], &[0, 4]);
+
+        let restored = decode(&bytes).unwrap();
+        assert_eq!(restored.accounts.len(), 2);
+        assert_eq!(restored.accounts[&1].available(), Decimal::new(105, 1));
+        assert_eq!(restored.accounts[&1].held(), Decimal::TWO);
+        assert!(restored.accounts[&2].is_locked());
+        assert_eq!(restored.transactions, HashMap::from(transactions));
+        assert!(restored.transactions[&9].is_charged_back());
+        assert!(!restored.transactions[&9].in_dispute());
+        assert_eq!(restored.partial_holds, HashMap::from([(7, Decimal::ONE)]));
+    }
+
+    #[test]
+    fn every_written_version_is_migrated() {
+        let restored = decode(SNAPSHOT_V1).unwrap();
+        assert_eq!(restored.accounts[&1].available(), Decimal::new(105, 1));
+        assert_eq!(restored.accounts[&1].held(), Decimal::TWO);
+        assert!(!restored.accounts[&1].is_locked());
+        assert!(restored.transactions.is_empty());
+        assert!(restored.partial_holds.is_empty());
+    }
+
+    #[tokio::test]
+    async fn csv_of_the_accounts_is_version_0() {
+        let path = std::env::temp_dir().join("effective-train-snapshot-v0.csv");
+        std::fs::write(
+            &path,
+            "client,available,held,total,locked\n1,10.5,2,12.5,false\n",
+        )
+        .unwrap();
+        let restored = load(&path.to_string_lossy(), None).await.unwrap().accounts;
+        assert_eq!(restored[&1].available(), Decimal::new(105, 1));
+        assert_eq!(restored[&1].total(), Decimal::new(125, 1));
+    }
+
+    #[test]
+    fn newer_or_foreign_snapshots_are_refused() {
+        let mut bytes = encode(&HashMap::new(), [], []).unwrap();
+        bytes[5] = 5;
+        assert!(decode(&bytes).unwrap_err().is::<UnsupportedSnapshot>());
+        assert!(!is_snapshot(b"client,available,held,total,locked\n"));
+        assert!(decode(b"client,available").is_err());
+        assert!(decode(b"ETSN\x00").is_err());
+    }
+
+    #[tokio::test]
+    async fn sealed_snapshots_only_open_with_their_key() {
+        let accounts = HashMap::from([(
+            1,
+            ClientState::restore(1, Decimal::new(105, 1), Decimal::TWO, false),
+        )]);
+        let snapshot = encode(&accounts, [], []).unwrap();
+        let key = SnapshotKey::from_hex(&"0f".repeat(32)).unwrap();
+        let sealed = key.seal(&snapshot).unwrap();
+        assert!(is_sealed(&sealed) && !is_snapshot(&sealed));
+        assert!(!sealed.windows(4).any(|window| window == b"held"));
+        // A fresh nonce each time
+        assert_ne!(key.seal(&snapshot).unwrap(), sealed);
+
+        let restored = parse(&sealed, Some(&key)).await.unwrap();
+        assert_eq!(restored.accounts[&1].available(), Decimal::new(105, 1));
+        // Plain snapshots still restore with a key set
+        assert!(parse(&snapshot, Some(&key)).await.is_ok());
+
+        let e = parse(&sealed, None).await.unwrap_err();
+        assert!(e.to_string().contains("EFFECTIVE_TRAIN_SNAPSHOT_KEY"));
+        let other = SnapshotKey::new(&[1; 32]).unwrap();
+        assert!(parse(&sealed, Some(&other)).await.is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(parse(&tampered, Some(&key)).await.is_err());
+        assert!(key.open(&sealed[..10]).is_err());
+    }
+
+    #[test]
+    fn snapshot_keys_are_64_hex_digits() {
+        assert!(SnapshotKey::from_hex(&"ab".repeat(32)).is_ok());
+        assert!(SnapshotKey::from_hex(&"ab".repeat(16)).is_err());
+        assert!(SnapshotKey::from_hex(&format!("{}a", "ab".repeat(31))).is_err());
+        assert!(SnapshotKey::from_hex(&"zz".repeat(32)).is_err());
+        assert!(SnapshotKey::from_hex(&"é".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Where the events of an input are read from
+//!
+//! `process_file` opens a source for its input and routes every batch it yields to the
+//! workers, so a new kind of input, e.g. JSON lines or a message queue, only needs an
+//! `EventSource` of its own. The CSV sources live in `io_ops`, next to their parsing.
+
+use std::future::Future;
+
+use anyhow::Result;
+
+use crate::{data::Sequenced, router::Router};
+
+/// Reads the events of an input in order
+pub trait EventSource {
+    /// The next events of the input, in the order they are to be applied and tagged with
+    /// offsets which increase through the input. Empty once the input is exhausted.
+    ///
+    /// # Errors
+    /// If the input cannot be read or holds a record which is not a transaction
+    fn next_batch(&mut self) -> impl Future<Output = Result<Vec<Sequenced>>> + Send;
+
+    /// Records read so far which were not transactions, skipped as the input is lenient
+    fn malformed(&self) -> u64 {
+        0
+    }
+}
+
+/// Routes every event of `source` until it is exhausted
+///
+/// # Errors
+/// If `source` fails, or the router does, e.g. with `SliceEnd` once the slice was routed
+pub async fn route_events(source: &mut impl EventSource, router: &mut Router) -> Result<()> {
+    loop {
+        let batch = source.next_batch().await?;
+        router.count_malformed(source.malformed());
+        if batch.is_empty() {
+            return Ok(());
+        }
+        for event in batch {
+            router.route(event)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+    use rust_decimal::Decimal;
+    use tokio::sync::mpsc;
+
+    use crate::{
+        data::{Sequenced, Transaction},
+        ledger::WorkerMsg,
+        router::Router,
+        source::{route_events, EventSource},
+    };
+
+    /// Batches handed over as they are
+    struct Batches(Vec<Vec<Sequenced>>);
+
+    impl EventSource for Batches {
+        async fn next_batch(&mut self) -> Result<Vec<Sequenced>> {
+            Ok(if self.0.is_empty() {
+                Vec::new()
+            } else {
+                self.0.remove(0)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_every_batch_in_order() {
+        let event = |seq, tx| Sequenced {
+            seq,
+            tx: Transaction::deposit(1, tx, Decimal::ONE),
+        };
+        let mut source = Batches(vec![vec![event(0, 1), event(10, 2)], vec![event(20, 3)]]);
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut router = Router::new(vec![sender]);
+        route_events(&mut source, &mut router).await.unwrap();
+        drop(router);
+
+        let mut routed = Vec::new();
+        while let Some(event) = receiver.recv().await.and_then(WorkerMsg::into_event) {
+            routed.push((event.seq, event.tx.tx_id()));
+        }
+        assert_eq!(routed, vec![(0, 1), (10, 2), (20, 3)]);
+    }
+}
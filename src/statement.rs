@@ -0,0 +1,547 @@
+//! Statement of one client's transactions, replayed from an input file
+
+use std::{collections::HashMap, fmt::Write as _, str::FromStr, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use csv_async::AsyncWriter;
+use futures::stream::StreamExt;
+use rust_decimal::{Decimal, RoundingStrategy};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    account::{AccountSummary, ClientState},
+    cli::Args,
+    data::Transaction,
+    io_ops::{async_read_csv, finish_csv, read_transaction},
+    ledger::Ledger,
+    limits::LimitConfig,
+    risk::risk_policy,
+};
+
+/// How a statement is rendered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatementFormat {
+    /// Aligned columns for a person to read
+    #[default]
+    Text,
+    Csv,
+}
+
+impl FromStr for StatementFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "csv" => Ok(Self::Csv),
+            _ => bail!("Unknown statement format `{s}`, expected `text` or `csv`"),
+        }
+    }
+}
+
+/// How the amounts of a text statement are written, a CSV statement keeps them as they are
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AmountFormat {
+    /// Separate the thousands of the integer part with `,`
+    pub thousands_separators: bool,
+    /// Round to this many decimal places, half away from zero, padding with zeros
+    pub decimal_places: Option<u32>,
+    /// Symbol of the currency, written in a column of its own
+    pub currency: Option<String>,
+}
+
+impl AmountFormat {
+    /// Most decimal places an amount can have
+    pub const MAX_DECIMAL_PLACES: u32 = 28;
+
+    pub fn format(&self, amount: Decimal) -> String {
+        let amount = match self.decimal_places {
+            Some(places) => {
+                let mut rounded =
+                    amount.round_dp_with_strategy(places, RoundingStrategy::MidpointAwayFromZero);
+                rounded.rescale(places);
+                rounded
+            }
+            None => amount,
+        };
+        let text = amount.to_string();
+        if !self.thousands_separators {
+            return text;
+        }
+        let (sign, digits) = match text.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", text.as_str()),
+        };
+        let (integer, fraction) = digits.split_at(digits.find('.').unwrap_or(digits.len()));
+        let mut grouped = String::with_capacity(text.len() + integer.len() / 3);
+        grouped.push_str(sign);
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                grouped.push(',');
+            }
+            grouped.push(digit);
+        }
+        grouped.push_str(fraction);
+        grouped
+    }
+}
+
+/// The client and range of input records, numbered from 1, a statement covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementOptions {
+    pub client: u16,
+    pub from: u64,
+    pub to: u64,
+    pub format: StatementFormat,
+    pub amounts: AmountFormat,
+    /// Columns of the input written along with each transaction, e.g. its merchant or
+    /// reference
+    pub passthrough: Vec<String>,
+}
+
+/// One of the client's transactions and the account right after it
+#[derive(Debug)]
+pub struct StatementLine {
+    /// Position of the transaction's record in the input
+    pub record: u64,
+    pub tx: Transaction,
+    /// Why the transaction was rejected, `None` if it applied
+    pub rejected: Option<String>,
+    pub balance: AccountSummary,
+    /// The record's values of the `passthrough` columns, empty when it has none
+    pub passthrough: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Statement {
+    pub options: StatementOptions,
+    /// The account before the first record of the range, `None` if it was not opened yet
+    pub opening: Option<AccountSummary>,
+    pub lines: Vec<StatementLine>,
+}
+
+impl Statement {
+    /// The account after the last record of the range, `None` if it was never opened
+    pub fn closing(&self) -> Option<&AccountSummary> {
+        self.lines
+            .last()
+            .map(|line| &line.balance)
+            .or(self.opening.as_ref())
+    }
+}
+
+/// Applies the client's `(record, transaction)`s in order to `ledger`, as a run without
+/// retries or a reorder window would, listing those within the range of `options`. The
+/// transactions before it only make up the opening balance.
+pub fn build_statement(
+    mut ledger: Ledger,
+    transactions: impl IntoIterator<Item = (u64, Transaction)>,
+    options: StatementOptions,
+) -> Statement {
+    let summary = |ledger: &Ledger| ledger.accounts().next().map(AccountSummary::from);
+    let mut opening = None;
+    let mut lines = Vec::new();
+    for (record, tx) in transactions {
+        if record > options.to {
+            break;
+        } else if tx.client_id() != options.client {
+            continue;
+        }
+        if record >= options.from && lines.is_empty() {
+            opening = summary(&ledger);
+        }
+        let rejected = ledger
+            .process_transaction(tx.clone())
+            .err()
+            .map(|(_, e)| e.to_string());
+        if record < options.from {
+            continue;
+        }
+        let balance = summary(&ledger)
+            .unwrap_or_else(|| AccountSummary::from(&ClientState::new(options.client)));
+        lines.push(StatementLine {
+            record,
+            tx,
+            rejected,
+            balance,
+            passthrough: Vec::new(),
+        });
+    }
+    if lines.is_empty() {
+        opening = summary(&ledger);
+    }
+    Statement {
+        options,
+        opening,
+        lines,
+    }
+}
+
+/// The balances of an account, `no account` if it was not opened
+pub fn describe_balance(balance: Option<&AccountSummary>, amounts: &AmountFormat) -> String {
+    match balance {
+        Some(balance) => format!(
+            "available {}, held {}, total {}{}",
+            amounts.format(balance.available),
+            amounts.format(balance.held),
+            amounts.format(balance.total),
+            if balance.locked { ", locked" } else { "" }
+        ),
+        None => "no account".to_owned(),
+    }
+}
+
+/// The statement with a line per transaction between its opening and closing balances
+pub fn render_text(statement: &Statement) -> String {
+    let StatementOptions {
+        client,
+        from,
+        to,
+        ref amounts,
+        ref passthrough,
+        ..
+    } = statement.options;
+    let to = if to == u64::MAX {
+        "the end of the input".to_owned()
+    } else {
+        to.to_string()
+    };
+    let currency = amounts
+        .currency
+        .as_deref()
+        .map(|currency| format!(" ({currency})"))
+        .unwrap_or_default();
+    let mut text = format!("Statement of client {client}, records {from} to {to}\n");
+    let _ = writeln!(
+        text,
+        "Opening balance{currency}: {}\n",
+        describe_balance(statement.opening.as_ref(), amounts)
+    );
+    // The currency column is as wide as its header or symbol
+    let currency_column = |symbol: &str| match &amounts.currency {
+        Some(currency) => format!(
+            "{symbol:<width$}  ",
+            width = currency.chars().count().max(8)
+        ),
+        None => String::new(),
+    };
+    // Each passed through column is as wide as its longest value or name
+    let widths: Vec<_> = passthrough
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            statement
+                .lines
+                .iter()
+                .filter_map(|line| line.passthrough.get(i))
+                .chain([column])
+                .map(|value| value.chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let passthrough_columns = |values: &[String]| {
+        let mut columns = String::new();
+        for (i, width) in widths.iter().enumerate() {
+            let value = values.get(i).map(String::as_str).unwrap_or_default();
+            let _ = write!(columns, "{value:<width$}  ");
+        }
+        columns
+    };
+    let _ = writeln!(
+        text,
+        "{:>8}  {:<12}  {:>10}  {}{:>14}  {:>14}  {:>14}  {:>14}  {}status",
+        "record",
+        "type",
+        "tx",
+        currency_column("currency"),
+        "amount",
+        "available",
+        "held",
+        "total",
+        passthrough_columns(passthrough)
+    );
+    for line in &statement.lines {
+        let amount = line
+            .tx
+            .amount()
+            .map(|a| amounts.format(a))
+            .unwrap_or_default();
+        let status = match &line.rejected {
+            Some(reason) => format!("rejected: {reason}"),
+            None if line.balance.locked => "applied, locked".to_owned(),
+            None => "applied".to_owned(),
+        };
+        let _ = writeln!(
+            text,
+            "{:>8}  {:<12}  {:>10}  {}{:>14}  {:>14}  {:>14}  {:>14}  {}{status}",
+            line.record,
+            line.tx.tx_type().to_string(),
+            line.tx.tx_id(),
+            currency_column(amounts.currency.as_deref().unwrap_or_default()),
+            amount,
+            amounts.format(line.balance.available),
+            amounts.format(line.balance.held),
+            amounts.format(line.balance.total),
+            passthrough_columns(&line.passthrough)
+        );
+    }
+    let _ = writeln!(
+        text,
+        "\nClosing balance{currency}: {}",
+        describe_balance(statement.closing(), amounts)
+    );
+    text
+}
+
+/// # Errors
+/// Can fail to write to `writer`
+pub async fn write_csv<W: AsyncWrite + Unpin>(statement: &Statement, writer: W) -> Result<()> {
+    let mut writer = AsyncWriter::from_writer(writer);
+    let header = [
+        "record",
+        "type",
+        "tx",
+        "amount",
+        "available",
+        "held",
+        "total",
+        "locked",
+        "error",
+    ];
+    let passthrough = statement.options.passthrough.iter().map(String::as_str);
+    writer
+        .write_record(header.into_iter().chain(passthrough))
+        .await?;
+    for line in &statement.lines {
+        let passthrough = (0..statement.options.passthrough.len())
+            .map(|i| line.passthrough.get(i).cloned().unwrap_or_default());
+        writer
+            .write_record(
+                [
+                    line.record.to_string(),
+                    line.tx.tx_type().to_string(),
+                    line.tx.tx_id().to_string(),
+                    line.tx.amount().map(|a| a.to_string()).unwrap_or_default(),
+                    line.balance.available.to_string(),
+                    line.balance.held.to_string(),
+                    line.balance.total.to_string(),
+                    line.balance.locked.to_string(),
+                    line.rejected.clone().unwrap_or_default(),
+                ]
+                .into_iter()
+                .chain(passthrough),
+            )
+            .await?;
+    }
+    finish_csv(writer).await?;
+
+    Ok(())
+}
+
+/// A ledger replaying a history with the account options of `args` and the withdrawal
+/// `limits` read from its `--withdrawal-limits`
+pub fn replay_ledger(args: &Args, limits: Option<Arc<LimitConfig>>) -> Ledger {
+    let ledger = Ledger::new()
+        .with_locked_policy(args.locked_policy)
+        .with_dispute_policy(args.dispute_policy)
+        .with_chargeback_limit(args.chargeback_limit)
+        .with_max_amount(args.max_amount)
+        .with_overdrafts(Arc::new(args.overdrafts.clone()))
+        .with_withdrawal_limits(limits)
+        .with_disputable_types(args.disputable);
+    match risk_policy(args.velocity, args.anomaly) {
+        Some(policy) => ledger.with_risk_policy(policy),
+        None => ledger,
+    }
+}
+
+/// Replays the input of `args` and writes the statement of `options` to `stdout`
+///
+/// # Errors
+/// If the input cannot be read or the statement cannot be written
+pub async fn run_statement(options: &StatementOptions, file_path: &str, args: &Args) -> Result<()> {
+    let limits = LimitConfig::load(args.withdrawal_limits.as_deref())?;
+    let mut reader = async_read_csv(file_path, args.csv, args.io_retry).await?;
+    // Positions of the passed through columns in the header
+    let mut columns = Vec::with_capacity(options.passthrough.len());
+    if !options.passthrough.is_empty() {
+        let header = reader.headers().await?;
+        for name in &options.passthrough {
+            let position = header
+                .iter()
+                .position(|column| column == name)
+                .with_context(|| {
+                    format!("Column `{name}` to pass through is not in {file_path}")
+                })?;
+            columns.push(position);
+        }
+    }
+    let mut records = reader.records();
+    let mut transactions = Vec::new();
+    let mut passthrough = HashMap::new();
+    for record in 1.. {
+        let Some(row) = records.next().await else {
+            break;
+        };
+        if record > options.to {
+            break;
+        }
+        let row = row?;
+        let tx = read_transaction(&row)?;
+        if tx.client_id() == options.client {
+            if record >= options.from && !columns.is_empty() {
+                let values = columns
+                    .iter()
+                    .map(|i| row.get(*i).unwrap_or_default().to_owned())
+                    .collect::<Vec<_>>();
+                passthrough.insert(record, values);
+            }
+            transactions.push((record, tx));
+        }
+    }
+
+    let mut statement = build_statement(replay_ledger(args, limits), transactions, options.clone());
+    for line in &mut statement.lines {
+        line.passthrough = passthrough.remove(&line.record).unwrap_or_default();
+    }
+    let mut stdout = tokio::io::stdout();
+    match options.format {
+        StatementFormat::Text => {
+            stdout.write_all(render_text(&statement).as_bytes()).await?;
+            stdout.flush().await?;
+            Ok(())
+        }
+        StatementFormat::Csv => write_csv(&statement, stdout).await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::Transaction,
+        ledger::Ledger,
+        statement::{
+            build_statement, render_text, write_csv, AmountFormat, StatementFormat,
+            StatementOptions,
+        },
+    };
+
+    fn options(from: u64, to: u64) -> StatementOptions {
+        StatementOptions {
+            client: 1,
+            from,
+            to,
+            format: StatementFormat::Text,
+            amounts: AmountFormat::default(),
+            passthrough: Vec::new(),
+        }
+    }
+
+    fn history() -> Vec<(u64, Transaction)> {
+        vec![
+            (1, Transaction::deposit(1, 1, Decimal::TEN)),
+            (2, Transaction::deposit(2, 2, Decimal::ONE)),
+            (3, Transaction::deposit(1, 3, Decimal::TWO)),
+            (4, Transaction::withdrawal(1, 4, Decimal::ONE_HUNDRED)),
+            (5, Transaction::dispute(1, 3)),
+            (6, Transaction::chargeback(1, 3)),
+        ]
+    }
+
+    #[test]
+    fn statement_lists_the_range_between_its_balances() {
+        let statement = build_statement(Ledger::new(), history(), options(3, 5));
+
+        let opening = statement.opening.as_ref().unwrap();
+        assert_eq!(opening.total, Decimal::TEN);
+        let records: Vec<_> = statement.lines.iter().map(|line| line.record).collect();
+        assert_eq!(records, vec![3, 4, 5]);
+        assert!(statement.lines[1]
+            .rejected
+            .as_deref()
+            .unwrap()
+            .starts_with("Withdrawal failed due to insufficient funds"));
+        let closing = statement.closing().unwrap();
+        assert_eq!(
+            (closing.available, closing.held),
+            (Decimal::TEN, Decimal::TWO)
+        );
+        assert!(!closing.locked);
+
+        let text = render_text(&statement);
+        assert!(text.starts_with("Statement of client 1, records 3 to 5\n"));
+        assert!(text.contains("Opening balance: available 10, held 0, total 10\n"));
+        assert!(text.ends_with("Closing balance: available 10, held 2, total 12\n"));
+    }
+
+    #[test]
+    fn statement_before_the_first_transaction_has_no_account() {
+        let statement = build_statement(Ledger::new(), history(), options(1, u64::MAX));
+        assert!(statement.opening.is_none());
+        assert_eq!(statement.lines.len(), 5);
+        assert!(statement.closing().unwrap().locked);
+
+        let statement = build_statement(Ledger::new(), Vec::new(), options(1, u64::MAX));
+        assert!(statement.closing().is_none());
+        assert!(render_text(&statement).contains("Opening balance: no account\n"));
+    }
+
+    #[test]
+    fn formats_the_amounts_of_a_text_statement() {
+        let amounts = AmountFormat {
+            thousands_separators: true,
+            decimal_places: Some(2),
+            currency: Some("EUR".to_owned()),
+        };
+        assert_eq!(amounts.format(Decimal::new(-12_345_675, 4)), "-1,234.57");
+        assert_eq!(amounts.format(Decimal::new(1_234_567, 0)), "1,234,567.00");
+        assert_eq!(amounts.format(Decimal::new(999, 1)), "99.90");
+        let plain = AmountFormat {
+            decimal_places: Some(0),
+            ..AmountFormat::default()
+        };
+        assert_eq!(plain.format(Decimal::new(1_234_565, 1)), "123457");
+        assert_eq!(
+            AmountFormat::default().format(Decimal::new(1_234_567, 3)),
+            "1234.567"
+        );
+
+        let mut options = options(1, u64::MAX);
+        options.amounts = amounts;
+        let history = vec![(1, Transaction::deposit(1, 1, Decimal::new(123_456, 1)))];
+        let text = render_text(&build_statement(Ledger::new(), history, options));
+        assert!(text.contains("  currency  "));
+        assert!(text.contains("  EUR            12,345.60  "));
+        assert!(text
+            .ends_with("Closing balance (EUR): available 12,345.60, held 0.00, total 12,345.60\n"));
+    }
+
+    #[tokio::test]
+    async fn passes_columns_of_the_input_through() {
+        let mut options = options(1, u64::MAX);
+        options.passthrough = vec!["merchant".to_owned(), "reference".to_owned()];
+        let history = vec![
+            (1, Transaction::deposit(1, 1, Decimal::TEN)),
+            (2, Transaction::withdrawal(1, 2, Decimal::ONE)),
+        ];
+        let mut statement = build_statement(Ledger::new(), history, options);
+        statement.lines[0].passthrough = vec!["Acme Stores".to_owned(), "INV-1".to_owned()];
+
+        let text = render_text(&statement);
+        assert!(text.contains("  total  merchant     reference  status\n"));
+        assert!(text.contains("  10  Acme Stores  INV-1      applied\n"));
+        assert!(text.contains("  9                          applied\n"));
+
+        let mut csv = Vec::new();
+        write_csv(&statement, &mut csv).await.unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with(
+            "record,type,tx,amount,available,held,total,locked,error,merchant,reference\n"
+        ));
+        assert!(csv.contains(",false,,Acme Stores,INV-1\n"));
+        assert!(csv.ends_with(",false,,,\n"));
+    }
+}
@@ -0,0 +1,208 @@
+//! Where a ledger keeps the deposits and withdrawals a later dispute may reference
+//!
+//! A ledger holds one record per approved deposit or withdrawal for the rest of the run, so
+//! they make up most of its memory on a long input. `PackedRecord` keeps the amount as an
+//! integer of scaled minor units next to its scale, so a record with its id takes 12 bytes
+//! rather than the 32 of the `(u32, Transaction)` it was first kept as. The rare amount
+//! with too many digits to be packed is kept whole in a side map.
+
+use rust_decimal::Decimal;
+
+use crate::{
+    data::{DisputeRecord, RecordState, TransactionType},
+    hasher::IdMap,
+};
+
+/// A `DisputeRecord` whose amount fits 40 bits of scaled minor units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackedRecord {
+    client_id: u16,
+    /// Whether it is a withdrawal in bit 0, its `RecordState` in bits 1-2 and the scale of
+    /// its amount in bits 3-7
+    meta: u8,
+    /// Little-endian two's complement of the amount's mantissa
+    mantissa: [u8; 5],
+}
+
+impl PackedRecord {
+    const MANTISSA_BITS: u32 = 40;
+    const MAX_MANTISSA: i64 = (1 << (Self::MANTISSA_BITS - 1)) - 1;
+    const MIN_MANTISSA: i64 = -(1 << (Self::MANTISSA_BITS - 1));
+    const STATES: [RecordState; 4] = [
+        RecordState::Settled,
+        RecordState::Disputed,
+        RecordState::Reversed,
+        RecordState::ChargedBack,
+    ];
+
+    /// `None` if the amount has too many digits, or is a negative zero, which unpacking
+    /// would lose the sign of
+    fn pack(record: &DisputeRecord) -> Option<Self> {
+        let amount = record.amount();
+        if amount.is_zero() && amount.is_sign_negative() {
+            return None;
+        }
+        let mantissa = i64::try_from(amount.mantissa())
+            .ok()
+            .filter(|mantissa| (Self::MIN_MANTISSA..=Self::MAX_MANTISSA).contains(mantissa))?;
+        let scale = u8::try_from(amount.scale()).ok()?;
+        let withdrawal = u8::from(record.tx_type() == TransactionType::Withdrawal);
+        let state = Self::STATES
+            .iter()
+            .position(|state| *state == record.state())?;
+        let state = u8::try_from(state).ok()?;
+        let mut bytes = [0; 5];
+        bytes.copy_from_slice(&mantissa.to_le_bytes()[..5]);
+        Some(Self {
+            client_id: record.client_id(),
+            meta: withdrawal | (state << 1) | (scale << 3),
+            mantissa: bytes,
+        })
+    }
+
+    fn unpack(self) -> DisputeRecord {
+        let negative = self.mantissa[4] & 0x80 != 0;
+        let mut bytes = if negative { [0xff; 8] } else { [0; 8] };
+        bytes[..5].copy_from_slice(&self.mantissa);
+        let amount = Decimal::new(i64::from_le_bytes(bytes), u32::from(self.meta >> 3));
+        let tx_type = if self.meta & 1 == 0 {
+            TransactionType::Deposit
+        } else {
+            TransactionType::Withdrawal
+        };
+        let state = Self::STATES[usize::from((self.meta >> 1) & 0b11)];
+        DisputeRecord::restore(self.client_id, tx_type, amount, state)
+    }
+}
+
+/// The approved deposits and withdrawals of a ledger by transaction id, packed when their
+/// amount allows. Records are handed out by value, so one whose state changed is put back
+/// with `insert`.
+#[derive(Debug, Default)]
+pub struct TransactionStore {
+    packed: IdMap<u32, PackedRecord>,
+    /// Records whose amount does not fit a `PackedRecord`
+    wide: IdMap<u32, DisputeRecord>,
+}
+
+impl TransactionStore {
+    pub fn len(&self) -> usize {
+        self.packed.len() + self.wide.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packed.is_empty() && self.wide.is_empty()
+    }
+
+    pub fn contains(&self, tx_id: u32) -> bool {
+        self.packed.contains_key(&tx_id) || self.wide.contains_key(&tx_id)
+    }
+
+    pub fn get(&self, tx_id: u32) -> Option<DisputeRecord> {
+        match self.packed.get(&tx_id) {
+            Some(packed) => Some(packed.unpack()),
+            None => self.wide.get(&tx_id).copied(),
+        }
+    }
+
+    /// Keeps `record` under `tx_id`, in place of any record it had
+    pub fn insert(&mut self, tx_id: u32, record: DisputeRecord) {
+        match PackedRecord::pack(&record) {
+            Some(packed) => {
+                if self.packed.insert(tx_id, packed).is_none() && !self.wide.is_empty() {
+                    self.wide.remove(&tx_id);
+                }
+            }
+            None => {
+                if self.wide.insert(tx_id, record).is_none() {
+                    self.packed.remove(&tx_id);
+                }
+            }
+        }
+    }
+
+    /// Every record, in no particular order
+    pub fn records(&self) -> impl Iterator<Item = (u32, DisputeRecord)> + '_ {
+        let packed = self
+            .packed
+            .iter()
+            .map(|(tx_id, packed)| (*tx_id, packed.unpack()));
+        packed.chain(self.wide.iter().map(|(tx_id, record)| (*tx_id, *record)))
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.packed.keys().chain(self.wide.keys()).copied()
+    }
+
+    /// Moves in every record of `other`, whose ids this store does not hold
+    pub fn append(&mut self, other: Self) {
+        self.packed.extend(other.packed);
+        self.wide.extend(other.wide);
+    }
+}
+
+impl Extend<(u32, DisputeRecord)> for TransactionStore {
+    fn extend<I: IntoIterator<Item = (u32, DisputeRecord)>>(&mut self, records: I) {
+        for (tx_id, record) in records {
+            self.insert(tx_id, record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::{DisputeRecord, RecordState, Transaction, TransactionType},
+        store::{PackedRecord, TransactionStore},
+    };
+
+    #[test]
+    fn records_take_less_than_half_of_a_transaction() {
+        assert_eq!(std::mem::size_of::<(u32, Transaction)>(), 32);
+        assert_eq!(std::mem::size_of::<(u32, PackedRecord)>(), 12);
+    }
+
+    #[test]
+    fn records_round_trip_packed_or_whole() {
+        let mut negative_zero = Decimal::new(0, 2);
+        negative_zero.set_sign_negative(true);
+        let amounts = [
+            Decimal::new(1050, 2),
+            Decimal::new(-1, 4),
+            Decimal::new(PackedRecord::MAX_MANTISSA, 0),
+            Decimal::new(PackedRecord::MIN_MANTISSA, 28),
+            // Too many digits to be packed
+            Decimal::new(PackedRecord::MAX_MANTISSA + 1, 3),
+            Decimal::MAX,
+            negative_zero,
+        ];
+        let mut store = TransactionStore::default();
+        for (tx_id, amount) in (1..).zip(amounts) {
+            let record = DisputeRecord::restore(
+                7,
+                TransactionType::Withdrawal,
+                amount,
+                RecordState::ChargedBack,
+            );
+            store.insert(tx_id, record);
+            assert_eq!(store.get(tx_id), Some(record));
+            assert_eq!(
+                store.get(tx_id).unwrap().amount().to_string(),
+                amount.to_string()
+            );
+        }
+        assert_eq!((store.packed.len(), store.wide.len()), (4, 3));
+
+        // A record put back keeps a single entry
+        let deposit = Transaction::deposit(3, 1, Decimal::TEN)
+            .dispute_record()
+            .unwrap();
+        store.insert(1, deposit);
+        store.insert(5, deposit);
+        assert_eq!(store.len(), amounts.len());
+        assert_eq!(store.get(5), Some(deposit));
+        assert!(store.contains(5) && !store.contains(8));
+    }
+}
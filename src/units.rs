@@ -0,0 +1,135 @@
+//! Amounts stated in whole minor units of a currency, e.g. cents, with `--amount-unit minor`
+//!
+//! The ledger always holds major units, so the amounts of the input are converted as their
+//! records are routed, and those of the accounts back as they are written.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::bail;
+use rust_decimal::Decimal;
+
+use crate::data::Transaction;
+
+/// The unit the amounts of the input and of the written accounts are stated in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AmountUnit {
+    /// Units of the currency, e.g. `10.50` euros
+    #[default]
+    Major,
+    /// Whole minor units of the currency, e.g. `1050` cents
+    Minor,
+}
+
+impl FromStr for AmountUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "major" => Ok(Self::Major),
+            "minor" => Ok(Self::Minor),
+            _ => bail!("unknown unit `{s}`"),
+        }
+    }
+}
+
+/// An amount of the input which is not a whole number of minor units
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FractionalMinorUnits {
+    pub tx_id: u32,
+    pub amount: Decimal,
+}
+
+impl fmt::Display for FractionalMinorUnits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Amount `{}` of transaction `{}` is not a whole number of minor units",
+            self.amount, self.tx_id
+        )
+    }
+}
+
+impl std::error::Error for FractionalMinorUnits {}
+
+/// Converts between the minor units of a currency with `exponent` decimal places, e.g. 2
+/// for the cents of a euro, and its major units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinorUnits {
+    exponent: u32,
+}
+
+impl MinorUnits {
+    pub fn new(exponent: u32) -> Self {
+        Self { exponent }
+    }
+
+    pub fn exponent(self) -> u32 {
+        self.exponent
+    }
+
+    /// Minor units per major unit
+    fn factor(self) -> Decimal {
+        Decimal::from(10_u64.pow(self.exponent))
+    }
+
+    /// The transaction with its amount, if it has one, converted from minor units
+    ///
+    /// # Errors
+    /// If the amount is not a whole number of minor units
+    pub fn to_major(self, tx: Transaction) -> Result<Transaction, FractionalMinorUnits> {
+        let Some(amount) = tx.amount() else {
+            return Ok(tx);
+        };
+        if !amount.fract().is_zero() {
+            return Err(FractionalMinorUnits {
+                tx_id: tx.tx_id(),
+                amount,
+            });
+        }
+        Ok(tx.with_amount(amount / self.factor()))
+    }
+
+    /// `amount` in minor units, without trailing zeros
+    pub fn from_major(self, amount: Decimal) -> Decimal {
+        (amount * self.factor()).normalize()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::Transaction,
+        units::{AmountUnit, MinorUnits},
+    };
+
+    #[test]
+    fn amounts_convert_between_minor_and_major_units() {
+        let cents = MinorUnits::new(2);
+        let deposit = cents
+            .to_major(Transaction::deposit(1, 7, Decimal::from(1050)))
+            .unwrap();
+        assert_eq!(deposit.amount(), Some(Decimal::new(105, 1)));
+        assert_eq!(cents.from_major(Decimal::new(105, 1)).to_string(), "1050");
+        assert_eq!(cents.from_major(Decimal::new(-1, 4)).to_string(), "-0.01");
+
+        let dispute = cents.to_major(Transaction::dispute(1, 7)).unwrap();
+        assert_eq!(dispute.amount(), None);
+        let e = cents
+            .to_major(Transaction::deposit(1, 8, Decimal::new(15, 1)))
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Amount `1.5` of transaction `8` is not a whole number of minor units"
+        );
+
+        let yen = MinorUnits::new(0);
+        let deposit = yen
+            .to_major(Transaction::deposit(1, 9, Decimal::from(500)))
+            .unwrap();
+        assert_eq!(deposit.amount(), Some(Decimal::from(500)));
+        assert_eq!("minor".parse::<AmountUnit>().unwrap(), AmountUnit::Minor);
+        assert!("cents".parse::<AmountUnit>().is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! Checks a transaction file held in memory before it is submitted, e.g. by the web tool
+//! through the `wasm` bindings. Every record is applied in order to one ledger, as a run
+//! with a single worker would, and those which would be rejected are listed with why.
+
+use std::fmt::Write as _;
+
+use anyhow::{bail, Result};
+use csv_async::{AsyncReaderBuilder, StringRecord, Trim};
+use futures::StreamExt;
+
+use crate::{account::AccountSummary, data::Transaction, ledger::Ledger};
+
+/// A record which cannot be read or which the ledger would reject
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedRecord {
+    /// Position of the record in the input, counting from 1
+    pub record: u64,
+    pub reason: String,
+}
+
+/// What applying a transaction file did
+#[derive(Debug)]
+pub struct Validation {
+    /// Records read, the header aside
+    pub records: u64,
+    pub rejected: Vec<RejectedRecord>,
+    /// The resulting accounts in client id order
+    pub accounts: Vec<AccountSummary>,
+}
+
+impl Validation {
+    /// The accounts with the columns of a run's output
+    pub fn accounts_csv(&self) -> String {
+        let mut csv = AccountSummary::HEADER.join(",");
+        csv.push('\n');
+        for account in &self.accounts {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{}",
+                account.label, account.available, account.held, account.total, account.locked
+            );
+        }
+        csv
+    }
+}
+
+/// Applies the records of `contents`, a CSV file with the header a run expects, to `ledger`.
+/// Polls the CSV reader on the calling thread, so no runtime is needed.
+///
+/// # Errors
+/// If the header is not the expected columns
+pub fn validate(contents: &[u8], mut ledger: Ledger) -> Result<Validation> {
+    futures::executor::block_on(async {
+        let mut reader = AsyncReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .create_reader(contents);
+        let header = reader.headers().await?;
+        if !header
+            .iter()
+            .take(Transaction::HEADER.len())
+            .eq(Transaction::HEADER)
+        {
+            bail!(
+                "Expected columns `{}`, found `{}`",
+                Transaction::HEADER.join(","),
+                header.iter().collect::<Vec<_>>().join(",")
+            );
+        }
+        let width = header.len();
+
+        let mut records = 0;
+        let mut rejected = Vec::new();
+        let mut rows = reader.records();
+        while let Some(row) = rows.next().await {
+            records += 1;
+            let outcome = match row {
+                Ok(row) if row.len() != width => {
+                    Err(format!("expected {width} fields, found {}", row.len()))
+                }
+                Ok(row) => read(&row)
+                    .map_err(|e| e.to_string())
+                    .and_then(|tx| ledger.apply(tx).map_err(|e| e.to_string())),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(reason) = outcome {
+                rejected.push(RejectedRecord {
+                    record: records,
+                    reason,
+                });
+            }
+        }
+
+        let mut accounts: Vec<_> = ledger.accounts().map(AccountSummary::from).collect();
+        accounts.sort_unstable_by_key(|account| account.client);
+        Ok(Validation {
+            records,
+            rejected,
+            accounts,
+        })
+    })
+}
+
+/// Deserialises the transaction of a record, ignoring any column past the expected ones
+fn read(record: &StringRecord) -> csv_async::Result<Transaction> {
+    let mut fields = record.clone();
+    fields.truncate(Transaction::HEADER.len());
+    fields.deserialize(None)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ledger::Ledger, validate::validate};
+
+    #[test]
+    fn lists_the_records_which_would_be_rejected() {
+        let contents = "type,client,tx,amount,reference\n\
+            deposit,1,1,10.0,a\n\
+            withdrawal,1,2,20.0,b\n\
+            deposit,2,3,oops,c\n\
+            dispute,1,9,,d\n\
+            deposit,1,4,5.0\n\
+            withdrawal,1,5,4.0,e\n";
+        let validation = validate(contents.as_bytes(), Ledger::new()).unwrap();
+        assert_eq!(validation.records, 6);
+        let rejected: Vec<_> = validation
+            .rejected
+            .iter()
+            .map(|rejected| rejected.record)
+            .collect();
+        assert_eq!(rejected, vec![2, 3, 4, 5]);
+        assert_eq!(validation.rejected[3].reason, "expected 5 fields, found 4");
+        assert_eq!(
+            validation.accounts_csv(),
+            "client,available,held,total,locked\n1,6.0,0,6.0,false\n"
+        );
+
+        let error = validate(b"client,type,tx,amount\n", Ledger::new()).unwrap_err();
+        assert!(error.to_string().starts_with("Expected columns"));
+    }
+}
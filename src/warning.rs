@@ -0,0 +1,78 @@
+//! Records skipped with a warning rather than rejected with an error
+//!
+//! Like a rejection, a warning leaves the ledger as it was, but for a record which most
+//! likely comes from how the input was produced, e.g. sent twice, rather than for a
+//! transaction which should have applied. Warnings are counted apart from rejections,
+//! logged at warning level and written to `--warnings-out` rather than `--dead-letter`.
+
+use std::fmt;
+
+use crate::data::{Sequenced, Transaction};
+
+/// Why a record was skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WarningKind {
+    /// A dispute, resolve, chargeback or reversal of a transaction the ledger does not hold
+    UnknownTransaction,
+    /// A deposit or withdrawal with the id of one already applied
+    DuplicateTransaction,
+}
+
+impl WarningKind {
+    /// Whether a later attempt may apply the record, once the transaction it references
+    /// arrived
+    pub fn retryable(self) -> bool {
+        self == Self::UnknownTransaction
+    }
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UnknownTransaction => "unknown_transaction",
+            Self::DuplicateTransaction => "duplicate_transaction",
+        })
+    }
+}
+
+/// The error of a record skipped with a warning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn unknown_transaction(tx: &Transaction) -> Self {
+        Self {
+            kind: WarningKind::UnknownTransaction,
+            message: format!("Unmatched transaction `{tx:?}`"),
+        }
+    }
+
+    pub fn duplicate_transaction(tx: &Transaction) -> Self {
+        Self {
+            kind: WarningKind::DuplicateTransaction,
+            message: format!(
+                "Transaction `{}` was already applied, the {} was skipped",
+                tx.tx_id(),
+                tx.tx_type()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Warning {}
+
+/// A record skipped with a warning, as published to `WorkerSinks::warnings`
+#[derive(Debug)]
+pub struct SkippedRecord {
+    pub event: Sequenced,
+    pub warning: Warning,
+}
@@ -0,0 +1,51 @@
+//! JavaScript bindings of `validate`, so the web tool can check a transaction file in the
+//! browser before it is submitted. Built without the runtime for `wasm32-unknown-unknown`:
+//!
+//!     cargo build --release --lib --no-default-features --features wasm \
+//!         --target wasm32-unknown-unknown
+//!     wasm-bindgen --target web --out-dir pkg \
+//!         target/wasm32-unknown-unknown/release/effective_train.wasm
+
+use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
+
+use crate::{ledger::Ledger, validate};
+
+/// What applying a transaction file did, as handed to JavaScript
+#[wasm_bindgen]
+pub struct Validation(validate::Validation);
+
+#[wasm_bindgen]
+impl Validation {
+    /// Records read, the header aside
+    #[wasm_bindgen(getter)]
+    pub fn records(&self) -> u32 {
+        u32::try_from(self.0.records).unwrap_or(u32::MAX)
+    }
+
+    /// A line per record which would be rejected, `record <n>: <reason>`
+    #[wasm_bindgen(getter)]
+    pub fn rejected(&self) -> String {
+        self.0
+            .rejected
+            .iter()
+            .map(|rejected| format!("record {}: {}\n", rejected.record, rejected.reason))
+            .collect()
+    }
+
+    /// The resulting accounts as the output of a run would hold them
+    #[wasm_bindgen(getter)]
+    pub fn accounts(&self) -> String {
+        self.0.accounts_csv()
+    }
+}
+
+/// Applies the CSV file `contents` to a fresh ledger with the default options
+///
+/// # Errors
+/// If the header is not the expected columns
+#[wasm_bindgen]
+pub fn validate(contents: &str) -> Result<Validation, JsValue> {
+    validate::validate(contents.as_bytes(), Ledger::new())
+        .map(Validation)
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
@@ -0,0 +1,302 @@
+//! Publishes dispute lifecycle events to an HTTP endpoint
+
+use std::{io, str::FromStr, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc::UnboundedReceiver,
+    time::timeout,
+};
+use tracing::error;
+
+use crate::data::{DisputeEvent, DisputeStage};
+
+/// Time allowed for a single delivery, including connecting
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A plain `http://host[:port]/path` endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for WebhookUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let rest = s
+            .strip_prefix("http://")
+            .context("Webhook URL must start with `http://`")?;
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().context("Invalid webhook port")?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            bail!("Webhook URL `{s}` has no host");
+        }
+
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            path: if path.is_empty() { "/" } else { path }.to_owned(),
+        })
+    }
+}
+
+/// JSON body of a webhook delivery, amounts are strings to keep their exact scale
+pub fn to_json(event: &DisputeEvent) -> String {
+    let stage = match event.stage {
+        DisputeStage::Opened => "dispute_opened",
+        DisputeStage::Resolved => "dispute_resolved",
+        DisputeStage::ChargedBack => "chargeback",
+    };
+    let amount = event
+        .amount
+        .map_or_else(|| "null".to_owned(), |amount| format!("\"{amount}\""));
+    format!(
+        r#"{{"event":"{stage}","client":{},"tx":{},"amount":{amount}}}"#,
+        event.client_id, event.tx_id
+    )
+}
+
+/// POSTs `body` as JSON, succeeding only on a 2xx response
+///
+/// # Errors
+/// If the endpoint cannot be reached in time or responds with a non-2xx status
+pub async fn post(url: &WebhookUrl, body: &str) -> Result<()> {
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        url.path,
+        url.host,
+        body.len()
+    );
+
+    let response = timeout(DELIVERY_TIMEOUT, async {
+        let mut stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        anyhow::Ok(response)
+    })
+    .await
+    .context("Webhook delivery timed out")??;
+
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if !status.starts_with('2') {
+        bail!("Webhook responded with status `{status}`");
+    }
+    Ok(())
+}
+
+/// Notifications the endpoint did not accept, kept one JSON body per line in a file so a
+/// later run delivers them again. A notification only leaves the outbox once it was
+/// accepted, so each is delivered at least once.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    path: String,
+    /// Bodies still to deliver, oldest first
+    pending: Vec<String>,
+}
+
+impl Outbox {
+    /// Reads the outbox at `path`, which is empty if it does not exist yet
+    ///
+    /// # Errors
+    /// If the outbox exists but cannot be read
+    pub async fn load(path: &str) -> io::Result<Self> {
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let pending = contents
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect();
+        Ok(Self {
+            path: path.to_owned(),
+            pending,
+        })
+    }
+
+    pub fn pending(&self) -> &[String] {
+        &self.pending
+    }
+
+    /// Appends a body the endpoint did not accept, so it outlives the run
+    ///
+    /// # Errors
+    /// If the outbox cannot be written
+    pub async fn push(&mut self, body: String) -> io::Result<()> {
+        let mut outbox = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        outbox.write_all(format!("{body}\n").as_bytes()).await?;
+        outbox.flush().await?;
+        self.pending.push(body);
+        Ok(())
+    }
+
+    /// Delivers the bodies left by earlier runs in order, then rewrites the outbox with
+    /// those the endpoint refused again
+    ///
+    /// # Errors
+    /// If the outbox cannot be rewritten
+    pub async fn redeliver(&mut self, url: &WebhookUrl) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut refused = Vec::new();
+        for body in std::mem::take(&mut self.pending) {
+            if let Err(e) = post(url, &body).await {
+                error!("Dispute webhook redelivery failed for `{}`: {}", body, e);
+                refused.push(body);
+            }
+        }
+        // A run stopped while rewriting still finds the previous outbox
+        let temporary = format!("{}.tmp", self.path);
+        let contents: String = refused.iter().map(|body| format!("{body}\n")).collect();
+        tokio::fs::write(&temporary, contents).await?;
+        tokio::fs::rename(&temporary, &self.path).await?;
+        self.pending = refused;
+        Ok(())
+    }
+}
+
+/// Delivers every event in the order it was published, logging failed deliveries. With an
+/// `outbox`, the notifications earlier runs failed to deliver are sent first, and those
+/// failing now are kept in it.
+///
+/// # Errors
+/// If the outbox cannot be read or written
+pub async fn publish_dispute_events(
+    url: WebhookUrl,
+    mut events: UnboundedReceiver<DisputeEvent>,
+    outbox: Option<String>,
+) -> io::Result<()> {
+    let mut outbox = match outbox {
+        Some(path) => Some(Outbox::load(&path).await?),
+        None => None,
+    };
+    if let Some(outbox) = &mut outbox {
+        outbox.redeliver(&url).await?;
+    }
+    while let Some(event) = events.recv().await {
+        let body = to_json(&event);
+        if let Err(e) = post(&url, &body).await {
+            error!(
+                "Dispute webhook delivery failed for tx `{}`: {}",
+                event.tx_id, e
+            );
+            if let Some(outbox) = &mut outbox {
+                outbox.push(body).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::{
+        data::{DisputeEvent, DisputeStage},
+        webhook::{post, to_json, Outbox, WebhookUrl},
+    };
+
+    #[test]
+    fn parses_http_urls() {
+        let url: WebhookUrl = "http://risk.local:8080/hooks/disputes".parse().unwrap();
+        assert_eq!(url.host, "risk.local");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/hooks/disputes");
+
+        let url: WebhookUrl = "http://risk.local".parse().unwrap();
+        assert_eq!((url.port, url.path.as_str()), (80, "/"));
+        assert!("https://risk.local".parse::<WebhookUrl>().is_err());
+    }
+
+    #[test]
+    fn serialises_events_as_json() {
+        let event = DisputeEvent {
+            stage: DisputeStage::Opened,
+            client_id: 3,
+            tx_id: 42,
+            amount: Some(Decimal::new(1050, 2)),
+        };
+        assert_eq!(
+            to_json(&event),
+            r#"{"event":"dispute_opened","client":3,"tx":42,"amount":"10.50"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn posts_to_the_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 1024];
+            let read = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request[..read]).into_owned()
+        });
+
+        let url = format!("http://127.0.0.1:{port}/disputes").parse().unwrap();
+        post(&url, "{}").await.unwrap();
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /disputes HTTP/1.1\r\n"));
+        assert!(request.ends_with("\r\n\r\n{}"));
+    }
+
+    #[tokio::test]
+    async fn outbox_keeps_refused_notifications_for_the_next_run() {
+        let path = std::env::temp_dir().join("effective-train-outbox.jsonl");
+        let path = path.to_string_lossy().into_owned();
+        std::fs::remove_file(&path).ok();
+
+        let mut outbox = Outbox::load(&path).await.unwrap();
+        assert!(outbox.pending().is_empty());
+        outbox.push(r#"{"tx":1}"#.to_owned()).await.unwrap();
+        outbox.push(r#"{"tx":2}"#.to_owned()).await.unwrap();
+
+        // The next run finds both, the endpoint accepts the first and refuses the second
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            for status in ["204 No Content", "503 Service Unavailable"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 1024];
+                stream.read(&mut request).await.unwrap();
+                let response = format!("HTTP/1.1 {status}\r\n\r\n");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let mut outbox = Outbox::load(&path).await.unwrap();
+        assert_eq!(outbox.pending(), [r#"{"tx":1}"#, r#"{"tx":2}"#]);
+        let url = format!("http://127.0.0.1:{port}/disputes").parse().unwrap();
+        outbox.redeliver(&url).await.unwrap();
+        server.await.unwrap();
+
+        let outbox = Outbox::load(&path).await.unwrap();
+        assert_eq!(outbox.pending(), [r#"{"tx":2}"#]);
+    }
+}
@@ -0,0 +1,228 @@
+//! Tumbling windows of the payment flow with `--flow-windows`, for monitoring it while
+//! `--emit updates` streams the accounts out
+//!
+//! Each applied transaction is counted in the window of wall-clock time it applied in.
+//! Windows are aligned to multiples of their width since the Unix epoch, and one row is
+//! written as soon as a window ends, with zeros for a window nothing applied in.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+use tokio::{sync::mpsc::UnboundedReceiver, time::sleep};
+
+use crate::{
+    data::{Transaction, TransactionType},
+    io_ops::{create_retrying, finish_csv},
+    retry::RetryPolicy,
+};
+
+/// Width of a window unless `--flow-window` is given
+pub const DEFAULT_WIDTH: Duration = Duration::from_secs(60);
+
+/// The transactions applied in one window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowWindow {
+    /// Milliseconds since the Unix epoch
+    pub start_ms: u64,
+    pub deposits: u64,
+    pub deposit_volume: Decimal,
+    pub withdrawals: u64,
+    pub withdrawal_volume: Decimal,
+    pub disputes: u64,
+}
+
+impl FlowWindow {
+    /// Column names, in the order of `to_record`
+    pub const HEADER: [&'static str; 6] = [
+        "window_start_ms",
+        "deposits",
+        "deposit_volume",
+        "withdrawals",
+        "withdrawal_volume",
+        "disputes",
+    ];
+
+    fn new(start_ms: u64) -> Self {
+        Self {
+            start_ms,
+            deposits: 0,
+            deposit_volume: Decimal::ZERO,
+            withdrawals: 0,
+            withdrawal_volume: Decimal::ZERO,
+            disputes: 0,
+        }
+    }
+
+    fn add(&mut self, tx: &Transaction) {
+        let amount = tx.amount().unwrap_or_default();
+        match tx.tx_type() {
+            TransactionType::Deposit => {
+                self.deposits += 1;
+                self.deposit_volume += amount;
+            }
+            TransactionType::Withdrawal => {
+                self.withdrawals += 1;
+                self.withdrawal_volume += amount;
+            }
+            TransactionType::Dispute => self.disputes += 1,
+            TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Reversal
+            | TransactionType::Adjustment(_)
+            | TransactionType::Custom(_) => {}
+        }
+    }
+
+    pub fn to_record(&self) -> [String; 6] {
+        [
+            self.start_ms.to_string(),
+            self.deposits.to_string(),
+            self.deposit_volume.to_string(),
+            self.withdrawals.to_string(),
+            self.withdrawal_volume.to_string(),
+            self.disputes.to_string(),
+        ]
+    }
+}
+
+/// Consecutive windows of a fixed width, the current one collecting transactions
+#[derive(Debug)]
+pub struct TumblingWindows {
+    width_ms: u64,
+    current: FlowWindow,
+}
+
+impl TumblingWindows {
+    /// Starts with the window holding `now_ms`
+    pub fn new(width: Duration, now_ms: u64) -> Self {
+        let width_ms = u64::try_from(width.as_millis()).unwrap_or(u64::MAX).max(1);
+        Self {
+            width_ms,
+            current: FlowWindow::new(now_ms - now_ms % width_ms),
+        }
+    }
+
+    /// When the current window ends, in milliseconds since the Unix epoch
+    pub fn end_ms(&self) -> u64 {
+        self.current.start_ms.saturating_add(self.width_ms)
+    }
+
+    /// Closes the windows which ended by `now_ms`, empty ones included, in order
+    pub fn advance(&mut self, now_ms: u64) -> Vec<FlowWindow> {
+        let mut closed = Vec::new();
+        while self.end_ms() <= now_ms {
+            closed.push(self.current);
+            self.current = FlowWindow::new(self.end_ms());
+        }
+        closed
+    }
+
+    /// Counts `tx` in the current window
+    pub fn add(&mut self, tx: &Transaction) {
+        self.current.add(tx);
+    }
+
+    /// The current window, cut short
+    pub fn finish(self) -> FlowWindow {
+        self.current
+    }
+}
+
+fn now_ms() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Writes a row per window of `width` to `file_path` as each one ends, until every sender of
+/// `applied` is dropped. The last window is written cut short.
+///
+/// # Errors
+/// Can fail to write to `file_path`
+pub async fn write_flow_windows(
+    file_path: String,
+    io_retry: RetryPolicy,
+    width: Duration,
+    mut applied: UnboundedReceiver<Transaction>,
+) -> Result<()> {
+    let file = create_retrying(&file_path, io_retry).await?;
+    let mut writer = csv_async::AsyncWriter::from_writer(file);
+    writer.write_record(&FlowWindow::HEADER).await?;
+    writer.flush().await?;
+
+    let mut windows = TumblingWindows::new(width, now_ms());
+    loop {
+        let remaining = Duration::from_millis(windows.end_ms().saturating_sub(now_ms()));
+        let tx = tokio::select! {
+            tx = applied.recv() => match tx {
+                Some(tx) => Some(tx),
+                None => break,
+            },
+            () = sleep(remaining) => None,
+        };
+        let closed = windows.advance(now_ms());
+        if let Some(tx) = tx {
+            windows.add(&tx);
+        }
+        if !closed.is_empty() {
+            for window in closed {
+                writer.write_record(&window.to_record()).await?;
+            }
+            writer.flush().await?;
+        }
+    }
+    writer.write_record(&windows.finish().to_record()).await?;
+    finish_csv(writer).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::Transaction,
+        window::{FlowWindow, TumblingWindows},
+    };
+
+    #[test]
+    fn counts_the_flow_of_each_window() {
+        let mut windows = TumblingWindows::new(Duration::from_secs(60), 125_000);
+        assert_eq!(windows.end_ms(), 180_000);
+        windows.add(&Transaction::deposit(1, 1, Decimal::TEN));
+        windows.add(&Transaction::deposit(2, 2, Decimal::TWO));
+        windows.add(&Transaction::withdrawal(1, 3, Decimal::ONE));
+        windows.add(&Transaction::dispute(1, 1));
+        windows.add(&Transaction::resolve(1, 1));
+        assert!(windows.advance(179_999).is_empty());
+
+        let closed = windows.advance(300_000);
+        assert_eq!(
+            closed,
+            vec![
+                FlowWindow {
+                    start_ms: 120_000,
+                    deposits: 2,
+                    deposit_volume: Decimal::from(12),
+                    withdrawals: 1,
+                    withdrawal_volume: Decimal::ONE,
+                    disputes: 1,
+                },
+                FlowWindow::new(180_000),
+                FlowWindow::new(240_000),
+            ]
+        );
+        windows.add(&Transaction::dispute(2, 2));
+        let last = windows.finish();
+        assert_eq!((last.start_ms, last.disputes), (300_000, 1));
+        assert_eq!(
+            last.to_record(),
+            ["300000", "0", "0", "0", "0", "1"].map(String::from)
+        );
+    }
+}
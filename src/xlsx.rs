@@ -0,0 +1,166 @@
+//! Excel input, for transactions kept in a workbook rather than exported to CSV
+//!
+//! The first sheet of an `.xlsx` input is read with calamine and each row is handed to the
+//! same deserialisation as a CSV record, so the sheet has the columns of
+//! `Transaction::HEADER` and its records are checked, skipped with `--lenient` and
+//! rejected as those of a CSV input would be. Numbers are read as Excel stores them, so an
+//! amount which must keep its exact digits is best typed as text.
+
+use std::{
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result};
+use calamine::{open_workbook, DataType, Reader, Xlsx};
+use csv_async::StringRecord;
+
+use crate::{
+    data::Sequenced,
+    io_ops::{check_header, read_transaction, CsvFormat, SOURCE_BATCH},
+    source::EventSource,
+};
+
+/// Whether the input at `file_path` is read as a workbook, by its extension
+pub fn is_workbook(file_path: &str) -> bool {
+    Path::new(file_path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("xlsx"))
+}
+
+/// Records of the first sheet of a workbook, tagged with their row number
+pub struct XlsxSource {
+    rows: std::vec::IntoIter<(u64, StringRecord)>,
+    format: CsvFormat,
+    malformed: AtomicU64,
+}
+
+impl XlsxSource {
+    /// Reads the whole sheet, which calamine cannot stream, on a blocking thread. Only
+    /// `has_header` and `lenient` of `format` apply.
+    ///
+    /// # Errors
+    /// If the file is not a workbook, has no sheet or its header is not the expected columns
+    pub async fn open(file_path: &str, format: CsvFormat) -> Result<Self> {
+        let path = file_path.to_owned();
+        let mut rows = tokio::task::spawn_blocking(move || first_sheet(&path)).await??;
+        if format.has_header {
+            let header = if rows.is_empty() {
+                StringRecord::new()
+            } else {
+                rows.remove(0).1
+            };
+            check_header(&header)?;
+        }
+        Ok(Self {
+            rows: rows.into_iter(),
+            format,
+            malformed: AtomicU64::new(0),
+        })
+    }
+}
+
+/// The rows of the first sheet with a value, numbered from 1 as Excel shows them
+fn first_sheet(file_path: &str) -> Result<Vec<(u64, StringRecord)>> {
+    let mut workbook: Xlsx<_> = open_workbook(file_path)
+        .with_context(|| format!("Cannot open the workbook {file_path}"))?;
+    let sheet = workbook
+        .worksheet_range_at(0)
+        .with_context(|| format!("The workbook {file_path} has no sheet"))??;
+    let first_row = sheet.start().map_or(0, |(row, _)| u64::from(row));
+    let rows = (first_row + 1..)
+        .zip(sheet.rows())
+        .filter(|(_, cells)| cells.iter().any(|cell| !cell.is_empty()))
+        .map(|(row, cells)| {
+            let mut record = StringRecord::new();
+            for cell in cells {
+                record.push_field(cell.to_string().trim());
+            }
+            (row, record)
+        })
+        .collect();
+    Ok(rows)
+}
+
+impl EventSource for XlsxSource {
+    async fn next_batch(&mut self) -> Result<Vec<Sequenced>> {
+        let mut batch = Vec::new();
+        while batch.len() < SOURCE_BATCH {
+            let Some((row, record)) = self.rows.next() else {
+                break;
+            };
+            let tx = self
+                .format
+                .tolerate(read_transaction(&record), &self.malformed)
+                .with_context(|| format!("Row {row} of the first sheet"))?;
+            batch.extend(tx.map(|tx| Sequenced { seq: row, tx }));
+        }
+        Ok(batch)
+    }
+
+    fn malformed(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::{
+        io_ops::CsvFormat,
+        source::EventSource,
+        xlsx::{is_workbook, XlsxSource},
+    };
+
+    /// The first sheet holds five records, the last with a client which is not a number.
+    /// The second sheet holds a deposit of its own.
+    fn fixture() -> String {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/transactions.xlsx")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Row and id of each transaction read, and the records skipped as malformed
+    async fn read_rows(format: CsvFormat) -> anyhow::Result<(Vec<(u64, u32)>, u64)> {
+        let mut source = XlsxSource::open(&fixture(), format).await?;
+        let mut rows = Vec::new();
+        loop {
+            let batch = source.next_batch().await?;
+            if batch.is_empty() {
+                return Ok((rows, source.malformed()));
+            }
+            rows.extend(batch.iter().map(|event| (event.seq, event.tx.tx_id())));
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_the_first_sheet_as_a_csv_input() {
+        let e = read_rows(CsvFormat::default()).await.unwrap_err();
+        assert!(format!("{e:#}").starts_with("Row 6 of the first sheet"));
+
+        let lenient = CsvFormat {
+            lenient: true,
+            ..CsvFormat::default()
+        };
+        let (rows, malformed) = read_rows(lenient).await.unwrap();
+        assert_eq!(rows, vec![(2, 1), (3, 2), (4, 3), (5, 2)]);
+        assert_eq!(malformed, 1);
+
+        // The header is read as a record
+        let headerless = CsvFormat {
+            has_header: false,
+            ..lenient
+        };
+        assert_eq!(read_rows(headerless).await.unwrap().1, 2);
+    }
+
+    #[test]
+    fn workbooks_are_told_by_their_extension() {
+        assert!(is_workbook("transactions.xlsx"));
+        assert!(is_workbook("in/Transactions.XLSX"));
+        assert!(!is_workbook("transactions.csv"));
+        assert!(!is_workbook("xlsx"));
+    }
+}
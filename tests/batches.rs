@@ -0,0 +1,238 @@
+//! Runs the binary over a batch with `--snapshot-out`, then simulates or processes a later
+//! batch on top of the snapshot, whose disputes reference transactions of the first batch,
+//! sealed or not.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use rust_decimal::Decimal;
+
+fn workdir(name: &str) -> PathBuf {
+    let workdir = std::env::temp_dir().join(format!("effective-train-batches-{name}"));
+    fs::create_dir_all(&workdir).unwrap();
+    workdir
+}
+
+/// Processes `transactions` as the first batch, returning the path of its snapshot
+fn first_batch(workdir: &Path, transactions: &str) -> PathBuf {
+    let input = workdir.join("first.csv");
+    fs::write(&input, transactions).unwrap();
+    let snapshot = workdir.join("accounts.msgpack");
+    let status = Command::new(env!("CARGO_BIN_EXE_effective-train"))
+        .arg(&input)
+        .arg("--snapshot-out")
+        .arg(&snapshot)
+        .current_dir(workdir)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    snapshot
+}
+
+/// Client, available, held and locked of each account written, in client id order
+type Balances = Vec<(u16, Decimal, Decimal, bool)>;
+
+/// The balances of the accounts written to `stdout`
+fn balances(stdout: Vec<u8>) -> Balances {
+    String::from_utf8(stdout)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let fields: Vec<_> = line.split(',').collect();
+            (
+                fields[0].parse().unwrap(),
+                fields[1].parse().unwrap(),
+                fields[2].parse().unwrap(),
+                fields[4] == "true",
+            )
+        })
+        .collect()
+}
+
+/// The accounts and rejections of `transactions` applied on top of `snapshot`
+fn next_batch(workdir: &Path, snapshot: &Path, transactions: &str) -> (Balances, String) {
+    let input = workdir.join("next.csv");
+    fs::write(&input, transactions).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_effective-train"))
+        .arg("simulate")
+        .arg(snapshot)
+        .arg(&input)
+        .arg("--sorted")
+        .current_dir(workdir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let rejections = String::from_utf8(output.stderr).unwrap();
+    (balances(output.stdout), rejections)
+}
+
+#[test]
+fn disputes_resolve_against_an_earlier_batch() {
+    let workdir = workdir("resolve");
+    let snapshot = first_batch(
+        &workdir,
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,1,2,4.0\n\
+         deposit,2,3,5.0\n\
+         dispute,1,2,\n",
+    );
+    let (accounts, rejections) = next_batch(
+        &workdir,
+        &snapshot,
+        "type,client,tx,amount\n\
+         dispute,2,3,\n\
+         chargeback,2,3,\n\
+         resolve,1,2,\n\
+         dispute,1,1,\n",
+    );
+    assert_eq!(
+        accounts,
+        vec![
+            (1, Decimal::from(4), Decimal::TEN, false),
+            (2, Decimal::ZERO, Decimal::ZERO, true),
+        ]
+    );
+    assert_eq!(rejections, "type,client,tx,amount,error\n");
+}
+
+#[test]
+fn unknown_and_repeated_transactions_stay_rejected() {
+    let workdir = workdir("rejected");
+    let snapshot = first_batch(&workdir, "type,client,tx,amount\ndeposit,1,1,10.0\n");
+    let (accounts, rejections) = next_batch(
+        &workdir,
+        &snapshot,
+        "type,client,tx,amount\n\
+         dispute,1,9,\n\
+         deposit,1,1,10.0\n",
+    );
+    assert_eq!(accounts, vec![(1, Decimal::TEN, Decimal::ZERO, false)]);
+    let errors: Vec<_> = rejections.lines().skip(1).collect();
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].starts_with("dispute,1,9,,"));
+    assert!(errors[0].contains("Unmatched transaction"));
+    assert_eq!(
+        errors[1],
+        "deposit,1,1,10.0,Transaction `1` was already applied, the deposit was skipped"
+    );
+}
+
+#[test]
+fn chargebacks_of_an_earlier_batch_stay_closed() {
+    let workdir = workdir("chargeback");
+    let snapshot = first_batch(
+        &workdir,
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,1,2,4.0\n\
+         dispute,1,2,\n\
+         chargeback,1,2,\n",
+    );
+    let (accounts, rejections) = next_batch(
+        &workdir,
+        &snapshot,
+        "type,client,tx,amount\n\
+         resolve,1,2,\n\
+         chargeback,1,2,\n",
+    );
+    // The funds taken by the chargeback are not released a second time
+    assert_eq!(accounts, vec![(1, Decimal::TEN, Decimal::ZERO, true)]);
+    let errors: Vec<_> = rejections.lines().skip(1).collect();
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].starts_with("resolve,1,2,,"));
+    assert!(errors[1].starts_with("chargeback,1,2,,"));
+}
+
+#[test]
+fn opening_balances_carry_the_history_of_a_snapshot() {
+    let workdir = workdir("opening");
+    let snapshot = first_batch(
+        &workdir,
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,1,2,4.0\n\
+         deposit,2,3,5.0\n\
+         dispute,1,2,\n",
+    );
+    let input = workdir.join("month.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount\n\
+         resolve,1,2,\n\
+         deposit,1,1,10.0\n\
+         dispute,2,3,\n\
+         chargeback,2,3,\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_effective-train"))
+        .arg(&input)
+        .arg("--opening-balances")
+        .arg(&snapshot)
+        .arg("--sorted")
+        .current_dir(&workdir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    // The dispute of the first batch is resolved, its repeated deposit skipped
+    assert_eq!(
+        balances(output.stdout),
+        vec![
+            (1, Decimal::from(14), Decimal::ZERO, false),
+            (2, Decimal::ZERO, Decimal::ZERO, true),
+        ]
+    );
+}
+
+#[test]
+fn sealed_snapshots_only_restore_with_their_key() {
+    let workdir = workdir("sealed");
+    let key = "5e".repeat(32);
+    let input = workdir.join("first.csv");
+    fs::write(&input, "type,client,tx,amount\ndeposit,1,1,10.0\n").unwrap();
+    let snapshot = workdir.join("accounts.msgpack");
+    let status = Command::new(env!("CARGO_BIN_EXE_effective-train"))
+        .arg(&input)
+        .arg("--snapshot-out")
+        .arg(&snapshot)
+        .env("EFFECTIVE_TRAIN_SNAPSHOT_KEY", &key)
+        .current_dir(&workdir)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    assert!(fs::read(&snapshot).unwrap().starts_with(b"ETSE"));
+
+    let next = workdir.join("next.csv");
+    fs::write(&next, "type,client,tx,amount\ndispute,1,1,\n").unwrap();
+    let simulate = |key: Option<&str>| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_effective-train"));
+        command
+            .arg("simulate")
+            .arg(&snapshot)
+            .arg(&next)
+            .env_remove("EFFECTIVE_TRAIN_SNAPSHOT_KEY")
+            .current_dir(&workdir);
+        if let Some(key) = key {
+            command.env("EFFECTIVE_TRAIN_SNAPSHOT_KEY", key);
+        }
+        command.output().unwrap()
+    };
+    let output = simulate(Some(&key));
+    assert!(output.status.success());
+    assert_eq!(
+        balances(output.stdout),
+        vec![(1, Decimal::ZERO, Decimal::TEN, false)]
+    );
+
+    let output = simulate(None);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("EFFECTIVE_TRAIN_SNAPSHOT_KEY"));
+    assert!(!simulate(Some(&"00".repeat(32))).status.success());
+}
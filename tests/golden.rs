@@ -0,0 +1,106 @@
+//! Runs the binary against each input in `tests/fixtures` and compares its exit status and
+//! output with the matching file in `tests/golden`. Each input is read with every parser, so
+//! they must all agree with the one golden file.
+//!
+//! After an intended change in output, rewrite the golden files with
+//!
+//!     UPDATE_GOLDEN=1 cargo test --test golden
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const PARSERS: [&[&str]; 3] = [&[], &["--fast-parse"], &["--readers", "3"]];
+
+fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+/// Exit status followed by the accounts on success, or the error on failure
+fn transcript(fixture: &Path, parser: &[&str]) -> String {
+    // The log file is written to the working directory
+    let workdir = std::env::temp_dir().join("effective-train-golden");
+    fs::create_dir_all(&workdir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_effective-train"))
+        .arg(fixture)
+        .arg("--sorted")
+        .args(parser)
+        .current_dir(workdir)
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let body = if output.status.success() {
+        stdout
+    } else {
+        without_position(stderr.lines().next().unwrap_or_default()) + "\n"
+    };
+    format!(
+        "exit status: {}\n{}",
+        output.status.code().unwrap(),
+        body.replace("\r\n", "\n")
+    )
+}
+
+/// The fast path parses the line of a record it falls back on by itself, so its errors
+/// cannot name the record's position in the file
+fn without_position(error: &str) -> String {
+    match (error.find(" record "), error.find("): ")) {
+        (Some(start), Some(end)) if start < end => {
+            format!("{} {}", &error[..start], &error[end + 3..])
+        }
+        _ => error.to_owned(),
+    }
+}
+
+fn check(name: &str) {
+    let fixture = tests_dir().join("fixtures").join(format!("{name}.csv"));
+    let golden = tests_dir().join("golden").join(format!("{name}.txt"));
+
+    for parser in PARSERS {
+        let actual = transcript(&fixture, parser);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() && parser.is_empty() {
+            fs::write(&golden, &actual).unwrap();
+        }
+        let expected = fs::read_to_string(&golden).unwrap();
+        assert_eq!(actual, expected, "{name} read with {parser:?}");
+    }
+}
+
+#[test]
+fn disputes_on_withdrawals() {
+    check("disputes_on_withdrawals");
+}
+
+#[test]
+fn locked_account() {
+    check("locked_account");
+}
+
+#[test]
+fn malformed_rows() {
+    check("malformed_rows");
+}
+
+#[test]
+fn huge_amounts() {
+    check("huge_amounts");
+}
+
+#[test]
+fn amount_out_of_range() {
+    check("amount_out_of_range");
+}
+
+#[test]
+fn invalid_record() {
+    check("invalid_record");
+}
+
+#[test]
+fn reversals() {
+    check("reversals");
+}
@@ -0,0 +1,68 @@
+//! Runs the binary with its output redirected to a file, as a shell pipes it, and checks
+//! that every account reached the file.
+
+use std::{
+    fmt::Write,
+    fs::{self, File},
+    process::{Command, Stdio},
+};
+
+use rust_decimal::Decimal;
+
+/// Every client id there is, more accounts than the writers buffer or flush at a time
+const CLIENTS: u32 = 1 << 16;
+
+fn accounts_written(name: &str, args: &[&str]) -> Vec<String> {
+    let workdir = std::env::temp_dir().join(format!("effective-train-output-{name}"));
+    fs::create_dir_all(&workdir).unwrap();
+    let input = workdir.join("transactions.csv");
+    let mut transactions = String::from("type,client,tx,amount\n");
+    for client in 0..CLIENTS {
+        let _ = writeln!(transactions, "deposit,{client},{client},1.5");
+    }
+    fs::write(&input, transactions).unwrap();
+
+    let output_path = workdir.join("accounts.csv");
+    let status = Command::new(env!("CARGO_BIN_EXE_effective-train"))
+        .arg(&input)
+        .args(args)
+        .current_dir(&workdir)
+        .stdout(Stdio::from(File::create(&output_path).unwrap()))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = fs::read_to_string(&output_path).unwrap();
+    assert!(output.ends_with('\n'), "the last account was cut short");
+    output.lines().map(str::to_owned).collect()
+}
+
+fn check_every_account(lines: &[String]) {
+    assert_eq!(lines[0], "client,available,held,total,locked");
+    let mut clients: Vec<u32> = lines[1..]
+        .iter()
+        .map(|line| {
+            let fields: Vec<_> = line.split(',').collect();
+            let total: f64 = fields[3].parse().unwrap();
+            assert!(
+                total == 1.5 && fields[4] == "false",
+                "unexpected account {line}"
+            );
+            fields[0].parse().unwrap()
+        })
+        .collect();
+    clients.sort_unstable();
+    assert_eq!(clients, (0..CLIENTS).collect::<Vec<_>>());
+}
+
+#[test]
+fn streamed_accounts_are_all_written() {
+    check_every_account(&accounts_written("streamed", &[]));
+}
+
+#[test]
+fn sorted_accounts_are_all_written() {
+    let lines = accounts_written("sorted", &["--sorted"]);
+    check_every_account(&lines);
+    assert!(lines[1].starts_with("0,") && lines[lines.len() - 1].starts_with("65535,"));
+}
@@ -0,0 +1,75 @@
+//! Runs the binary, built with `--features signing`, with `--sign-key`, then checks `verify`
+//! accepts the accounts and their signature, and refuses them once altered.
+#![cfg(feature = "signing")]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn run(workdir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_effective-train"))
+        .args(args)
+        .current_dir(workdir)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn signed_accounts_only_verify_unaltered() {
+    let workdir = std::env::temp_dir().join("effective-train-signing");
+    fs::create_dir_all(&workdir).unwrap();
+    fs::write(
+        workdir.join("transactions.csv"),
+        "type,client,tx,amount\ndeposit,1,1,10.5\ndeposit,2,2,3\nwithdrawal,1,3,2\n",
+    )
+    .unwrap();
+    let private_key = fixture("signing_key.pem");
+    let public_key = fixture("signing_key.pub.pem");
+    let (private_key, public_key) = (private_key.to_str().unwrap(), public_key.to_str().unwrap());
+
+    let output = run(
+        &workdir,
+        &[
+            "transactions.csv",
+            "--sign-key",
+            private_key,
+            "--signature-out",
+            "accounts.csv.sig",
+        ],
+    );
+    assert!(output.status.success());
+    fs::write(workdir.join("accounts.csv"), &output.stdout).unwrap();
+    let verify = ["verify", "accounts.csv", "--public-key", public_key];
+    let verified = run(&workdir, &verify);
+    assert!(verified.status.success());
+    assert_eq!(verified.stdout, b"accounts.csv: signature verified\n");
+
+    // Each tenant's file is signed next to it
+    let tenant = run(
+        &workdir,
+        &["--tenant", "eu=transactions.csv", "--sign-key", private_key],
+    );
+    assert!(tenant.status.success());
+    assert!(run(
+        &workdir,
+        &["verify", "accounts_eu.csv", "--public-key", public_key]
+    )
+    .status
+    .success());
+
+    let altered = String::from_utf8(output.stdout)
+        .unwrap()
+        .replace("8.5", "9.5");
+    fs::write(workdir.join("accounts.csv"), altered).unwrap();
+    let refused = run(&workdir, &verify);
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("does not match"));
+}
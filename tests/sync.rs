@@ -0,0 +1,53 @@
+//! Runs the binary on the same input with and without `--sync`, and checks that both apply
+//! and reject the same records.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+use rust_decimal::Decimal;
+
+fn run(fixture: &str, args: &[&str]) -> Output {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(fixture);
+    // The log file is written to the working directory
+    let workdir = std::env::temp_dir().join("effective-train-sync");
+    fs::create_dir_all(&workdir).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_effective-train"))
+        .arg(fixture)
+        .arg("--sorted")
+        .args(args)
+        .current_dir(workdir)
+        .output()
+        .unwrap()
+}
+
+/// The client and total of each account written
+fn totals(output: &Output) -> Vec<(u16, Decimal)> {
+    assert!(output.status.success());
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let fields: Vec<_> = line.split(',').collect();
+            (fields[0].parse().unwrap(), fields[3].parse().unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn fractional_minor_units_are_rejected_alone_in_both_modes() {
+    let minor = ["--currency", "EUR", "--amount-unit", "minor"];
+    let workers = run("fractional_minor_units.csv", &minor);
+    let sync = run(
+        "fractional_minor_units.csv",
+        &[&minor[..], &["--sync"]].concat(),
+    );
+    let expected = vec![(1, Decimal::from(1000)), (2, Decimal::from(300))];
+    assert_eq!(totals(&workers), expected);
+    assert_eq!(totals(&sync), expected);
+}
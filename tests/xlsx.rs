@@ -0,0 +1,47 @@
+//! Runs the binary over a workbook, built with `--features xlsx`, and checks the first sheet
+//! is applied as the same records in a CSV input would be.
+#![cfg(feature = "xlsx")]
+
+use std::{path::Path, process::Command};
+
+use rust_decimal::Decimal;
+
+#[test]
+fn the_first_sheet_of_a_workbook_is_the_input() {
+    let workdir = std::env::temp_dir().join("effective-train-xlsx");
+    std::fs::create_dir_all(&workdir).unwrap();
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/transactions.xlsx");
+    let run = |lenient: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_effective-train"));
+        command.arg(&fixture).arg("--sorted").current_dir(&workdir);
+        if lenient {
+            command.arg("--lenient");
+        }
+        command.output().unwrap()
+    };
+
+    // The last record's client is not a number
+    assert!(!run(false).status.success());
+    let output = run(true);
+    assert!(output.status.success());
+    let accounts: Vec<(u16, Decimal, Decimal)> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let fields: Vec<_> = line.split(',').collect();
+            (
+                fields[0].parse().unwrap(),
+                fields[1].parse().unwrap(),
+                fields[2].parse().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        accounts,
+        vec![
+            (1, Decimal::new(85, 1), Decimal::ZERO),
+            (2, Decimal::ZERO, Decimal::new(325, 2)),
+        ]
+    );
+}